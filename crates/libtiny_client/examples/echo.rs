@@ -31,19 +31,27 @@ fn main() {
 
     let chans = args_vec[1..]
         .iter()
-        .map(|c| ChanNameRef::new(c).to_owned())
+        .map(|c| (ChanNameRef::new(c).to_owned(), None))
         .collect::<Vec<_>>();
 
     let server_info = ServerInfo {
         addr: server,
         port,
         tls: false,
+        proxy: None,
+        tls_client_cert: None,
         pass: None,
         realname: "tiny echo bot".to_owned(),
         nicks: vec![nick],
         auto_join: chans.to_owned(),
         nickserv_ident: None,
         sasl_auth: None,
+        reconnect: Default::default(),
+        flood_control: Default::default(),
+        rejoin_on_kick: None,
+        ctcp_replies: Default::default(),
+        dcc: None,
+        caps: vec![],
     };
 
     println!("{:?}", server_info);
@@ -70,15 +78,17 @@ async fn echo_bot_task(server_info: ServerInfo) {
         println!("Client event: {:?}", ev);
         if let Event::Msg(Msg {
             pfx: Some(Pfx::User { nick, .. }),
-            cmd: Cmd::PRIVMSG { target, msg, .. },
+            cmd: Cmd::PRIVMSG { targets, msg, .. },
+            ..
         }) = ev
         {
-            let echo_msg = match target {
-                MsgTarget::User(_) => {
+            let echo_msg = match targets.into_iter().next() {
+                None => None,
+                Some(MsgTarget::User(_)) => {
                     // Message is a PRIVMSG to us, just echo the whole message to the sender
                     Some((nick, msg))
                 }
-                MsgTarget::Chan(chan) => {
+                Some(MsgTarget::Chan(chan)) => {
                     // Message was sent to a channel. Only echo if it's directed at us
                     let our_nick = client.get_nick();
                     if msg.starts_with(&our_nick) {
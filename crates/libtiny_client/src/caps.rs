@@ -0,0 +1,202 @@
+//! IRCv3 capability negotiation state. See
+//! https://ircv3.net/specs/extensions/capability-negotiation
+
+use libtiny_wire as wire;
+
+use std::collections::{HashMap, HashSet};
+
+/// Capability negotiation state of a connection. Reset on reconnect.
+#[derive(Debug, Default)]
+pub(crate) struct Caps {
+    /// Capabilities advertised by the server, with optional values. For example, for
+    /// `sasl=PLAIN,EXTERNAL` the key is `sasl` and the value is `PLAIN,EXTERNAL`. Updated with
+    /// `CAP LS`, `CAP NEW`, and `CAP DEL`.
+    available: HashMap<String, Option<String>>,
+
+    /// Capabilities acknowledged by the server.
+    enabled: HashSet<String>,
+
+    /// Number of `CAP REQ`s sent and not acknowledged (or rejected) yet.
+    pending_reqs: usize,
+
+    /// Did we send `CAP END`? Capabilities advertised after this point (with `CAP NEW`) are
+    /// requested without ending the negotiation again.
+    ended: bool,
+}
+
+impl Caps {
+    pub(crate) fn reset(&mut self) {
+        *self = Caps::default();
+    }
+
+    /// Add capabilities advertised with `CAP LS` or `CAP NEW`.
+    pub(crate) fn add_available(&mut self, caps: &[String]) {
+        for cap in caps {
+            let (name, value) = match cap.find('=') {
+                None => (cap.as_str(), None),
+                Some(eq_idx) => (&cap[..eq_idx], Some(cap[eq_idx + 1..].to_owned())),
+            };
+            self.available.insert(name.to_owned(), value);
+        }
+    }
+
+    /// Remove capabilities with `CAP DEL`. Removed capabilities are also disabled.
+    pub(crate) fn remove(&mut self, caps: &[String]) {
+        for cap in caps {
+            self.available.remove(cap);
+            self.enabled.remove(cap);
+        }
+    }
+
+    pub(crate) fn is_available(&self, cap: &str) -> bool {
+        self.available.contains_key(cap)
+    }
+
+    /// Value of an advertised capability. E.g. for `sasl=PLAIN,EXTERNAL` this returns
+    /// `Some("PLAIN,EXTERNAL")` for `sasl`.
+    pub(crate) fn value(&self, cap: &str) -> Option<&str> {
+        self.available.get(cap).and_then(|value| value.as_deref())
+    }
+
+    pub(crate) fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    pub(crate) fn enabled(&self) -> &HashSet<String> {
+        &self.enabled
+    }
+
+    pub(crate) fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Generate `CAP REQ` messages for capabilities in `wanted` that are advertised by the server
+    /// but not enabled yet. Capabilities are split into multiple messages when they don't fit
+    /// into one IRC message.
+    pub(crate) fn request<'a, I>(&mut self, wanted: I) -> Vec<String>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        // "CAP REQ :" + "\r\n"
+        const MAX_CAPS_LEN: usize = 512 - 11;
+
+        let mut msgs = vec![];
+        let mut caps: Vec<&str> = vec![];
+        let mut caps_len = 0;
+
+        for cap in wanted {
+            if !self.is_available(cap) || self.is_enabled(cap) || caps.contains(&cap) {
+                continue;
+            }
+            if !caps.is_empty() && caps_len + 1 + cap.len() > MAX_CAPS_LEN {
+                msgs.push(wire::cap_req(&caps));
+                caps.clear();
+                caps_len = 0;
+            }
+            caps_len += cap.len() + if caps.is_empty() { 0 } else { 1 };
+            caps.push(cap);
+        }
+
+        if !caps.is_empty() {
+            msgs.push(wire::cap_req(&caps));
+        }
+
+        self.pending_reqs += msgs.len();
+        msgs
+    }
+
+    /// Handle `CAP ACK`. Capabilities with `-` prefix are disabled.
+    pub(crate) fn ack(&mut self, caps: &[String]) {
+        for cap in caps {
+            match cap.strip_prefix('-') {
+                Some(cap) => {
+                    self.enabled.remove(cap);
+                }
+                None => {
+                    self.enabled.insert(cap.to_owned());
+                }
+            }
+        }
+        self.pending_reqs = self.pending_reqs.saturating_sub(1);
+    }
+
+    /// Handle `CAP NAK`.
+    pub(crate) fn nak(&mut self) {
+        self.pending_reqs = self.pending_reqs.saturating_sub(1);
+    }
+
+    /// Returns a `CAP END` message if the negotiation is not ended yet and can be ended: all
+    /// requests are answered and SASL authentication (if any) is done.
+    pub(crate) fn end(&mut self, sasl_in_progress: bool) -> Option<String> {
+        if self.ended || self.pending_reqs != 0 || sasl_in_progress {
+            None
+        } else {
+            self.ended = true;
+            Some(wire::cap_end())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn negotiation() {
+        let mut caps = Caps::default();
+        caps.add_available(&strs(&["multi-prefix", "sasl=PLAIN,EXTERNAL"]));
+        caps.add_available(&strs(&["server-time"]));
+        assert_eq!(caps.value("sasl"), Some("PLAIN,EXTERNAL"));
+        assert_eq!(caps.value("multi-prefix"), None);
+
+        let reqs = caps.request(
+            ["server-time", "away-notify", "multi-prefix"]
+                .iter()
+                .copied(),
+        );
+        assert_eq!(
+            reqs,
+            vec!["CAP REQ :server-time multi-prefix\r\n".to_owned()]
+        );
+        assert_eq!(caps.end(false), None);
+
+        caps.ack(&strs(&["server-time", "multi-prefix"]));
+        assert!(caps.is_enabled("server-time"));
+        assert_eq!(caps.end(true), None);
+        assert_eq!(caps.end(false), Some("CAP END\r\n".to_owned()));
+        assert_eq!(caps.end(false), None);
+
+        // Enabled caps are not requested again
+        assert!(caps.request(["server-time"].iter().copied()).is_empty());
+
+        caps.remove(&strs(&["multi-prefix"]));
+        assert!(!caps.is_enabled("multi-prefix"));
+        assert!(!caps.is_available("multi-prefix"));
+
+        caps.ack(&strs(&["-server-time"]));
+        assert!(!caps.is_enabled("server-time"));
+    }
+
+    #[test]
+    fn request_splitting() {
+        let mut caps = Caps::default();
+        let wanted: Vec<String> = (0..100)
+            .map(|i| format!("vendor.example/cap{}", i))
+            .collect();
+        caps.add_available(&wanted);
+        let reqs = caps.request(wanted.iter().map(String::as_str));
+        assert!(reqs.len() > 1);
+        for req in &reqs {
+            assert!(req.len() <= 512);
+        }
+        assert_eq!(caps.end(false), None);
+        for _ in &reqs {
+            caps.nak();
+        }
+        assert_eq!(caps.end(false), Some("CAP END\r\n".to_owned()));
+    }
+}
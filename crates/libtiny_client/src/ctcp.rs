@@ -0,0 +1,96 @@
+//! Automatic replies to CTCP requests (see `CtcpReplies`), and CTCP PING round-trip times.
+
+use crate::CtcpReplies;
+use libtiny_wire::CTCP;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Arguments of the reply to a CTCP request. `None` if we don't reply to the request.
+pub(crate) fn reply_args(ctcp: &CTCP, args: &str, replies: &CtcpReplies) -> Option<String> {
+    match ctcp {
+        CTCP::Version => replies.version.clone(),
+        CTCP::Source => replies.source.clone(),
+        CTCP::UserInfo => replies.userinfo.clone(),
+        CTCP::Ping if replies.ping => Some(args.to_owned()),
+        CTCP::Time if replies.time => Some(time::now().rfc822().to_string()),
+        CTCP::ClientInfo if replies.clientinfo => Some(clientinfo(replies)),
+        _ => None,
+    }
+}
+
+/// Supported CTCP requests, for CLIENTINFO replies.
+fn clientinfo(replies: &CtcpReplies) -> String {
+    let supported = [
+        ("ACTION", true),
+        ("CLIENTINFO", true),
+        ("PING", replies.ping),
+        ("SOURCE", replies.source.is_some()),
+        ("TIME", replies.time),
+        ("USERINFO", replies.userinfo.is_some()),
+        ("VERSION", replies.version.is_some()),
+    ];
+    supported
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(ctcp, _)| *ctcp)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Argument for CTCP PING requests: current time in milliseconds since the Unix epoch. The reply
+/// echoes it back, see `ctcp_ping_rtt`.
+pub(crate) fn ping_arg() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_millis().to_string()
+}
+
+/// Round-trip time of a CTCP PING, from the argument of the reply. `None` if the argument is not
+/// a timestamp sent by `Client::ctcp`.
+pub fn ctcp_ping_rtt(arg: &str) -> Option<Duration> {
+    let sent = Duration::from_millis(arg.trim().parse().ok()?);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+    now.checked_sub(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies() {
+        let mut replies = CtcpReplies {
+            version: Some("tiny".to_owned()),
+            ..CtcpReplies::default()
+        };
+        assert_eq!(
+            reply_args(&CTCP::Version, "", &replies),
+            Some("tiny".to_owned())
+        );
+        assert_eq!(
+            reply_args(&CTCP::Ping, "123", &replies),
+            Some("123".to_owned())
+        );
+        assert_eq!(reply_args(&CTCP::UserInfo, "", &replies), None);
+        assert_eq!(reply_args(&CTCP::Action, "", &replies), None);
+        assert_eq!(
+            reply_args(&CTCP::Other("FINGER".to_owned()), "", &replies),
+            None
+        );
+        assert_eq!(
+            reply_args(&CTCP::ClientInfo, "", &replies),
+            Some("ACTION CLIENTINFO PING SOURCE TIME VERSION".to_owned())
+        );
+
+        replies.ping = false;
+        assert_eq!(reply_args(&CTCP::Ping, "123", &replies), None);
+    }
+
+    #[test]
+    fn ping_rtt() {
+        let rtt = ctcp_ping_rtt(&ping_arg()).unwrap();
+        assert!(rtt < Duration::from_secs(1));
+        assert_eq!(ctcp_ping_rtt("abc"), None);
+    }
+}
@@ -0,0 +1,1574 @@
+//! DCC SEND file transfers and DCC CHAT sessions. See https://modern.ircdocs.horse/dcc.html
+//!
+//! A file is offered with a CTCP `DCC SEND <file> <ip> <port> <size>` message and the receiver
+//! connects to the sender. With passive (reverse) DCC the sender sends port 0 and a token, the
+//! receiver listens and replies with `DCC SEND <file> <ip> <port> <size> <token>`, and the sender
+//! connects to the receiver. The receiver acknowledges received data by sending the number of
+//! bytes received so far as a 32-bit big-endian integer.
+//!
+//! Interrupted transfers are resumed with `DCC RESUME <file> <port> <position>`, which the sender
+//! answers with `DCC ACCEPT <file> <port> <position>` before the receiver connects.
+//!
+//! Chats are offered with `DCC CHAT chat <ip> <port>`, passive offers work the same way as
+//! passive file offers. Chat messages are sent as lines, `ACTION` messages are sent in CTCP
+//! format.
+
+use crate::{DccChat, DccConfig, DccTransfer, Event};
+use libtiny_common::CaseMapping;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+/// How long to wait for the other side to connect, after offering a file or accepting a passive
+/// offer.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait when connecting to the other side.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for acknowledgements after sending a file.
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long incoming offers wait for the user to accept them.
+const OFFER_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Transfer and chat ids are unique in the process, so that transfers can be found without knowing
+/// the server.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A DCC request, from the arguments of a CTCP DCC message.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DccMsg {
+    /// `DCC SEND <file> <ip> <port> [<size> [<token>]]`. `port` is 0 in passive offers.
+    Send {
+        filename: String,
+        addr: IpAddr,
+        port: u16,
+        size: Option<u64>,
+        token: Option<String>,
+    },
+    /// `DCC RESUME <file> <port> <position> [<token>]`
+    Resume {
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+    /// `DCC ACCEPT <file> <port> <position> [<token>]`
+    Accept {
+        port: u16,
+        position: u64,
+        token: Option<String>,
+    },
+    /// `DCC CHAT chat <ip> <port> [<token>]`. `port` is 0 in passive offers.
+    Chat {
+        addr: IpAddr,
+        port: u16,
+        token: Option<String>,
+    },
+}
+
+pub(crate) fn parse(args: &str) -> Option<DccMsg> {
+    let (ty, rest) = args.trim().split_once(' ')?;
+    let (filename, rest) = parse_filename(rest.trim_start())?;
+    let mut words = rest.split_whitespace();
+    if ty.eq_ignore_ascii_case("SEND") {
+        let addr = parse_addr(words.next()?)?;
+        let port = words.next()?.parse().ok()?;
+        let size = words.next().and_then(|size| size.parse().ok());
+        let token = words.next().map(str::to_owned);
+        Some(DccMsg::Send {
+            filename,
+            addr,
+            port,
+            size,
+            token,
+        })
+    } else if ty.eq_ignore_ascii_case("CHAT") && filename.eq_ignore_ascii_case("chat") {
+        let addr = parse_addr(words.next()?)?;
+        let port = words.next()?.parse().ok()?;
+        let token = words.next().map(str::to_owned);
+        Some(DccMsg::Chat { addr, port, token })
+    } else if ty.eq_ignore_ascii_case("RESUME") || ty.eq_ignore_ascii_case("ACCEPT") {
+        let port = words.next()?.parse().ok()?;
+        let position = words.next()?.parse().ok()?;
+        let token = words.next().map(str::to_owned);
+        if ty.eq_ignore_ascii_case("RESUME") {
+            Some(DccMsg::Resume {
+                port,
+                position,
+                token,
+            })
+        } else {
+            Some(DccMsg::Accept {
+                port,
+                position,
+                token,
+            })
+        }
+    } else {
+        None
+    }
+}
+
+/// Parse a file name, which is quoted when it has spaces. Returns the file name and the rest.
+fn parse_filename(s: &str) -> Option<(String, &str)> {
+    if let Some(s) = s.strip_prefix('"') {
+        let end = s.find('"')?;
+        Some((s[..end].to_owned(), &s[end + 1..]))
+    } else {
+        let end = s.find(' ').unwrap_or(s.len());
+        if end == 0 {
+            return None;
+        }
+        Some((s[..end].to_owned(), &s[end..]))
+    }
+}
+
+/// IPv4 addresses are sent as integers, IPv6 addresses in the usual notation.
+fn parse_addr(s: &str) -> Option<IpAddr> {
+    match s.parse::<u32>() {
+        Ok(ip) => Some(IpAddr::V4(Ipv4Addr::from(ip))),
+        Err(_) => s.parse().ok(),
+    }
+}
+
+fn format_addr(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(ip) => u32::from(ip).to_string(),
+        IpAddr::V6(ip) => ip.to_string(),
+    }
+}
+
+fn format_filename(filename: &str) -> String {
+    if filename.contains(' ') {
+        format!("\"{}\"", filename)
+    } else {
+        filename.to_owned()
+    }
+}
+
+/// Arguments of a `DCC SEND` message.
+fn send_args(
+    filename: &str,
+    addr: IpAddr,
+    port: u16,
+    size: Option<u64>,
+    token: Option<&str>,
+) -> String {
+    let mut args = format!(
+        "SEND {} {} {}",
+        format_filename(filename),
+        format_addr(addr),
+        port
+    );
+    if let Some(size) = size {
+        args.push_str(&format!(" {}", size));
+        if let Some(token) = token {
+            args.push_str(&format!(" {}", token));
+        }
+    }
+    args
+}
+
+/// Arguments of a `DCC CHAT` message.
+fn chat_args(addr: IpAddr, port: u16, token: Option<&str>) -> String {
+    let mut args = format!("CHAT chat {} {}", format_addr(addr), port);
+    if let Some(token) = token {
+        args.push_str(&format!(" {}", token));
+    }
+    args
+}
+
+/// Arguments of a `DCC RESUME` or `DCC ACCEPT` message.
+fn resume_args(ty: &str, filename: &str, port: u16, position: u64, token: Option<&str>) -> String {
+    let mut args = format!("{} {} {} {}", ty, format_filename(filename), port, position);
+    if let Some(token) = token {
+        args.push_str(&format!(" {}", token));
+    }
+    args
+}
+
+/// Name of an offered file in the download directory: directories are dropped, and leading dots
+/// are removed to avoid hidden files and `..`.
+fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let name = name.trim_start_matches('.').trim();
+    if name.is_empty() {
+        "file".to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+type Transfers = Rc<RefCell<HashMap<usize, Transfer>>>;
+
+type Chats = Rc<RefCell<HashMap<usize, Chat>>>;
+
+/// Incomplete downloads, with nicks of the senders.
+type Partials = Rc<RefCell<HashMap<PathBuf, String>>>;
+
+pub(crate) struct Dcc {
+    /// `None` when DCC is disabled. Offers are ignored and files can't be sent.
+    config: Option<DccConfig>,
+
+    /// Channel to send transfer events to the user
+    snd_ev: Sender<Event>,
+
+    /// Local address of the server connection. Used in offers when `DccConfig::ip` is not set.
+    local_ip: Option<IpAddr>,
+
+    /// Transfers and offers. Tasks remove their transfers when they finish.
+    transfers: Transfers,
+
+    /// Chats and chat offers. Tasks remove their chats when the connection is closed.
+    chats: Chats,
+
+    /// Files of downloads that did not finish. Only these files are resumed, and only when offered
+    /// by the same nick again. Receive tasks add their files when they start and remove them when
+    /// they finish successfully.
+    partials: Partials,
+}
+
+struct Transfer {
+    info: DccTransfer,
+    state: TransferState,
+}
+
+enum TransferState {
+    /// Incoming offer, waiting for the user to accept it
+    Offered {
+        addr: IpAddr,
+        port: u16,
+        token: Option<String>,
+    },
+
+    /// Accepted an offer of a partially received file, waiting for `DCC ACCEPT`
+    Resuming {
+        addr: IpAddr,
+        port: u16,
+        token: Option<String>,
+        path: PathBuf,
+    },
+
+    /// We offered a file. `port` is the port we're listening on, 0 in passive offers. `offset` is
+    /// updated by `DCC RESUME` requests before the receiver connects.
+    Offering {
+        path: PathBuf,
+        port: u16,
+        token: Option<String>,
+        offset: Rc<Cell<u64>>,
+        task: Option<JoinHandle<()>>,
+    },
+
+    /// Transfer in progress
+    Running { task: JoinHandle<()> },
+}
+
+struct Chat {
+    info: DccChat,
+    state: ChatState,
+    /// Session task, when the chat is accepted
+    task: Option<JoinHandle<()>>,
+    /// Lines to send to the other side. Set when the task is spawned.
+    snd_line: Option<Sender<String>>,
+}
+
+enum ChatState {
+    /// Incoming offer, waiting for the user to accept it
+    Offered {
+        addr: IpAddr,
+        port: u16,
+        token: Option<String>,
+    },
+
+    /// We offered a chat. `port` is the port we're listening on, 0 in passive offers.
+    Offering {
+        port: u16,
+        token: Option<String>,
+    },
+
+    /// Accepted, waiting for the connection
+    Connecting,
+
+    Connected,
+}
+
+impl Dcc {
+    pub(crate) fn new(config: Option<DccConfig>, snd_ev: Sender<Event>) -> Dcc {
+        Dcc {
+            config,
+            snd_ev,
+            local_ip: None,
+            transfers: Rc::new(RefCell::new(HashMap::new())),
+            chats: Rc::new(RefCell::new(HashMap::new())),
+            partials: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn set_local_ip(&mut self, ip: Option<IpAddr>) {
+        self.local_ip = ip;
+    }
+
+    pub(crate) fn has_transfer(&self, id: usize) -> bool {
+        self.transfers.borrow().contains_key(&id) || self.chats.borrow().contains_key(&id)
+    }
+
+    /// Nick of the other side of a transfer or chat.
+    pub(crate) fn nick(&self, id: usize) -> Option<String> {
+        match self.transfers.borrow().get(&id) {
+            Some(transfer) => Some(transfer.info.nick.clone()),
+            None => self
+                .chats
+                .borrow()
+                .get(&id)
+                .map(|chat| chat.info.nick.clone()),
+        }
+    }
+
+    fn own_ip(&self) -> Result<IpAddr, String> {
+        self.config
+            .as_ref()
+            .and_then(|config| config.ip)
+            .or(self.local_ip)
+            .ok_or_else(|| "Can't find own IP address, set it in DCC settings".to_owned())
+    }
+
+    /// Handle a CTCP DCC message from `nick`. Returns arguments of a CTCP DCC message to send back
+    /// to `nick`.
+    pub(crate) fn handle_msg(
+        &mut self,
+        nick: &str,
+        args: &str,
+        casemapping: CaseMapping,
+    ) -> Option<String> {
+        let config = self.config.clone()?;
+        let msg = match parse(args) {
+            None => {
+                debug!("Can't parse DCC message: {:?}", args);
+                return None;
+            }
+            Some(msg) => msg,
+        };
+        let is_peer = |transfer: &Transfer| casemapping.eq_ignore_case(&transfer.info.nick, nick);
+
+        match msg {
+            DccMsg::Send {
+                filename,
+                addr,
+                port,
+                size,
+                token,
+            } => {
+                // Reply to our passive offer?
+                if port != 0 && token.is_some() {
+                    let mut transfers = self.transfers.borrow_mut();
+                    let found = transfers.iter_mut().find(|(_, transfer)| {
+                        is_peer(transfer)
+                            && matches!(&transfer.state, TransferState::Offering { port: 0, token: our_token, .. } if *our_token == token)
+                    });
+                    if let Some((id, transfer)) = found {
+                        let id = *id;
+                        if let TransferState::Offering { path, offset, .. } = &transfer.state {
+                            let task = tokio::task::spawn_local(send_task(
+                                transfer.info.clone(),
+                                Connect::To(SocketAddr::new(addr, port)),
+                                path.clone(),
+                                offset.clone(),
+                                self.transfers.clone(),
+                                self.snd_ev.clone(),
+                            ));
+                            transfer.state = TransferState::Running { task };
+                            debug!("Passive DCC offer {} accepted", id);
+                        }
+                        return None;
+                    }
+                }
+
+                // Passive offers need a token
+                if port == 0 && token.is_none() {
+                    return None;
+                }
+
+                let info = DccTransfer {
+                    id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                    nick: nick.to_owned(),
+                    filename,
+                    size,
+                    incoming: true,
+                };
+
+                if let (Some(size), Some(max_size)) = (size, config.max_size) {
+                    if size > max_size {
+                        // Dropped when the event channel is full, e.g. when flooded with offers
+                        let _ = self.snd_ev.try_send(Event::DccFailed {
+                            transfer: info,
+                            msg: format!(
+                                "File is larger than the limit ({} bytes), offer rejected",
+                                max_size
+                            ),
+                        });
+                        return None;
+                    }
+                }
+
+                // Offers the user doesn't know about can't be accepted, drop the offer when the
+                // event channel is full
+                if self.snd_ev.try_send(Event::DccOffer(info.clone())).is_err() {
+                    debug!("Event channel full, dropping DCC offer {:?}", info);
+                    return None;
+                }
+                let id = info.id;
+                self.transfers.borrow_mut().insert(
+                    id,
+                    Transfer {
+                        info,
+                        state: TransferState::Offered { addr, port, token },
+                    },
+                );
+                tokio::task::spawn_local(expire_offer(
+                    id,
+                    self.transfers.clone(),
+                    self.snd_ev.clone(),
+                ));
+                None
+            }
+
+            DccMsg::Chat { addr, port, token } => {
+                // Reply to our passive offer?
+                if port != 0 && token.is_some() {
+                    let mut chats = self.chats.borrow_mut();
+                    let found = chats.values_mut().find(|chat| {
+                        casemapping.eq_ignore_case(&chat.info.nick, nick)
+                            && matches!(&chat.state, ChatState::Offering { port: 0, token: our_token } if *our_token == token)
+                    });
+                    if let Some(chat) = found {
+                        spawn_chat(
+                            chat,
+                            Connect::To(SocketAddr::new(addr, port)),
+                            self.chats.clone(),
+                            self.snd_ev.clone(),
+                        );
+                        return None;
+                    }
+                }
+
+                // Passive offers need a token
+                if port == 0 && token.is_none() {
+                    return None;
+                }
+
+                let info = DccChat {
+                    id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                    nick: nick.to_owned(),
+                };
+                if self
+                    .snd_ev
+                    .try_send(Event::DccChatOffer(info.clone()))
+                    .is_err()
+                {
+                    debug!("Event channel full, dropping DCC chat offer {:?}", info);
+                    return None;
+                }
+                let id = info.id;
+                self.chats.borrow_mut().insert(
+                    id,
+                    Chat {
+                        info,
+                        state: ChatState::Offered { addr, port, token },
+                        task: None,
+                        snd_line: None,
+                    },
+                );
+                tokio::task::spawn_local(expire_chat_offer(
+                    id,
+                    self.chats.clone(),
+                    self.snd_ev.clone(),
+                ));
+                None
+            }
+
+            DccMsg::Resume {
+                port,
+                position,
+                token,
+            } => {
+                let transfers = self.transfers.borrow();
+                let transfer = transfers.values().find(|transfer| {
+                    is_peer(transfer)
+                        && match &transfer.state {
+                            TransferState::Offering {
+                                port: our_port,
+                                token: our_token,
+                                ..
+                            } => {
+                                (*our_port != 0 && *our_port == port)
+                                    || (token.is_some() && *our_token == token)
+                            }
+                            _ => false,
+                        }
+                })?;
+                if let TransferState::Offering {
+                    offset, port, path, ..
+                } = &transfer.state
+                {
+                    if position > transfer.info.size.unwrap_or(0) {
+                        return None;
+                    }
+                    offset.set(position);
+                    let filename = path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    return Some(resume_args(
+                        "ACCEPT",
+                        &filename,
+                        *port,
+                        position,
+                        token.as_deref(),
+                    ));
+                }
+                None
+            }
+
+            DccMsg::Accept {
+                port,
+                position,
+                token,
+            } => {
+                let id = self
+                    .transfers
+                    .borrow()
+                    .iter()
+                    .find(|(_, transfer)| {
+                        is_peer(transfer)
+                            && match &transfer.state {
+                                TransferState::Resuming {
+                                    port: offer_port,
+                                    token: offer_token,
+                                    ..
+                                } => {
+                                    (*offer_port != 0 && *offer_port == port)
+                                        || (token.is_some() && *offer_token == token)
+                                }
+                                _ => false,
+                            }
+                    })
+                    .map(|(id, _)| *id)?;
+                match self.start_receive(id, position) {
+                    Ok(reply) => reply,
+                    Err(err) => {
+                        self.fail(id, err);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Accept a file or chat offer. Returns arguments of a CTCP DCC message to send to the sender.
+    pub(crate) fn accept(
+        &mut self,
+        id: usize,
+        casemapping: CaseMapping,
+    ) -> Result<Option<String>, String> {
+        if self.chats.borrow().contains_key(&id) {
+            return self.accept_chat(id);
+        }
+        let config = self.config.clone().ok_or("DCC is disabled")?;
+        let mut transfers = self.transfers.borrow_mut();
+        let transfer = transfers
+            .get_mut(&id)
+            .ok_or_else(|| format!("Can't find DCC offer {}", id))?;
+        let (addr, port, token) = match &transfer.state {
+            TransferState::Offered { addr, port, token } => (*addr, *port, token.clone()),
+            _ => return Err(format!("DCC transfer {} is already accepted", id)),
+        };
+
+        std::fs::create_dir_all(&config.download_dir).map_err(|err| err.to_string())?;
+        let path = config
+            .download_dir
+            .join(sanitize_filename(&transfer.info.filename));
+
+        // Resume partially received files, when we received the file from the same nick
+        let existing_len = std::fs::metadata(&path).map(|meta| meta.len()).ok();
+        let is_partial = self
+            .partials
+            .borrow()
+            .get(&path)
+            .is_some_and(|peer| casemapping.eq_ignore_case(peer, &transfer.info.nick));
+        match (existing_len, transfer.info.size) {
+            (Some(len), Some(size)) if is_partial && len > 0 && len < size => {
+                transfer.state = TransferState::Resuming {
+                    addr,
+                    port,
+                    token: token.clone(),
+                    path,
+                };
+                Ok(Some(resume_args(
+                    "RESUME",
+                    &transfer.info.filename,
+                    port,
+                    len,
+                    token.as_deref(),
+                )))
+            }
+            _ => {
+                let path = if existing_len.is_some() {
+                    unique_path(&path)
+                } else {
+                    path
+                };
+                transfer.state = TransferState::Resuming {
+                    addr,
+                    port,
+                    token,
+                    path,
+                };
+                drop(transfers);
+                self.start_receive(id, 0)
+            }
+        }
+    }
+
+    /// Start receiving a file accepted by the user. Returns arguments of a `DCC SEND` reply for
+    /// passive offers.
+    fn start_receive(&mut self, id: usize, offset: u64) -> Result<Option<String>, String> {
+        let own_ip = self.own_ip();
+        let config = self.config.as_ref().ok_or("DCC is disabled")?;
+        let mut transfers = self.transfers.borrow_mut();
+        let transfer = transfers
+            .get_mut(&id)
+            .ok_or_else(|| format!("Can't find DCC offer {}", id))?;
+        let (addr, port, token, path) = match &transfer.state {
+            TransferState::Resuming {
+                addr,
+                port,
+                token,
+                path,
+            } => (*addr, *port, token.clone(), path.clone()),
+            _ => return Err(format!("DCC transfer {} is already running", id)),
+        };
+
+        let (connect, reply) = if port == 0 {
+            // Passive offer: listen and send our address
+            let listener = bind(config)?;
+            let listen_port = listener.local_addr().map_err(|err| err.to_string())?.port();
+            let reply = send_args(
+                &transfer.info.filename,
+                own_ip?,
+                listen_port,
+                transfer.info.size,
+                token.as_deref(),
+            );
+            (Connect::Accept(listener), Some(reply))
+        } else {
+            (Connect::To(SocketAddr::new(addr, port)), None)
+        };
+
+        let task = tokio::task::spawn_local(receive_task(
+            transfer.info.clone(),
+            connect,
+            path,
+            offset,
+            config.max_size,
+            self.transfers.clone(),
+            self.partials.clone(),
+            self.snd_ev.clone(),
+        ));
+        transfer.state = TransferState::Running { task };
+        Ok(reply)
+    }
+
+    /// Offer a file to `nick`. Returns the transfer and arguments of the `DCC SEND` message to send
+    /// to `nick`.
+    pub(crate) fn send(
+        &mut self,
+        nick: &str,
+        path: &Path,
+    ) -> Result<(DccTransfer, String), String> {
+        let config = self.config.clone().ok_or("DCC is disabled")?;
+        let meta = std::fs::metadata(path).map_err(|err| err.to_string())?;
+        if !meta.is_file() {
+            return Err(format!("{} is not a file", path.display()));
+        }
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("{} is not a file", path.display()))?;
+        let own_ip = self.own_ip()?;
+
+        let info = DccTransfer {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nick: nick.to_owned(),
+            filename: filename.clone(),
+            size: Some(meta.len()),
+            incoming: false,
+        };
+        let offset = Rc::new(Cell::new(0));
+
+        let (port, token, task) = if config.passive {
+            let token = info.id.to_string();
+            (0, Some(token), None)
+        } else {
+            let listener = bind(&config)?;
+            let port = listener.local_addr().map_err(|err| err.to_string())?.port();
+            let task = tokio::task::spawn_local(send_task(
+                info.clone(),
+                Connect::Accept(listener),
+                path.to_owned(),
+                offset.clone(),
+                self.transfers.clone(),
+                self.snd_ev.clone(),
+            ));
+            (port, None, Some(task))
+        };
+
+        let args = send_args(&filename, own_ip, port, info.size, token.as_deref());
+        self.transfers.borrow_mut().insert(
+            info.id,
+            Transfer {
+                info: info.clone(),
+                state: TransferState::Offering {
+                    path: path.to_owned(),
+                    port,
+                    token,
+                    offset,
+                    task,
+                },
+            },
+        );
+        Ok((info, args))
+    }
+
+    fn accept_chat(&mut self, id: usize) -> Result<Option<String>, String> {
+        let own_ip = self.own_ip();
+        let config = self.config.as_ref().ok_or("DCC is disabled")?;
+        let mut chats = self.chats.borrow_mut();
+        let chat = chats
+            .get_mut(&id)
+            .ok_or_else(|| format!("Can't find DCC chat offer {}", id))?;
+        let (addr, port, token) = match &chat.state {
+            ChatState::Offered { addr, port, token } => (*addr, *port, token.clone()),
+            _ => return Err(format!("DCC chat {} is already accepted", id)),
+        };
+        if port == 0 {
+            // Passive offer: listen and send our address
+            let listener = bind(config)?;
+            let listen_port = listener.local_addr().map_err(|err| err.to_string())?.port();
+            let reply = chat_args(own_ip?, listen_port, token.as_deref());
+            spawn_chat(
+                chat,
+                Connect::Accept(listener),
+                self.chats.clone(),
+                self.snd_ev.clone(),
+            );
+            Ok(Some(reply))
+        } else {
+            spawn_chat(
+                chat,
+                Connect::To(SocketAddr::new(addr, port)),
+                self.chats.clone(),
+                self.snd_ev.clone(),
+            );
+            Ok(None)
+        }
+    }
+
+    /// Offer a chat to `nick`. An existing offer from `nick` is accepted instead. Returns the chat
+    /// and arguments of the `DCC CHAT` message to send to `nick`.
+    pub(crate) fn chat(
+        &mut self,
+        nick: &str,
+        casemapping: CaseMapping,
+    ) -> Result<(DccChat, Option<String>), String> {
+        let config = self.config.clone().ok_or("DCC is disabled")?;
+        let existing = self
+            .chats
+            .borrow()
+            .values()
+            .find(|chat| casemapping.eq_ignore_case(&chat.info.nick, nick))
+            .map(|chat| {
+                (
+                    chat.info.clone(),
+                    matches!(chat.state, ChatState::Offered { .. }),
+                )
+            });
+        match existing {
+            Some((info, true)) => {
+                let reply = self.accept_chat(info.id)?;
+                return Ok((info, reply));
+            }
+            Some((_, false)) => return Err(format!("Already chatting with {}", nick)),
+            None => {}
+        }
+
+        let own_ip = self.own_ip()?;
+        let info = DccChat {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nick: nick.to_owned(),
+        };
+        let mut chat = Chat {
+            info: info.clone(),
+            state: ChatState::Offering {
+                port: 0,
+                token: None,
+            },
+            task: None,
+            snd_line: None,
+        };
+        let (port, token) = if config.passive {
+            (0, Some(info.id.to_string()))
+        } else {
+            let listener = bind(&config)?;
+            let port = listener.local_addr().map_err(|err| err.to_string())?.port();
+            spawn_chat(
+                &mut chat,
+                Connect::Accept(listener),
+                self.chats.clone(),
+                self.snd_ev.clone(),
+            );
+            (port, None)
+        };
+        chat.state = ChatState::Offering {
+            port,
+            token: token.clone(),
+        };
+        self.chats.borrow_mut().insert(info.id, chat);
+        Ok((info, Some(chat_args(own_ip, port, token.as_deref()))))
+    }
+
+    /// Send a message in the chat with `nick`.
+    pub(crate) fn chat_send(
+        &self,
+        nick: &str,
+        msg: &str,
+        is_action: bool,
+        casemapping: CaseMapping,
+    ) -> Result<(), String> {
+        let chats = self.chats.borrow();
+        let chat = chats
+            .values()
+            .find(|chat| {
+                casemapping.eq_ignore_case(&chat.info.nick, nick)
+                    && !matches!(chat.state, ChatState::Offered { .. })
+            })
+            .ok_or_else(|| format!("No DCC chat with {}", nick))?;
+        match (&chat.state, &chat.snd_line) {
+            (ChatState::Connected, Some(snd_line)) => {
+                let line = if is_action {
+                    format!("\x01ACTION {}\x01", msg)
+                } else {
+                    msg.to_owned()
+                };
+                snd_line
+                    .try_send(line)
+                    .map_err(|_| format!("Can't send message to {}", nick))
+            }
+            _ => Err(format!("DCC chat with {} is not connected yet", nick)),
+        }
+    }
+
+    /// Close the chat with `nick`, or reject their offer. Returns `false` if there's no chat with
+    /// `nick`.
+    pub(crate) fn chat_close(&mut self, nick: &str, casemapping: CaseMapping) -> bool {
+        let id = self
+            .chats
+            .borrow()
+            .values()
+            .find(|chat| casemapping.eq_ignore_case(&chat.info.nick, nick))
+            .map(|chat| chat.info.id);
+        match id {
+            None => false,
+            Some(id) => self.cancel(id),
+        }
+    }
+
+    /// Reject an offer or cancel a transfer or chat. Returns `false` if the transfer can't be
+    /// found.
+    pub(crate) fn cancel(&mut self, id: usize) -> bool {
+        if let Some(chat) = self.chats.borrow_mut().remove(&id) {
+            if let Some(task) = chat.task {
+                task.abort();
+            }
+            return true;
+        }
+        match self.transfers.borrow_mut().remove(&id) {
+            None => false,
+            Some(transfer) => {
+                match transfer.state {
+                    TransferState::Running { task }
+                    | TransferState::Offering {
+                        task: Some(task), ..
+                    } => task.abort(),
+                    _ => {}
+                }
+                true
+            }
+        }
+    }
+
+    fn fail(&mut self, id: usize, msg: String) {
+        if let Some(transfer) = self.transfers.borrow_mut().remove(&id) {
+            let _ = self.snd_ev.try_send(Event::DccFailed {
+                transfer: transfer.info,
+                msg,
+            });
+        }
+    }
+}
+
+/// Spawn the session task of a chat.
+fn spawn_chat(chat: &mut Chat, conn: Connect, chats: Chats, snd_ev: Sender<Event>) {
+    let (snd_line, rcv_line) = mpsc::channel(100);
+    chat.task = Some(tokio::task::spawn_local(chat_task(
+        chat.info.clone(),
+        conn,
+        rcv_line,
+        chats,
+        snd_ev,
+    )));
+    chat.snd_line = Some(snd_line);
+    chat.state = ChatState::Connecting;
+}
+
+/// Bind a listener for a transfer or chat, using a port in `DccConfig::ports` if set.
+fn bind(config: &DccConfig) -> Result<TcpListener, String> {
+    let ports = match config.ports {
+        None => 0..=0,
+        Some((first, last)) => first..=last,
+    };
+    let mut last_err = None;
+    for port in ports {
+        match std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)) {
+            Ok(listener) => {
+                listener
+                    .set_nonblocking(true)
+                    .map_err(|err| err.to_string())?;
+                return TcpListener::from_std(listener).map_err(|err| err.to_string());
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err
+        .map(|err| err.to_string())
+        .unwrap_or_else(|| "Empty DCC port range".to_owned()))
+}
+
+/// `path` with a number added to make it unique, e.g. `file.1.txt`.
+fn unique_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let mut i = 1;
+    loop {
+        let path = path.with_file_name(format!("{}.{}{}", stem, i, ext));
+        if !path.exists() {
+            return path;
+        }
+        i += 1;
+    }
+}
+
+enum Connect {
+    /// Connect to the other side
+    To(SocketAddr),
+    /// Wait for the other side to connect
+    Accept(TcpListener),
+}
+
+async fn connect(connect: Connect) -> std::io::Result<TcpStream> {
+    let timed_out = |_| std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out");
+    match connect {
+        Connect::To(addr) => timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(timed_out)?,
+        Connect::Accept(listener) => timeout(ACCEPT_TIMEOUT, listener.accept())
+            .await
+            .map_err(timed_out)?
+            .map(|(stream, _)| stream),
+    }
+}
+
+/// Sends progress events, at most one per 10% of the file (or per MiB when the size is not
+/// known).
+struct Progress {
+    transfer: DccTransfer,
+    snd_ev: Sender<Event>,
+    last_step: u64,
+}
+
+impl Progress {
+    fn new(transfer: DccTransfer, snd_ev: Sender<Event>) -> Progress {
+        Progress {
+            transfer,
+            snd_ev,
+            last_step: 0,
+        }
+    }
+
+    async fn update(&mut self, transferred: u64) {
+        let step = match self.transfer.size {
+            Some(size) if size > 0 => transferred * 10 / size,
+            _ => transferred / (1024 * 1024),
+        };
+        if step > self.last_step {
+            self.last_step = step;
+            let _ = self
+                .snd_ev
+                .send(Event::DccProgress {
+                    transfer: self.transfer.clone(),
+                    transferred,
+                })
+                .await;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn receive_task(
+    info: DccTransfer,
+    conn: Connect,
+    path: PathBuf,
+    offset: u64,
+    max_size: Option<u64>,
+    transfers: Transfers,
+    partials: Partials,
+    snd_ev: Sender<Event>,
+) {
+    let mut progress = Progress::new(info.clone(), snd_ev.clone());
+    let result = async {
+        let stream = connect(conn).await?;
+        let _ = snd_ev.send(Event::DccStarted(info.clone())).await;
+        partials
+            .borrow_mut()
+            .insert(path.clone(), info.nick.clone());
+        let file = if offset == 0 {
+            File::create(&path).await?
+        } else {
+            OpenOptions::new().append(true).open(&path).await?
+        };
+        receive(stream, file, offset, info.size, max_size, &mut progress).await
+    }
+    .await;
+    if result.is_ok() {
+        partials.borrow_mut().remove(&path);
+    }
+    finish(info, path, result, transfers, snd_ev).await;
+}
+
+/// Remove a file offer if the user doesn't accept it in `OFFER_TIMEOUT`.
+async fn expire_offer(id: usize, transfers: Transfers, snd_ev: Sender<Event>) {
+    tokio::time::sleep(OFFER_TIMEOUT).await;
+    let transfer = {
+        let mut transfers = transfers.borrow_mut();
+        match transfers.get(&id) {
+            Some(Transfer {
+                state: TransferState::Offered { .. },
+                ..
+            }) => transfers.remove(&id),
+            _ => None,
+        }
+    };
+    if let Some(transfer) = transfer {
+        let _ = snd_ev
+            .send(Event::DccFailed {
+                transfer: transfer.info,
+                msg: "Offer expired".to_owned(),
+            })
+            .await;
+    }
+}
+
+/// Remove a chat offer if the user doesn't accept it in `OFFER_TIMEOUT`.
+async fn expire_chat_offer(id: usize, chats: Chats, snd_ev: Sender<Event>) {
+    tokio::time::sleep(OFFER_TIMEOUT).await;
+    let chat = {
+        let mut chats = chats.borrow_mut();
+        match chats.get(&id) {
+            Some(Chat {
+                state: ChatState::Offered { .. },
+                ..
+            }) => chats.remove(&id),
+            _ => None,
+        }
+    };
+    if let Some(chat) = chat {
+        let _ = snd_ev
+            .send(Event::DccChatClosed {
+                chat: chat.info,
+                msg: Some("Offer expired".to_owned()),
+            })
+            .await;
+    }
+}
+
+async fn send_task(
+    info: DccTransfer,
+    conn: Connect,
+    path: PathBuf,
+    offset: Rc<Cell<u64>>,
+    transfers: Transfers,
+    snd_ev: Sender<Event>,
+) {
+    let mut progress = Progress::new(info.clone(), snd_ev.clone());
+    let result = async {
+        let stream = connect(conn).await?;
+        if let Some(transfer) = transfers.borrow_mut().get_mut(&info.id) {
+            if let TransferState::Offering { task, .. } = &mut transfer.state {
+                if let Some(task) = task.take() {
+                    transfer.state = TransferState::Running { task };
+                }
+            }
+        }
+        let _ = snd_ev.send(Event::DccStarted(info.clone())).await;
+        let file = File::open(&path).await?;
+        send(stream, file, offset.get(), &mut progress).await
+    }
+    .await;
+    finish(info, path, result, transfers, snd_ev).await;
+}
+
+async fn chat_task(
+    info: DccChat,
+    conn: Connect,
+    rcv_line: Receiver<String>,
+    chats: Chats,
+    snd_ev: Sender<Event>,
+) {
+    let result = async {
+        let stream = connect(conn).await?;
+        if let Some(chat) = chats.borrow_mut().get_mut(&info.id) {
+            chat.state = ChatState::Connected;
+        }
+        let _ = snd_ev.send(Event::DccChatStarted(info.clone())).await;
+        chat_session(stream, rcv_line, &info, &snd_ev).await
+    }
+    .await;
+    chats.borrow_mut().remove(&info.id);
+    let _ = snd_ev
+        .send(Event::DccChatClosed {
+            chat: info,
+            msg: result.err().map(|err| err.to_string()),
+        })
+        .await;
+}
+
+/// Relay lines between the connection and the user until one of the sides closes the chat.
+async fn chat_session(
+    stream: TcpStream,
+    mut rcv_line: Receiver<String>,
+    info: &DccChat,
+    snd_ev: &Sender<Event>,
+) -> std::io::Result<()> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).split(b'\n');
+    loop {
+        tokio::select! {
+            line = lines.next_segment() => {
+                let line = match line? {
+                    None => return Ok(()),
+                    Some(line) => line,
+                };
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches('\r');
+                let (msg, is_action) = match line
+                    .strip_prefix("\x01ACTION ")
+                    .map(|msg| msg.strip_suffix('\x01').unwrap_or(msg))
+                {
+                    Some(msg) => (msg, true),
+                    None => (line, false),
+                };
+                let _ = snd_ev
+                    .send(Event::DccChatMsg {
+                        chat: info.clone(),
+                        msg: msg.to_owned(),
+                        is_action,
+                    })
+                    .await;
+            }
+            line = rcv_line.recv() => {
+                match line {
+                    None => return Ok(()),
+                    Some(line) => {
+                        write_half.write_all(line.as_bytes()).await?;
+                        write_half.write_all(b"\n").await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn finish(
+    info: DccTransfer,
+    path: PathBuf,
+    result: std::io::Result<u64>,
+    transfers: Transfers,
+    snd_ev: Sender<Event>,
+) {
+    transfers.borrow_mut().remove(&info.id);
+    let ev = match result {
+        Ok(_) => Event::DccDone {
+            transfer: info,
+            path,
+        },
+        Err(err) => Event::DccFailed {
+            transfer: info,
+            msg: err.to_string(),
+        },
+    };
+    let _ = snd_ev.send(ev).await;
+}
+
+/// Receive a file, starting at `offset`. Returns size of the file.
+async fn receive(
+    mut stream: TcpStream,
+    mut file: File,
+    offset: u64,
+    size: Option<u64>,
+    max_size: Option<u64>,
+    progress: &mut Progress,
+) -> std::io::Result<u64> {
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut received = offset;
+    loop {
+        if size == Some(received) {
+            break;
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            if let Some(size) = size {
+                if received < size {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        format!("Connection closed after {} of {} bytes", received, size),
+                    ));
+                }
+            }
+            break;
+        }
+        received += n as u64;
+        if let Some(max_size) = max_size {
+            if received > max_size {
+                return Err(std::io::Error::other(format!(
+                    "File is larger than the limit ({} bytes)",
+                    max_size
+                )));
+            }
+        }
+        file.write_all(&buf[..n]).await?;
+        stream.write_all(&(received as u32).to_be_bytes()).await?;
+        progress.update(received).await;
+    }
+    file.flush().await?;
+    Ok(received)
+}
+
+/// Send a file, starting at `offset`. Returns size of the file.
+async fn send(
+    mut stream: TcpStream,
+    mut file: File,
+    offset: u64,
+    progress: &mut Progress,
+) -> std::io::Result<u64> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut sent = offset;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        stream.write_all(&buf[..n]).await?;
+        sent += n as u64;
+        progress.update(sent).await;
+    }
+    stream.flush().await?;
+
+    // Wait until the receiver acknowledges everything or closes the connection
+    let mut ack = [0u8; 4];
+    let wait_acks = async {
+        loop {
+            match stream.read_exact(&mut ack).await {
+                Ok(_) => {
+                    if u32::from_be_bytes(ack) == sent as u32 {
+                        return Ok(());
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    };
+    if let Ok(result) = timeout(ACK_TIMEOUT, wait_acks).await {
+        result?;
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dcc() {
+        assert_eq!(
+            parse("SEND file.txt 2130706433 5000 1024"),
+            Some(DccMsg::Send {
+                filename: "file.txt".to_owned(),
+                addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                port: 5000,
+                size: Some(1024),
+                token: None,
+            })
+        );
+        assert_eq!(
+            parse("SEND \"my file.txt\" ::1 0 1024 42"),
+            Some(DccMsg::Send {
+                filename: "my file.txt".to_owned(),
+                addr: "::1".parse().unwrap(),
+                port: 0,
+                size: Some(1024),
+                token: Some("42".to_owned()),
+            })
+        );
+        assert_eq!(
+            parse("RESUME file.txt 5000 512"),
+            Some(DccMsg::Resume {
+                port: 5000,
+                position: 512,
+                token: None,
+            })
+        );
+        assert_eq!(
+            parse("ACCEPT \"my file.txt\" 0 512 42"),
+            Some(DccMsg::Accept {
+                port: 0,
+                position: 512,
+                token: Some("42".to_owned()),
+            })
+        );
+        assert_eq!(
+            parse("CHAT chat 2130706433 5000"),
+            Some(DccMsg::Chat {
+                addr: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                port: 5000,
+                token: None,
+            })
+        );
+        assert_eq!(parse("CHAT file 2130706433 5000"), None);
+        assert_eq!(parse("SEND file.txt"), None);
+    }
+
+    #[test]
+    fn format_dcc() {
+        let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        assert_eq!(
+            send_args("my file.txt", ip, 0, Some(10), Some("1")),
+            "SEND \"my file.txt\" 2130706433 0 10 1"
+        );
+        assert_eq!(
+            resume_args("ACCEPT", "file.txt", 5000, 5, None),
+            "ACCEPT file.txt 5000 5"
+        );
+        assert_eq!(chat_args(ip, 0, Some("2")), "CHAT chat 2130706433 0 2");
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_filename("file.txt"), "file.txt");
+        assert_eq!(sanitize_filename("../../.bashrc"), "bashrc");
+        assert_eq!(sanitize_filename("C:\\x\\file.txt"), "file.txt");
+        assert_eq!(sanitize_filename(".."), "file");
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "libtiny_client_dcc_{}_{}",
+            std::process::id(),
+            name
+        ));
+        path
+    }
+
+    /// Transfer `contents` over loopback, with `existing` already received.
+    async fn loopback_transfer(name: &str, contents: &[u8], existing: &[u8]) -> Vec<u8> {
+        let src = temp_path(&format!("{}_src", name));
+        let dst = temp_path(&format!("{}_dst", name));
+        std::fs::write(&src, contents).unwrap();
+        std::fs::write(&dst, existing).unwrap();
+
+        let (snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(100);
+        let info = DccTransfer {
+            id: 0,
+            nick: "nick".to_owned(),
+            filename: "file".to_owned(),
+            size: Some(contents.len() as u64),
+            incoming: true,
+        };
+        let mut send_progress = Progress::new(info.clone(), snd_ev.clone());
+        let mut receive_progress = Progress::new(info, snd_ev);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let offset = existing.len() as u64;
+        let sender = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let file = File::open(&src).await.unwrap();
+            send(stream, file, offset, &mut send_progress).await
+        };
+        let receiver = async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let file = OpenOptions::new().append(true).open(&dst).await.unwrap();
+            receive(
+                stream,
+                file,
+                offset,
+                Some(contents.len() as u64),
+                None,
+                &mut receive_progress,
+            )
+            .await
+        };
+        let (sent, received) = tokio::join!(sender, receiver);
+        assert_eq!(sent.unwrap(), contents.len() as u64);
+        assert_eq!(received.unwrap(), contents.len() as u64);
+        rcv_ev.close();
+
+        let result = std::fs::read(&dst).unwrap();
+        let _ = std::fs::remove_file(&src);
+        let _ = std::fs::remove_file(&dst);
+        result
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        assert_eq!(loopback_transfer("full", &contents, &[]).await, contents);
+        assert_eq!(
+            loopback_transfer("resume", &contents, &contents[..12345]).await,
+            contents
+        );
+    }
+
+    #[tokio::test]
+    async fn resume_only_partials() {
+        let download_dir = temp_path("resume_only_partials");
+        std::fs::create_dir_all(&download_dir).unwrap();
+        std::fs::write(download_dir.join("file"), b"0123456789").unwrap();
+        let config = DccConfig {
+            download_dir: download_dir.clone(),
+            max_size: None,
+            ip: None,
+            ports: None,
+            passive: false,
+        };
+        let (snd_ev, _rcv_ev) = tokio::sync::mpsc::channel(100);
+        let mut dcc = Dcc::new(Some(config), snd_ev);
+        let casemapping = CaseMapping::default();
+
+        let offer = |dcc: &mut Dcc, nick: &str| {
+            assert_eq!(
+                dcc.handle_msg(nick, "SEND file 2130706433 1 100", casemapping),
+                None
+            );
+            dcc.transfers
+                .borrow()
+                .values()
+                .find(|transfer| matches!(transfer.state, TransferState::Offered { .. }))
+                .map(|transfer| transfer.info.id)
+                .unwrap()
+        };
+
+        tokio::task::LocalSet::new()
+            .run_until(async {
+                // Not received by us, saved to a new file
+                let id = offer(&mut dcc, "nick");
+                assert_eq!(dcc.accept(id, casemapping), Ok(None));
+                dcc.cancel(id);
+
+                // Received from another nick, saved to a new file
+                dcc.partials
+                    .borrow_mut()
+                    .insert(download_dir.join("file"), "other".to_owned());
+                let id = offer(&mut dcc, "nick");
+                assert_eq!(dcc.accept(id, casemapping), Ok(None));
+                dcc.cancel(id);
+
+                // Received from the same nick, resumed
+                dcc.partials
+                    .borrow_mut()
+                    .insert(download_dir.join("file"), "Nick".to_owned());
+                let id = offer(&mut dcc, "nick");
+                assert_eq!(
+                    dcc.accept(id, casemapping),
+                    Ok(Some("RESUME file 1 10".to_owned()))
+                );
+            })
+            .await;
+
+        let _ = std::fs::remove_dir_all(&download_dir);
+    }
+
+    #[tokio::test]
+    async fn chat() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (snd_ev, mut rcv_ev) = tokio::sync::mpsc::channel(100);
+        let (snd_line, rcv_line) = tokio::sync::mpsc::channel(100);
+        let info = DccChat {
+            id: 0,
+            nick: "nick".to_owned(),
+        };
+
+        let session = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            chat_session(stream, rcv_line, &info, &snd_ev).await
+        };
+        let peer = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"hi\r\n\x01ACTION waves\x01\n")
+                .await
+                .unwrap();
+            snd_line.send("hello".to_owned()).await.unwrap();
+            let mut buf = [0u8; 6];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello\n");
+            // Closing the connection ends the session
+        };
+        let (result, ()) = tokio::join!(session, peer);
+        result.unwrap();
+
+        let mut msgs = vec![];
+        rcv_ev.close();
+        while let Some(ev) = rcv_ev.recv().await {
+            if let Event::DccChatMsg { msg, is_action, .. } = ev {
+                msgs.push((msg, is_action));
+            }
+        }
+        assert_eq!(
+            msgs,
+            vec![("hi".to_owned(), false), ("waves".to_owned(), true)]
+        );
+    }
+}
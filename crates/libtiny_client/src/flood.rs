@@ -0,0 +1,149 @@
+//! Token bucket for outgoing message rate limiting. See `FloodControl`.
+
+use crate::FloodControl;
+
+use std::time::{Duration, Instant};
+
+/// Min. time between `Event::SendQueue`s while the queue is being drained.
+pub(crate) const QUEUE_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) struct TokenBucket {
+    burst: u32,
+    interval: Duration,
+    tokens: u32,
+    /// When the last token was added. Only meaningful when `tokens < burst`.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(flood_control: &FloodControl, now: Instant) -> TokenBucket {
+        let burst = flood_control.burst.max(1);
+        TokenBucket {
+            burst,
+            interval: flood_control.interval,
+            tokens: burst,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if self.tokens == self.burst {
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let new_tokens = (elapsed.as_nanos() / self.interval.as_nanos()).min(u128::from(u32::MAX));
+        let new_tokens = new_tokens as u32;
+        if new_tokens != 0 {
+            self.tokens = self.tokens.saturating_add(new_tokens).min(self.burst);
+            self.last_refill += self.interval * new_tokens;
+        }
+    }
+
+    /// Take a token if available. Always succeeds when flood control is disabled.
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        if self.interval.as_nanos() == 0 {
+            return true;
+        }
+        self.refill(now);
+        if self.tokens == 0 {
+            false
+        } else {
+            self.tokens -= 1;
+            true
+        }
+    }
+
+    /// Take a token if available, without failing when there are none. Used for messages that
+    /// bypass the queue, to still count them towards the limit.
+    pub(crate) fn take_any(&mut self, now: Instant) {
+        let _ = self.try_take(now);
+    }
+
+    /// Time until the next token is available.
+    pub(crate) fn next_token_in(&mut self, now: Instant) -> Duration {
+        if self.interval.as_nanos() == 0 {
+            return Duration::from_secs(0);
+        }
+        self.refill(now);
+        if self.tokens != 0 {
+            Duration::from_secs(0)
+        } else {
+            (self.last_refill + self.interval).saturating_duration_since(now)
+        }
+    }
+}
+
+/// Is the message sent without waiting in the queue? `PING`s and `PONG`s are sent immediately to
+/// avoid ping timeouts, `QUIT`s to avoid waiting on exit.
+pub(crate) fn is_priority_msg(msg: &str) -> bool {
+    msg.starts_with("PING ") || msg.starts_with("PONG ") || msg.starts_with("QUIT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let secs = Duration::from_secs;
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &FloodControl {
+                burst: 3,
+                interval: secs(2),
+            },
+            now,
+        );
+
+        // Burst
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(!bucket.try_take(now));
+        assert_eq!(bucket.next_token_in(now), secs(2));
+        assert_eq!(bucket.next_token_in(now + secs(1)), secs(1));
+
+        // One token every 2 seconds
+        assert!(!bucket.try_take(now + secs(1)));
+        assert!(bucket.try_take(now + secs(2)));
+        assert!(!bucket.try_take(now + secs(3)));
+        assert!(bucket.try_take(now + secs(4)));
+
+        // Refills up to the burst size
+        let later = now + secs(100);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+
+        // `take_any` never fails but uses tokens
+        bucket.take_any(later);
+        assert!(!bucket.try_take(later + secs(1)));
+    }
+
+    #[test]
+    fn disabled() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(
+            &FloodControl {
+                burst: 1,
+                interval: Duration::from_secs(0),
+            },
+            now,
+        );
+        for _ in 0..100 {
+            assert!(bucket.try_take(now));
+        }
+        assert_eq!(bucket.next_token_in(now), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn priority_msgs() {
+        assert!(is_priority_msg("PONG :irc.example.org\r\n"));
+        assert!(is_priority_msg("QUIT :bye\r\n"));
+        assert!(is_priority_msg("QUIT\r\n"));
+        assert!(is_priority_msg("PING irc.example.org\r\n"));
+        assert!(!is_priority_msg("PRIVMSG #tiny :PONG\r\n"));
+    }
+}
@@ -1,26 +1,42 @@
 #![allow(clippy::unneeded_field_pattern)]
 #![allow(clippy::cognitive_complexity)]
 
+mod caps;
+mod ctcp;
+mod dcc;
+mod flood;
+mod modes;
 mod pinger;
+mod proxy;
+mod sasl;
 mod state;
 mod stream;
 mod utils;
+mod whois;
 
-use libtiny_common::{ChanName, ChanNameRef};
+use libtiny_common::{ChanListEntry, ChanName, ChanNameRef};
 pub use libtiny_wire as wire;
 
+pub use ctcp::ctcp_ping_rtt;
+use flood::TokenBucket;
+pub use libtiny_common::CaseMapping;
+pub use modes::{ChanInfo, ChanMember, ListEntry};
 use pinger::Pinger;
 use state::State;
+pub use state::{ChanModes, ServerFeatures};
 use stream::{Stream, StreamError};
+pub use whois::WhoisInfo;
 
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::Duration;
+use std::collections::{HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use futures_util::future::FutureExt;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 use tokio::{pin, select};
-use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 use tokio_stream::StreamExt;
 
 #[macro_use]
@@ -30,8 +46,16 @@ extern crate log;
 // Public API
 //
 
-/// `Client` tries to reconnect on error after this many seconds.
-pub const RECONNECT_SECS: u64 = 30;
+/// IRCv3 capabilities requested by default. See `ServerInfo::caps`.
+pub const DEFAULT_CAPS: [&str; 7] = [
+    "server-time",
+    "message-tags",
+    "multi-prefix",
+    "away-notify",
+    "account-notify",
+    "extended-join",
+    "chghost",
+];
 
 #[derive(Debug, Clone)]
 pub struct ServerInfo {
@@ -44,6 +68,14 @@ pub struct ServerInfo {
     /// Use TLS?
     pub tls: bool,
 
+    /// Proxy to connect to the server through. TLS (when enabled) runs over the proxied
+    /// connection.
+    pub proxy: Option<Proxy>,
+
+    /// TLS client certificate to present to the server. Only used when `tls` is set. Needed for
+    /// SASL `EXTERNAL` authentication (CertFP).
+    pub tls_client_cert: Option<TlsClientCert>,
+
     /// Server password.
     pub pass: Option<String>,
 
@@ -52,8 +84,8 @@ pub struct ServerInfo {
     /// Nicks to select when logging in.
     pub nicks: Vec<String>,
 
-    /// Channels to automatically join
-    pub auto_join: Vec<ChanName>,
+    /// Channels to automatically join, with optional channel keys
+    pub auto_join: Vec<(ChanName, Option<String>)>,
 
     /// Nickserv password. Sent to NickServ on connecting to the server and nick change, before
     /// join commands.
@@ -61,32 +93,273 @@ pub struct ServerInfo {
 
     /// SASL authentication credentials,
     pub sasl_auth: Option<SASLAuth>,
+
+    /// When to reconnect, and how to check liveness of the connection.
+    pub reconnect: ReconnectPolicy,
+
+    /// Outgoing message rate limit
+    pub flood_control: FloodControl,
+
+    /// Rejoin channels after being kicked, after the given delay. `None` means don't rejoin.
+    pub rejoin_on_kick: Option<Duration>,
+
+    /// Replies to CTCP requests
+    pub ctcp_replies: CtcpReplies,
+
+    /// DCC file transfer settings. `None` disables DCC: offers are ignored and files can't be
+    /// sent.
+    pub dcc: Option<DccConfig>,
+
+    /// IRCv3 capabilities to request when the server supports them. `sasl` does not need to be
+    /// listed here, it's requested when `sasl_auth` is set. See also `DEFAULT_CAPS`.
+    pub caps: Vec<String>,
+}
+
+/// Reconnect policy of a `Client`. After a connection error the client waits `initial_delay`
+/// before reconnecting, and the delay is doubled after every failed attempt, up to `max_delay`.
+/// The delay is reset when the client registers to the server successfully.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnect attempt
+    pub initial_delay: Duration,
+
+    /// Max. delay between reconnect attempts
+    pub max_delay: Duration,
+
+    /// Delays are randomized by this fraction to avoid many clients reconnecting at the same
+    /// time. E.g. with `0.2` a 30 second delay becomes a random delay between 24 and 36 seconds.
+    pub jitter: f64,
+
+    /// Give up reconnecting after this many failed attempts. `None` means never give up. Use
+    /// `Client::reconnect` to try again after giving up.
+    pub max_attempts: Option<u32>,
+
+    /// Send a `PING` after this long without any messages from the server.
+    pub ping_interval: Duration,
+
+    /// Disconnect (and reconnect) when the server doesn't send anything for this long after a
+    /// `PING`.
+    pub pong_timeout: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(5),
+            max_delay: Duration::from_secs(300),
+            jitter: 0.2,
+            max_attempts: None,
+            ping_interval: Duration::from_secs(60),
+            pong_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Outgoing message rate limit, to avoid getting disconnected for flooding. Messages are sent
+/// immediately until `burst` messages are sent, after that one message is sent every `interval`.
+/// `PING`, `PONG`, and `QUIT` messages are never delayed.
+#[derive(Debug, Clone)]
+pub struct FloodControl {
+    /// Max. number of messages to send at once
+    pub burst: u32,
+
+    /// Interval between messages after a burst. Zero disables flood control.
+    pub interval: Duration,
+}
+
+impl Default for FloodControl {
+    fn default() -> Self {
+        FloodControl {
+            burst: 5,
+            interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Automatic replies to CTCP requests. Replies are sent as NOTICEs to the sender of the request.
+#[derive(Debug, Clone)]
+pub struct CtcpReplies {
+    /// Reply to VERSION requests. `None` disables VERSION replies.
+    pub version: Option<String>,
+
+    /// Reply to SOURCE requests, usually a URL. `None` disables SOURCE replies.
+    pub source: Option<String>,
+
+    /// Reply to USERINFO requests. `None` disables USERINFO replies.
+    pub userinfo: Option<String>,
+
+    /// Echo PING requests back
+    pub ping: bool,
+
+    /// Reply to TIME requests with the local time
+    pub time: bool,
+
+    /// Reply to CLIENTINFO requests with the list of supported requests
+    pub clientinfo: bool,
 }
 
-/// SASL authentication credentials
+impl Default for CtcpReplies {
+    fn default() -> Self {
+        CtcpReplies {
+            version: Some(format!("libtiny_client {}", env!("CARGO_PKG_VERSION"))),
+            source: Some("https://github.com/osa1/tiny".to_owned()),
+            userinfo: None,
+            ping: true,
+            time: true,
+            clientinfo: true,
+        }
+    }
+}
+
+/// DCC file transfer settings
 #[derive(Debug, Clone)]
-pub struct SASLAuth {
+pub struct DccConfig {
+    /// Where to save received files
+    pub download_dir: PathBuf,
+
+    /// Offers of files larger than this are rejected, and transfers are stopped when they exceed
+    /// it. `None` means no limit.
+    pub max_size: Option<u64>,
+
+    /// IP address to send in offers. When not set the local address of the server connection is
+    /// used, which does not work behind NAT.
+    pub ip: Option<IpAddr>,
+
+    /// Ports to listen on for transfers, inclusive. `None` means any port.
+    pub ports: Option<(u16, u16)>,
+
+    /// Send passive offers, where the receiver listens and we connect. Useful when we can't accept
+    /// connections.
+    pub passive: bool,
+}
+
+/// A DCC file transfer or offer
+#[derive(Debug, Clone)]
+pub struct DccTransfer {
+    /// Id of the transfer, unique in the process. Used in `Client::dcc_accept` and
+    /// `Client::dcc_cancel`.
+    pub id: usize,
+
+    /// The other side of the transfer
+    pub nick: String,
+
+    /// Name of the file, as sent in the offer
+    pub filename: String,
+
+    /// Size of the file, if known
+    pub size: Option<u64>,
+
+    /// Are we receiving the file?
+    pub incoming: bool,
+}
+
+/// A DCC chat or chat offer
+#[derive(Debug, Clone)]
+pub struct DccChat {
+    /// Id of the chat, unique in the process. Used in `Client::dcc_accept` and
+    /// `Client::dcc_cancel`.
+    pub id: usize,
+
+    /// The other side of the chat
+    pub nick: String,
+}
+
+/// A proxy server
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+
+    /// Proxy address
+    pub addr: String,
+
+    /// Proxy port
+    pub port: u16,
+
+    /// Credentials, for proxies that require authentication
+    pub auth: Option<ProxyAuth>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// SOCKS5 proxy. When `remote_dns` is set the server address is resolved by the proxy
+    /// (needed e.g. for Tor onion services), otherwise it's resolved locally.
+    Socks5 { remote_dns: bool },
+
+    /// HTTP proxy, using the `CONNECT` method. Server address is resolved by the proxy.
+    Http,
+}
+
+/// Proxy credentials
+#[derive(Debug, Clone)]
+pub struct ProxyAuth {
     pub username: String,
     pub password: String,
 }
 
+/// SASL authentication mechanism and credentials
+#[derive(Debug, Clone)]
+pub enum SASLAuth {
+    /// Authenticate with username and password. The strongest mechanism supported by the server
+    /// is used: `SCRAM-SHA-256`, `SCRAM-SHA-1`, or `PLAIN`.
+    Password { username: String, password: String },
+
+    /// `EXTERNAL` mechanism: authenticate with the TLS client certificate. See
+    /// `ServerInfo::tls_client_cert`.
+    External,
+}
+
+/// Reasons of SASL authentication failures. See `Event::SASLFailed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SASLFailure {
+    /// 902 ERR_NICKLOCKED: the account is locked
+    NickLocked,
+    /// 904 ERR_SASLFAIL: invalid credentials, or the mechanism is not supported by the server
+    Failed,
+    /// 905 ERR_SASLTOOLONG: a payload was too long
+    TooLong,
+    /// 906 ERR_SASLABORTED: authentication was aborted, by us or by the server
+    Aborted,
+    /// 907 ERR_SASLALREADY: already authenticated
+    AlreadyAuthenticated,
+    /// Server sent an invalid or unexpected message, or the server could not be verified (in
+    /// SCRAM). Authentication is aborted.
+    Protocol,
+}
+
+/// A TLS client certificate chain and private key, in PEM format. The two can be in the same PEM
+/// file. With `tls-native` the private key needs to be in PKCS #8 format (`BEGIN PRIVATE KEY`),
+/// with `tls-rustls` PKCS #1 RSA keys (`BEGIN RSA PRIVATE KEY`) are also accepted.
+#[derive(Debug, Clone)]
+pub struct TlsClientCert {
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+}
+
 /// IRC client events. Returned by `Client` to the users via a channel.
 ///
 /// Note that Client only returns when it can't resolve the domain name. In all other cases (no
 /// matter what the error is) it continues, in case of a connection error either by trying another
-/// IP address of the same domain, or by waiting `RECONNECT_SECS` seconds and then trying again.
-/// The latter happens after sending a `Disconnected` event.
+/// IP address of the same domain, or by waiting (according to `ServerInfo::reconnect`) and then
+/// trying again. The latter happens after sending a `Disconnected` event, followed by a
+/// `ReconnectScheduled` event.
 #[derive(Debug)]
 pub enum Event {
     /// Client resolving domain name
     ResolvingHost,
-    /// Domain name resolved, client trying to connect to the given IP address
+    /// Domain name resolved, client trying to connect to the given IP address. When using a proxy
+    /// this is the address of the proxy.
     Connecting(SocketAddr),
     /// TCP connection established *and* the introduction sequence with the IRC server started.
     Connected,
-    /// Disconnected from the server. Usually sent right after an `Event::IoErr`. Client tries to
-    /// reconnect after `RECONNECT_SECS` seconds after sending this event.
+    /// Disconnected from the server. Usually sent right after an `Event::IoErr`. Followed by
+    /// `ReconnectScheduled` or `ReconnectGaveUp`.
     Disconnected,
+    /// Client will try to reconnect after `delay`. `attempt` is the number of the attempt, since
+    /// the last successful registration (starting from 1).
+    ReconnectScheduled { attempt: u32, delay: Duration },
+    /// Client gave up reconnecting after `ReconnectPolicy::max_attempts` failed attempts. Client
+    /// tries again when `Client::reconnect` is called.
+    ReconnectGaveUp { attempts: u32 },
     /// An IO error happened.
     IoErr(std::io::Error),
     /// A TLS error happened
@@ -97,12 +370,68 @@ pub enum Event {
     CantResolveAddr,
     /// Nick changed.
     NickChange { new_nick: String },
-    /// A message from the server
+    /// Number of messages waiting to be sent because of flood control changed. Sent when messages
+    /// start waiting and when all waiting messages are sent (`len` is 0), and at most every 10
+    /// seconds in between. See `ServerInfo::flood_control`.
+    SendQueue { len: usize },
+    /// A message from the server. Replies to WHOIS and WHOWAS queries are reported with `Whois`
+    /// instead. Replies to channel mode queries that are not sent with `Client::mode` are not
+    /// reported.
     Msg(wire::Msg),
+    /// Replies to a WHOIS or WHOWAS query, sent after RPL_ENDOFWHOIS or RPL_ENDOFWHOWAS. WHOWAS
+    /// queries may return multiple entries, each is sent as a separate event.
+    Whois(WhoisInfo),
+    /// Channel list requested with `Client::list`, sent after RPL_LISTEND.
+    ChanList(Vec<ChanListEntry>),
     /// A wire-protocol error
     WireError(String),
     /// Channel join error message
     ChannelJoinError { chan: ChanName, msg: String },
+    /// We're kicked from a channel. The channel state is dropped, unless
+    /// `ServerInfo::rejoin_on_kick` is set, in which case the client tries to join the channel
+    /// again and sends a `ChannelJoinError` event with the delay.
+    Kicked {
+        chan: ChanName,
+        /// Nick (or server name) of the kicker
+        by: String,
+        reason: Option<String>,
+    },
+    /// A DCC file offer. Accept with `Client::dcc_accept`, reject with `Client::dcc_cancel`.
+    DccOffer(DccTransfer),
+    /// A DCC transfer connected and started
+    DccStarted(DccTransfer),
+    /// DCC transfer progress. `transferred` is the number of bytes sent or received, including the
+    /// resumed part.
+    DccProgress {
+        transfer: DccTransfer,
+        transferred: u64,
+    },
+    /// A DCC transfer finished. `path` is the received or sent file.
+    DccDone {
+        transfer: DccTransfer,
+        path: PathBuf,
+    },
+    /// A DCC transfer failed, or an offer was rejected automatically
+    DccFailed { transfer: DccTransfer, msg: String },
+    /// A DCC chat offer. Accept with `Client::dcc_accept`, reject with `Client::dcc_cancel`.
+    DccChatOffer(DccChat),
+    /// A DCC chat connected. Send messages with `Client::dcc_chat_send`.
+    DccChatStarted(DccChat),
+    /// A message in a DCC chat
+    DccChatMsg {
+        chat: DccChat,
+        msg: String,
+        is_action: bool,
+    },
+    /// A DCC chat was closed by the other side, or failed. `msg` is the error message if the chat
+    /// failed. Not sent when the chat is closed with `Client::dcc_chat_close` or
+    /// `Client::dcc_cancel`.
+    DccChatClosed { chat: DccChat, msg: Option<String> },
+    /// SASL authentication is configured but the server does not support it
+    SASLNotSupported,
+    /// SASL authentication failed. `msg` is the message sent by the server, or a description of
+    /// the error for `SASLFailure::Protocol`.
+    SASLFailed { reason: SASLFailure, msg: String },
 }
 
 impl From<StreamError> for Event {
@@ -119,7 +448,7 @@ impl From<StreamError> for Event {
 pub struct Client {
     /// Channel to the send commands to the main loop. Usually just for sending messages to the
     /// server.
-    msg_chan: mpsc::Sender<Cmd>,
+    msg_chan: mpsc::UnboundedSender<Cmd>,
 
     // TODO: This is mostly here to make switching from the old `conn.rs` easier; it may be
     // possible to remove this and maybe have a unique usize in each client as id.
@@ -140,7 +469,7 @@ impl Client {
     /// Reconnect to the server, possibly using a new port.
     pub fn reconnect(&mut self, port: Option<u16>) {
         debug!("reconnect cmd received, port: {:?}", port);
-        self.msg_chan.try_send(Cmd::Reconnect(port)).unwrap()
+        self.msg_chan.send(Cmd::Reconnect(port)).unwrap()
     }
 
     /// Get host name of this connection.
@@ -164,7 +493,7 @@ impl Client {
     /// Send a message directly to the server. "\r\n" suffix is added by this method.
     pub fn raw_msg(&mut self, msg: &str) {
         self.msg_chan
-            .try_send(Cmd::Msg(format!("{}\r\n", msg)))
+            .send(Cmd::Msg(format!("{}\r\n", msg)))
             .unwrap();
     }
 
@@ -179,7 +508,9 @@ impl Client {
     ) -> impl Iterator<Item = &'a str> {
         // Max msg len calculation adapted from hexchat
         // (src/common/outbound.c:split_up_text)
-        let mut max = 512; // RFC 2812
+        // 512 in RFC 2812. Servers can advertise a different limit with LINELEN, but `wire`
+        // functions assume the RFC limit so we only use it when it's smaller.
+        let mut max = self.state.get_linelen().min(512);
         max -= 3; // :, !, @
         max -= 13; // " PRIVMSG ", " ", :, \r, \n
         max -= self.get_nick().len();
@@ -209,17 +540,29 @@ impl Client {
         } else {
             wire::privmsg
         };
-        self.msg_chan
-            .try_send(Cmd::Msg(wire_fn(target, msg)))
-            .unwrap();
+        self.msg_chan.send(Cmd::Msg(wire_fn(target, msg))).unwrap();
     }
 
-    /// Join the given list of channels.
+    /// Join the given list of channels, with optional channel keys. Keys are remembered and used
+    /// when rejoining the channels (e.g. after a reconnect). When a key is not given the last key
+    /// used for the channel is used.
     pub fn join<'a, I>(&mut self, chans: I)
     where
-        I: Iterator<Item = &'a ChanNameRef> + 'a,
+        I: Iterator<Item = (&'a ChanNameRef, Option<&'a str>)> + 'a,
     {
-        self.msg_chan.try_send(Cmd::Msg(wire::join(chans))).unwrap()
+        let chans = chans
+            .map(|(chan, key)| match key {
+                Some(key) => {
+                    self.state.set_chan_key(chan, key);
+                    (chan, Some(key.to_owned()))
+                }
+                None => (chan, self.state.get_chan_key(chan)),
+            })
+            .collect::<Vec<_>>();
+        let chans = chans.iter().map(|(chan, key)| (*chan, key.as_deref()));
+        self.msg_chan
+            .send(Cmd::Msg(wire::join_with_keys(chans)))
+            .unwrap()
     }
 
     /// Leave a channel.
@@ -227,18 +570,153 @@ impl Client {
         self.state.leave_channel(&mut self.msg_chan, chan)
     }
 
+    /// Send a CTCP request. When `args` of a PING request is not given the current time is sent,
+    /// pass the argument of the reply to `ctcp_ping_rtt` to get the round-trip time.
+    pub fn ctcp(&mut self, target: &str, ctcp: &wire::CTCP, args: Option<&str>) {
+        let ping_arg;
+        let args = match (ctcp, args) {
+            (wire::CTCP::Ping, None) => {
+                ping_arg = ctcp::ping_arg();
+                Some(ping_arg.as_str())
+            }
+            _ => args,
+        };
+        self.msg_chan
+            .send(Cmd::Msg(wire::ctcp(target, ctcp, args)))
+            .unwrap()
+    }
+
+    /// Offer a file to `nick` with DCC SEND. Returns id of the transfer. Progress of the transfer
+    /// is reported with `Dcc*` events.
+    pub fn dcc_send(&mut self, nick: &str, path: &Path) -> Result<usize, String> {
+        let (transfer, args) = self.state.dcc_send(nick, path)?;
+        self.ctcp(nick, &wire::CTCP::Dcc, Some(&args));
+        Ok(transfer.id)
+    }
+
+    /// Offer a DCC chat to `nick`. When `nick` already offered a chat, their offer is accepted.
+    /// Returns id of the chat.
+    pub fn dcc_chat(&mut self, nick: &str) -> Result<usize, String> {
+        let (chat, args) = self.state.dcc_chat(nick)?;
+        if let Some(args) = args {
+            self.ctcp(nick, &wire::CTCP::Dcc, Some(&args));
+        }
+        Ok(chat.id)
+    }
+
+    /// Send a message in the DCC chat with `nick`.
+    pub fn dcc_chat_send(&mut self, nick: &str, msg: &str, is_action: bool) -> Result<(), String> {
+        self.state.dcc_chat_send(nick, msg, is_action)
+    }
+
+    /// Close the DCC chat with `nick`, or reject their chat offer. Returns `false` if there's no
+    /// chat with `nick`.
+    pub fn dcc_chat_close(&mut self, nick: &str) -> bool {
+        self.state.dcc_chat_close(nick)
+    }
+
+    /// Accept a DCC file or chat offer. Partially received files in the download directory are
+    /// resumed.
+    pub fn dcc_accept(&mut self, id: usize) -> Result<(), String> {
+        let (nick, reply) = self.state.dcc_accept(id)?;
+        if let Some(args) = reply {
+            self.ctcp(&nick, &wire::CTCP::Dcc, Some(&args));
+        }
+        Ok(())
+    }
+
+    /// Reject a DCC offer, or cancel a transfer or chat. Returns `false` if this client does not
+    /// have the transfer.
+    pub fn dcc_cancel(&mut self, id: usize) -> bool {
+        self.state.dcc_cancel(id)
+    }
+
+    /// Does this client have the DCC transfer, chat, or offer with the given id?
+    pub fn has_dcc_transfer(&self, id: usize) -> bool {
+        self.state.has_dcc_transfer(id)
+    }
+
+    /// Query information about a user. The replies are reported with an `Event::Whois`.
+    pub fn whois(&mut self, nick: &str) {
+        self.state.request_whois(nick);
+        self.msg_chan.send(Cmd::Msg(wire::whois(nick))).unwrap()
+    }
+
+    /// Query information about a user that is no longer online. `count` is the max. number of
+    /// entries to return. The replies are reported with `Event::Whois`s.
+    pub fn whowas(&mut self, nick: &str, count: Option<u32>) {
+        self.state.request_whois(nick);
+        self.msg_chan
+            .send(Cmd::Msg(wire::whowas(nick, count)))
+            .unwrap()
+    }
+
+    /// Send a MODE message. Without `modes` this queries the current modes of the target. Replies
+    /// to channel mode and list queries (e.g. `b` for the ban list) are sent as `Event::Msg`s;
+    /// replies to the queries the client sends on its own only update the channel state.
+    pub fn mode(&mut self, target: &str, modes: &[&str]) {
+        if self.state.is_chan_name(target) {
+            self.state.request_chan_modes(target, modes);
+        }
+        self.msg_chan
+            .send(Cmd::Msg(wire::mode(target, modes)))
+            .unwrap()
+    }
+
+    /// Set (or unset, when `set` is `false`) a channel mode with each of `params`, e.g. `+o` for
+    /// a list of nicks or `+b` for a list of masks. Changes are sent in as few MODE messages as
+    /// the server's MODES limit allows.
+    pub fn chan_mode_changes(
+        &mut self,
+        chan: &ChanNameRef,
+        set: bool,
+        mode: char,
+        params: &[&str],
+    ) {
+        let max_modes = self.state.get_server_features().modes;
+        for msg in wire::mode_batches(chan.display(), set, mode, params, max_modes) {
+            self.msg_chan.send(Cmd::Msg(msg)).unwrap();
+        }
+    }
+
+    /// Kick a user from a channel.
+    pub fn kick(&mut self, chan: &ChanNameRef, nick: &str, reason: Option<&str>) {
+        self.msg_chan
+            .send(Cmd::Msg(wire::kick(chan, nick, reason)))
+            .unwrap()
+    }
+
+    /// Invite a user to a channel.
+    pub fn invite(&mut self, nick: &str, chan: &ChanNameRef) {
+        self.msg_chan
+            .send(Cmd::Msg(wire::invite(nick, chan)))
+            .unwrap()
+    }
+
+    /// Set the topic of a channel, or query it when `topic` is `None`.
+    pub fn topic(&mut self, chan: &ChanNameRef, topic: Option<&str>) {
+        self.msg_chan
+            .send(Cmd::Msg(wire::topic(chan, topic)))
+            .unwrap()
+    }
+
+    /// Request the channel list. The list is reported with an `Event::ChanList` when all
+    /// entries are received.
+    pub fn list(&mut self) {
+        self.state.request_list();
+        self.msg_chan.send(Cmd::Msg(wire::list())).unwrap()
+    }
+
     /// Set away status. `None` means not away.
     pub fn away(&mut self, msg: Option<&str>) {
         self.state.set_away(msg);
-        self.msg_chan.try_send(Cmd::Msg(wire::away(msg))).unwrap()
+        self.msg_chan.send(Cmd::Msg(wire::away(msg))).unwrap()
     }
 
     /// Change nick. This may fail (ERR_NICKNAMEINUSE) so wait for confirmation (a NICK message
     /// back from the server, with the old nick as prefix).
     pub fn nick(&mut self, new_nick: &str) {
-        self.msg_chan
-            .try_send(Cmd::Msg(wire::nick(new_nick)))
-            .unwrap()
+        self.msg_chan.send(Cmd::Msg(wire::nick(new_nick))).unwrap()
     }
 
     /// Send a QUIT message to the server, with optional "reason". This stops the client; so the
@@ -248,13 +726,59 @@ impl Client {
         debug!("quit cmd received");
         // Kill any retry join tasks to prevent quit delay
         self.state.kill_join_tasks();
-        self.msg_chan.try_send(Cmd::Quit(reason)).unwrap();
+        self.msg_chan.send(Cmd::Quit(reason)).unwrap();
+    }
+
+    /// Get `user@host` part of a user's usermask, if known. Hosts are learned from messages of
+    /// the user and WHOIS replies.
+    pub fn get_user_host(&self, nick: &str) -> Option<String> {
+        self.state.get_user_host(nick)
     }
 
     /// Get all nicks in a channel.
     pub fn get_chan_nicks(&self, chan: &ChanNameRef) -> Vec<String> {
         self.state.get_chan_nicks(chan)
     }
+
+    /// Get all users in a channel with their membership prefixes. Sorted by rank, then by nick.
+    pub fn get_chan_members(&self, chan: &ChanNameRef) -> Vec<ChanMember> {
+        self.state.get_chan_members(chan)
+    }
+
+    /// Get a user in a channel with their membership prefixes. Use with `get_nick` to check our
+    /// privileges in a channel.
+    pub fn get_chan_member(&self, chan: &ChanNameRef, nick: &str) -> Option<ChanMember> {
+        self.state.get_chan_member(chan, nick)
+    }
+
+    /// Get modes and mode lists (bans etc.) of a channel. Lists are only available after they're
+    /// requested, e.g. with `MODE <chan> +b`. `None` if we're not in the channel.
+    pub fn get_chan_info(&self, chan: &ChanNameRef) -> Option<ChanInfo> {
+        self.state.get_chan_info(chan)
+    }
+
+    /// Get IRCv3 capabilities enabled in the current connection.
+    // FIXME: This allocates a HashSet
+    pub fn enabled_caps(&self) -> HashSet<String> {
+        self.state.get_enabled_caps()
+    }
+
+    /// Get server features advertised with RPL_ISUPPORT (005) in the current connection.
+    // FIXME: This allocates
+    pub fn server_features(&self) -> ServerFeatures {
+        self.state.get_server_features()
+    }
+
+    /// Is the name a channel name, according to the channel prefixes advertised by the server?
+    pub fn is_chan_name(&self, name: &str) -> bool {
+        self.state.is_chan_name(name)
+    }
+
+    /// Drop membership prefixes (e.g. `@`, `+`) of a nick, according to the prefixes advertised
+    /// by the server.
+    pub fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        self.state.drop_nick_prefix(nick)
+    }
 }
 
 //
@@ -284,13 +808,13 @@ fn connect(server_info: ServerInfo) -> (Client, mpsc::Receiver<Event>) {
     let (snd_ev, rcv_ev) = mpsc::channel::<Event>(100);
 
     // Channel for commands from user.
-    let (snd_cmd, rcv_cmd) = mpsc::channel::<Cmd>(100);
+    let (snd_cmd, rcv_cmd) = mpsc::unbounded_channel::<Cmd>();
 
     //
     // Create the main loop task
     //
 
-    let irc_state = State::new(server_info.clone());
+    let irc_state = State::new(server_info.clone(), snd_ev.clone());
     let irc_state_clone = irc_state.clone();
 
     let task = main_loop(server_info, irc_state_clone, snd_ev, rcv_cmd);
@@ -310,9 +834,9 @@ async fn main_loop(
     server_info: ServerInfo,
     irc_state: State,
     mut snd_ev: mpsc::Sender<Event>,
-    rcv_cmd: mpsc::Receiver<Cmd>,
+    rcv_cmd: mpsc::UnboundedReceiver<Cmd>,
 ) {
-    let mut rcv_cmd = ReceiverStream::new(rcv_cmd).fuse();
+    let mut rcv_cmd = UnboundedReceiverStream::new(rcv_cmd).fuse();
 
     // We allow changing ports when reconnecting, so `mut`
     let mut port = server_info.port;
@@ -320,13 +844,48 @@ async fn main_loop(
     // Whether to wait before trying to (re)connect
     let mut wait = false;
 
+    // Number of failed connection attempts since the last successful registration (or the last
+    // reconnect command)
+    let mut attempts: u32 = 0;
+
     // Main loop just tries to (re)connect
     'connect: loop {
         if wait {
-            match wait_(&mut rcv_cmd).await {
+            attempts += 1;
+            let reconnect = &server_info.reconnect;
+            let delay = match reconnect.max_attempts {
+                Some(max_attempts) if attempts > max_attempts => {
+                    snd_ev
+                        .send(Event::ReconnectGaveUp {
+                            attempts: attempts - 1,
+                        })
+                        .await
+                        .unwrap();
+                    None
+                }
+                _ => {
+                    let delay = utils::reconnect_delay(
+                        reconnect.initial_delay,
+                        reconnect.max_delay,
+                        reconnect.jitter,
+                        attempts,
+                        utils::random_unit(),
+                    );
+                    snd_ev
+                        .send(Event::ReconnectScheduled {
+                            attempt: attempts,
+                            delay,
+                        })
+                        .await
+                        .unwrap();
+                    Some(delay)
+                }
+            };
+            match wait_(&mut rcv_cmd, delay).await {
                 TaskResult::Done(()) => {}
                 TaskResult::Reconnect(mb_port) => {
                     port = mb_port.unwrap_or(port);
+                    attempts = 0;
                     wait = false;
                     continue;
                 }
@@ -338,7 +897,7 @@ async fn main_loop(
 
         // Channel for the sender task. Messages are complete IRC messages (including the
         // trailing "\r\n") and the task directly sends them to the server.
-        let (mut snd_msg, rcv_msg) = mpsc::channel::<String>(100);
+        let (mut snd_msg, rcv_msg) = mpsc::unbounded_channel::<String>();
 
         //
         // Resolve IP address
@@ -346,13 +905,15 @@ async fn main_loop(
 
         snd_ev.send(Event::ResolvingHost).await.unwrap();
 
-        let serv_name = server_info.addr.clone();
+        // When using a proxy we connect to the proxy, which then connects to the server
+        let (dial_name, dial_port) = match server_info.proxy {
+            Some(ref proxy) => (proxy.addr.clone(), proxy.port),
+            None => (server_info.addr.clone(), port),
+        };
 
         debug!("Resolving address");
 
-        let serv_name_clone = serv_name.clone();
-
-        let addr_iter = match resolve_addr(serv_name_clone, port, &mut rcv_cmd).await {
+        let addr_iter = match resolve_addr(dial_name, dial_port, &mut rcv_cmd).await {
             TaskResult::Done(Ok(addr_iter)) => {
                 debug!("resolve_addr: done");
                 addr_iter
@@ -366,6 +927,7 @@ async fn main_loop(
             TaskResult::Reconnect(mb_port) => {
                 debug!("resolve_addr: try again");
                 port = mb_port.unwrap_or(port);
+                attempts = 0;
                 wait = false;
                 continue;
             }
@@ -388,15 +950,7 @@ async fn main_loop(
         // Establish TCP connection to the server
         //
 
-        let stream = match try_connect(
-            addrs,
-            &serv_name,
-            server_info.tls,
-            &mut rcv_cmd,
-            &mut snd_ev,
-        )
-        .await
-        {
+        let stream = match try_connect(addrs, &server_info, port, &mut rcv_cmd, &mut snd_ev).await {
             TaskResult::Done(Some(stream)) => stream,
             TaskResult::Done(None) => {
                 snd_ev.send(Event::Disconnected).await.unwrap();
@@ -408,11 +962,13 @@ async fn main_loop(
             }
             TaskResult::Reconnect(mb_port) => {
                 port = mb_port.unwrap_or(port);
+                attempts = 0;
                 wait = false;
                 continue;
             }
         };
 
+        irc_state.set_local_ip(stream.local_addr().ok().map(|addr| addr.ip()));
         let (mut read_half, mut write_half) = tokio::io::split(stream);
 
         debug!("Done");
@@ -423,31 +979,100 @@ async fn main_loop(
 
         // Reset the connection state
         irc_state.reset();
-        // Introduce self
-        if server_info.sasl_auth.is_some() {
-            // Will introduce self after getting a response to this LS command.
-            // This is to avoid getting stuck during nick registration. See the
-            // discussion in #91.
-            snd_msg.try_send(wire::cap_ls()).unwrap();
-        } else {
+        // Start capability negotiation and introduce self. Servers that don't support
+        // capability negotiation ignore the LS command.
+        snd_msg.send(wire::cap_ls()).unwrap();
+        if server_info.sasl_auth.is_none() {
             irc_state.introduce(&mut snd_msg);
         }
+        // Otherwise will introduce self after getting a response to the LS command. This is to
+        // avoid getting stuck during nick registration. See the discussion in #91.
 
         // Spawn a task for outgoing messages.
         let snd_ev_clone = snd_ev.clone();
+        let flood_control = server_info.flood_control.clone();
         tokio::task::spawn_local(async move {
-            let mut rcv_msg = ReceiverStream::new(rcv_msg);
-            while let Some(msg) = rcv_msg.next().await {
-                if let Err(io_err) = write_half.write_all(msg.as_str().as_bytes()).await {
-                    debug!("IO error when writing: {:?}", io_err);
-                    snd_ev_clone.send(Event::IoErr(io_err)).await.unwrap();
-                    return;
+            let mut rcv_msg = UnboundedReceiverStream::new(rcv_msg);
+            // Messages waiting for flood control
+            let mut queue: VecDeque<String> = VecDeque::new();
+            // Queue length in the last `Event::SendQueue`, and when it was sent
+            let mut reported_len = 0;
+            let mut reported_at = Instant::now();
+            let mut bucket = TokenBucket::new(&flood_control, Instant::now());
+            loop {
+                while !queue.is_empty() && bucket.try_take(Instant::now()) {
+                    let msg = queue.pop_front().unwrap();
+                    if let Err(io_err) = write_half.write_all(msg.as_bytes()).await {
+                        debug!("IO error when writing: {:?}", io_err);
+                        snd_ev_clone.send(Event::IoErr(io_err)).await.unwrap();
+                        return;
+                    }
+                }
+                let len = queue.len();
+                if len != reported_len
+                    && (len == 0
+                        || reported_len == 0
+                        || reported_at.elapsed() >= flood::QUEUE_REPORT_INTERVAL)
+                {
+                    reported_len = len;
+                    reported_at = Instant::now();
+                    snd_ev_clone
+                        .send(Event::SendQueue { len: reported_len })
+                        .await
+                        .unwrap();
+                }
+
+                let next_token_in = if queue.is_empty() {
+                    None
+                } else {
+                    Some(bucket.next_token_in(Instant::now()))
+                };
+                let next_token = async move {
+                    match next_token_in {
+                        Some(delay) => tokio::time::sleep(delay).await,
+                        None => futures_util::future::pending().await,
+                    }
+                };
+
+                select! {
+                    msg = rcv_msg.next() => {
+                        match msg {
+                            None => {
+                                // Main loop dropped the sender: we're quitting or reconnecting
+                                return;
+                            }
+                            Some(mut msg) => loop {
+                                if flood::is_priority_msg(&msg) {
+                                    bucket.take_any(Instant::now());
+                                    if let Err(io_err) = write_half.write_all(msg.as_bytes()).await {
+                                        debug!("IO error when writing: {:?}", io_err);
+                                        snd_ev_clone.send(Event::IoErr(io_err)).await.unwrap();
+                                        return;
+                                    }
+                                } else {
+                                    queue.push_back(msg);
+                                }
+                                // Also handle the messages that are already in the channel, to
+                                // report the number of queued messages of e.g. a pasted block at
+                                // once
+                                match rcv_msg.next().now_or_never() {
+                                    None => break,
+                                    Some(None) => return,
+                                    Some(Some(msg_)) => msg = msg_,
+                                }
+                            },
+                        }
+                    }
+                    () = next_token => {}
                 }
             }
         });
 
         // Spawn pinger task
-        let (mut pinger, rcv_ping_evs) = Pinger::new();
+        let (mut pinger, rcv_ping_evs) = Pinger::new(
+            server_info.reconnect.ping_interval,
+            server_info.reconnect.pong_timeout,
+        );
         let mut rcv_ping_evs = ReceiverStream::new(rcv_ping_evs).fuse();
 
         let mut parse_buf: Vec<u8> = Vec::with_capacity(1024);
@@ -463,17 +1088,18 @@ async fn main_loop(
                             // That's OK, rcv_cmd will never be ready again
                         }
                         Some(Cmd::Msg(irc_msg)) => {
-                            snd_msg.try_send(irc_msg).unwrap();
+                            snd_msg.send(irc_msg).unwrap();
                         }
                         Some(Cmd::Reconnect(mb_port)) => {
                             if let Some(new_port) = mb_port {
                                 port = new_port;
                             }
+                            attempts = 0;
                             wait = false;
                             continue 'connect;
                         }
                         Some(Cmd::Quit(reason)) => {
-                            snd_msg.try_send(wire::quit(reason)).unwrap();
+                            snd_msg.send(wire::quit(reason)).unwrap();
                             // This drops the sender end of the channel that the sender task
                             // uses, which in turn causes the sender task to return. Somewhat
                             // hacky?
@@ -510,8 +1136,14 @@ async fn main_loop(
                                     Ok(mut msg) => {
                                         debug!("parsed msg: {:?}", msg);
                                         pinger.reset();
-                                        irc_state.update(&mut msg, &mut snd_ev, &mut snd_msg);
-                                        snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                        let forward = irc_state.update(&mut msg, &mut snd_ev, &mut snd_msg);
+                                        if irc_state.is_nick_accepted() {
+                                            // Registered, reset reconnect delay
+                                            attempts = 0;
+                                        }
+                                        if forward {
+                                            snd_ev.send(Event::Msg(msg)).await.unwrap();
+                                        }
                                     }
                                 }
                             }
@@ -546,8 +1178,18 @@ enum TaskResult<A> {
     Reconnect(Option<u16>),
 }
 
-async fn wait_<S: StreamExt<Item = Cmd> + Unpin>(rcv_cmd: &mut S) -> TaskResult<()> {
-    let delay = tokio::time::sleep(Duration::from_secs(RECONNECT_SECS)).fuse();
+/// Wait for `delay`, or for a command when `delay` is `None`.
+async fn wait_<S: StreamExt<Item = Cmd> + Unpin>(
+    rcv_cmd: &mut S,
+    delay: Option<Duration>,
+) -> TaskResult<()> {
+    let delay = async move {
+        match delay {
+            Some(delay) => tokio::time::sleep(delay).await,
+            None => futures_util::future::pending().await,
+        }
+    }
+    .fuse();
     pin!(delay);
 
     loop {
@@ -622,18 +1264,27 @@ async fn resolve_addr<S: StreamExt<Item = Cmd> + Unpin>(
 
 async fn try_connect<S: StreamExt<Item = Cmd> + Unpin>(
     addrs: Vec<SocketAddr>,
-    serv_name: &str,
-    use_tls: bool,
+    server_info: &ServerInfo,
+    port: u16,
     rcv_cmd: &mut S,
     snd_ev: &mut mpsc::Sender<Event>,
 ) -> TaskResult<Option<Stream>> {
+    let serv_name = &server_info.addr;
+    let proxy = server_info.proxy.as_ref();
     let connect_task = async move {
         for addr in addrs {
             snd_ev.send(Event::Connecting(addr)).await.unwrap();
-            let mb_stream = if use_tls {
-                Stream::new_tls(addr, &serv_name).await
+            let mb_stream = if server_info.tls {
+                Stream::new_tls(
+                    addr,
+                    serv_name,
+                    port,
+                    proxy,
+                    server_info.tls_client_cert.as_ref(),
+                )
+                .await
             } else {
-                Stream::new_tcp(addr).await
+                Stream::new_tcp(addr, serv_name, port, proxy).await
             };
             match mb_stream {
                 Err(err) => {
@@ -0,0 +1,251 @@
+//! Channel modes and membership prefixes. Mode strings are parsed using the CHANMODES and PREFIX
+//! parameters of RPL_ISUPPORT, see `ServerFeatures`.
+
+use crate::ServerFeatures;
+use libtiny_wire as wire;
+use libtiny_wire::ModeChange;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// A user in a channel, with membership prefixes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChanMember {
+    pub nick: String,
+
+    /// Membership modes of the user, from highest to lowest rank. E.g. `['o', 'v']`.
+    pub modes: Vec<char>,
+
+    /// Prefixes for `modes`, e.g. `['@', '+']`.
+    pub prefixes: Vec<char>,
+
+    /// Does the user have channel operator (`o`) or a higher rank?
+    pub is_op: bool,
+}
+
+impl ChanMember {
+    /// Highest ranked prefix of the user, e.g. `@` for an operator with voice.
+    pub fn highest_prefix(&self) -> Option<char> {
+        self.prefixes.first().copied()
+    }
+}
+
+/// Modes and mode lists of a channel. Updated with MODE messages and replies to `MODE <chan>`
+/// (RPL_CHANNELMODEIS, RPL_CREATIONTIME) and to list queries like `MODE <chan> +b`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChanInfo {
+    /// Channel modes that are set, with parameters. E.g. `n -> None`, `l -> Some("10")`.
+    /// Parameters of modes hidden by the server (e.g. channel keys) may be `*`.
+    pub modes: BTreeMap<char, Option<String>>,
+
+    /// Lists of list modes (`b`, `e`, `I` etc.).
+    pub lists: HashMap<char, Vec<ListEntry>>,
+
+    /// Creation time of the channel as a Unix timestamp, from RPL_CREATIONTIME.
+    pub created: Option<u64>,
+}
+
+impl ChanInfo {
+    pub fn bans(&self) -> &[ListEntry] {
+        self.list('b')
+    }
+
+    pub fn excepts(&self) -> &[ListEntry] {
+        self.list('e')
+    }
+
+    pub fn invites(&self) -> &[ListEntry] {
+        self.list('I')
+    }
+
+    pub fn list(&self, mode: char) -> &[ListEntry] {
+        self.lists.get(&mode).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Mode string like `+ntl 10`.
+    pub fn mode_string(&self) -> String {
+        let mut modes = String::from("+");
+        let mut params = vec![];
+        for (mode, param) in &self.modes {
+            modes.push(*mode);
+            if let Some(param) = param {
+                params.push(param.as_str());
+            }
+        }
+        if !params.is_empty() {
+            modes.push(' ');
+            modes.push_str(&params.join(" "));
+        }
+        modes
+    }
+}
+
+/// An entry in a list mode like bans.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListEntry {
+    pub mask: String,
+    pub set_by: Option<String>,
+    /// Unix timestamp
+    pub set_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModeKind {
+    /// Membership mode, parameter is a nick
+    Membership,
+    /// List mode, parameter is a mask
+    List,
+    /// A setting, with or without a parameter
+    Setting,
+}
+
+pub(crate) fn mode_kind(mode: char, features: &ServerFeatures) -> ModeKind {
+    if features.prefix.iter().any(|(m, _)| *m == mode) {
+        ModeKind::Membership
+    } else if features.chanmodes.a.contains(&mode) {
+        ModeKind::List
+    } else {
+        ModeKind::Setting
+    }
+}
+
+/// Split a channel mode string and its parameters, e.g. `+ov-b nick1 nick2 *!*@host`, using
+/// CHANMODES and PREFIX. Modes not in CHANMODES or PREFIX are assumed to not take parameters.
+pub(crate) fn parse_mode_changes(
+    modes: &str,
+    params: &[String],
+    features: &ServerFeatures,
+) -> Vec<ModeChange> {
+    let chanmodes = &features.chanmodes;
+    wire::split_mode_changes(modes, params, |set, mode| {
+        features.prefix.iter().any(|(m, _)| *m == mode)
+            || chanmodes.a.contains(&mode)
+            || chanmodes.b.contains(&mode)
+            || (set && chanmodes.c.contains(&mode))
+    })
+}
+
+/// Split membership prefixes of a nick in RPL_NAMREPLY. Returns membership modes, sorted by rank,
+/// and the nick.
+pub(crate) fn parse_names_nick<'a>(
+    nick: &'a str,
+    features: &ServerFeatures,
+) -> (Vec<char>, &'a str) {
+    let mut modes = vec![];
+    let mut rest = nick;
+    while let Some(c) = rest.chars().next() {
+        match features.prefix.iter().find(|(_, prefix)| *prefix == c) {
+            None => break,
+            Some((mode, _)) => {
+                modes.push(*mode);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    sort_membership_modes(&mut modes, features);
+    (modes, rest)
+}
+
+/// Sort membership modes from highest to lowest rank.
+pub(crate) fn sort_membership_modes(modes: &mut [char], features: &ServerFeatures) {
+    modes.sort_by_key(|mode| mode_rank(*mode, features));
+}
+
+/// Rank of a membership mode, 0 being the highest.
+fn mode_rank(mode: char, features: &ServerFeatures) -> usize {
+    features
+        .prefix
+        .iter()
+        .position(|(m, _)| *m == mode)
+        .unwrap_or(usize::MAX)
+}
+
+pub(crate) fn make_member(nick: &str, modes: &[char], features: &ServerFeatures) -> ChanMember {
+    let prefixes = modes
+        .iter()
+        .filter_map(|mode| {
+            features
+                .prefix
+                .iter()
+                .find(|(m, _)| m == mode)
+                .map(|(_, prefix)| *prefix)
+        })
+        .collect();
+    let op_rank = mode_rank('o', features);
+    let is_op = op_rank != usize::MAX
+        && modes
+            .iter()
+            .any(|mode| mode_rank(*mode, features) <= op_rank);
+    ChanMember {
+        nick: nick.to_owned(),
+        modes: modes.to_vec(),
+        prefixes,
+        is_op,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(strs: &[&str]) -> Vec<String> {
+        strs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn mode_changes() {
+        let mut features = ServerFeatures::default();
+        features.update(&strs(&["PREFIX=(qov)~@+", "CHANMODES=beI,k,l,imnpst"]));
+
+        let changes = parse_mode_changes(
+            "+ov-b+lk-l+n",
+            &strs(&["nick1", "nick2", "*!*@host", "10", "key"]),
+            &features,
+        );
+        let summary: Vec<(bool, char, Option<&str>, ModeKind)> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.set,
+                    c.mode,
+                    c.param.as_deref(),
+                    mode_kind(c.mode, &features),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (true, 'o', Some("nick1"), ModeKind::Membership),
+                (true, 'v', Some("nick2"), ModeKind::Membership),
+                (false, 'b', Some("*!*@host"), ModeKind::List),
+                (true, 'l', Some("10"), ModeKind::Setting),
+                (true, 'k', Some("key"), ModeKind::Setting),
+                (false, 'l', None, ModeKind::Setting),
+                (true, 'n', None, ModeKind::Setting),
+            ]
+        );
+
+        // Missing parameters
+        assert!(parse_mode_changes("+o", &[], &features).is_empty());
+    }
+
+    #[test]
+    fn names_prefixes() {
+        let mut features = ServerFeatures::default();
+        features.update(&strs(&["PREFIX=(qaohv)~&@%+"]));
+
+        let (modes, nick) = parse_names_nick("+@nick", &features);
+        assert_eq!(nick, "nick");
+        assert_eq!(modes, vec!['o', 'v']);
+
+        let member = make_member(nick, &modes, &features);
+        assert_eq!(member.prefixes, vec!['@', '+']);
+        assert_eq!(member.highest_prefix(), Some('@'));
+        assert!(member.is_op);
+
+        let member = make_member("nick", &['h'], &features);
+        assert!(!member.is_op);
+        let member = make_member("nick", &['q'], &features);
+        assert!(member.is_op);
+    }
+}
@@ -24,11 +24,20 @@ enum PingerState {
     ExpectPong,
 }
 
-async fn pinger_task(rcv_rst: mpsc::Receiver<()>, snd_ev: mpsc::Sender<Event>) {
+async fn pinger_task(
+    rcv_rst: mpsc::Receiver<()>,
+    snd_ev: mpsc::Sender<Event>,
+    ping_interval: Duration,
+    pong_timeout: Duration,
+) {
     let mut rcv_rst_fused = ReceiverStream::new(rcv_rst).fuse();
     let mut state = PingerState::SendPing;
     loop {
-        match timeout(Duration::from_secs(60), rcv_rst_fused.next()).await {
+        let timeout_duration = match state {
+            PingerState::SendPing => ping_interval,
+            PingerState::ExpectPong => pong_timeout,
+        };
+        match timeout(timeout_duration, rcv_rst_fused.next()).await {
             Err(_) => match state {
                 PingerState::SendPing => {
                     state = PingerState::ExpectPong;
@@ -52,11 +61,16 @@ async fn pinger_task(rcv_rst: mpsc::Receiver<()>, snd_ev: mpsc::Sender<Event>) {
 }
 
 impl Pinger {
-    pub(crate) fn new() -> (Pinger, mpsc::Receiver<Event>) {
+    /// `ping_interval`: signal a "ping" after this long without a reset. `pong_timeout`: signal a
+    /// "disconnect" after this long without a reset after a "ping".
+    pub(crate) fn new(
+        ping_interval: Duration,
+        pong_timeout: Duration,
+    ) -> (Pinger, mpsc::Receiver<Event>) {
         let (snd_ev, rcv_ev) = mpsc::channel(1);
         // No need for sending another "reset" when there's already one waiting to be processed
         let (snd_rst, rcv_rst) = mpsc::channel(1);
-        tokio::task::spawn_local(pinger_task(rcv_rst, snd_ev));
+        tokio::task::spawn_local(pinger_task(rcv_rst, snd_ev, ping_interval, pong_timeout));
         (Pinger { snd_rst }, rcv_ev)
     }
 
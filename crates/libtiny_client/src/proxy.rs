@@ -0,0 +1,407 @@
+//! Proxy handshakes: SOCKS5 (RFC 1928, RFC 1929 for authentication) and HTTP `CONNECT`.
+
+use crate::{Proxy, ProxyAuth, ProxyKind};
+
+use std::io::Error;
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Max. size of HTTP response headers we accept from a proxy.
+const MAX_HTTP_RESPONSE_LEN: usize = 8 * 1024;
+
+/// Ask the proxy at the other end of `stream` to connect to `host_name:port`. When this returns
+/// successfully `stream` is connected to the server.
+pub(crate) async fn connect<S>(
+    stream: &mut S,
+    proxy: &Proxy,
+    host_name: &str,
+    port: u16,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match proxy.kind {
+        ProxyKind::Socks5 { remote_dns } => {
+            let target = if remote_dns {
+                Target::Domain(host_name)
+            } else {
+                Target::Addr(resolve(host_name, port).await?)
+            };
+            socks5_connect(stream, proxy.auth.as_ref(), target, port).await
+        }
+        ProxyKind::Http => http_connect(stream, proxy.auth.as_ref(), host_name, port).await,
+    }
+}
+
+fn proxy_error(msg: String) -> Error {
+    Error::other(msg)
+}
+
+async fn resolve(host_name: &str, port: u16) -> Result<IpAddr, Error> {
+    tokio::net::lookup_host((host_name, port))
+        .await?
+        .next()
+        .map(|addr: SocketAddr| addr.ip())
+        .ok_or_else(|| proxy_error(format!("Can't resolve {}", host_name)))
+}
+
+enum Target<'a> {
+    Domain(&'a str),
+    Addr(IpAddr),
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// SOCKS5
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_METHOD_NO_AUTH: u8 = 0;
+const SOCKS_METHOD_USER_PASS: u8 = 2;
+const SOCKS_METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+
+async fn socks5_connect<S>(
+    stream: &mut S,
+    auth: Option<&ProxyAuth>,
+    target: Target<'_>,
+    port: u16,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // Method selection
+    let greeting: &[u8] = if auth.is_some() {
+        &[
+            SOCKS_VERSION,
+            2,
+            SOCKS_METHOD_NO_AUTH,
+            SOCKS_METHOD_USER_PASS,
+        ]
+    } else {
+        &[SOCKS_VERSION, 1, SOCKS_METHOD_NO_AUTH]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("SOCKS5 proxy sent invalid reply".to_owned()));
+    }
+
+    match (reply[1], auth) {
+        (SOCKS_METHOD_NO_AUTH, _) => {}
+        (SOCKS_METHOD_USER_PASS, Some(auth)) => socks5_auth(stream, auth).await?,
+        (SOCKS_METHOD_NONE_ACCEPTABLE, None) => {
+            return Err(proxy_error(
+                "SOCKS5 proxy requires authentication".to_owned(),
+            ));
+        }
+        _ => {
+            return Err(proxy_error(
+                "SOCKS5 proxy does not support any of our authentication methods".to_owned(),
+            ));
+        }
+    }
+
+    // Connect request
+    let mut req = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+    match target {
+        Target::Domain(domain) => {
+            if domain.len() > 255 {
+                return Err(proxy_error(format!("Host name too long: {}", domain)));
+            }
+            req.push(SOCKS_ATYP_DOMAIN);
+            req.push(domain.len() as u8);
+            req.extend_from_slice(domain.as_bytes());
+        }
+        Target::Addr(IpAddr::V4(addr)) => {
+            req.push(SOCKS_ATYP_IPV4);
+            req.extend_from_slice(&addr.octets());
+        }
+        Target::Addr(IpAddr::V6(addr)) => {
+            req.push(SOCKS_ATYP_IPV6);
+            req.extend_from_slice(&addr.octets());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).await?;
+
+    // Reply: version, status, reserved, bound address type, bound address, bound port
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("SOCKS5 proxy sent invalid reply".to_owned()));
+    }
+    if reply[1] != 0 {
+        return Err(proxy_error(format!(
+            "SOCKS5 proxy can't connect: {}",
+            socks5_status_str(reply[1])
+        )));
+    }
+    let addr_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            usize::from(len[0])
+        }
+        _ => {
+            return Err(proxy_error("SOCKS5 proxy sent invalid reply".to_owned()));
+        }
+    };
+    // We don't need the bound address and port
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+async fn socks5_auth<S>(stream: &mut S, auth: &ProxyAuth) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if auth.username.len() > 255 || auth.password.len() > 255 {
+        return Err(proxy_error(
+            "SOCKS5 username and password can't be longer than 255 bytes".to_owned(),
+        ));
+    }
+
+    let mut req = vec![1, auth.username.len() as u8];
+    req.extend_from_slice(auth.username.as_bytes());
+    req.push(auth.password.len() as u8);
+    req.extend_from_slice(auth.password.as_bytes());
+    stream.write_all(&req).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(proxy_error("SOCKS5 proxy authentication failed".to_owned()));
+    }
+    Ok(())
+}
+
+fn socks5_status_str(status: u8) -> &'static str {
+    match status {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// HTTP CONNECT
+
+async fn http_connect<S>(
+    stream: &mut S,
+    auth: Option<&ProxyAuth>,
+    host_name: &str,
+    port: u16,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let authority = if host_name.contains(':') {
+        // IPv6 address
+        format!("[{}]:{}", host_name, port)
+    } else {
+        format!("{}:{}", host_name, port)
+    };
+
+    let mut req = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", authority, authority);
+    if let Some(auth) = auth {
+        let credentials = base64::encode(format!("{}:{}", auth.username, auth.password));
+        req.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    req.push_str("\r\n");
+    stream.write_all(req.as_bytes()).await?;
+
+    // Read the response headers one byte at a time to avoid consuming data sent by the server
+    // after the headers
+    let mut response: Vec<u8> = Vec::with_capacity(256);
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() == MAX_HTTP_RESPONSE_LEN {
+            return Err(proxy_error("HTTP proxy response too long".to_owned()));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    // "HTTP/1.1 200 Connection established"
+    let mut words = status_line.split_whitespace();
+    let version = words.next().unwrap_or("");
+    let status = words.next().unwrap_or("");
+    if !version.starts_with("HTTP/") || !status.starts_with('2') {
+        return Err(proxy_error(format!(
+            "HTTP proxy can't connect: {}",
+            status_line
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(kind: ProxyKind, auth: bool) -> Proxy {
+        Proxy {
+            kind,
+            addr: "proxy".to_owned(),
+            port: 1080,
+            auth: if auth {
+                Some(ProxyAuth {
+                    username: "user".to_owned(),
+                    password: "pass".to_owned(),
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_remote_dns() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = proxy(ProxyKind::Socks5 { remote_dns: true }, true);
+
+        let server_task = async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            server.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0u8; 11];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut req = [0u8; 5 + 15 + 2];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req[..5], &[5, 1, 0, 3, 15]);
+            assert_eq!(&req[5..20], b"irc.example.org");
+            assert_eq!(&req[20..], &6697u16.to_be_bytes());
+            server
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+            server.write_all(b"hello").await.unwrap();
+        };
+
+        let client_task = async {
+            connect(&mut client, &proxy, "irc.example.org", 6697)
+                .await
+                .unwrap();
+            let mut hello = [0u8; 5];
+            client.read_exact(&mut hello).await.unwrap();
+            assert_eq!(&hello, b"hello");
+        };
+
+        tokio::join!(server_task, client_task);
+    }
+
+    #[tokio::test]
+    async fn socks5_failure() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = proxy(ProxyKind::Socks5 { remote_dns: false }, false);
+
+        let server_task = async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            server.write_all(&[5, 0]).await.unwrap();
+
+            let mut req = [0u8; 4 + 4 + 2];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req[..8], &[5, 1, 0, 1, 127, 0, 0, 1]);
+            server
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        };
+
+        let client_task = async {
+            let err = connect(&mut client, &proxy, "127.0.0.1", 6667)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "SOCKS5 proxy can't connect: connection refused"
+            );
+        };
+
+        tokio::join!(server_task, client_task);
+    }
+
+    #[tokio::test]
+    async fn http() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = proxy(ProxyKind::Http, true);
+
+        let server_task = async move {
+            let expected = "CONNECT irc.example.org:6697 HTTP/1.1\r\n\
+                            Host: irc.example.org:6697\r\n\
+                            Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n";
+            let mut req = vec![0u8; expected.len()];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(String::from_utf8(req).unwrap(), expected);
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+                .await
+                .unwrap();
+        };
+
+        let client_task = async {
+            connect(&mut client, &proxy, "irc.example.org", 6697)
+                .await
+                .unwrap();
+            let mut hello = [0u8; 5];
+            client.read_exact(&mut hello).await.unwrap();
+            assert_eq!(&hello, b"hello");
+        };
+
+        tokio::join!(server_task, client_task);
+    }
+
+    #[tokio::test]
+    async fn http_failure() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = proxy(ProxyKind::Http, false);
+
+        let server_task = async move {
+            let mut buf = [0u8; 1];
+            // Read until the end of the request
+            let mut req = vec![];
+            while !req.ends_with(b"\r\n\r\n") {
+                server.read_exact(&mut buf).await.unwrap();
+                req.push(buf[0]);
+            }
+            server
+                .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        };
+
+        let client_task = async {
+            let err = connect(&mut client, &proxy, "irc.example.org", 6697)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "HTTP proxy can't connect: HTTP/1.1 403 Forbidden"
+            );
+        };
+
+        tokio::join!(server_task, client_task);
+    }
+}
@@ -0,0 +1,523 @@
+//! SASL authentication. See https://ircv3.net/specs/extensions/sasl-3.1 for the IRC side, RFC 5802
+//! and RFC 7677 for SCRAM.
+
+use crate::SASLAuth;
+use libtiny_wire as wire;
+
+use hmac::digest::core_api::BlockSizeUser;
+use hmac::digest::Digest;
+use hmac::{Mac, SimpleHmac};
+
+/// Max. length of a base64 payload in an `AUTHENTICATE` message. Longer payloads are split into
+/// multiple messages.
+const MAX_PAYLOAD_LEN: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mechanism {
+    External,
+    Plain,
+    ScramSha1,
+    ScramSha256,
+}
+
+impl Mechanism {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Mechanism::External => "EXTERNAL",
+            Mechanism::Plain => "PLAIN",
+            Mechanism::ScramSha1 => "SCRAM-SHA-1",
+            Mechanism::ScramSha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    /// Select the mechanism to use. `advertised` is the value of the `sasl` capability: a comma
+    /// separated list of mechanisms supported by the server. With password authentication the
+    /// strongest mechanism supported by the server is selected. When the server doesn't advertise
+    /// mechanisms we use `PLAIN`.
+    pub(crate) fn select(auth: &SASLAuth, advertised: Option<&str>) -> Mechanism {
+        match auth {
+            SASLAuth::External => Mechanism::External,
+            SASLAuth::Password { .. } => {
+                let advertised = advertised.unwrap_or("");
+                let supports = |mechanism: Mechanism| {
+                    advertised
+                        .split(',')
+                        .any(|name| name.eq_ignore_ascii_case(mechanism.name()))
+                };
+                [Mechanism::ScramSha256, Mechanism::ScramSha1]
+                    .iter()
+                    .copied()
+                    .find(|mechanism| supports(*mechanism))
+                    .unwrap_or(Mechanism::Plain)
+            }
+        }
+    }
+}
+
+/// State of an authentication exchange.
+#[derive(Debug)]
+pub(crate) struct Session {
+    state: SessionState,
+
+    /// Payload received so far. Server splits payloads longer than 400 bytes into multiple
+    /// `AUTHENTICATE` messages.
+    buf: String,
+}
+
+#[derive(Debug)]
+enum SessionState {
+    /// Waiting for the server to accept `EXTERNAL`
+    External,
+
+    /// Waiting for the server to accept `PLAIN`
+    Plain { username: String, password: String },
+
+    /// Waiting for the server to accept `SCRAM-SHA-*`. We send the client-first message next.
+    ScramStart {
+        hash: ScramHash,
+        username: String,
+        password: String,
+        nonce: String,
+    },
+
+    /// Sent client-first message, waiting for server-first message
+    ScramServerFirst {
+        hash: ScramHash,
+        password: String,
+        nonce: String,
+        client_first_bare: String,
+    },
+
+    /// Sent client-final message, waiting for server-final message
+    ScramServerFinal { server_signature: Vec<u8> },
+
+    /// Sent our last response, waiting for the result (903 or one of the error numerics)
+    Done,
+
+    /// We aborted the exchange because of an error, waiting for 906 ERR_SASLABORTED
+    Aborted,
+}
+
+impl Session {
+    pub(crate) fn new(auth: &SASLAuth, mechanism: Mechanism) -> Session {
+        Session::with_nonce(auth, mechanism, random_nonce())
+    }
+
+    fn with_nonce(auth: &SASLAuth, mechanism: Mechanism, nonce: String) -> Session {
+        let (username, password) = match auth {
+            SASLAuth::Password { username, password } => (username.clone(), password.clone()),
+            SASLAuth::External => (String::new(), String::new()),
+        };
+        let state = match mechanism {
+            Mechanism::External => SessionState::External,
+            Mechanism::Plain => SessionState::Plain { username, password },
+            Mechanism::ScramSha1 | Mechanism::ScramSha256 => SessionState::ScramStart {
+                hash: if mechanism == Mechanism::ScramSha1 {
+                    ScramHash::Sha1
+                } else {
+                    ScramHash::Sha256
+                },
+                username,
+                password,
+                nonce,
+            },
+        };
+        Session {
+            state,
+            buf: String::new(),
+        }
+    }
+
+    /// Handle an `AUTHENTICATE` message from the server. Returns `AUTHENTICATE` messages to send.
+    /// On error the exchange should be aborted with `AUTHENTICATE *`.
+    pub(crate) fn step(&mut self, param: &str) -> Result<Vec<String>, String> {
+        let ret = self.step_(param);
+        if ret.is_err() {
+            self.state = SessionState::Aborted;
+        }
+        ret
+    }
+
+    /// Did we abort the exchange because of an error in `step`?
+    pub(crate) fn is_aborted(&self) -> bool {
+        matches!(self.state, SessionState::Aborted)
+    }
+
+    fn step_(&mut self, param: &str) -> Result<Vec<String>, String> {
+        if param != "+" {
+            self.buf.push_str(param);
+            if param.len() == MAX_PAYLOAD_LEN {
+                // More chunks to come
+                return Ok(vec![]);
+            }
+        }
+
+        let payload = base64::decode(std::mem::take(&mut self.buf))
+            .map_err(|_| "Server sent invalid base64 in AUTHENTICATE".to_owned())?;
+
+        let (response, next_state) = match std::mem::replace(&mut self.state, SessionState::Done) {
+            SessionState::External => (vec![], SessionState::Done),
+
+            SessionState::Plain { username, password } => (
+                format!("{}\x00{}\x00{}", username, username, password).into_bytes(),
+                SessionState::Done,
+            ),
+
+            SessionState::ScramStart {
+                hash,
+                username,
+                password,
+                nonce,
+            } => {
+                let client_first_bare = format!("n={},r={}", scram_escape(&username), nonce);
+                (
+                    format!("n,,{}", client_first_bare).into_bytes(),
+                    SessionState::ScramServerFirst {
+                        hash,
+                        password,
+                        nonce,
+                        client_first_bare,
+                    },
+                )
+            }
+
+            SessionState::ScramServerFirst {
+                hash,
+                password,
+                nonce,
+                client_first_bare,
+            } => {
+                let server_first = String::from_utf8(payload)
+                    .map_err(|_| "Invalid SCRAM server-first message".to_owned())?;
+                let (client_final, server_signature) =
+                    scram_client_final(hash, &password, &nonce, &client_first_bare, &server_first)?;
+                (
+                    client_final.into_bytes(),
+                    SessionState::ScramServerFinal { server_signature },
+                )
+            }
+
+            SessionState::ScramServerFinal { server_signature } => {
+                let server_final = String::from_utf8(payload)
+                    .map_err(|_| "Invalid SCRAM server-final message".to_owned())?;
+                if let Some(err) = scram_attr(&server_final, 'e') {
+                    return Err(format!("SCRAM error: {}", err));
+                }
+                let verifier = scram_attr(&server_final, 'v')
+                    .and_then(|v| base64::decode(v).ok())
+                    .ok_or_else(|| "Invalid SCRAM server-final message".to_owned())?;
+                if verifier != server_signature {
+                    return Err("Invalid SCRAM server signature".to_owned());
+                }
+                (vec![], SessionState::Done)
+            }
+
+            SessionState::Done | SessionState::Aborted => {
+                return Err("Unexpected AUTHENTICATE message".to_owned());
+            }
+        };
+
+        self.state = next_state;
+        Ok(authenticate_msgs(&response))
+    }
+}
+
+/// Generate `AUTHENTICATE` messages for a response payload, splitting the payload into 400-byte
+/// chunks. An empty payload, or a payload with length a multiple of 400, is terminated with
+/// `AUTHENTICATE +`.
+fn authenticate_msgs(payload: &[u8]) -> Vec<String> {
+    let encoded = base64::encode(payload);
+    let mut msgs: Vec<String> = encoded
+        .as_bytes()
+        .chunks(MAX_PAYLOAD_LEN)
+        // base64 is ASCII so chunks are valid UTF-8
+        .map(|chunk| wire::authenticate(std::str::from_utf8(chunk).unwrap()))
+        .collect();
+    if encoded.len().is_multiple_of(MAX_PAYLOAD_LEN) {
+        msgs.push(wire::authenticate("+"));
+    }
+    msgs
+}
+
+fn random_nonce() -> String {
+    let mut bytes = [0u8; 18];
+    getrandom::getrandom(&mut bytes).unwrap();
+    base64::encode(bytes)
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// SCRAM
+
+#[derive(Debug, Clone, Copy)]
+enum ScramHash {
+    Sha1,
+    Sha256,
+}
+
+impl ScramHash {
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            ScramHash::Sha256 => sha2::Sha256::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramHash::Sha1 => hmac::<sha1::Sha1>(key, data),
+            ScramHash::Sha256 => hmac::<sha2::Sha256>(key, data),
+        }
+    }
+
+    /// The `Hi` function in RFC 5802 (PBKDF2 with HMAC)
+    fn hi(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut salt_1 = salt.to_vec();
+        salt_1.extend_from_slice(&1u32.to_be_bytes());
+        let mut u = self.hmac(password, &salt_1);
+        let mut result = u.clone();
+        for _ in 1..iterations {
+            u = self.hmac(password, &u);
+            xor(&mut result, &u);
+        }
+        result
+    }
+}
+
+fn hmac<D: Digest + BlockSizeUser>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any length
+    let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn xor(lhs: &mut [u8], rhs: &[u8]) {
+    for (l, r) in lhs.iter_mut().zip(rhs.iter()) {
+        *l ^= r;
+    }
+}
+
+/// Escape a username for SCRAM (`saslname` in RFC 5802)
+fn scram_escape(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+/// Find value of an attribute in a SCRAM message
+fn scram_attr(msg: &str, attr: char) -> Option<&str> {
+    msg.split(',').find_map(|field| {
+        let mut chars = field.chars();
+        if chars.next() == Some(attr) && chars.next() == Some('=') {
+            Some(&field[2..])
+        } else {
+            None
+        }
+    })
+}
+
+/// Generate the client-final message for the given server-first message. Also returns the
+/// expected server signature, to be checked against the server-final message.
+fn scram_client_final(
+    hash: ScramHash,
+    password: &str,
+    nonce: &str,
+    client_first_bare: &str,
+    server_first: &str,
+) -> Result<(String, Vec<u8>), String> {
+    let invalid = || format!("Invalid SCRAM server-first message: {:?}", server_first);
+
+    let server_nonce = scram_attr(server_first, 'r').ok_or_else(invalid)?;
+    let salt = scram_attr(server_first, 's')
+        .and_then(|s| base64::decode(s).ok())
+        .ok_or_else(invalid)?;
+    let iterations: u32 = scram_attr(server_first, 'i')
+        .and_then(|i| i.parse().ok())
+        .filter(|i| *i > 0)
+        .ok_or_else(invalid)?;
+
+    if !server_nonce.starts_with(nonce) || server_nonce.len() == nonce.len() {
+        return Err("Invalid SCRAM nonce".to_owned());
+    }
+
+    let salted_password = hash.hi(password.as_bytes(), &salt, iterations);
+    let client_key = hash.hmac(&salted_password, b"Client Key");
+    let stored_key = hash.hash(&client_key);
+    let server_key = hash.hmac(&salted_password, b"Server Key");
+
+    // "biws" is base64 of the GS2 header "n,,"
+    let client_final_without_proof = format!("c=biws,r={}", server_nonce);
+    let auth_msg = format!(
+        "{},{},{}",
+        client_first_bare, server_first, client_final_without_proof
+    );
+
+    let mut client_proof = client_key;
+    xor(
+        &mut client_proof,
+        &hash.hmac(&stored_key, auth_msg.as_bytes()),
+    );
+    let server_signature = hash.hmac(&server_key, auth_msg.as_bytes());
+
+    Ok((
+        format!(
+            "{},p={}",
+            client_final_without_proof,
+            base64::encode(client_proof)
+        ),
+        server_signature,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn password_auth() -> SASLAuth {
+        SASLAuth::Password {
+            username: "user".to_owned(),
+            password: "pencil".to_owned(),
+        }
+    }
+
+    fn decode(msgs: &[String]) -> String {
+        assert_eq!(msgs.len(), 1);
+        let payload = msgs[0]
+            .strip_prefix("AUTHENTICATE ")
+            .unwrap()
+            .strip_suffix("\r\n")
+            .unwrap();
+        String::from_utf8(base64::decode(payload).unwrap()).unwrap()
+    }
+
+    fn encode(msg: &str) -> String {
+        base64::encode(msg)
+    }
+
+    #[test]
+    fn mechanism_selection() {
+        let auth = password_auth();
+        assert_eq!(Mechanism::select(&auth, None), Mechanism::Plain);
+        assert_eq!(
+            Mechanism::select(&auth, Some("PLAIN,EXTERNAL")),
+            Mechanism::Plain
+        );
+        assert_eq!(
+            Mechanism::select(&auth, Some("PLAIN,SCRAM-SHA-1")),
+            Mechanism::ScramSha1
+        );
+        assert_eq!(
+            Mechanism::select(&auth, Some("SCRAM-SHA-1,SCRAM-SHA-256,PLAIN")),
+            Mechanism::ScramSha256
+        );
+        assert_eq!(
+            Mechanism::select(&SASLAuth::External, Some("PLAIN")),
+            Mechanism::External
+        );
+    }
+
+    #[test]
+    fn plain() {
+        let mut session = Session::new(&password_auth(), Mechanism::Plain);
+        assert_eq!(decode(&session.step("+").unwrap()), "user\0user\0pencil");
+        assert!(!session.is_aborted());
+        assert!(session.step("+").is_err());
+        assert!(session.is_aborted());
+    }
+
+    #[test]
+    fn external() {
+        let mut session = Session::new(&SASLAuth::External, Mechanism::External);
+        assert_eq!(
+            session.step("+").unwrap(),
+            vec!["AUTHENTICATE +\r\n".to_owned()]
+        );
+    }
+
+    // Test vectors from RFC 5802 section 5
+    #[test]
+    fn scram_sha_1() {
+        let mut session = Session::with_nonce(
+            &password_auth(),
+            Mechanism::ScramSha1,
+            "fyko+d2lbbFgONRv9qkxdawL".to_owned(),
+        );
+        assert_eq!(
+            decode(&session.step("+").unwrap()),
+            "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL"
+        );
+        let server_first = "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096";
+        assert_eq!(
+            decode(&session.step(&encode(server_first)).unwrap()),
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        let server_final = "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=";
+        assert_eq!(
+            session.step(&encode(server_final)).unwrap(),
+            vec!["AUTHENTICATE +\r\n".to_owned()]
+        );
+    }
+
+    // Test vectors from RFC 7677 section 3
+    #[test]
+    fn scram_sha_256() {
+        let mut session = Session::with_nonce(
+            &password_auth(),
+            Mechanism::ScramSha256,
+            "rOprNGfwEbeRWgbNEkqO".to_owned(),
+        );
+        assert_eq!(
+            decode(&session.step("+").unwrap()),
+            "n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+        );
+        let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert_eq!(
+            decode(&session.step(&encode(server_first)).unwrap()),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+
+        // Invalid server signature
+        let server_final = "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G5=";
+        assert!(session.step(&encode(server_final)).is_err());
+    }
+
+    #[test]
+    fn scram_invalid_nonce() {
+        let mut session = Session::with_nonce(
+            &password_auth(),
+            Mechanism::ScramSha256,
+            "rOprNGfwEbeRWgbNEkqO".to_owned(),
+        );
+        session.step("+").unwrap();
+        let server_first = "r=someothernonce,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        assert!(session.step(&encode(server_first)).is_err());
+    }
+
+    #[test]
+    fn payload_chunking() {
+        assert_eq!(authenticate_msgs(b""), vec!["AUTHENTICATE +\r\n"]);
+
+        // 300 bytes = 400 bytes in base64
+        let msgs = authenticate_msgs(&[0u8; 300]);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[1], "AUTHENTICATE +\r\n");
+
+        let msgs = authenticate_msgs(&[0u8; 500]);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].len(), "AUTHENTICATE \r\n".len() + 400);
+
+        // Incoming chunks are joined
+        let mut session = Session::with_nonce(
+            &password_auth(),
+            Mechanism::ScramSha256,
+            "rOprNGfwEbeRWgbNEkqO".to_owned(),
+        );
+        session.step("+").unwrap();
+        let server_first = format!(
+            "r=rOprNGfwEbeRWgbNEkqO{},s=W22ZaJ0SNY7soEsUEjb6gQ==,i=1",
+            "x".repeat(300)
+        );
+        let encoded = encode(&server_first);
+        assert!(encoded.len() > 400);
+        assert!(session.step(&encoded[..400]).unwrap().is_empty());
+        // Client-final message includes the long nonce, so it's split too
+        assert_eq!(session.step(&encoded[400..]).unwrap().len(), 2);
+    }
+}
@@ -1,16 +1,24 @@
 #![allow(clippy::zero_prefixed_literal)]
 
+use crate::caps::Caps;
+use crate::ctcp;
+use crate::dcc::Dcc;
+use crate::modes::{self, ChanInfo, ChanMember, ListEntry, ModeKind};
+use crate::sasl;
 use crate::utils;
-use crate::{Cmd, Event, ServerInfo};
-use libtiny_common::{ChanName, ChanNameRef};
+use crate::whois::{Whois, WhoisUpdate};
+use crate::{Cmd, DccChat, DccTransfer, Event, SASLFailure, ServerInfo};
+use libtiny_common::{CaseMapping, ChanListEntry, ChanName, ChanNameRef, Nick};
 use libtiny_wire as wire;
 use libtiny_wire::{Msg, Pfx};
 
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
 use std::rc::Rc;
 
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
 use tokio::time::{timeout, Duration};
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
@@ -21,9 +29,9 @@ pub struct State {
 }
 
 impl State {
-    pub(crate) fn new(server_info: ServerInfo) -> State {
+    pub(crate) fn new(server_info: ServerInfo, snd_ev: Sender<Event>) -> State {
         State {
-            inner: Rc::new(RefCell::new(StateInner::new(server_info))),
+            inner: Rc::new(RefCell::new(StateInner::new(server_info, snd_ev))),
         }
     }
 
@@ -31,20 +39,30 @@ impl State {
         self.inner.borrow_mut().reset()
     }
 
-    pub(crate) fn send_ping(&self, snd_irc_msg: &mut Sender<String>) {
+    pub(crate) fn send_ping(&self, snd_irc_msg: &mut UnboundedSender<String>) {
         self.inner.borrow_mut().send_ping(snd_irc_msg)
     }
 
+    /// Update the state with a message from the server. Returns whether the message should be
+    /// sent to the user as an `Event::Msg`: messages that are reported with other events (e.g.
+    /// WHOIS replies) are not.
     pub(crate) fn update(
         &self,
         msg: &mut Msg,
         snd_ev: &mut Sender<Event>,
-        snd_irc_msg: &mut Sender<String>,
-    ) {
-        self.inner.borrow_mut().update(msg, snd_ev, snd_irc_msg);
+        snd_irc_msg: &mut UnboundedSender<String>,
+    ) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        inner.update_hosts(msg);
+        if inner.update_whois(msg, snd_ev) || inner.update_chan_list(msg, snd_ev) {
+            return false;
+        }
+        let requested = inner.update_mode_queries(msg);
+        inner.update(msg, snd_ev, snd_irc_msg);
+        requested
     }
 
-    pub(crate) fn introduce(&self, snd_irc_msg: &mut Sender<String>) {
+    pub(crate) fn introduce(&self, snd_irc_msg: &mut UnboundedSender<String>) {
         self.inner.borrow_mut().introduce(snd_irc_msg)
     }
 
@@ -62,6 +80,49 @@ impl State {
         self.inner.borrow().usermask.clone()
     }
 
+    pub(crate) fn set_chan_key(&self, chan: &ChanNameRef, key: &str) {
+        self.inner.borrow_mut().set_chan_key(chan, key.to_owned());
+    }
+
+    pub(crate) fn get_chan_key(&self, chan: &ChanNameRef) -> Option<String> {
+        self.inner.borrow().chan_key(chan).map(str::to_owned)
+    }
+
+    /// Remember a `MODE <chan>` query without mode changes (e.g. `MODE #chan` or `MODE #chan b`)
+    /// so that the replies are sent to the user.
+    pub(crate) fn request_chan_modes(&self, chan: &str, modes: &[&str]) {
+        let mut inner = self.inner.borrow_mut();
+        let chan = inner.features.casemapping.normalize(chan);
+        match modes {
+            [] => {
+                inner.mode_queries.insert((chan, None));
+            }
+            [modes] if !modes.contains('-') => {
+                for mode in modes.trim_start_matches('+').chars() {
+                    if LIST_REPLY_MODES.contains(&mode) {
+                        inner.mode_queries.insert((chan.clone(), Some(mode)));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn request_whois(&self, nick: &str) {
+        let mut inner = self.inner.borrow_mut();
+        let casemapping = inner.features.casemapping;
+        inner.whois.request(nick, casemapping);
+    }
+
+    pub(crate) fn request_list(&self) {
+        self.inner.borrow_mut().chan_list = Some(vec![]);
+    }
+
+    pub(crate) fn get_user_host(&self, nick: &str) -> Option<String> {
+        let inner = self.inner.borrow();
+        inner.hosts.get(&inner.nick(nick)).cloned()
+    }
+
     pub(crate) fn set_away(&self, msg: Option<&str>) {
         self.inner.borrow_mut().away_status = msg.map(str::to_owned);
     }
@@ -70,13 +131,101 @@ impl State {
         self.inner.borrow().get_chan_nicks(chan)
     }
 
-    pub(crate) fn leave_channel(&self, msg_chan: &mut Sender<Cmd>, chan: &ChanNameRef) {
+    pub(crate) fn get_chan_members(&self, chan: &ChanNameRef) -> Vec<ChanMember> {
+        self.inner.borrow().get_chan_members(chan)
+    }
+
+    pub(crate) fn get_chan_member(&self, chan: &ChanNameRef, nick: &str) -> Option<ChanMember> {
+        self.inner.borrow().get_chan_member(chan, nick)
+    }
+
+    pub(crate) fn get_chan_info(&self, chan: &ChanNameRef) -> Option<ChanInfo> {
+        self.inner.borrow().get_chan_info(chan)
+    }
+
+    pub(crate) fn leave_channel(&self, msg_chan: &mut UnboundedSender<Cmd>, chan: &ChanNameRef) {
         self.inner.borrow_mut().leave_channel(msg_chan, chan)
     }
 
     pub(crate) fn kill_join_tasks(&self) {
         self.inner.borrow_mut().kill_join_tasks();
     }
+
+    pub(crate) fn get_enabled_caps(&self) -> HashSet<String> {
+        self.inner.borrow().caps.enabled().clone()
+    }
+
+    pub(crate) fn get_server_features(&self) -> ServerFeatures {
+        self.inner.borrow().features.clone()
+    }
+
+    pub(crate) fn is_chan_name(&self, name: &str) -> bool {
+        self.inner.borrow().features.is_chan_name(name)
+    }
+
+    pub(crate) fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        self.inner.borrow().features.drop_nick_prefix(nick)
+    }
+
+    pub(crate) fn get_linelen(&self) -> usize {
+        self.inner.borrow().features.linelen
+    }
+
+    pub(crate) fn set_local_ip(&self, ip: Option<IpAddr>) {
+        self.inner.borrow_mut().dcc.set_local_ip(ip)
+    }
+
+    pub(crate) fn dcc_send(
+        &self,
+        nick: &str,
+        path: &Path,
+    ) -> Result<(DccTransfer, String), String> {
+        self.inner.borrow_mut().dcc.send(nick, path)
+    }
+
+    /// Returns nick of the sender and arguments of the DCC message to send to the sender.
+    pub(crate) fn dcc_accept(&self, id: usize) -> Result<(String, Option<String>), String> {
+        let mut inner = self.inner.borrow_mut();
+        let nick = inner
+            .dcc
+            .nick(id)
+            .ok_or_else(|| format!("Can't find DCC offer {}", id))?;
+        let casemapping = inner.features.casemapping;
+        let reply = inner.dcc.accept(id, casemapping)?;
+        Ok((nick, reply))
+    }
+
+    pub(crate) fn dcc_chat(&self, nick: &str) -> Result<(DccChat, Option<String>), String> {
+        let mut inner = self.inner.borrow_mut();
+        let casemapping = inner.features.casemapping;
+        inner.dcc.chat(nick, casemapping)
+    }
+
+    pub(crate) fn dcc_chat_send(
+        &self,
+        nick: &str,
+        msg: &str,
+        is_action: bool,
+    ) -> Result<(), String> {
+        let inner = self.inner.borrow();
+        inner
+            .dcc
+            .chat_send(nick, msg, is_action, inner.features.casemapping)
+    }
+
+    pub(crate) fn dcc_chat_close(&self, nick: &str) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let casemapping = inner.features.casemapping;
+        inner.dcc.chat_close(nick, casemapping)
+    }
+
+    pub(crate) fn dcc_cancel(&self, id: usize) -> bool {
+        self.inner.borrow_mut().dcc.cancel(id)
+    }
+
+    pub(crate) fn has_dcc_transfer(&self, id: usize) -> bool {
+        self.inner.borrow().dcc.has_transfer(id)
+    }
 }
 
 struct StateInner {
@@ -104,6 +253,26 @@ struct StateInner {
     /// order, in TUI?
     chans: Vec<Chan>,
 
+    /// Keys of channels, from `ServerInfo::auto_join` and `Client::join`. Used when (re)joining
+    /// channels. Not reset on reconnect.
+    ///
+    /// Maps channel names normalized with the server's casemapping to the channel names and keys.
+    chan_keys: HashMap<String, (ChanName, String)>,
+
+    /// DCC transfers and offers. Not reset on reconnect.
+    dcc: Dcc,
+
+    /// WHOIS and WHOWAS replies being collected
+    whois: Whois,
+
+    /// Channel list being collected, after a LIST request until RPL_LISTEND (323)
+    chan_list: Option<Vec<ChanListEntry>>,
+
+    /// Channel mode queries sent by the user, as normalized channel names and the list mode
+    /// queried (`None` for channel modes). Replies to other queries (e.g. the one we send after
+    /// joining a channel) only update the channel states and are not sent to the user.
+    mode_queries: HashSet<(String, Option<char>)>,
+
     /// Away reason if away mode is on. `None` otherwise.
     away_status: Option<String>,
 
@@ -116,9 +285,24 @@ struct StateInner {
     /// responses to set this field.
     usermask: Option<String>,
 
+    /// `user@host` parts of other users, from message prefixes and WHOIS replies. Used to make
+    /// ban masks. Entries are removed when users quit, or leave the last channel we share with
+    /// them.
+    hosts: HashMap<Nick, String>,
+
     /// Do we have a nick yet? Try another nick on ERR_NICKNAMEINUSE (433) until we've got a nick.
     nick_accepted: bool,
 
+    /// IRCv3 capability negotiation state.
+    caps: Caps,
+
+    /// SASL authentication exchange, when authentication is in progress. Capability negotiation
+    /// is ended after authentication.
+    sasl: Option<sasl::Session>,
+
+    /// Server features advertised with RPL_ISUPPORT (005).
+    features: ServerFeatures,
+
     /// Server information
     server_info: ServerInfo,
 }
@@ -127,8 +311,13 @@ struct StateInner {
 struct Chan {
     /// Name of the channel
     name: ChanName,
-    /// Set of nicknames in channel
-    nicks: HashSet<String>,
+    /// Nicknames in channel, with membership modes (sorted from highest to lowest rank)
+    nicks: HashMap<Nick, Vec<char>>,
+    /// Channel modes and lists
+    info: ChanInfo,
+    /// List modes that we're receiving a list for (e.g. RPL_BANLIST until RPL_ENDOFBANLIST). The
+    /// list is cleared with the first entry.
+    receiving_lists: HashSet<char>,
     /// Channel joined state
     join_state: JoinState,
     /// Join attempts
@@ -161,16 +350,20 @@ impl Chan {
     fn new(name: ChanName) -> Chan {
         Chan {
             name,
-            nicks: HashSet::new(),
+            nicks: HashMap::new(),
+            info: ChanInfo::default(),
+            receiving_lists: HashSet::new(),
             join_state: JoinState::NotJoined,
             join_attempts: MAX_JOIN_RETRIES,
         }
     }
 
-    fn with_nicks(name: ChanName, nicks: HashSet<String>) -> Chan {
+    fn with_nicks(name: ChanName, nicks: HashMap<Nick, Vec<char>>) -> Chan {
         Chan {
             name,
             nicks,
+            info: ChanInfo::default(),
+            receiving_lists: HashSet::new(),
             join_state: JoinState::NotJoined,
             join_attempts: MAX_JOIN_RETRIES,
         }
@@ -178,6 +371,8 @@ impl Chan {
 
     fn reset(&mut self) {
         self.nicks.clear();
+        self.info = ChanInfo::default();
+        self.receiving_lists.clear();
         self.join_state = JoinState::NotJoined;
         self.join_attempts = MAX_JOIN_RETRIES;
     }
@@ -200,12 +395,23 @@ impl Chan {
 }
 
 impl StateInner {
-    fn new(server_info: ServerInfo) -> StateInner {
+    fn new(server_info: ServerInfo, snd_ev: Sender<Event>) -> StateInner {
         let current_nick = server_info.nicks[0].to_owned();
         let chans = server_info
             .auto_join
             .iter()
-            .map(|s| Chan::new(s.to_owned()))
+            .map(|(chan, _)| Chan::new(chan.to_owned()))
+            .collect();
+        let casemapping = CaseMapping::default();
+        let chan_keys = server_info
+            .auto_join
+            .iter()
+            .filter_map(|(chan, key)| {
+                Some((
+                    casemapping.normalize(chan.display()),
+                    (chan.to_owned(), key.clone()?),
+                ))
+            })
             .collect();
         StateInner {
             nicks: server_info.nicks.clone(),
@@ -213,10 +419,19 @@ impl StateInner {
             current_nick_idx: 0,
             current_nick,
             chans,
+            chan_keys,
             away_status: None,
             servername: None,
             usermask: None,
+            hosts: HashMap::new(),
             nick_accepted: false,
+            caps: Caps::default(),
+            sasl: None,
+            features: ServerFeatures::default(),
+            dcc: Dcc::new(server_info.dcc.clone(), snd_ev),
+            whois: Whois::default(),
+            chan_list: None,
+            mode_queries: HashSet::new(),
             server_info,
         }
     }
@@ -232,23 +447,28 @@ impl StateInner {
         }
         self.servername = None;
         self.usermask = None;
+        self.hosts.clear();
+        self.caps.reset();
+        self.whois.reset();
+        self.chan_list = None;
+        self.mode_queries.clear();
+        self.sasl = None;
+        self.features = ServerFeatures::default();
     }
 
-    fn send_ping(&mut self, snd_irc_msg: &mut Sender<String>) {
+    fn send_ping(&mut self, snd_irc_msg: &mut UnboundedSender<String>) {
         if let Some(ref servername) = self.servername {
-            snd_irc_msg.try_send(wire::ping(servername)).unwrap();
+            snd_irc_msg.send(wire::ping(servername)).unwrap();
         }
     }
 
-    fn introduce(&mut self, snd_irc_msg: &mut Sender<String>) {
+    fn introduce(&mut self, snd_irc_msg: &mut UnboundedSender<String>) {
         if let Some(ref pass) = self.server_info.pass {
-            snd_irc_msg.try_send(wire::pass(pass)).unwrap();
+            snd_irc_msg.send(wire::pass(pass)).unwrap();
         }
+        snd_irc_msg.send(wire::nick(&self.current_nick)).unwrap();
         snd_irc_msg
-            .try_send(wire::nick(&self.current_nick))
-            .unwrap();
-        snd_irc_msg
-            .try_send(wire::user(&self.nicks[0], &self.server_info.realname))
+            .send(wire::user(&self.nicks[0], &self.server_info.realname))
             .unwrap();
     }
 
@@ -268,29 +488,129 @@ impl StateInner {
         &self.current_nick
     }
 
+    /// Update `user@host`s of users.
+    fn update_hosts(&mut self, msg: &Msg) {
+        if let Some(Pfx::User { nick, user }) = &msg.pfx {
+            if !self.is_current_nick(nick) {
+                self.hosts.insert(self.nick(nick), user.clone());
+            }
+        }
+        match (&msg.cmd, &msg.pfx) {
+            (wire::Cmd::NICK { nick: new_nick, .. }, Some(Pfx::User { nick, .. })) => {
+                if let Some(host) = self.hosts.remove(&self.nick(nick)) {
+                    self.hosts.insert(self.nick(new_nick), host);
+                }
+            }
+            (wire::Cmd::QUIT { .. }, Some(Pfx::User { nick, .. })) => {
+                self.hosts.remove(&self.nick(nick));
+            }
+            (wire::Cmd::CHGHOST { new_user, new_host }, Some(Pfx::User { nick, .. })) => {
+                if let Some(host) = self.hosts.get_mut(&self.nick(nick)) {
+                    *host = format!("{}@{}", new_user, new_host);
+                }
+            }
+            // RPL_WHOISUSER
+            (wire::Cmd::Reply { num: 311, params }, _) if params.len() > 3 => {
+                let host = format!("{}@{}", params[2], params[3]);
+                self.hosts.insert(self.nick(&params[1]), host);
+            }
+            _ => {}
+        }
+    }
+
+    /// Collect WHOIS and WHOWAS replies. Returns whether the message is consumed.
+    fn update_whois(&mut self, msg: &Msg, snd_ev: &mut Sender<Event>) -> bool {
+        let (num, params) = match &msg.cmd {
+            wire::Cmd::Reply { num, params } => (*num, params),
+            _ => return false,
+        };
+        match self.whois.update(num, params, self.features.casemapping) {
+            WhoisUpdate::Ignored => false,
+            WhoisUpdate::Consumed => true,
+            WhoisUpdate::Done(info) => {
+                snd_ev.try_send(Event::Whois(*info)).unwrap();
+                true
+            }
+        }
+    }
+
+    /// Collect RPL_LIST replies of a requested channel list. Returns whether the message is
+    /// consumed.
+    fn update_chan_list(&mut self, msg: &Msg, snd_ev: &mut Sender<Event>) -> bool {
+        let chan_list = match &mut self.chan_list {
+            None => return false,
+            Some(chan_list) => chan_list,
+        };
+        match &msg.cmd {
+            // RPL_LISTSTART
+            wire::Cmd::Reply { num: 321, .. } => true,
+            // RPL_LIST
+            wire::Cmd::Reply { num: 322, params } if params.len() > 2 => {
+                chan_list.push(ChanListEntry {
+                    chan: ChanName::new(params[1].clone()),
+                    users: params[2].parse().unwrap_or(0),
+                    topic: params.get(3).cloned().unwrap_or_default(),
+                });
+                true
+            }
+            // RPL_LISTEND
+            wire::Cmd::Reply { num: 323, .. } => {
+                let chan_list = self.chan_list.take().unwrap();
+                snd_ev.try_send(Event::ChanList(chan_list)).unwrap();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check channel mode replies against the queries sent by the user. Returns whether the
+    /// message should be sent to the user.
+    fn update_mode_queries(&mut self, msg: &Msg) -> bool {
+        let (num, params) = match &msg.cmd {
+            wire::Cmd::Reply { num, params } if params.len() >= 2 => (*num, params),
+            _ => return true,
+        };
+        let chan = self.features.casemapping.normalize(&params[1]);
+        match num {
+            // RPL_CHANNELMODEIS, followed by RPL_CREATIONTIME which ends the query
+            324 => self.mode_queries.contains(&(chan, None)),
+            329 => self.mode_queries.remove(&(chan, None)),
+            // RPL_INVITELIST, RPL_EXCEPTLIST, RPL_BANLIST
+            346 | 348 | 367 => self
+                .mode_queries
+                .contains(&(chan, Some(list_reply_mode(num)))),
+            // RPL_ENDOFINVITELIST, RPL_ENDOFEXCEPTLIST, RPL_ENDOFBANLIST
+            347 | 349 | 368 => self
+                .mode_queries
+                .remove(&(chan, Some(list_reply_mode(num - 1)))),
+            _ => true,
+        }
+    }
+
     fn update(
         &mut self,
         msg: &mut Msg,
         snd_ev: &mut Sender<Event>,
-        snd_irc_msg: &mut Sender<String>,
+        snd_irc_msg: &mut UnboundedSender<String>,
     ) {
         let Msg {
             ref pfx,
             ref mut cmd,
+            ..
         } = msg;
 
         use wire::Cmd::*;
         match cmd {
             // PING: Send PONG
             PING { server } => {
-                snd_irc_msg.try_send(wire::pong(server)).unwrap();
+                snd_irc_msg.send(wire::pong(server)).unwrap();
             }
 
-            // JOIN: If this is us then update usermask if possible, create the channel state. If
-            // someone else add the nick to channel.
-            JOIN { chan } => {
+            // JOIN: If this is us then update usermask if possible, create the channel states. If
+            // someone else add the nick to the channels.
+            JOIN { chans, .. } => {
                 match pfx {
-                    Some(Pfx::User { nick, user }) if nick == &self.current_nick => {
+                    Some(Pfx::User { nick, user }) if self.is_current_nick(nick) => {
                         // Set usermask
                         let usermask = format!("{}!{}", nick, user);
                         self.usermask = Some(usermask);
@@ -300,69 +620,60 @@ impl StateInner {
 
                 match pfx {
                     Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => {
-                        if nick == &self.current_nick {
-                            // We joined a channel, initialize channel state
-                            match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                                None => {
-                                    let mut chan = Chan::new(chan.to_owned());
-                                    // Since nick was found in the prefix, we are in the channel
-                                    chan.join_state = JoinState::Joined;
-                                    self.chans.push(chan);
-                                }
-                                Some(chan_idx) => {
-                                    // This happens because we initialize channel states for channels
-                                    // that we will join on connection when the client is first created
-                                    let chan = &mut self.chans[chan_idx];
-                                    chan.join_state = JoinState::Joined;
-                                    chan.nicks.clear();
-                                }
-                            }
-                        } else {
-                            match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                                Some(chan_idx) => {
-                                    self.chans[chan_idx]
-                                        .nicks
-                                        .insert(wire::drop_nick_prefix(nick).to_owned());
-                                }
-                                None => {
-                                    debug!("Can't find channel state for JOIN: {:?}", cmd);
-                                }
-                            }
+                        for chan in chans.iter() {
+                            self.join(nick, chan, snd_irc_msg);
                         }
                     }
                     Some(Pfx::Server(_)) | None => {}
                 }
             }
 
-            // PART: If this is us remove the channel state. Otherwise remove the nick from the
-            // channel.
-            PART { chan, .. } => match pfx {
+            // PART: If this is us remove the channel states. Otherwise remove the nick from the
+            // channels.
+            PART { chans, .. } => match pfx {
                 Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => {
-                    if nick == &self.current_nick {
-                        match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                            None => {
-                                debug!("Can't find channel state: {}", chan.display());
-                            }
-                            Some(chan_idx) => {
-                                self.chans.remove(chan_idx);
-                            }
-                        }
-                    } else {
-                        match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                            Some(chan_idx) => {
-                                self.chans[chan_idx]
-                                    .nicks
-                                    .remove(wire::drop_nick_prefix(nick));
-                            }
-                            None => {
-                                debug!("Can't find channel state for PART: {:?}", cmd);
-                            }
-                        }
+                    for chan in chans.iter() {
+                        self.part(nick, chan);
                     }
                 }
                 Some(Pfx::Server(_)) | None => {}
             },
 
+            // KICK: If this is us remove the channel state (or rejoin, see `kicked`). Otherwise
+            // remove the nick from the channel.
+            KICK { chan, nick, msg } => {
+                if self.is_current_nick(nick) {
+                    let by = match pfx {
+                        Some(Pfx::User { nick, .. })
+                        | Some(Pfx::Ambiguous(nick))
+                        | Some(Pfx::Server(nick)) => nick.clone(),
+                        None => String::new(),
+                    };
+                    snd_ev
+                        .try_send(Event::Kicked {
+                            chan: chan.to_owned(),
+                            by,
+                            reason: msg.clone(),
+                        })
+                        .unwrap();
+                    self.kicked(chan, snd_ev, snd_irc_msg);
+                } else if let Some(chan_idx) = self.find_chan_idx(chan) {
+                    let nick = self.nick(nick);
+                    self.chans[chan_idx].nicks.remove(&nick);
+                    self.prune_host(&nick);
+                }
+            }
+
+            // CHGHOST: Update usermask if this is us
+            CHGHOST { new_user, new_host } => match pfx {
+                Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick))
+                    if self.is_current_nick(nick) =>
+                {
+                    self.usermask = Some(format!("{}!{}@{}", nick, new_user, new_host));
+                }
+                _ => {}
+            },
+
             // QUIT: Update the `chans` field for the channels that the user was in
             QUIT { ref mut chans, .. } => {
                 let nick = match pfx {
@@ -371,23 +682,20 @@ impl StateInner {
                         return;
                     }
                 };
+                let nick = self.nick(nick);
                 for chan in self.chans.iter_mut() {
-                    if chan.nicks.contains(nick) {
+                    if chan.nicks.remove(&nick).is_some() {
                         chans.push(chan.name.to_owned());
-                        chan.nicks.remove(nick);
                     }
                 }
             }
 
             // 396: Try to set usermask.
-            Reply { num: 396, params } => {
+            Reply { num: 396, params } if params.len() == 3 => {
                 // :hobana.freenode.net 396 osa1 haskell/developer/osa1
                 // :is now your hidden host (set by services.)
-                if params.len() == 3 {
-                    let usermask =
-                        format!("{}!~{}@{}", self.current_nick, self.nicks[0], params[1]);
-                    self.usermask = Some(usermask);
-                }
+                let usermask = format!("{}!~{}@{}", self.current_nick, self.nicks[0], params[1]);
+                self.usermask = Some(usermask);
             }
 
             // Reply 477 when user needs to be identified with NickServ to join a channel
@@ -398,12 +706,14 @@ impl StateInner {
                     let channel = ChanNameRef::new(channel);
                     snd_ev
                         .try_send(Event::Msg(wire::Msg {
+                            tags: HashMap::new(),
+                            server_time: None,
                             pfx: pfx.clone(),
                             cmd: wire::Cmd::PRIVMSG {
                                 ctcp: None,
                                 is_notice: true,
                                 msg: msg_477.clone(),
-                                target: wire::MsgTarget::Chan(channel.to_owned()),
+                                targets: vec![wire::MsgTarget::Chan(channel.to_owned())],
                             },
                         }))
                         .unwrap();
@@ -415,7 +725,7 @@ impl StateInner {
                             msg,
                         };
                         // Find channel in self.chans
-                        if let Some(idx) = utils::find_idx(&self.chans, |c| c.name == *channel) {
+                        if let Some(idx) = self.find_chan_idx(channel) {
                             let chan = &mut self.chans[idx];
                             // Retry joining channel if retries are available
                             if let Some(retries) = chan.retry_join() {
@@ -439,6 +749,8 @@ impl StateInner {
                                 }
                                 tokio::task::spawn_local(retry_channel_join(
                                     channel.to_owned(),
+                                    self.chan_key(channel).map(str::to_owned),
+                                    Duration::from_secs(10),
                                     snd_irc_msg,
                                     rcv_abort,
                                 ));
@@ -480,7 +792,7 @@ impl StateInner {
                         {
                             i += 1;
                         }
-                        let usermask = (&param[i..]).trim();
+                        let usermask = param[i..].trim();
                         self.usermask = Some(usermask.to_owned());
                     }
                 }
@@ -497,7 +809,7 @@ impl StateInner {
                 self.nick_accepted = true;
                 if let Some(ref pwd) = self.nickserv_ident {
                     snd_irc_msg
-                        .try_send(wire::privmsg("NickServ", &format!("identify {}", pwd)))
+                        .send(wire::privmsg("NickServ", &format!("identify {}", pwd)))
                         .unwrap();
                 }
             }
@@ -520,17 +832,15 @@ impl StateInner {
             }
 
             // ERR_NICKNAMEINUSE: Try another nick if we don't have a nick yet.
-            Reply { num: 433, .. } => {
-                if !self.nick_accepted {
-                    let new_nick = self.get_next_nick();
-                    // debug!("new nick: {}", new_nick);
-                    snd_ev
-                        .try_send(Event::NickChange {
-                            new_nick: new_nick.to_owned(),
-                        })
-                        .unwrap();
-                    snd_irc_msg.try_send(wire::nick(new_nick)).unwrap();
-                }
+            Reply { num: 433, .. } if !self.nick_accepted => {
+                let new_nick = self.get_next_nick();
+                // debug!("new nick: {}", new_nick);
+                snd_ev
+                    .try_send(Event::NickChange {
+                        new_nick: new_nick.to_owned(),
+                    })
+                    .unwrap();
+                snd_irc_msg.send(wire::nick(new_nick)).unwrap();
             }
 
             // NICK message sent from the server when our nick change request was successful
@@ -540,7 +850,7 @@ impl StateInner {
             } => {
                 match pfx {
                     Some(Pfx::User { nick: old_nick, .. }) | Some(Pfx::Ambiguous(old_nick)) => {
-                        if old_nick == &self.current_nick {
+                        if self.is_current_nick(old_nick) {
                             snd_ev
                                 .try_send(Event::NickChange {
                                     new_nick: new_nick.to_owned(),
@@ -561,18 +871,17 @@ impl StateInner {
 
                             if let Some(ref pwd) = self.nickserv_ident {
                                 snd_irc_msg
-                                    .try_send(wire::privmsg(
-                                        "NickServ",
-                                        &format!("identify {}", pwd),
-                                    ))
+                                    .send(wire::privmsg("NickServ", &format!("identify {}", pwd)))
                                     .unwrap();
                             }
                         }
 
                         // Rename the nick in channel states, also populate the chan list
+                        let old_nick = self.nick(old_nick);
+                        let new_nick = self.nick(new_nick);
                         for chan in &mut self.chans {
-                            if chan.nicks.remove(old_nick) {
-                                chan.nicks.insert(new_nick.to_owned());
+                            if let Some(modes) = chan.nicks.remove(&old_nick) {
+                                chan.nicks.insert(new_nick.clone(), modes);
                                 chans.push(chan.name.to_owned());
                             }
                         }
@@ -581,15 +890,98 @@ impl StateInner {
                 }
             }
 
+            // PRIVMSG and NOTICE: Reply to CTCP requests. The parser only knows about '#'
+            // channels, fix the targets using CHANTYPES.
+            PRIVMSG {
+                targets,
+                msg,
+                is_notice,
+                ctcp,
+            } => {
+                match (*is_notice, ctcp.as_ref(), pfx) {
+                    // CTCP requests, except our own messages echoed back by the server
+                    (false, Some(ctcp), Some(Pfx::User { nick, .. } | Pfx::Ambiguous(nick)))
+                        if !self.is_current_nick(nick) =>
+                    {
+                        if *ctcp == wire::CTCP::Dcc {
+                            // DCC offers are replied with DCC messages, not NOTICEs
+                            if let Some(args) =
+                                self.dcc.handle_msg(nick, msg, self.features.casemapping)
+                            {
+                                snd_irc_msg
+                                    .send(wire::ctcp(nick, ctcp, Some(&args)))
+                                    .unwrap();
+                            }
+                        } else if let Some(args) =
+                            ctcp::reply_args(ctcp, msg, &self.server_info.ctcp_replies)
+                        {
+                            let args = Some(args.as_str()).filter(|args| !args.is_empty());
+                            snd_irc_msg
+                                .send(wire::ctcp_reply(nick, ctcp, args))
+                                .unwrap();
+                        }
+                    }
+                    _ => {}
+                }
+
+                for target in targets.iter_mut() {
+                    let fixed_target = match target {
+                        wire::MsgTarget::User(name) if self.features.is_chan_name(name) => {
+                            Some(wire::MsgTarget::Chan(ChanName::new(std::mem::take(name))))
+                        }
+                        wire::MsgTarget::Chan(name)
+                            if !self.features.is_chan_name(name.display()) =>
+                        {
+                            Some(wire::MsgTarget::User(name.display().to_owned()))
+                        }
+                        _ => None,
+                    };
+                    if let Some(fixed_target) = fixed_target {
+                        *target = fixed_target;
+                    }
+                }
+            }
+
+            // RPL_ISUPPORT: Update server features
+            Reply { num: 005, params } if params.len() > 2 => {
+                let old_casemapping = self.features.casemapping;
+                self.features.update(&params[1..params.len() - 1]);
+                let casemapping = self.features.casemapping;
+                if casemapping != old_casemapping {
+                    for chan in &mut self.chans {
+                        chan.nicks = chan
+                            .nicks
+                            .drain()
+                            .map(|(nick, modes)| (nick.with_casemapping(casemapping), modes))
+                            .collect();
+                    }
+                    self.hosts = self
+                        .hosts
+                        .drain()
+                        .map(|(nick, host)| (nick.with_casemapping(casemapping), host))
+                        .collect();
+                    self.chan_keys = self
+                        .chan_keys
+                        .drain()
+                        .map(|(_, (chan, key))| {
+                            (casemapping.normalize(chan.display()), (chan, key))
+                        })
+                        .collect();
+                }
+            }
+
             // RPL_ENDOFMOTD: Join channels, set away status
             Reply { num: 376, .. } => {
                 if !self.chans.is_empty() {
-                    let chans = self.chans.iter().map(|c| c.name.as_ref());
-                    snd_irc_msg.try_send(wire::join(chans)).unwrap();
+                    let chans = self
+                        .chans
+                        .iter()
+                        .map(|c| (c.name.as_ref(), self.chan_key(&c.name)));
+                    snd_irc_msg.send(wire::join_with_keys(chans)).unwrap();
                 }
                 if self.away_status.is_some() {
                     snd_irc_msg
-                        .try_send(wire::away(self.away_status.as_deref()))
+                        .send(wire::away(self.away_status.as_deref()))
                         .unwrap();
                 }
             }
@@ -597,68 +989,205 @@ impl StateInner {
             // RPL_NAMREPLY: Set users in a channel
             Reply { num: 353, params } => {
                 let chan = ChanNameRef::new(&params[2]);
-                match utils::find_idx(&self.chans, |c| &c.name == chan) {
-                    None => self.chans.push(Chan::with_nicks(
-                        chan.to_owned(),
-                        params[3]
-                            .split_whitespace()
-                            .map(|s| wire::drop_nick_prefix(s).to_owned())
-                            .collect(),
-                    )),
+                let nicks = params[3].split_whitespace().map(|nick| {
+                    let (modes, nick) = modes::parse_names_nick(nick, &self.features);
+                    (self.nick(nick), modes)
+                });
+                match self.find_chan_idx(chan) {
+                    None => {
+                        let chan = Chan::with_nicks(chan.to_owned(), nicks.collect());
+                        self.chans.push(chan);
+                    }
                     Some(idx) => {
-                        let nick_set = &mut self.chans[idx].nicks;
-                        for nick in params[3].split_whitespace() {
-                            nick_set.insert(wire::drop_nick_prefix(nick).to_owned());
-                        }
+                        let nicks: Vec<(Nick, Vec<char>)> = nicks.collect();
+                        self.chans[idx].nicks.extend(nicks);
                     }
                 }
             }
 
-            // SASL authentication
+            // MODE: Update channel modes and membership modes
+            // Changes are split again using the modes advertised by the server, for users of
+            // the message. User modes are not tracked.
+            MODE {
+                target,
+                modes,
+                params,
+                changes,
+            } if self.features.is_chan_name(target) => {
+                *changes = modes::parse_mode_changes(modes, params, &self.features);
+                let set_by = match pfx {
+                    Some(Pfx::User { nick, .. }) | Some(Pfx::Ambiguous(nick)) => Some(nick),
+                    Some(Pfx::Server(_)) | None => None,
+                };
+                let chan = ChanNameRef::new(target);
+                self.apply_chan_modes(chan, changes, set_by.map(String::as_str));
+            }
+
+            // RPL_CHANNELMODEIS: Channel modes, sent in response to `MODE <chan>`
+            Reply { num: 324, params } if params.len() >= 3 => {
+                let chan = ChanNameRef::new(&params[1]);
+                if let Some(idx) = self.find_chan_idx(chan) {
+                    self.chans[idx].info.modes.clear();
+                }
+                let changes = modes::parse_mode_changes(&params[2], &params[3..], &self.features);
+                self.apply_chan_modes(chan, &changes, None);
+            }
+
+            // RPL_CREATIONTIME
+            Reply { num: 329, params } if params.len() >= 3 => {
+                if let Some(idx) = self.find_chan_idx(ChanNameRef::new(&params[1])) {
+                    self.chans[idx].info.created = params[2].parse().ok();
+                }
+            }
+
+            // RPL_INVITELIST, RPL_EXCEPTLIST, RPL_BANLIST
+            Reply {
+                num: num @ (346 | 348 | 367),
+                params,
+            } if params.len() >= 3 => {
+                let mode = list_reply_mode(*num);
+                if let Some(idx) = self.find_chan_idx(ChanNameRef::new(&params[1])) {
+                    let chan = &mut self.chans[idx];
+                    let list = chan.info.lists.entry(mode).or_default();
+                    if chan.receiving_lists.insert(mode) {
+                        // First entry in the reply, drop the old list
+                        list.clear();
+                    }
+                    list.push(ListEntry {
+                        mask: params[2].clone(),
+                        set_by: params.get(3).cloned(),
+                        set_at: params.get(4).and_then(|ts| ts.parse().ok()),
+                    });
+                }
+            }
+
+            // RPL_ENDOFINVITELIST, RPL_ENDOFEXCEPTLIST, RPL_ENDOFBANLIST
+            Reply {
+                num: num @ (347 | 349 | 368),
+                params,
+            } if params.len() >= 2 => {
+                let mode = list_reply_mode(*num - 1);
+                if let Some(idx) = self.find_chan_idx(ChanNameRef::new(&params[1])) {
+                    let chan = &mut self.chans[idx];
+                    if !chan.receiving_lists.remove(&mode) {
+                        // Empty list
+                        chan.info.lists.remove(&mode);
+                    }
+                }
+            }
+
+            // Capability negotiation and SASL authentication
             CAP {
                 client: _,
                 subcommand,
                 params,
+                more,
             } => {
                 match subcommand.as_ref() {
-                    "ACK" => {
-                        if params.iter().any(|cap| cap.as_str() == "sasl") {
-                            snd_irc_msg.try_send(wire::authenticate("PLAIN")).unwrap();
+                    "LS" => {
+                        self.caps.add_available(params);
+                        if *more {
+                            // Wait for the rest of the list
+                            return;
+                        }
+                        let sasl = self.server_info.sasl_auth.is_some();
+                        if sasl {
+                            // We delay introduction until the LS reply when using SASL. See
+                            // `main_loop`.
+                            self.introduce(snd_irc_msg);
+                            if !self.caps.is_available("sasl") {
+                                snd_ev.try_send(Event::SASLNotSupported).unwrap();
+                            }
                         }
+                        // Will wait for CAP ... ACK from server before authentication.
+                        self.request_caps(sasl, snd_irc_msg);
                     }
-                    "NAK" => {
-                        snd_irc_msg.try_send(wire::cap_end()).unwrap();
+                    "NEW" => {
+                        self.caps.add_available(params);
+                        // SASL authentication is only done during registration
+                        self.request_caps(false, snd_irc_msg);
                     }
-                    "LS" => {
-                        self.introduce(snd_irc_msg);
-                        if params.iter().any(|cap| cap == "sasl") {
-                            snd_irc_msg.try_send(wire::cap_req(&["sasl"])).unwrap();
-                            // Will wait for CAP ... ACK from server before authentication.
+                    "DEL" => {
+                        self.caps.remove(params);
+                    }
+                    "ACK" => {
+                        self.caps.ack(params);
+                        if !self.caps.is_ended() && params.iter().any(|cap| cap == "sasl") {
+                            if let Some(ref auth) = self.server_info.sasl_auth {
+                                let mechanism =
+                                    sasl::Mechanism::select(auth, self.caps.value("sasl"));
+                                self.sasl = Some(sasl::Session::new(auth, mechanism));
+                                snd_irc_msg
+                                    .send(wire::authenticate(mechanism.name()))
+                                    .unwrap();
+                            }
                         }
+                        self.end_cap_negotiation(snd_irc_msg);
+                    }
+                    "NAK" => {
+                        self.caps.nak();
+                        self.end_cap_negotiation(snd_irc_msg);
                     }
                     _ => {}
                 }
             }
 
             AUTHENTICATE { ref param } => {
-                if param.as_str() == "+" {
-                    // Empty AUTHENTICATE response; server accepted the specified SASL mechanism
-                    // (PLAIN)
-                    if let Some(ref auth) = self.server_info.sasl_auth {
-                        let msg = format!(
-                            "{}\x00{}\x00{}",
-                            auth.username, auth.username, auth.password
-                        );
-                        snd_irc_msg
-                            .try_send(wire::authenticate(&base64::encode(&msg)))
-                            .unwrap();
+                if let Some(ref mut session) = self.sasl {
+                    match session.step(param) {
+                        Ok(msgs) => {
+                            for msg in msgs {
+                                snd_irc_msg.send(msg).unwrap();
+                            }
+                        }
+                        Err(msg) => {
+                            // Server will reply with 906 ERR_SASLABORTED
+                            snd_irc_msg.send(wire::authenticate("*")).unwrap();
+                            snd_ev
+                                .try_send(Event::SASLFailed {
+                                    reason: SASLFailure::Protocol,
+                                    msg,
+                                })
+                                .unwrap();
+                        }
                     }
                 }
             }
 
-            Reply { num: 903, .. } | Reply { num: 904, .. } => {
-                // 903: RPL_SASLSUCCESS, 904: ERR_SASLFAIL
-                snd_irc_msg.try_send(wire::cap_end()).unwrap();
+            Reply { num: 903, .. } => {
+                // RPL_SASLSUCCESS
+                self.sasl = None;
+                self.end_cap_negotiation(snd_irc_msg);
+            }
+
+            Reply {
+                num: num @ (902 | 904 | 905 | 906 | 907),
+                params,
+            } => {
+                let num = *num;
+                let reason = match num {
+                    902 => SASLFailure::NickLocked,
+                    904 => SASLFailure::Failed,
+                    905 => SASLFailure::TooLong,
+                    906 => SASLFailure::Aborted,
+                    _ => SASLFailure::AlreadyAuthenticated,
+                };
+                // Don't report the 906 caused by aborting after a protocol error, which is
+                // already reported
+                let aborted_by_us = self
+                    .sasl
+                    .take()
+                    .map(|session| session.is_aborted())
+                    .unwrap_or(false);
+                if !(num == 906 && aborted_by_us) {
+                    snd_ev
+                        .try_send(Event::SASLFailed {
+                            reason,
+                            msg: params.last().cloned().unwrap_or_default(),
+                        })
+                        .unwrap();
+                }
+                self.end_cap_negotiation(snd_irc_msg);
             }
 
             // Ignore the rest
@@ -666,8 +1195,262 @@ impl StateInner {
         }
     }
 
+    /// Handle a JOIN of `nick` to `chan`.
+    fn join(&mut self, nick: &str, chan: &ChanNameRef, snd_irc_msg: &mut UnboundedSender<String>) {
+        if self.is_current_nick(nick) {
+            // We joined a channel, initialize channel state
+            match self.find_chan_idx(chan) {
+                None => {
+                    let mut chan = Chan::new(chan.to_owned());
+                    // Since nick was found in the prefix, we are in the channel
+                    chan.join_state = JoinState::Joined;
+                    self.chans.push(chan);
+                }
+                Some(chan_idx) => {
+                    // This happens because we initialize channel states for channels that we will
+                    // join on connection when the client is first created
+                    let chan = &mut self.chans[chan_idx];
+                    chan.join_state = JoinState::Joined;
+                    chan.nicks.clear();
+                    chan.info = ChanInfo::default();
+                }
+            }
+            // Get channel modes
+            snd_irc_msg.send(wire::mode(chan.display(), &[])).unwrap();
+        } else {
+            match self.find_chan_idx(chan) {
+                Some(chan_idx) => {
+                    let nick = self.nick(self.features.drop_nick_prefix(nick));
+                    self.chans[chan_idx].nicks.insert(nick, vec![]);
+                }
+                None => {
+                    debug!("Can't find channel state for JOIN: {}", chan.display());
+                }
+            }
+        }
+    }
+
+    /// Handle a PART of `nick` from `chan`.
+    fn part(&mut self, nick: &str, chan: &ChanNameRef) {
+        match self.find_chan_idx(chan) {
+            None => {
+                debug!("Can't find channel state for PART: {}", chan.display());
+            }
+            Some(chan_idx) => {
+                if self.is_current_nick(nick) {
+                    self.remove_chan(chan_idx);
+                } else {
+                    let nick = self.nick(self.features.drop_nick_prefix(nick));
+                    self.chans[chan_idx].nicks.remove(&nick);
+                    self.prune_host(&nick);
+                }
+            }
+        }
+    }
+
+    /// Drop the channel state after being kicked, or schedule a rejoin when
+    /// `ServerInfo::rejoin_on_kick` is set.
+    fn kicked(
+        &mut self,
+        chan: &ChanNameRef,
+        snd_ev: &mut Sender<Event>,
+        snd_irc_msg: &mut UnboundedSender<String>,
+    ) {
+        let chan_idx = match self.find_chan_idx(chan) {
+            None => {
+                return;
+            }
+            Some(chan_idx) => chan_idx,
+        };
+        match self.server_info.rejoin_on_kick {
+            None => {
+                self.remove_chan(chan_idx);
+            }
+            Some(delay) => {
+                let key = self.chan_key(chan).map(str::to_owned);
+                let chan = &mut self.chans[chan_idx];
+                let nicks: Vec<Nick> = chan.nicks.keys().cloned().collect();
+                chan.reset();
+                let (snd_abort, rcv_abort) = tokio::sync::mpsc::channel(1);
+                chan.set_joining(snd_abort);
+                snd_ev
+                    .try_send(Event::ChannelJoinError {
+                        chan: chan.name.clone(),
+                        msg: format!(
+                            "Attempting to rejoin {} in {} seconds...",
+                            chan.name.display(),
+                            delay.as_secs()
+                        ),
+                    })
+                    .unwrap();
+                tokio::task::spawn_local(retry_channel_join(
+                    chan.name.clone(),
+                    key,
+                    delay,
+                    snd_irc_msg.clone(),
+                    rcv_abort,
+                ));
+                for nick in &nicks {
+                    self.prune_host(nick);
+                }
+            }
+        }
+    }
+
+    /// Remove a channel state, and hosts of users we no longer share a channel with.
+    fn remove_chan(&mut self, chan_idx: usize) {
+        let chan = self.chans.remove(chan_idx);
+        for nick in chan.nicks.keys() {
+            self.prune_host(nick);
+        }
+    }
+
+    /// Forget the host of a user if we no longer share a channel with them.
+    fn prune_host(&mut self, nick: &Nick) {
+        if !self.chans.iter().any(|chan| chan.nicks.contains_key(nick)) {
+            self.hosts.remove(nick);
+        }
+    }
+
+    /// Apply mode changes to a channel. `set_by` is the nick that changed the modes, used for
+    /// list modes.
+    fn apply_chan_modes(
+        &mut self,
+        chan: &ChanNameRef,
+        changes: &[wire::ModeChange],
+        set_by: Option<&str>,
+    ) {
+        let idx = match self.find_chan_idx(chan) {
+            None => {
+                return;
+            }
+            Some(idx) => idx,
+        };
+        let casemapping = self.features.casemapping;
+        let chan = &mut self.chans[idx];
+        for change in changes {
+            let param = change.param.clone();
+            match modes::mode_kind(change.mode, &self.features) {
+                ModeKind::Membership => {
+                    let nick = Nick::new(param.unwrap(), casemapping);
+                    let mode = change.mode;
+                    if let Some(nick_modes) = chan.nicks.get_mut(&nick) {
+                        nick_modes.retain(|m| *m != mode);
+                        if change.set {
+                            nick_modes.push(mode);
+                            modes::sort_membership_modes(nick_modes, &self.features);
+                        }
+                    }
+                }
+                ModeKind::List => {
+                    let mask = param.unwrap();
+                    let list = chan.info.lists.entry(change.mode).or_default();
+                    list.retain(|entry| entry.mask != mask);
+                    if change.set {
+                        list.push(ListEntry {
+                            mask,
+                            set_by: set_by.map(str::to_owned),
+                            set_at: None,
+                        });
+                    }
+                }
+                ModeKind::Setting => {
+                    if change.set {
+                        chan.info.modes.insert(change.mode, param);
+                    } else {
+                        chan.info.modes.remove(&change.mode);
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_chan_members(&self, chan: &ChanNameRef) -> Vec<ChanMember> {
+        match self.find_chan_idx(chan) {
+            None => vec![],
+            Some(idx) => {
+                let mut members: Vec<ChanMember> = self.chans[idx]
+                    .nicks
+                    .iter()
+                    .map(|(nick, modes)| modes::make_member(nick.display(), modes, &self.features))
+                    .collect();
+                let casemapping = self.features.casemapping;
+                members.sort_by_cached_key(|member| {
+                    (
+                        member
+                            .modes
+                            .first()
+                            .and_then(|mode| {
+                                self.features.prefix.iter().position(|(m, _)| m == mode)
+                            })
+                            .unwrap_or(usize::MAX),
+                        casemapping.normalize(&member.nick),
+                    )
+                });
+                members
+            }
+        }
+    }
+
+    fn get_chan_member(&self, chan: &ChanNameRef, nick: &str) -> Option<ChanMember> {
+        let idx = self.find_chan_idx(chan)?;
+        let (nick, modes) = self.chans[idx].nicks.get_key_value(&self.nick(nick))?;
+        Some(modes::make_member(nick.display(), modes, &self.features))
+    }
+
+    fn get_chan_info(&self, chan: &ChanNameRef) -> Option<ChanInfo> {
+        let idx = self.find_chan_idx(chan)?;
+        Some(self.chans[idx].info.clone())
+    }
+
+    fn set_chan_key(&mut self, chan: &ChanNameRef, key: String) {
+        let normalized = self.features.casemapping.normalize(chan.display());
+        self.chan_keys.insert(normalized, (chan.to_owned(), key));
+    }
+
+    fn chan_key(&self, chan: &ChanNameRef) -> Option<&str> {
+        let normalized = self.features.casemapping.normalize(chan.display());
+        self.chan_keys.get(&normalized).map(|(_, key)| key.as_str())
+    }
+
+    fn find_chan_idx(&self, chan: &ChanNameRef) -> Option<usize> {
+        let casemapping = self.features.casemapping;
+        utils::find_idx(&self.chans, |c| {
+            casemapping.eq_ignore_case(c.name.display(), chan.display())
+        })
+    }
+
+    fn is_current_nick(&self, nick: &str) -> bool {
+        self.features
+            .casemapping
+            .eq_ignore_case(&self.current_nick, nick)
+    }
+
+    /// Make a `Nick` with the server's casemapping.
+    fn nick(&self, nick: &str) -> Nick {
+        Nick::new(nick.to_owned(), self.features.casemapping)
+    }
+
+    /// Request capabilities in `ServerInfo::caps` that are advertised by the server and not
+    /// enabled yet. `sasl` is requested when `with_sasl` is set.
+    fn request_caps(&mut self, with_sasl: bool, snd_irc_msg: &mut UnboundedSender<String>) {
+        let sasl = if with_sasl { Some("sasl") } else { None };
+        let wanted = self.server_info.caps.iter().map(String::as_str).chain(sasl);
+        for msg in self.caps.request(wanted) {
+            snd_irc_msg.send(msg).unwrap();
+        }
+        self.end_cap_negotiation(snd_irc_msg);
+    }
+
+    /// Send `CAP END` if all capability requests are answered and SASL authentication is done.
+    fn end_cap_negotiation(&mut self, snd_irc_msg: &mut UnboundedSender<String>) {
+        if let Some(msg) = self.caps.end(self.sasl.is_some()) {
+            snd_irc_msg.send(msg).unwrap();
+        }
+    }
+
     fn get_chan_nicks(&self, chan: &ChanNameRef) -> Vec<String> {
-        match utils::find_idx(&self.chans, |c| c.name == *chan) {
+        match self.find_chan_idx(chan) {
             None => {
                 error!("Could not find channel index in get_chan_nicks.");
                 vec![]
@@ -675,8 +1458,8 @@ impl StateInner {
             Some(chan_idx) => {
                 let mut nicks = self.chans[chan_idx]
                     .nicks
-                    .iter()
-                    .cloned()
+                    .keys()
+                    .map(|nick| nick.display().to_owned())
                     .collect::<Vec<String>>();
                 nicks.sort_unstable_by(|a, b| {
                     a.to_lowercase().partial_cmp(&b.to_lowercase()).unwrap()
@@ -687,15 +1470,17 @@ impl StateInner {
     }
 
     /// If channel is in Joining state cancel Joining task, otherwise sent part message
-    fn leave_channel(&mut self, msg_chan: &mut Sender<Cmd>, chan: &ChanNameRef) {
-        if let Some(idx) = utils::find_idx(&self.chans, |c| c.name == *chan) {
+    fn leave_channel(&mut self, msg_chan: &mut UnboundedSender<Cmd>, chan: &ChanNameRef) {
+        if let Some(idx) = self.find_chan_idx(chan) {
             match &mut self.chans[idx].join_state {
                 JoinState::NotJoined => {}
                 JoinState::Joining { stop_task, .. } => {
                     debug!("Aborting task to retry joining {}", chan.display());
                     let _ = stop_task.try_send(());
                 }
-                JoinState::Joined => msg_chan.try_send(Cmd::Msg(wire::part(chan))).unwrap(),
+                JoinState::Joined => msg_chan
+                    .send(Cmd::Msg(wire::part(std::iter::once(chan))))
+                    .unwrap(),
             }
         }
     }
@@ -712,18 +1497,23 @@ impl StateInner {
 
 async fn retry_channel_join(
     channel: ChanName,
-    snd_irc_msg: Sender<String>,
+    key: Option<String>,
+    delay: Duration,
+    snd_irc_msg: UnboundedSender<String>,
     rcv_abort: Receiver<()>,
 ) {
     debug!("Attempting to re-join channel {}", channel.display());
 
     let mut rcv_abort = ReceiverStream::new(rcv_abort).fuse();
 
-    match timeout(Duration::from_secs(10), rcv_abort.next()).await {
+    match timeout(delay, rcv_abort.next()).await {
         Err(_) => {
             // Send join message
             snd_irc_msg
-                .try_send(wire::join(std::iter::once(channel.as_ref())))
+                .send(wire::join_with_keys(std::iter::once((
+                    channel.as_ref(),
+                    key.as_deref(),
+                ))))
                 .unwrap();
         }
         Ok(_) => {
@@ -732,22 +1522,34 @@ async fn retry_channel_join(
     }
 }
 
+/// List modes that have RPL_INVITELIST, RPL_EXCEPTLIST, and RPL_BANLIST replies.
+const LIST_REPLY_MODES: [char; 3] = ['I', 'e', 'b'];
+
+/// List mode of a RPL_INVITELIST, RPL_EXCEPTLIST, or RPL_BANLIST reply.
+fn list_reply_mode(num: u16) -> char {
+    match num {
+        346 => 'I',
+        348 => 'e',
+        _ => 'b',
+    }
+}
+
 const SERVERNAME_PREFIX: &str = "Your host is ";
 const SERVERNAME_PREFIX_LEN: usize = SERVERNAME_PREFIX.len();
 
 /// Parse server name from RPL_YOURHOST reply or fallback to using the server name inside
 /// Pfx::Server. See https://www.irc.com/dev/docs/refs/numerics/002.html for more info.
 fn parse_servername(pfx: Option<&Pfx>, params: &[String]) -> Option<String> {
-    parse_yourhost_msg(&params).or_else(|| parse_server_pfx(pfx))
+    parse_yourhost_msg(params).or_else(|| parse_server_pfx(pfx))
 }
 
 /// Try to parse servername in a 002 RPL_YOURHOST reply params.
 fn parse_yourhost_msg(params: &[String]) -> Option<String> {
-    let msg = params.get(1).or_else(|| params.get(0))?;
+    let msg = params.get(1).or_else(|| params.first())?;
     if msg.len() >= SERVERNAME_PREFIX_LEN && &msg[..SERVERNAME_PREFIX_LEN] == SERVERNAME_PREFIX {
         let slice1 = &msg[SERVERNAME_PREFIX_LEN..];
         let servername_ends = slice1.find('[').or_else(|| slice1.find(','))?;
-        Some((&slice1[..servername_ends]).to_owned())
+        Some(slice1[..servername_ends].to_owned())
     } else {
         None
     }
@@ -763,6 +1565,209 @@ fn parse_server_pfx(pfx: Option<&Pfx>) -> Option<String> {
     }
 }
 
+/// Server features advertised with RPL_ISUPPORT (005). Until the server sends 005 (and for
+/// parameters it doesn't advertise) the fields have the defaults suggested in
+/// https://modern.ircdocs.horse/#rplisupport-parameters. Reset on reconnect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerFeatures {
+    /// CHANTYPES: Characters that channel names start with.
+    pub chantypes: Vec<char>,
+
+    /// PREFIX: Channel membership modes and the nick prefixes for them, from highest to lowest
+    /// rank. E.g. `[('o', '@'), ('v', '+')]`.
+    pub prefix: Vec<(char, char)>,
+
+    /// CASEMAPPING: How nicks and channel names are compared.
+    pub casemapping: CaseMapping,
+
+    /// NICKLEN: Max length of a nick.
+    pub nicklen: Option<usize>,
+
+    /// TARGMAX: Max number of targets for commands that take multiple targets. `None` values
+    /// mean there's no limit. Commands not in the map don't take multiple targets.
+    pub targmax: HashMap<String, Option<usize>>,
+
+    /// CHANMODES: Channel modes by the type of their parameters.
+    pub chanmodes: ChanModes,
+
+    /// MODES: Max number of mode changes with a parameter in a MODE message. `None` means
+    /// there's no limit.
+    pub modes: Option<usize>,
+
+    /// NETWORK: Name of the IRC network.
+    pub network: Option<String>,
+
+    /// LINELEN: Max length of an IRC message, including the trailing "\r\n".
+    pub linelen: usize,
+
+    /// All advertised parameters, including the ones above, with their (unescaped) values.
+    pub params: HashMap<String, Option<String>>,
+}
+
+/// See `ServerFeatures::chanmodes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChanModes {
+    /// Modes that add or remove an address to or from a list (e.g. `b`). Always take a parameter.
+    pub a: Vec<char>,
+    /// Modes that change a setting and always take a parameter (e.g. `k`).
+    pub b: Vec<char>,
+    /// Modes that change a setting and take a parameter only when set (e.g. `l`).
+    pub c: Vec<char>,
+    /// Modes that change a setting and never take a parameter (e.g. `m`).
+    pub d: Vec<char>,
+}
+
+impl Default for ServerFeatures {
+    fn default() -> Self {
+        ServerFeatures {
+            chantypes: vec!['#', '&'],
+            prefix: vec![('o', '@'), ('v', '+')],
+            casemapping: CaseMapping::Rfc1459,
+            nicklen: None,
+            targmax: HashMap::new(),
+            chanmodes: ChanModes {
+                a: vec!['b', 'e', 'I'],
+                b: vec!['k'],
+                c: vec!['l'],
+                d: vec!['i', 'm', 'n', 'p', 's', 't'],
+            },
+            modes: Some(3),
+            network: None,
+            linelen: 512,
+            params: HashMap::new(),
+        }
+    }
+}
+
+impl ServerFeatures {
+    /// Does the name start with one of the channel prefixes in CHANTYPES?
+    pub fn is_chan_name(&self, name: &str) -> bool {
+        match name.chars().next() {
+            None => false,
+            Some(c) => self.chantypes.contains(&c),
+        }
+    }
+
+    /// Drop membership prefixes (e.g. `@`, `+`) of a nick in a RPL_NAMREPLY.
+    pub fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        nick.trim_start_matches(|c| self.prefix.iter().any(|(_, p)| *p == c))
+    }
+
+    /// Update features with parameters of a RPL_ISUPPORT, without the first (our nick) and the
+    /// last ("are supported by this server") parameters.
+    pub(crate) fn update(&mut self, params: &[String]) {
+        let default = ServerFeatures::default();
+        for param in params {
+            if let Some(name) = param.strip_prefix('-') {
+                // Parameter negated, reset to default
+                self.params.remove(name);
+                match name {
+                    "CHANTYPES" => self.chantypes = default.chantypes.clone(),
+                    "PREFIX" => self.prefix = default.prefix.clone(),
+                    "CASEMAPPING" => self.casemapping = default.casemapping,
+                    "NICKLEN" => self.nicklen = default.nicklen,
+                    "TARGMAX" => self.targmax.clear(),
+                    "CHANMODES" => self.chanmodes = default.chanmodes.clone(),
+                    "MODES" => self.modes = default.modes,
+                    "NETWORK" => self.network = None,
+                    "LINELEN" => self.linelen = default.linelen,
+                    _ => {}
+                }
+                continue;
+            }
+
+            let (name, value) = match param.find('=') {
+                None => (param.as_str(), None),
+                Some(eq_idx) => (
+                    &param[..eq_idx],
+                    Some(unescape_isupport(&param[eq_idx + 1..])),
+                ),
+            };
+            let value_str = value.as_deref().unwrap_or("");
+
+            match name {
+                "CHANTYPES" => self.chantypes = value_str.chars().collect(),
+                "PREFIX" => {
+                    if let Some(prefix) = parse_prefix(value_str) {
+                        self.prefix = prefix;
+                    }
+                }
+                "CASEMAPPING" => self.casemapping = CaseMapping::from_isupport(value_str),
+                "NICKLEN" => self.nicklen = value_str.parse().ok(),
+                "TARGMAX" => {
+                    self.targmax = value_str
+                        .split(',')
+                        .filter_map(|target| {
+                            let colon_idx = target.find(':')?;
+                            let limit = target[colon_idx + 1..].parse().ok();
+                            Some((target[..colon_idx].to_ascii_uppercase(), limit))
+                        })
+                        .collect();
+                }
+                "CHANMODES" => {
+                    let mut groups = value_str.split(',').map(|group| group.chars().collect());
+                    self.chanmodes = ChanModes {
+                        a: groups.next().unwrap_or_default(),
+                        b: groups.next().unwrap_or_default(),
+                        c: groups.next().unwrap_or_default(),
+                        d: groups.next().unwrap_or_default(),
+                    };
+                }
+                "MODES" => {
+                    if value_str.is_empty() {
+                        self.modes = None;
+                    } else if let Ok(modes) = value_str.parse() {
+                        self.modes = Some(modes);
+                    }
+                }
+                "NETWORK" => self.network = value.clone(),
+                "LINELEN" => {
+                    if let Ok(linelen) = value_str.parse() {
+                        self.linelen = linelen;
+                    }
+                }
+                _ => {}
+            }
+
+            self.params.insert(name.to_owned(), value);
+        }
+    }
+}
+
+/// Parse a PREFIX value like `(ov)@+`. An empty value means no membership prefixes.
+fn parse_prefix(value: &str) -> Option<Vec<(char, char)>> {
+    if value.is_empty() {
+        return Some(vec![]);
+    }
+    let value = value.strip_prefix('(')?;
+    let close_idx = value.find(')')?;
+    let modes = value[..close_idx].chars();
+    let prefixes = value[close_idx + 1..].chars();
+    if modes.clone().count() != prefixes.clone().count() {
+        return None;
+    }
+    Some(modes.zip(prefixes).collect())
+}
+
+/// Unescape `\xHH` sequences in a RPL_ISUPPORT value.
+fn unescape_isupport(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while !rest.is_empty() {
+        if rest.len() >= 4 && rest[0] == b'\\' && rest[1] == b'x' {
+            let hex = std::str::from_utf8(&rest[2..4]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                bytes.push(byte);
+                rest = &rest[4..];
+                continue;
+            }
+        }
+        bytes.push(rest[0]);
+        rest = &rest[1..];
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -815,4 +1820,99 @@ mod tests {
             Some("irc.gitter.im".to_owned())
         );
     }
+
+    #[test]
+    fn test_server_features() {
+        let params: Vec<String> = [
+            "CHANTYPES=#",
+            "PREFIX=(qaohv)~&@%+",
+            "CASEMAPPING=ascii",
+            "NICKLEN=30",
+            "TARGMAX=NAMES:1,PRIVMSG:4,JOIN:",
+            "CHANMODES=beI,k,l,imnpst",
+            "NETWORK=Example\\x20Net",
+            "LINELEN=1024",
+            "MODES=4",
+            "EXCEPTS",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        let mut features = ServerFeatures::default();
+        assert!(features.is_chan_name("&local"));
+        features.update(&params);
+
+        assert_eq!(features.chantypes, vec!['#']);
+        assert!(features.is_chan_name("#tiny"));
+        assert!(!features.is_chan_name("&local"));
+        assert!(!features.is_chan_name(""));
+        assert_eq!(
+            features.prefix,
+            vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')]
+        );
+        assert_eq!(features.drop_nick_prefix("~@nick"), "nick");
+        assert_eq!(features.casemapping, CaseMapping::Ascii);
+        assert_eq!(features.nicklen, Some(30));
+        assert_eq!(features.targmax.get("PRIVMSG"), Some(&Some(4)));
+        assert_eq!(features.targmax.get("JOIN"), Some(&None));
+        assert_eq!(features.targmax.get("PART"), None);
+        assert_eq!(features.chanmodes.a, vec!['b', 'e', 'I']);
+        assert_eq!(features.chanmodes.d, vec!['i', 'm', 'n', 'p', 's', 't']);
+        assert_eq!(features.network.as_deref(), Some("Example Net"));
+        assert_eq!(features.linelen, 1024);
+        assert_eq!(features.modes, Some(4));
+        assert_eq!(features.params.get("EXCEPTS"), Some(&None));
+
+        // Negated parameters are reset to defaults
+        features.update(&["-CHANTYPES".to_owned(), "-EXCEPTS".to_owned()]);
+        assert_eq!(features.chantypes, vec!['#', '&']);
+        assert_eq!(features.params.get("EXCEPTS"), None);
+
+        // MODES without a value means no limit
+        features.update(&["MODES".to_owned()]);
+        assert_eq!(features.modes, None);
+        features.update(&["-MODES".to_owned()]);
+        assert_eq!(features.modes, Some(3));
+
+        // Invalid PREFIX is ignored
+        features.update(&["PREFIX=(ov)@".to_owned()]);
+        assert_eq!(features.prefix.len(), 5);
+    }
+
+    #[test]
+    fn test_nick_casemapping() {
+        let nick = |name: &str, casemapping| Nick::new(name.to_owned(), casemapping);
+
+        let mut nicks = HashSet::new();
+        nicks.insert(nick("Foo[m]", CaseMapping::Rfc1459));
+        assert!(nicks.contains(&nick("foo{M}", CaseMapping::Rfc1459)));
+        assert!(nicks.contains(&nick("FOO[M]", CaseMapping::Rfc1459)));
+
+        assert_eq!(
+            nick("a~", CaseMapping::Rfc1459),
+            nick("A^", CaseMapping::Rfc1459)
+        );
+        assert_ne!(
+            nick("a~", CaseMapping::Rfc1459Strict),
+            nick("A^", CaseMapping::Rfc1459Strict)
+        );
+        assert_eq!(
+            nick("a[", CaseMapping::Rfc1459Strict),
+            nick("A{", CaseMapping::Rfc1459Strict)
+        );
+        assert_ne!(
+            nick("a[", CaseMapping::Ascii),
+            nick("A{", CaseMapping::Ascii)
+        );
+        assert_eq!(
+            nick("Foo", CaseMapping::Ascii),
+            nick("fOO", CaseMapping::Ascii)
+        );
+
+        let mut features = ServerFeatures::default();
+        features.update(&["CASEMAPPING=ascii".to_owned()]);
+        assert_eq!(features.casemapping, CaseMapping::Ascii);
+        assert!(!features.casemapping.eq_ignore_case("#a[", "#a{"));
+    }
 }
@@ -1,3 +1,6 @@
+use crate::proxy;
+use crate::{Proxy, TlsClientCert};
+
 use lazy_static::lazy_static;
 use std::{
     net::SocketAddr,
@@ -22,11 +25,58 @@ lazy_static! {
 
 #[cfg(feature = "tls-rustls")]
 lazy_static! {
-    static ref TLS_CONNECTOR: tokio_rustls::TlsConnector = {
-        let mut config = tokio_rustls::rustls::ClientConfig::default();
-        config.root_store = rustls_native_certs::load_native_certs().unwrap();
-        tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
-    };
+    static ref TLS_CONNECTOR: tokio_rustls::TlsConnector =
+        tokio_rustls::TlsConnector::from(std::sync::Arc::new(rustls_config()));
+}
+
+#[cfg(feature = "tls-rustls")]
+fn rustls_config() -> tokio_rustls::rustls::ClientConfig {
+    let mut config = tokio_rustls::rustls::ClientConfig::default();
+    config.root_store = rustls_native_certs::load_native_certs().unwrap();
+    config
+}
+
+/// Build a connector that presents the given client certificate. Unlike `TLS_CONNECTOR` this is
+/// not shared, as every server can have a different certificate.
+#[cfg(feature = "tls-native")]
+fn client_cert_connector(cert: &TlsClientCert) -> Result<tokio_native_tls::TlsConnector, TlsError> {
+    let identity = native_tls::Identity::from_pkcs8(&cert.cert_pem, &cert.key_pem)?;
+    let connector = native_tls::TlsConnector::builder()
+        .identity(identity)
+        .build()?;
+    Ok(tokio_native_tls::TlsConnector::from(connector))
+}
+
+/// Build a connector that presents the given client certificate. Unlike `TLS_CONNECTOR` this is
+/// not shared, as every server can have a different certificate.
+#[cfg(feature = "tls-rustls")]
+fn client_cert_connector(cert: &TlsClientCert) -> Result<tokio_rustls::TlsConnector, TlsError> {
+    use tokio_rustls::rustls::internal::pemfile;
+
+    let invalid = |what: &str| TlsError::General(format!("Invalid client {}", what));
+
+    let certs =
+        pemfile::certs(&mut cert.cert_pem.as_slice()).map_err(|()| invalid("certificate"))?;
+    if certs.is_empty() {
+        return Err(invalid("certificate"));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut cert.key_pem.as_slice())
+        .map_err(|()| invalid("private key"))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut cert.key_pem.as_slice())
+            .map_err(|()| invalid("private key"))?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("private key"))?;
+
+    let mut config = rustls_config();
+    config.set_single_client_cert(certs, key)?;
+    Ok(tokio_rustls::TlsConnector::from(std::sync::Arc::new(
+        config,
+    )))
 }
 
 #[derive(Debug)]
@@ -60,26 +110,75 @@ impl From<std::io::Error> for StreamError {
     }
 }
 
+/// Connect to `addr`. When `proxy` is given `addr` is the proxy's address, and the proxy is asked
+/// to connect to `host_name:port`.
+async fn connect_tcp(
+    addr: SocketAddr,
+    host_name: &str,
+    port: u16,
+    proxy: Option<&Proxy>,
+) -> Result<TcpStream, std::io::Error> {
+    let mut tcp_stream = TcpStream::connect(addr).await?;
+    if let Some(proxy) = proxy {
+        proxy::connect(&mut tcp_stream, proxy, host_name, port).await?;
+    }
+    Ok(tcp_stream)
+}
+
 impl Stream {
-    pub(crate) async fn new_tcp(addr: SocketAddr) -> Result<Stream, StreamError> {
-        Ok(Stream::TcpStream(TcpStream::connect(addr).await?.into()))
+    pub(crate) async fn new_tcp(
+        addr: SocketAddr,
+        host_name: &str,
+        port: u16,
+        proxy: Option<&Proxy>,
+    ) -> Result<Stream, StreamError> {
+        let tcp_stream = connect_tcp(addr, host_name, port, proxy).await?;
+        Ok(Stream::TcpStream(tcp_stream.into()))
     }
 
     #[cfg(feature = "tls-native")]
-    pub(crate) async fn new_tls(addr: SocketAddr, host_name: &str) -> Result<Stream, StreamError> {
-        let tcp_stream = TcpStream::connect(addr).await?;
-        let tls_stream = TLS_CONNECTOR.connect(host_name, tcp_stream).await?;
+    pub(crate) async fn new_tls(
+        addr: SocketAddr,
+        host_name: &str,
+        port: u16,
+        proxy: Option<&Proxy>,
+        client_cert: Option<&TlsClientCert>,
+    ) -> Result<Stream, StreamError> {
+        let client_cert_connector = client_cert.map(client_cert_connector).transpose()?;
+        let connector = client_cert_connector.as_ref().unwrap_or(&*TLS_CONNECTOR);
+        let tcp_stream = connect_tcp(addr, host_name, port, proxy).await?;
+        let tls_stream = connector.connect(host_name, tcp_stream).await?;
         Ok(Stream::TlsStream(tls_stream.into()))
     }
 
     #[cfg(feature = "tls-rustls")]
-    pub(crate) async fn new_tls(addr: SocketAddr, host_name: &str) -> Result<Stream, StreamError> {
-        let tcp_stream = TcpStream::connect(addr).await?;
+    pub(crate) async fn new_tls(
+        addr: SocketAddr,
+        host_name: &str,
+        port: u16,
+        proxy: Option<&Proxy>,
+        client_cert: Option<&TlsClientCert>,
+    ) -> Result<Stream, StreamError> {
+        let client_cert_connector = client_cert.map(client_cert_connector).transpose()?;
+        let connector = client_cert_connector.as_ref().unwrap_or(&*TLS_CONNECTOR);
+        let tcp_stream = connect_tcp(addr, host_name, port, proxy).await?;
         let name = tokio_rustls::webpki::DNSNameRef::try_from_ascii_str(host_name)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        let tls_stream = TLS_CONNECTOR.connect(name, tcp_stream).await?;
+            .map_err(std::io::Error::other)?;
+        let tls_stream = connector.connect(name, tcp_stream).await?;
         Ok(Stream::TlsStream(tls_stream.into()))
     }
+
+    /// Local address of the connection. When connected through a proxy this is the address of the
+    /// connection to the proxy.
+    pub(crate) fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Stream::TcpStream(tcp_stream) => tcp_stream.local_addr(),
+            #[cfg(feature = "tls-native")]
+            Stream::TlsStream(tls_stream) => tls_stream.get_ref().get_ref().get_ref().local_addr(),
+            #[cfg(feature = "tls-rustls")]
+            Stream::TlsStream(tls_stream) => tls_stream.get_ref().0.local_addr(),
+        }
+    }
 }
 
 //
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 pub(crate) struct SplitIterator<'a> {
     s: Option<&'a str>,
     max: usize,
@@ -5,7 +7,7 @@ pub(crate) struct SplitIterator<'a> {
 
 /// Iterate over subslices that are at most `max` long (in bytes). Splits are
 /// made on whitespace characters when possible.
-pub(crate) fn split_iterator(s: &str, max: usize) -> SplitIterator {
+pub(crate) fn split_iterator(s: &str, max: usize) -> SplitIterator<'_> {
     SplitIterator { s: Some(s), max }
 }
 
@@ -72,6 +74,30 @@ pub(crate) fn find_idx<A, F: Fn(&A) -> bool>(slice: &[A], f: F) -> Option<usize>
     None
 }
 
+/// Delay before reconnect attempt number `attempt` (starting from 1): `initial` doubled after
+/// every attempt, up to `max`, randomized by `jitter` (a fraction of the delay). `rand` is a
+/// random number in `[0, 1)`.
+pub(crate) fn reconnect_delay(
+    initial: Duration,
+    max: Duration,
+    jitter: f64,
+    attempt: u32,
+    rand: f64,
+) -> Duration {
+    let exp = attempt.saturating_sub(1).min(31);
+    let delay = initial.checked_mul(1 << exp).unwrap_or(max).min(max);
+    let jitter = jitter.clamp(0.0, 1.0);
+    // Scale by a random factor in [1 - jitter, 1 + jitter)
+    delay.mul_f64(1.0 - jitter + 2.0 * jitter * rand)
+}
+
+/// A random number in `[0, 1)`
+pub(crate) fn random_unit() -> f64 {
+    let mut bytes = [0u8; 4];
+    getrandom::getrandom(&mut bytes).unwrap();
+    f64::from(u32::from_le_bytes(bytes)) / (f64::from(u32::MAX) + 1.0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,4 +148,20 @@ mod tests {
         let ret: Vec<&str> = vec![];
         assert_eq!(iter.collect::<Vec<&str>>(), ret);
     }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let secs = Duration::from_secs;
+        let delay = |attempt, rand| reconnect_delay(secs(5), secs(60), 0.2, attempt, rand);
+        assert_eq!(delay(1, 0.5), secs(5));
+        assert_eq!(delay(2, 0.5), secs(10));
+        assert_eq!(delay(4, 0.5), secs(40));
+        assert_eq!(delay(5, 0.5), secs(60));
+        assert_eq!(delay(100, 0.5), secs(60));
+        assert_eq!(delay(1, 0.0), secs(4));
+        assert_eq!(delay(5, 0.0), secs(48));
+
+        let rand = random_unit();
+        assert!((0.0..1.0).contains(&rand));
+    }
 }
@@ -0,0 +1,324 @@
+//! Aggregation of WHOIS and WHOWAS replies. Replies to a query are collected until
+//! RPL_ENDOFWHOIS (318) or RPL_ENDOFWHOWAS (369) and reported as a single `WhoisInfo`.
+
+use libtiny_common::CaseMapping;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Information about a user, from replies to a WHOIS or WHOWAS query.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WhoisInfo {
+    /// Nick of the user
+    pub nick: String,
+
+    /// Is this a reply to a WHOWAS query? WHOWAS replies are about users that are no longer
+    /// online and only have `user`, `host`, `realname`, and `server` information.
+    pub whowas: bool,
+
+    pub user: Option<String>,
+
+    pub host: Option<String>,
+
+    pub realname: Option<String>,
+
+    /// Server the user is connected to, from RPL_WHOISSERVER (312)
+    pub server: Option<String>,
+
+    /// Description of `server`. For WHOWAS replies this is usually the time the user left.
+    pub server_info: Option<String>,
+
+    /// Operator status, from RPL_WHOISOPERATOR (313), e.g. "is an IRC operator"
+    pub operator: Option<String>,
+
+    /// Idle time, from RPL_WHOISIDLE (317)
+    pub idle: Option<Duration>,
+
+    /// Sign on time as a Unix timestamp, from RPL_WHOISIDLE (317)
+    pub signon: Option<u64>,
+
+    /// Channels of the user with membership prefixes, from RPL_WHOISCHANNELS (319)
+    pub channels: Vec<String>,
+
+    /// Services account of the user, from RPL_WHOISACCOUNT (330)
+    pub account: Option<String>,
+
+    /// Actual host or IP address of the user, from RPL_WHOISACTUALLY (338)
+    pub actual_host: Option<String>,
+
+    /// Is the user connected with TLS? From RPL_WHOISSECURE (671)
+    pub secure: bool,
+
+    /// Away message of the user, from RPL_AWAY (301)
+    pub away: Option<String>,
+
+    /// Other server-specific replies, e.g. RPL_WHOISHOST (378) and RPL_WHOISMODES (379)
+    pub other: Vec<String>,
+
+    /// Error reply to the query, e.g. ERR_NOSUCHNICK (401) or ERR_WASNOSUCHNICK (406). Other
+    /// fields are empty when this is set.
+    pub error: Option<String>,
+}
+
+/// Collects WHOIS and WHOWAS replies.
+#[derive(Debug, Default)]
+pub(crate) struct Whois {
+    /// Normalized nicks we sent queries for. Error replies (e.g. ERR_NOSUCHNICK) are only collected
+    /// for these, as the same errors are sent for other commands.
+    requests: HashSet<String>,
+
+    /// Replies received so far, by normalized nick
+    replies: HashMap<String, WhoisInfo>,
+}
+
+/// Result of `Whois::update`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum WhoisUpdate {
+    /// Not a WHOIS or WHOWAS reply
+    Ignored,
+
+    /// Reply collected
+    Consumed,
+
+    /// Reply collected, and the replies about a user are complete
+    Done(Box<WhoisInfo>),
+}
+
+impl Whois {
+    /// Register a query sent for `nick`.
+    pub(crate) fn request(&mut self, nick: &str, casemapping: CaseMapping) {
+        self.requests.insert(casemapping.normalize(nick));
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.requests.clear();
+        self.replies.clear();
+    }
+
+    /// Handle a numeric reply. `params[0]` is our nick, replies about a user have the nick of the
+    /// user in `params[1]`.
+    pub(crate) fn update(
+        &mut self,
+        num: u16,
+        params: &[String],
+        casemapping: CaseMapping,
+    ) -> WhoisUpdate {
+        if params.len() < 2 {
+            return WhoisUpdate::Ignored;
+        }
+        let nick = &params[1];
+        let key = casemapping.normalize(nick);
+        // Last parameter, usually a description
+        let text = || params.last().cloned().unwrap_or_default();
+
+        match num {
+            // RPL_WHOISUSER, RPL_WHOWASUSER: start of the replies about a user. WHOWAS replies
+            // may have multiple entries for a nick, each starting with RPL_WHOWASUSER.
+            311 | 314 => {
+                let info = WhoisInfo {
+                    nick: nick.clone(),
+                    whowas: num == 314,
+                    user: params.get(2).cloned(),
+                    host: params.get(3).cloned(),
+                    realname: params.get(5).cloned(),
+                    ..WhoisInfo::default()
+                };
+                match self.replies.insert(key, info) {
+                    Some(prev) if prev.whowas && num == 314 => WhoisUpdate::Done(Box::new(prev)),
+                    _ => WhoisUpdate::Consumed,
+                }
+            }
+
+            // RPL_ENDOFWHOIS, RPL_ENDOFWHOWAS
+            318 | 369 => {
+                self.requests.remove(&key);
+                match self.replies.remove(&key) {
+                    Some(info) => WhoisUpdate::Done(Box::new(info)),
+                    None => WhoisUpdate::Consumed,
+                }
+            }
+
+            // ERR_NOSUCHNICK, ERR_WASNOSUCHNICK
+            401 | 406 => {
+                if !self.requests.contains(&key) {
+                    return WhoisUpdate::Ignored;
+                }
+                let info = self.replies.entry(key).or_insert_with(|| WhoisInfo {
+                    nick: nick.clone(),
+                    whowas: num == 406,
+                    ..WhoisInfo::default()
+                });
+                info.error = Some(text());
+                WhoisUpdate::Consumed
+            }
+
+            // Replies in the middle of a WHOIS. These are only collected after RPL_WHOISUSER, as
+            // RPL_AWAY is also sent in reply to PRIVMSGs.
+            301 | 312 | 313 | 317 | 319 | 330 | 338 | 671 | 276 | 307 | 310 | 320 | 335 | 378
+            | 379 => {
+                let info = match self.replies.get_mut(&key) {
+                    None => return WhoisUpdate::Ignored,
+                    Some(info) => info,
+                };
+                match num {
+                    301 => info.away = Some(text()),
+                    312 => {
+                        info.server = params.get(2).cloned();
+                        info.server_info = params.get(3).cloned();
+                    }
+                    313 => info.operator = Some(text()),
+                    317 => {
+                        info.idle = params
+                            .get(2)
+                            .and_then(|idle| idle.parse().ok())
+                            .map(Duration::from_secs);
+                        // Sign on time is not sent by all servers
+                        if params.len() > 4 {
+                            info.signon = params[3].parse().ok();
+                        }
+                    }
+                    319 => info
+                        .channels
+                        .extend(text().split_whitespace().map(str::to_owned)),
+                    330 if params.len() > 3 => info.account = Some(params[2].clone()),
+                    338 if params.len() > 3 => {
+                        info.actual_host = Some(params[2..params.len() - 1].join(" "))
+                    }
+                    671 => info.secure = true,
+                    _ => info.other.push(params[2..].join(" ")),
+                }
+                WhoisUpdate::Consumed
+            }
+
+            _ => WhoisUpdate::Ignored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(whois: &mut Whois, num: u16, params: &[&str]) -> WhoisUpdate {
+        let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+        whois.update(num, &params, CaseMapping::default())
+    }
+
+    #[test]
+    fn whois_aggregation() {
+        let mut whois = Whois::default();
+        whois.request("Nick", CaseMapping::default());
+
+        // RPL_AWAY without a WHOIS in progress is passed through
+        assert_eq!(
+            update(&mut whois, 301, &["me", "nick", "gone"]),
+            WhoisUpdate::Ignored
+        );
+
+        let replies: &[(u16, &[&str])] = &[
+            (311, &["me", "Nick", "user", "host", "*", "Real Name"]),
+            (312, &["me", "nick", "irc.server", "Server info"]),
+            (313, &["me", "nick", "is an IRC operator"]),
+            (
+                317,
+                &[
+                    "me",
+                    "nick",
+                    "42",
+                    "1600000000",
+                    "seconds idle, signon time",
+                ],
+            ),
+            (319, &["me", "nick", "@#chan1 #chan2"]),
+            (319, &["me", "nick", "+#chan3"]),
+            (330, &["me", "nick", "account", "is logged in as"]),
+            (338, &["me", "nick", "1.2.3.4", "actually using host"]),
+            (671, &["me", "nick", "is using a secure connection"]),
+            (301, &["me", "nick", "gone"]),
+            (378, &["me", "nick", "is connecting from *@host 1.2.3.4"]),
+        ];
+        for (num, params) in replies {
+            assert_eq!(update(&mut whois, *num, params), WhoisUpdate::Consumed);
+        }
+
+        assert_eq!(
+            update(&mut whois, 318, &["me", "nick", "End of /WHOIS list."]),
+            WhoisUpdate::Done(Box::new(WhoisInfo {
+                nick: "Nick".to_owned(),
+                whowas: false,
+                user: Some("user".to_owned()),
+                host: Some("host".to_owned()),
+                realname: Some("Real Name".to_owned()),
+                server: Some("irc.server".to_owned()),
+                server_info: Some("Server info".to_owned()),
+                operator: Some("is an IRC operator".to_owned()),
+                idle: Some(Duration::from_secs(42)),
+                signon: Some(1600000000),
+                channels: vec![
+                    "@#chan1".to_owned(),
+                    "#chan2".to_owned(),
+                    "+#chan3".to_owned()
+                ],
+                account: Some("account".to_owned()),
+                actual_host: Some("1.2.3.4".to_owned()),
+                secure: true,
+                away: Some("gone".to_owned()),
+                other: vec!["is connecting from *@host 1.2.3.4".to_owned()],
+                error: None,
+            }))
+        );
+        assert!(whois.requests.is_empty());
+        assert!(whois.replies.is_empty());
+    }
+
+    #[test]
+    fn whois_errors() {
+        let mut whois = Whois::default();
+
+        // Not requested, e.g. a reply to PRIVMSG
+        assert_eq!(
+            update(&mut whois, 401, &["me", "nick", "No such nick/channel"]),
+            WhoisUpdate::Ignored
+        );
+
+        whois.request("nick", CaseMapping::default());
+        assert_eq!(
+            update(&mut whois, 401, &["me", "nick", "No such nick/channel"]),
+            WhoisUpdate::Consumed
+        );
+        assert_eq!(
+            update(&mut whois, 318, &["me", "nick", "End of /WHOIS list."]),
+            WhoisUpdate::Done(Box::new(WhoisInfo {
+                nick: "nick".to_owned(),
+                error: Some("No such nick/channel".to_owned()),
+                ..WhoisInfo::default()
+            }))
+        );
+    }
+
+    #[test]
+    fn whowas_entries() {
+        let mut whois = Whois::default();
+        whois.request("nick", CaseMapping::default());
+        assert_eq!(
+            update(&mut whois, 314, &["me", "nick", "u1", "h1", "*", "Real"]),
+            WhoisUpdate::Consumed
+        );
+        assert_eq!(
+            update(&mut whois, 312, &["me", "nick", "irc.server", "Mon Jan 1"]),
+            WhoisUpdate::Consumed
+        );
+        match update(&mut whois, 314, &["me", "nick", "u2", "h2", "*", "Real"]) {
+            WhoisUpdate::Done(info) => {
+                assert!(info.whowas);
+                assert_eq!(info.user.as_deref(), Some("u1"));
+                assert_eq!(info.server_info.as_deref(), Some("Mon Jan 1"));
+            }
+            other => panic!("Unexpected update: {:?}", other),
+        }
+        match update(&mut whois, 369, &["me", "nick", "End of WHOWAS"]) {
+            WhoisUpdate::Done(info) => assert_eq!(info.user.as_deref(), Some("u2")),
+            other => panic!("Unexpected update: {:?}", other),
+        }
+    }
+}
@@ -7,6 +7,9 @@ use std::ops::Deref;
 /// Channel names according to RFC 2812, section 1.3. Channel names are case insensitive, so this
 /// type defines `Eq`, and `Hash` traits that work in a case-insensitive way. `ChanName::display`
 /// method shows the channel name with the original casing.
+///
+/// `Eq` and `Hash` use the `rfc1459` casemapping. Use `CaseMapping::eq_ignore_case` to compare
+/// channel names using the server's casemapping.
 #[derive(Debug, Clone)]
 pub struct ChanName(String);
 
@@ -29,7 +32,7 @@ impl AsRef<ChanNameRef> for ChanName {
     }
 }
 
-impl<'a> Borrow<ChanNameRef> for ChanName {
+impl Borrow<ChanNameRef> for ChanName {
     fn borrow(&self) -> &ChanNameRef {
         self.as_ref()
     }
@@ -41,12 +44,119 @@ impl<'a> Borrow<ChanNameRef> for ChanName {
 // - '[', ']', '\\', '~' are mapped to '{', '}', '|', '^', respectively. See RFC 2812 section 2.2.
 // - Non-ASCII characters are left unchanged.
 fn to_lower(c: char) -> char {
-    match c {
-        '[' => '{',
-        ']' => '}',
-        '\\' => '|',
-        '~' => '^',
-        _ => c.to_ascii_lowercase(),
+    CaseMapping::Rfc1459.to_lower(c)
+}
+
+/// Casemapping of an IRC server, used to compare nicks and channel names. Advertised by servers
+/// with the `CASEMAPPING` RPL_ISUPPORT parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CaseMapping {
+    /// Only ASCII letters are case insensitive.
+    Ascii,
+    /// In addition to ASCII letters, `[]\~` are lowercase versions of `{}|^`.
+    #[default]
+    Rfc1459,
+    /// Same as `Rfc1459`, but `~` and `^` are different characters.
+    Rfc1459Strict,
+}
+
+impl CaseMapping {
+    /// Parse a `CASEMAPPING` value. Unknown casemappings are mapped to `Rfc1459`.
+    pub fn from_isupport(value: &str) -> CaseMapping {
+        match value {
+            "ascii" => CaseMapping::Ascii,
+            "strict-rfc1459" => CaseMapping::Rfc1459Strict,
+            _ => CaseMapping::Rfc1459,
+        }
+    }
+
+    /// Lowercase version of a character. Non-ASCII characters are left unchanged.
+    pub fn to_lower(self, c: char) -> char {
+        match (self, c) {
+            (CaseMapping::Rfc1459, '[') | (CaseMapping::Rfc1459Strict, '[') => '{',
+            (CaseMapping::Rfc1459, ']') | (CaseMapping::Rfc1459Strict, ']') => '}',
+            (CaseMapping::Rfc1459, '\\') | (CaseMapping::Rfc1459Strict, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c.to_ascii_lowercase(),
+        }
+    }
+
+    /// Lowercase version of a nick or channel name.
+    pub fn normalize(self, s: &str) -> String {
+        s.chars().map(|c| self.to_lower(c)).collect()
+    }
+
+    /// Compare nicks or channel names case-insensitively.
+    pub fn eq_ignore_case(self, s1: &str, s2: &str) -> bool {
+        // All characters that are mapped are ASCII so we can compare byte lengths
+        s1.len() == s2.len()
+            && s1
+                .chars()
+                .zip(s2.chars())
+                .all(|(c1, c2)| self.to_lower(c1) == self.to_lower(c2))
+    }
+}
+
+/// A nick, compared and hashed using the casemapping of the server the nick is from.
+/// `Nick::display` shows the nick with the original casing.
+///
+/// Comparing nicks with different casemappings uses the casemapping of the left-hand side, which
+/// is not symmetric; only nicks from the same server should be compared.
+#[derive(Debug, Clone)]
+pub struct Nick {
+    name: String,
+    casemapping: CaseMapping,
+}
+
+impl Nick {
+    pub fn new(name: String, casemapping: CaseMapping) -> Self {
+        Nick { name, casemapping }
+    }
+
+    pub fn display(&self) -> &str {
+        &self.name
+    }
+
+    pub fn casemapping(&self) -> CaseMapping {
+        self.casemapping
+    }
+
+    pub fn normalized(&self) -> String {
+        self.casemapping.normalize(&self.name)
+    }
+
+    /// Same nick with a different casemapping.
+    pub fn with_casemapping(self, casemapping: CaseMapping) -> Self {
+        Nick {
+            name: self.name,
+            casemapping,
+        }
+    }
+
+    /// Compare with a nick string, without allocating a `Nick`.
+    pub fn matches(&self, nick: &str) -> bool {
+        self.casemapping.eq_ignore_case(&self.name, nick)
+    }
+
+    pub fn into_string(self) -> String {
+        self.name
+    }
+}
+
+impl PartialEq for Nick {
+    fn eq(&self, other: &Self) -> bool {
+        self.matches(&other.name)
+    }
+}
+
+impl Eq for Nick {}
+
+impl Hash for Nick {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.len().hash(state);
+        for c in self.name.chars() {
+            self.casemapping.to_lower(c).hash(state);
+        }
     }
 }
 
@@ -111,7 +221,7 @@ impl PartialEq for ChanNameRef {
         // https://github.com/rust-lang/rust/blob/b4acb110333392ecdaf890fce080e4b576106aae/library/core/src/slice/mod.rs#L6678-L6684
 
         // All characters in ASCII have the same encoding length so we can compare byte lenghts.
-        if self.0.as_bytes().len() != other.0.as_bytes().len() {
+        if self.0.len() != other.0.len() {
             return false;
         }
 
@@ -157,6 +267,10 @@ pub enum MsgTarget<'a> {
     /// Show the message in the privmsg tab.
     User { serv: &'a str, nick: &'a str },
 
+    /// Show the message in the DCC chat tab. DCC chats are direct connections to users, the
+    /// server is the one the chat was offered on.
+    DccChat { serv: &'a str, nick: &'a str },
+
     /// Show the message in all tabs of a server.
     AllServTabs { serv: &'a str },
 
@@ -175,6 +289,9 @@ pub enum MsgSource {
 
     /// Message sent in a privmsg tab.
     User { serv: String, nick: String },
+
+    /// Message sent in a DCC chat tab.
+    DccChat { serv: String, nick: String },
 }
 
 impl MsgSource {
@@ -182,11 +299,12 @@ impl MsgSource {
         match self {
             MsgSource::Serv { serv }
             | MsgSource::Chan { serv, .. }
-            | MsgSource::User { serv, .. } => serv,
+            | MsgSource::User { serv, .. }
+            | MsgSource::DccChat { serv, .. } => serv,
         }
     }
 
-    pub fn to_target(&self) -> MsgTarget {
+    pub fn to_target(&self) -> MsgTarget<'_> {
         match self {
             MsgSource::Serv { serv } => MsgTarget::Server { serv },
             MsgSource::Chan { serv, chan } => MsgTarget::Chan {
@@ -194,6 +312,7 @@ impl MsgSource {
                 chan: chan.borrow(),
             },
             MsgSource::User { serv, nick } => MsgTarget::User { serv, nick },
+            MsgSource::DccChat { serv, nick } => MsgTarget::DccChat { serv, nick },
         }
     }
 
@@ -201,7 +320,7 @@ impl MsgSource {
         match self {
             MsgSource::Serv { serv, .. } => serv,
             MsgSource::Chan { chan, .. } => chan.display(),
-            MsgSource::User { nick, .. } => nick,
+            MsgSource::User { nick, .. } | MsgSource::DccChat { nick, .. } => nick,
         }
     }
 }
@@ -234,3 +353,362 @@ pub enum Event {
         source: MsgSource,
     },
 }
+
+/// Name, description, and usage of a command, shown in `/help`.
+#[derive(Debug, Clone, Copy)]
+pub struct CmdUsage {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub usage: &'static str,
+}
+
+impl CmdUsage {
+    pub const fn new(name: &'static str, description: &'static str, usage: &'static str) -> Self {
+        CmdUsage {
+            name,
+            description,
+            usage,
+        }
+    }
+}
+
+/// Result of `match_cmd`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CmdMatch<'a> {
+    /// Name of the command matching the given name or prefix
+    Match(&'a str),
+
+    /// The prefix matches multiple commands, here are their names, sorted
+    Ambiguous(Vec<&'a str>),
+
+    Unknown,
+}
+
+/// Find the command with the given name, or the only command starting with the given prefix.
+/// Commands with the same name can appear multiple times in `cmds`.
+pub fn match_cmd<'a, I>(name: &str, cmds: I) -> CmdMatch<'a>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut candidates: Vec<&'a str> = vec![];
+    for cmd in cmds {
+        if cmd == name {
+            return CmdMatch::Match(cmd);
+        }
+        if cmd.starts_with(name) {
+            candidates.push(cmd);
+        }
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+    match candidates.len() {
+        0 => CmdMatch::Unknown,
+        1 => CmdMatch::Match(candidates[0]),
+        _ => CmdMatch::Ambiguous(candidates),
+    }
+}
+
+/// Error message for a command with an ambiguous name. `cmd` is the command without the leading
+/// `/`, `candidates` are the names in `CmdMatch::Ambiguous`.
+pub fn ambiguous_cmd_msg<S: AsRef<str>>(cmd: &str, candidates: &[S]) -> String {
+    format!(
+        "Ambiguous command \"/{}\", did you mean one of: {}",
+        cmd.split_whitespace().next().unwrap_or(""),
+        candidates
+            .iter()
+            .map(|candidate| format!("/{}", candidate.as_ref()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// A channel in a channel list, from RPL_LIST (322).
+#[derive(Debug, Clone)]
+pub struct ChanListEntry {
+    pub chan: ChanName,
+
+    /// Number of (visible) users in the channel
+    pub users: usize,
+
+    pub topic: String,
+}
+
+/// Filter for channel lists, parsed from `[<pattern>] [><users>]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChanListFilter {
+    /// Channel names are matched against the pattern when it has wildcards (`*` or `?`).
+    /// Otherwise channels with the pattern in their name or topic match. Wildcards escaped with
+    /// `\\` match themselves. Matching is case-insensitive.
+    pattern: Option<ChanListPattern>,
+
+    /// Only channels with more users than this match.
+    pub more_users_than: Option<usize>,
+}
+
+impl ChanListFilter {
+    pub fn parse(s: &str) -> Result<ChanListFilter, String> {
+        let mut filter = ChanListFilter::default();
+        for word in s.split_whitespace() {
+            if let Some(users) = word.strip_prefix('>') {
+                if users.is_empty() {
+                    return Err("Number of users missing after >".to_owned());
+                }
+                match users.parse() {
+                    Ok(users) => filter.more_users_than = Some(users),
+                    Err(_) => return Err(format!("Invalid number of users: {}", users)),
+                }
+            } else if filter.pattern.is_some() {
+                return Err(format!("Unexpected argument: {}", word));
+            } else {
+                filter.pattern = Some(ChanListPattern::new(word));
+            }
+        }
+        Ok(filter)
+    }
+
+    /// The pattern as given to `parse`.
+    pub fn pattern(&self) -> Option<&str> {
+        self.pattern.as_ref().map(|pattern| pattern.source.as_str())
+    }
+
+    pub fn matches(&self, entry: &ChanListEntry) -> bool {
+        if let Some(users) = self.more_users_than {
+            if entry.users <= users {
+                return false;
+            }
+        }
+        let pattern = match &self.pattern {
+            None => return true,
+            Some(pattern) => pattern,
+        };
+        match &pattern.matcher {
+            PatternMatcher::Wildcard(pattern) => {
+                let name: Vec<char> = entry.chan.display().to_lowercase().chars().collect();
+                wildcard_match(pattern, &name)
+            }
+            PatternMatcher::Substring(pattern) => {
+                entry.chan.display().to_lowercase().contains(pattern)
+                    || entry.topic.to_lowercase().contains(pattern)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ChanListFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.pattern(), self.more_users_than) {
+            (None, None) => Ok(()),
+            (Some(pattern), None) => write!(f, "{}", pattern),
+            (None, Some(users)) => write!(f, ">{}", users),
+            (Some(pattern), Some(users)) => write!(f, "{} >{}", pattern, users),
+        }
+    }
+}
+
+/// A channel list filter pattern, parsed and lowercased once so that matching a list entry does
+/// not allocate for the pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChanListPattern {
+    /// The pattern as given by the user
+    source: String,
+    matcher: PatternMatcher,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PatternMatcher {
+    /// Pattern with wildcards, matched against channel names
+    Wildcard(Vec<PatternChar>),
+    /// Pattern without wildcards (escaped wildcards are unescaped), searched in channel names and
+    /// topics
+    Substring(String),
+}
+
+impl ChanListPattern {
+    fn new(pattern: &str) -> ChanListPattern {
+        let chars = parse_pattern(&pattern.to_lowercase());
+        let matcher = if chars.iter().any(|c| !matches!(c, PatternChar::Char(_))) {
+            PatternMatcher::Wildcard(chars)
+        } else {
+            PatternMatcher::Substring(
+                chars
+                    .iter()
+                    .filter_map(|c| match c {
+                        PatternChar::Char(c) => Some(c),
+                        PatternChar::AnyChar | PatternChar::AnyChars => None,
+                    })
+                    .collect(),
+            )
+        };
+        ChanListPattern {
+            source: pattern.to_owned(),
+            matcher,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatternChar {
+    /// A character that matches itself
+    Char(char),
+    /// `?`: Matches one character
+    AnyChar,
+    /// `*`: Matches any number of characters
+    AnyChars,
+}
+
+/// Parse a pattern with `*` and `?` wildcards. `\\` makes the next character match itself. A
+/// trailing `\\` matches itself.
+fn parse_pattern(pattern: &str) -> Vec<PatternChar> {
+    let mut ret = Vec::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        ret.push(match c {
+            '\\' => PatternChar::Char(chars.next().unwrap_or('\\')),
+            '?' => PatternChar::AnyChar,
+            '*' => PatternChar::AnyChars,
+            c => PatternChar::Char(c),
+        });
+    }
+    ret
+}
+
+/// Match `s` against a pattern parsed with `parse_pattern`.
+fn wildcard_match(pattern: &[PatternChar], s: &[char]) -> bool {
+    // Position of the last `*` in the pattern and the position in `s` it's matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut i) = (0, 0);
+    while i < s.len() {
+        match pattern.get(p) {
+            Some(PatternChar::AnyChar) => {
+                p += 1;
+                i += 1;
+            }
+            Some(PatternChar::Char(c)) if *c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            Some(PatternChar::AnyChars) => {
+                star = Some((p, i));
+                p += 1;
+            }
+            _ => match star {
+                Some((star_p, star_i)) => {
+                    // Match one more character with the last `*`
+                    p = star_p + 1;
+                    i = star_i + 1;
+                    star = Some((star_p, star_i + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == PatternChar::AnyChars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wildcard_match_str(pattern: &str, s: &str) -> bool {
+        let s: Vec<char> = s.chars().collect();
+        wildcard_match(&parse_pattern(pattern), &s)
+    }
+
+    fn entry(chan: &str, users: usize, topic: &str) -> ChanListEntry {
+        ChanListEntry {
+            chan: ChanName::new(chan.to_owned()),
+            users,
+            topic: topic.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match_str("#rust*", "#rust"));
+        assert!(wildcard_match_str("#rust*", "#rust-beginners"));
+        assert!(wildcard_match_str("*rust*", "##rust-offtopic"));
+        assert!(wildcard_match_str("#?ust", "#rust"));
+        assert!(wildcard_match_str("*", ""));
+        assert!(wildcard_match_str("**", "#tiny"));
+        assert!(!wildcard_match_str("#?ust", "#ust"));
+        assert!(!wildcard_match_str("#rust*", "#tiny"));
+        assert!(!wildcard_match_str("*rust", "#rust-beginners"));
+
+        // Empty pattern
+        assert!(wildcard_match_str("", ""));
+        assert!(!wildcard_match_str("", "#tiny"));
+
+        // Escaped wildcards
+        assert!(wildcard_match_str("#a\\*b", "#a*b"));
+        assert!(!wildcard_match_str("#a\\*b", "#axb"));
+        assert!(wildcard_match_str("#a\\?", "#a?"));
+        assert!(!wildcard_match_str("#a\\?", "#ab"));
+        assert!(wildcard_match_str("#a\\\\*", "#a\\b"));
+        assert!(wildcard_match_str("#a\\", "#a\\"));
+
+        // Unescaped wildcards match literal `*` and `?` too
+        assert!(wildcard_match_str("#a*", "#a*"));
+        assert!(wildcard_match_str("#a?", "#a?"));
+    }
+
+    #[test]
+    fn test_chan_list_filter_parse() {
+        assert_eq!(ChanListFilter::parse(""), Ok(ChanListFilter::default()));
+        assert_eq!(ChanListFilter::parse(" \t "), Ok(ChanListFilter::default()));
+        assert_eq!(
+            ChanListFilter::parse("  #rust*  >10 "),
+            Ok(ChanListFilter {
+                pattern: Some(ChanListPattern::new("#rust*")),
+                more_users_than: Some(10),
+            })
+        );
+        assert_eq!(
+            ChanListFilter::parse(">10"),
+            Ok(ChanListFilter {
+                pattern: None,
+                more_users_than: Some(10),
+            })
+        );
+        assert_eq!(
+            ChanListFilter::parse(">"),
+            Err("Number of users missing after >".to_owned())
+        );
+        assert_eq!(
+            ChanListFilter::parse("#rust >"),
+            Err("Number of users missing after >".to_owned())
+        );
+        assert_eq!(
+            ChanListFilter::parse(">ten"),
+            Err("Invalid number of users: ten".to_owned())
+        );
+        assert_eq!(
+            ChanListFilter::parse("#rust #tiny"),
+            Err("Unexpected argument: #tiny".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_chan_list_filter_matches() {
+        let filter = |s| ChanListFilter::parse(s).unwrap();
+        let rust = entry("#Rust", 100, "The Rust programming language");
+        let tiny = entry("#tiny", 5, "tiny IRC client, written in rust");
+        let star = entry("#a*b", 2, "");
+
+        // Substring of name or topic, case-insensitive
+        assert!(filter("rust").matches(&rust));
+        assert!(filter("RUST").matches(&tiny));
+        assert!(!filter("python").matches(&rust));
+
+        // Wildcards only match names
+        assert!(filter("#r*").matches(&rust));
+        assert!(!filter("*written*").matches(&tiny));
+
+        // Escaped wildcards are matched as substrings
+        assert!(filter("a\\*").matches(&star));
+        assert!(!filter("a\\*").matches(&rust));
+
+        assert!(filter(">5").matches(&rust));
+        assert!(!filter(">5").matches(&tiny));
+        assert!(!filter("#r* >100").matches(&rust));
+    }
+}
@@ -8,7 +8,7 @@ use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use time::Tm;
 
-use libtiny_common::{ChanName, ChanNameRef, MsgTarget};
+use libtiny_common::{CaseMapping, ChanName, ChanNameRef, MsgTarget, Nick};
 
 #[macro_use]
 extern crate log;
@@ -48,6 +48,8 @@ impl Logger {
     delegate!(new_chan_tab(serv: &str, chan: &ChanNameRef,));
     delegate!(close_chan_tab(serv: &str, chan: &ChanNameRef,));
     delegate!(close_user_tab(serv: &str, nick: &str,));
+    delegate!(close_dcc_chat_tab(serv: &str, nick: &str,));
+    delegate!(set_casemapping(serv: &str, casemapping: CaseMapping,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_privmsg(
@@ -96,7 +98,10 @@ impl Drop for LoggerInner {
 struct ServerLogs {
     fd: File,
     chans: HashMap<ChanName, File>,
-    users: HashMap<String, File>,
+    users: HashMap<Nick, File>,
+    dcc_chats: HashMap<Nick, File>,
+    /// Casemapping of the server, used for the keys of `users` and `dcc_chats`
+    casemapping: CaseMapping,
 }
 
 fn print_header(fd: &mut File) -> io::Result<()> {
@@ -155,16 +160,42 @@ fn close_server_tabs(server: ServerLogs, report_err: &dyn Fn(String)) {
         mut fd,
         chans,
         users,
+        dcc_chats,
+        ..
     } = server;
     report_io_err!(report_err, print_footer(&mut fd));
     for (_, mut fd) in chans.into_iter() {
         report_io_err!(report_err, print_footer(&mut fd));
     }
-    for (_, mut fd) in users.into_iter() {
+    for (_, mut fd) in users.into_iter().chain(dcc_chats) {
         report_io_err!(report_err, print_footer(&mut fd));
     }
 }
 
+/// Apply `f` to the log file of a user in `users`, creating the file if it doesn't exist.
+fn apply_to_user_log(
+    users: &mut HashMap<Nick, File>,
+    nick: Nick,
+    path: PathBuf,
+    report_err: &dyn Fn(String),
+    f: impl Fn(&mut File, &dyn Fn(String)),
+) {
+    match users.get_mut(&nick) {
+        Some(fd) => {
+            f(fd, report_err);
+        }
+        None => {
+            // We don't have `new_user_tab` and `new_dcc_chat_tab` trait methods so user log files
+            // are created here
+            if let Some(mut fd) = try_open_log_file(&path, report_err) {
+                report_io_err!(report_err, print_header(&mut fd));
+                f(&mut fd, report_err);
+                users.insert(nick, fd);
+            }
+        }
+    }
+}
+
 impl LoggerInner {
     fn new(
         log_dir: PathBuf,
@@ -192,7 +223,7 @@ impl LoggerInner {
         }
 
         let mut path = self.log_dir.clone();
-        path.push(&format!("{}.txt", serv));
+        path.push(format!("{}.txt", serv));
         if let Some(mut fd) = try_open_log_file(&path, &*self.report_err) {
             report_io_err!(self.report_err, print_header(&mut fd));
             self.servers.insert(
@@ -201,6 +232,8 @@ impl LoggerInner {
                     fd,
                     chans: HashMap::new(),
                     users: HashMap::new(),
+                    dcc_chats: HashMap::new(),
+                    casemapping: CaseMapping::default(),
                 },
             );
         }
@@ -232,7 +265,7 @@ impl LoggerInner {
                 }
 
                 let mut path = self.log_dir.clone();
-                path.push(&format!(
+                path.push(format!(
                     "{}_{}.txt",
                     serv,
                     replace_forward_slash(&chan_name_normalized)
@@ -270,7 +303,10 @@ impl LoggerInner {
             None => {
                 info!("close_user_tab: can't find server: {:?}", serv);
             }
-            Some(server) => match server.users.remove(nick) {
+            Some(server) => match server
+                .users
+                .remove(&Nick::new(nick.to_owned(), server.casemapping))
+            {
                 None => {
                     info!(
                         "close_user_tab: can't find user {:?} in server {:?}",
@@ -284,6 +320,49 @@ impl LoggerInner {
         }
     }
 
+    fn close_dcc_chat_tab(&mut self, serv: &str, nick: &str) {
+        match self.servers.get_mut(serv) {
+            None => {
+                info!("close_dcc_chat_tab: can't find server: {:?}", serv);
+            }
+            Some(server) => match server
+                .dcc_chats
+                .remove(&Nick::new(nick.to_owned(), server.casemapping))
+            {
+                None => {
+                    info!(
+                        "close_dcc_chat_tab: can't find DCC chat with {:?} in server {:?}",
+                        nick, serv
+                    );
+                }
+                Some(mut fd) => {
+                    report_io_err!(self.report_err, print_footer(&mut fd));
+                }
+            },
+        }
+    }
+
+    fn set_casemapping(&mut self, serv: &str, casemapping: CaseMapping) {
+        match self.servers.get_mut(serv) {
+            None => {
+                info!("set_casemapping: can't find server: {:?}", serv);
+            }
+            Some(server) => {
+                server.casemapping = casemapping;
+                server.users = server
+                    .users
+                    .drain()
+                    .map(|(nick, fd)| (nick.with_casemapping(casemapping), fd))
+                    .collect();
+                server.dcc_chats = server
+                    .dcc_chats
+                    .drain()
+                    .map(|(nick, fd)| (nick.with_casemapping(casemapping), fd))
+                    .collect();
+            }
+        }
+    }
+
     fn add_client_msg(&mut self, msg: &str, target: &MsgTarget) {
         let now = now();
         self.apply_to_target(target, |fd: &mut File, report_err: &dyn Fn(String)| {
@@ -317,22 +396,22 @@ impl LoggerInner {
     }
 
     fn add_nick(&mut self, nick: &str, ts: Option<Tm>, target: &MsgTarget) {
-        if let Some(_ts) = ts {
+        if let Some(ts) = ts {
             // This method is only called when a user joins a chan
             self.apply_to_target(target, |fd: &mut File, report_err: &dyn Fn(String)| {
                 report_io_err!(
                     report_err,
-                    writeln!(fd, "[{}] {} joined the channel.", now(), nick)
+                    writeln!(fd, "[{}] {} joined the channel.", strf(&ts), nick)
                 );
             });
         }
     }
 
     fn remove_nick(&mut self, nick: &str, ts: Option<Tm>, target: &MsgTarget) {
-        if let Some(_ts) = ts {
+        if let Some(ts) = ts {
             // TODO: Did the user leave a channel or the server? Currently we can't tell.
             self.apply_to_target(target, |fd: &mut File, report_err: &dyn Fn(String)| {
-                report_io_err!(report_err, writeln!(fd, "[{}] {} left.", now(), nick));
+                report_io_err!(report_err, writeln!(fd, "[{}] {} left.", strf(&ts), nick));
             });
         }
     }
@@ -382,7 +461,7 @@ impl LoggerInner {
                         // can't reuse it because of borrowchk issues.
                         let mut path = self.log_dir.clone();
                         let chan_name_normalized = chan.normalized();
-                        path.push(&format!(
+                        path.push(format!(
                             "{}_{}.txt",
                             serv,
                             replace_forward_slash(&chan_name_normalized)
@@ -398,31 +477,36 @@ impl LoggerInner {
                     }
                 },
             },
-            MsgTarget::User { serv, nick } => {
-                match self.servers.get_mut(serv) {
-                    None => {
-                        info!("Can't find server: {:?}", serv);
-                    }
-                    Some(ServerLogs { ref mut users, .. }) => {
-                        match users.get_mut(nick) {
-                            Some(fd) => {
-                                f(fd, &*self.report_err);
-                            }
-                            None => {
-                                // We don't have a `new_user_tab` trait method so user log files
-                                // are created here
-                                let mut path = self.log_dir.clone();
-                                path.push(&format!("{}_{}.txt", serv, replace_forward_slash(nick)));
-                                if let Some(mut fd) = try_open_log_file(&path, &*self.report_err) {
-                                    report_io_err!(self.report_err, print_header(&mut fd));
-                                    f(&mut fd, &*self.report_err);
-                                    users.insert(nick.to_owned(), fd);
-                                }
-                            }
-                        }
-                    }
+            MsgTarget::User { serv, nick } => match self.servers.get_mut(serv) {
+                None => {
+                    info!("Can't find server: {:?}", serv);
                 }
-            }
+                Some(ServerLogs {
+                    ref mut users,
+                    casemapping,
+                    ..
+                }) => {
+                    let mut path = self.log_dir.clone();
+                    path.push(format!("{}_{}.txt", serv, replace_forward_slash(nick)));
+                    let nick = Nick::new(nick.to_string(), *casemapping);
+                    apply_to_user_log(users, nick, path, &*self.report_err, f);
+                }
+            },
+            MsgTarget::DccChat { serv, nick } => match self.servers.get_mut(serv) {
+                None => {
+                    info!("Can't find server: {:?}", serv);
+                }
+                Some(ServerLogs {
+                    ref mut dcc_chats,
+                    casemapping,
+                    ..
+                }) => {
+                    let mut path = self.log_dir.clone();
+                    path.push(format!("{}_dcc_{}.txt", serv, replace_forward_slash(nick)));
+                    let nick = Nick::new(nick.to_string(), *casemapping);
+                    apply_to_user_log(dcc_chats, nick, path, &*self.report_err, f);
+                }
+            },
             MsgTarget::AllServTabs { serv } => match self.servers.get_mut(serv) {
                 None => {
                     info!("Can't find server: {:?}", serv);
@@ -431,13 +515,14 @@ impl LoggerInner {
                     ref mut fd,
                     ref mut chans,
                     ref mut users,
+                    ref mut dcc_chats,
                     ..
                 }) => {
                     f(fd, &*self.report_err);
                     for (_, fd) in chans.iter_mut() {
                         f(fd, &*self.report_err);
                     }
-                    for (_, fd) in users.iter_mut() {
+                    for (_, fd) in users.iter_mut().chain(dcc_chats.iter_mut()) {
                         f(fd, &*self.report_err);
                     }
                 }
@@ -0,0 +1,342 @@
+//! Channel list widget, shown instead of the message area in a tab after a `/list` command. The
+//! list only keeps indices of entries matching the filter and renders only the visible rows, so
+//! it works fine with lists of tens of thousands of channels.
+
+use libtiny_common::{ChanListEntry, ChanListFilter, ChanNameRef};
+use termbox_simple::Termbox;
+use unicode_width::UnicodeWidthChar;
+
+use crate::config::{Colors, Style};
+use crate::utils::remove_irc_control_chars;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    /// Most users first
+    Users,
+    /// Alphabetical
+    Name,
+}
+
+pub(crate) struct ChanList {
+    /// Server of the channels
+    serv: String,
+
+    /// All channels, sorted by `sort_by`. `None` until the list is received.
+    entries: Option<Vec<ChanListEntry>>,
+
+    filter: ChanListFilter,
+
+    /// Indices of entries matching the filter
+    shown: Vec<usize>,
+
+    sort_by: SortBy,
+
+    /// Index of the selected entry in `shown`
+    selected: usize,
+
+    /// Index of the first visible entry in `shown`
+    scroll: usize,
+
+    /// Number of visible entries, updated on draw
+    height: usize,
+
+    /// Error in the last filter entered, shown in the header
+    error: Option<String>,
+}
+
+impl ChanList {
+    pub(crate) fn new(serv: &str, filter: ChanListFilter) -> ChanList {
+        ChanList {
+            serv: serv.to_owned(),
+            entries: None,
+            filter,
+            shown: vec![],
+            sort_by: SortBy::Users,
+            selected: 0,
+            scroll: 0,
+            height: 1,
+            error: None,
+        }
+    }
+
+    pub(crate) fn serv(&self) -> &str {
+        &self.serv
+    }
+
+    /// All channels in the list, ignoring the filter.
+    pub(crate) fn chans(&self) -> impl Iterator<Item = &ChanNameRef> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|entry| entry.chan.as_ref())
+    }
+
+    pub(crate) fn set_entries(&mut self, entries: Vec<ChanListEntry>) {
+        self.entries = Some(entries);
+        self.sort();
+    }
+
+    /// Set the filter from user input in the form `[<pattern>] [><users>]`.
+    pub(crate) fn set_filter(&mut self, filter: &str) {
+        match ChanListFilter::parse(filter) {
+            Ok(filter) => {
+                self.filter = filter;
+                self.error = None;
+                self.apply_filter();
+            }
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Switch between sorting by number of users and by name.
+    pub(crate) fn toggle_sort(&mut self) {
+        self.sort_by = match self.sort_by {
+            SortBy::Users => SortBy::Name,
+            SortBy::Name => SortBy::Users,
+        };
+        self.sort();
+    }
+
+    pub(crate) fn selected_chan(&self) -> Option<&ChanNameRef> {
+        let entries = self.entries.as_ref()?;
+        let idx = *self.shown.get(self.selected)?;
+        Some(&entries[idx].chan)
+    }
+
+    fn sort(&mut self) {
+        if let Some(entries) = &mut self.entries {
+            match self.sort_by {
+                SortBy::Users => entries.sort_by(|e1, e2| {
+                    e2.users
+                        .cmp(&e1.users)
+                        .then_with(|| e1.chan.display().cmp(e2.chan.display()))
+                }),
+                SortBy::Name => entries.sort_by_cached_key(|e| e.chan.display().to_lowercase()),
+            }
+        }
+        self.apply_filter();
+    }
+
+    fn apply_filter(&mut self) {
+        let filter = &self.filter;
+        self.shown = match &self.entries {
+            None => vec![],
+            Some(entries) => entries
+                .iter()
+                .enumerate()
+                .filter(|(_, entry)| filter.matches(entry))
+                .map(|(idx, _)| idx)
+                .collect(),
+        };
+        self.selected = 0;
+        self.scroll = 0;
+    }
+
+    /// Move the selection up (negative `n`) or down.
+    pub(crate) fn move_selection(&mut self, n: isize) {
+        if self.shown.is_empty() {
+            return;
+        }
+        let max = self.shown.len() - 1;
+        self.selected = if n < 0 {
+            self.selected.saturating_sub(n.unsigned_abs())
+        } else {
+            self.selected.saturating_add(n as usize).min(max)
+        };
+        // Keep the selection visible
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.height {
+            self.scroll = self.selected + 1 - self.height;
+        }
+    }
+
+    pub(crate) fn page_up(&mut self) {
+        self.move_selection(-(self.height as isize));
+    }
+
+    pub(crate) fn page_down(&mut self) {
+        self.move_selection(self.height as isize);
+    }
+
+    pub(crate) fn select_first(&mut self) {
+        self.move_selection(isize::MIN);
+    }
+
+    pub(crate) fn select_last(&mut self) {
+        self.move_selection(isize::MAX);
+    }
+
+    fn header(&self) -> String {
+        let entries = match &self.entries {
+            None => return format!("Receiving channel list of {} ...", self.serv),
+            Some(entries) => entries,
+        };
+        let mut header = format!(
+            "{}: {} of {} channels, sorted by {}",
+            self.serv,
+            self.shown.len(),
+            entries.len(),
+            match self.sort_by {
+                SortBy::Users => "users",
+                SortBy::Name => "name",
+            }
+        );
+        if self.filter != ChanListFilter::default() {
+            header.push_str(&format!(", filter: {}", self.filter));
+        }
+        header.push_str(" | Enter: join, Tab: sort, type `<pattern> [><users>]` to filter");
+        header
+    }
+
+    pub(crate) fn draw(
+        &mut self,
+        tb: &mut Termbox,
+        colors: &Colors,
+        pos_x: i32,
+        pos_y: i32,
+        width: i32,
+        height: i32,
+    ) {
+        if height < 1 {
+            return;
+        }
+
+        match &self.error {
+            None => draw_line(tb, colors.topic, pos_x, pos_y, width, &self.header()),
+            Some(err) => draw_line(tb, colors.err_msg, pos_x, pos_y, width, err),
+        }
+
+        // Update the scroll after a resize
+        self.height = (height - 1).max(1) as usize;
+        if self.selected >= self.scroll + self.height {
+            self.scroll = self.selected + 1 - self.height;
+        }
+
+        let entries = match &self.entries {
+            None => return,
+            Some(entries) => entries,
+        };
+
+        let users_width = self
+            .shown
+            .iter()
+            .map(|idx| entries[*idx].users)
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+
+        for (row, shown_idx) in (self.scroll..self.shown.len())
+            .take(self.height)
+            .enumerate()
+        {
+            let entry = &entries[self.shown[shown_idx]];
+            let line = format!(
+                "{:>width$} {} {}",
+                entry.users,
+                entry.chan.display(),
+                remove_irc_control_chars(&entry.topic),
+                width = users_width
+            );
+            let style = if shown_idx == self.selected {
+                colors.completion
+            } else {
+                colors.user_msg
+            };
+            draw_line(tb, style, pos_x, pos_y + 1 + row as i32, width, &line);
+        }
+    }
+}
+
+/// Draw a line, truncated to `width` columns.
+fn draw_line(tb: &mut Termbox, style: Style, pos_x: i32, pos_y: i32, width: i32, line: &str) {
+    let mut col = 0;
+    for c in line.chars() {
+        let c_width = c.width().unwrap_or(0) as i32;
+        if c_width == 0 {
+            continue;
+        }
+        if col + c_width > width {
+            break;
+        }
+        tb.change_cell(pos_x + col, pos_y, c, style.fg, style.bg);
+        col += c_width;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtiny_common::ChanName;
+
+    fn entry(chan: &str, users: usize, topic: &str) -> ChanListEntry {
+        ChanListEntry {
+            chan: ChanName::new(chan.to_owned()),
+            users,
+            topic: topic.to_owned(),
+        }
+    }
+
+    fn shown_chans(list: &ChanList) -> Vec<&str> {
+        let entries = list.entries.as_ref().unwrap();
+        list.shown
+            .iter()
+            .map(|idx| entries[*idx].chan.display())
+            .collect()
+    }
+
+    #[test]
+    fn sort_and_filter() {
+        let mut list = ChanList::new("server", ChanListFilter::default());
+        assert_eq!(list.selected_chan(), None);
+        list.set_entries(vec![
+            entry("#rust", 100, "Rust programming language"),
+            entry("#tiny", 10, "tiny IRC client"),
+            entry("#haskell", 200, "Functional programming"),
+            entry("#a", 1, ""),
+        ]);
+        assert_eq!(shown_chans(&list), vec!["#haskell", "#rust", "#tiny", "#a"]);
+
+        list.toggle_sort();
+        assert_eq!(shown_chans(&list), vec!["#a", "#haskell", "#rust", "#tiny"]);
+
+        list.set_filter("programming");
+        assert_eq!(shown_chans(&list), vec!["#haskell", "#rust"]);
+
+        list.set_filter("#*s* >150");
+        assert_eq!(shown_chans(&list), vec!["#haskell"]);
+
+        list.set_filter(">abc");
+        assert!(list.error.is_some());
+        assert_eq!(shown_chans(&list), vec!["#haskell"]);
+
+        list.set_filter("");
+        assert!(list.error.is_none());
+        assert_eq!(shown_chans(&list).len(), 4);
+    }
+
+    #[test]
+    fn selection() {
+        let mut list = ChanList::new("server", ChanListFilter::default());
+        list.set_entries((0..100).map(|i| entry(&format!("#{}", i), i, "")).collect());
+        list.height = 10;
+        assert_eq!(list.selected_chan().unwrap().display(), "#99");
+
+        list.page_down();
+        assert_eq!(list.selected, 10);
+        assert_eq!(list.scroll, 1);
+
+        list.move_selection(-5);
+        assert_eq!(list.selected, 5);
+        assert_eq!(list.scroll, 1);
+
+        list.select_last();
+        assert_eq!(list.selected_chan().unwrap().display(), "#0");
+        assert_eq!(list.scroll, 90);
+
+        list.select_first();
+        assert_eq!(list.selected, 0);
+        assert_eq!(list.scroll, 0);
+    }
+}
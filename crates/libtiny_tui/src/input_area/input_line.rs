@@ -34,9 +34,9 @@ impl InputLine {
         &self.buffer
     }
 
-    /**
-     **    Functions to interface with InputLine::buffer
-     **/
+    //
+    // Functions to interface with InputLine::buffer
+    //
 
     /// Interface for Vec::get()
     pub(crate) fn get(&self, idx: usize) -> char {
@@ -49,7 +49,7 @@ impl InputLine {
     }
 
     /// Interface for Vec::drain()
-    pub(crate) fn drain<R>(&mut self, range: R) -> Drain<char>
+    pub(crate) fn drain<R>(&mut self, range: R) -> Drain<'_, char>
     where
         R: RangeBounds<usize>,
     {
@@ -77,9 +77,9 @@ impl InputLine {
         }
     }
 
-    /**
-     **    End of InputLine::buffer interface
-     **/
+    //
+    // End of InputLine::buffer interface
+    //
 
     /// Calculate hedight of the widget, taking nickname length into account. Only needed when
     /// buffer is wider than width and scrolling is off.
@@ -325,7 +325,7 @@ pub(crate) fn draw_line(
             pos_y,
             width,
             cursor,
-            &line,
+            line,
             &completion_range,
         );
     }
@@ -128,11 +128,7 @@ impl InputArea {
     }
 
     pub(crate) fn get_nick(&self) -> Option<String> {
-        if let Some(nick) = &self.nick {
-            Some(nick.value.clone())
-        } else {
-            None
-        }
+        self.nick.as_ref().map(|nick| nick.value.clone())
     }
 
     /// Resizes input area
@@ -572,7 +568,7 @@ impl InputArea {
         line_count
     }
 
-    fn current_buffer_len(&self) -> i32 {
+    pub(crate) fn current_buffer_len(&self) -> i32 {
         match self.mode {
             Mode::Edit => self.buffer.len() as i32,
             Mode::History(hist_curs) => self.history[hist_curs as usize].len() as i32,
@@ -634,7 +630,7 @@ impl InputArea {
                 ..
             } => {
                 let mut buffer = mem::replace(original_buffer, InputLine::new());
-                let completions: Vec<String> = mem::replace(completions, vec![]);
+                let completions: Vec<String> = std::mem::take(completions);
                 let word = &completions[current_completion];
 
                 // FIXME: This is inefficient
@@ -716,8 +712,78 @@ impl InputArea {
     }
 }
 
+/// Where the word before the cursor is in the input, for completing the word.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum CompletionCtx {
+    /// Command name of a `/cmd` input
+    CmdName,
+
+    /// An argument of a command. `arg_idx` starts from 0.
+    CmdArg {
+        cmd: String,
+        arg_idx: usize,
+        word: String,
+    },
+
+    /// A word in a message
+    Word { word: String },
+}
+
+/// Characters of words completed as channel names or command arguments. Commas are not included
+/// to be able to complete channels in e.g. `/join #a,#b`.
+pub(crate) fn is_arg_char(c: char) -> bool {
+    !c.is_whitespace() && c != ','
+}
+
 impl InputArea {
+    /// Find where the word before the cursor is in the input. Words are separated by whitespace
+    /// and commas.
+    pub(crate) fn completion_ctx(&mut self) -> CompletionCtx {
+        // When completing, the completion is not in the buffer yet
+        let cursor = match self.mode {
+            Mode::Autocomplete {
+                insertion_point, ..
+            } => insertion_point,
+            _ => self.cursor as usize,
+        };
+        let line = self.shown_line().get_buffer();
+        let before_cursor = &line[..cursor];
+        let word_start = before_cursor
+            .iter()
+            .rposition(|c| !is_arg_char(*c))
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        let word: String = before_cursor[word_start..].iter().collect();
+
+        if line.first() != Some(&'/') || before_cursor.is_empty() {
+            return CompletionCtx::Word { word };
+        }
+
+        let mut words = before_cursor.split(|c| c.is_whitespace());
+        let cmd: String = words.next().unwrap()[1..].iter().collect();
+        // Number of whitespace-separated words before the cursor, excluding the command name. The
+        // last word is the one the cursor is on.
+        let n_words = words.filter(|word| !word.is_empty()).count()
+            + usize::from(before_cursor.last().map(|c| c.is_whitespace()) == Some(true));
+        if n_words == 0 {
+            CompletionCtx::CmdName
+        } else {
+            CompletionCtx::CmdArg {
+                cmd,
+                arg_idx: n_words - 1,
+                word,
+            }
+        }
+    }
+
+    /// Complete the word before the cursor using the words in `dict`, or switch to the previous
+    /// completion when already completing. Nick characters are considered part of the word.
     pub(crate) fn autocomplete(&mut self, dict: &Trie) {
+        self.autocomplete_word(dict, utils::is_nick_char)
+    }
+
+    /// Like `autocomplete`, but with word characters given by `is_word_char`.
+    pub(crate) fn autocomplete_word(&mut self, dict: &Trie, is_word_char: fn(char) -> bool) {
         if self.in_autocomplete() {
             // scroll next if you hit the KeyAction::InputAutoComplete key again
             self.completion_prev_entry();
@@ -736,7 +802,7 @@ impl InputArea {
             while cursor_left >= 0
                 && line
                     .get(cursor_left as usize)
-                    .map(|c| utils::is_nick_char(*c))
+                    .map(|c| is_word_char(*c))
                     .unwrap_or(false)
             {
                 cursor_left -= 1;
@@ -803,7 +869,39 @@ mod tests {
         let mut input_area = InputArea::new(40, 50);
         // a string that will be more than one line - 41 characters
         let multiline_string_no_spaces = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
-        input_area.set(&multiline_string_no_spaces);
+        input_area.set(multiline_string_no_spaces);
         assert_eq!(input_area.get_height(input_area.width), 2);
     }
+
+    #[test]
+    fn completion_ctx() {
+        fn expect(input: &mut InputArea, chars: &str, ctx: CompletionCtx) {
+            for c in chars.chars() {
+                input.keypressed(KeyAction::Input(c));
+            }
+            assert_eq!(input.completion_ctx(), ctx);
+        }
+        let mut input = InputArea::new(100, 50);
+        let arg = |arg_idx: usize, word: &str| CompletionCtx::CmdArg {
+            cmd: "join".to_owned(),
+            arg_idx,
+            word: word.to_owned(),
+        };
+
+        expect(&mut input, "/jo", CompletionCtx::CmdName);
+        expect(&mut input, "in", CompletionCtx::CmdName);
+        expect(&mut input, " ", arg(0, ""));
+        expect(&mut input, "#a,#b", arg(0, "#b"));
+        expect(&mut input, " key", arg(1, "key"));
+        expect(&mut input, " ", arg(2, ""));
+
+        input.keypressed(KeyAction::InputSend);
+        expect(
+            &mut input,
+            "hi #ti",
+            CompletionCtx::Word {
+                word: "#ti".to_owned(),
+            },
+        );
+    }
 }
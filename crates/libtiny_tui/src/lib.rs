@@ -1,6 +1,7 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(clippy::cognitive_complexity)]
 
+mod chan_list;
 mod config;
 mod editor;
 mod exit_dialogue;
@@ -23,8 +24,11 @@ mod widget;
 #[cfg(test)]
 mod tests;
 
+pub use crate::tui::tui_cmds;
 use crate::tui::{CmdResult, TUIRet};
-use libtiny_common::{ChanNameRef, Event, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{
+    CaseMapping, ChanListEntry, ChanListFilter, ChanNameRef, Event, MsgSource, MsgTarget, TabStyle,
+};
 use term_input::Input;
 
 use std::cell::RefCell;
@@ -175,15 +179,15 @@ async fn input_handler<S>(
                         let _ = snd_abort.try_send(());
                         return;
                     }
-                    TUIRet::KeyHandled | TUIRet::KeyIgnored(_) | TUIRet::EventIgnored(_) => {}
+                    TUIRet::KeyHandled | TUIRet::KeyIgnored | TUIRet::EventIgnored => {}
                     TUIRet::Input { msg, from } => {
                         if msg[0] == '/' {
                             // Handle TUI commands, send others to downstream
-                            let cmd: String = (&msg[1..]).iter().collect();
+                            let cmd: String = msg[1..].iter().collect();
                             let result = tui.borrow_mut().try_handle_cmd(&cmd, &from);
                             match result {
                                 CmdResult::Ok => {}
-                                CmdResult::Continue => {
+                                CmdResult::Continue(cmd) => {
                                     snd_ev.try_send(Event::Cmd { cmd, source: from }).unwrap()
                                 }
                                 CmdResult::Quit => {
@@ -226,11 +230,14 @@ impl TUI {
     delegate!(new_chan_tab(serv_name: &str, chan: &ChanNameRef,));
     delegate!(close_chan_tab(serv_name: &str, chan: &ChanNameRef,));
     delegate!(close_user_tab(serv_name: &str, nick: &str,));
+    delegate!(new_dcc_chat_tab(serv_name: &str, nick: &str,));
+    delegate!(close_dcc_chat_tab(serv_name: &str, nick: &str,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_client_err_msg(msg: &str, target: &MsgTarget,));
     delegate!(clear_nicks(serv_name: &str,));
+    delegate!(set_chan_parted(serv_name: &str, chan: &ChanNameRef,));
     delegate!(set_nick(serv_name: &str, new_nick: &str,));
     delegate!(add_privmsg(
         sender: &str,
@@ -255,6 +262,15 @@ impl TUI {
         chan_name: &ChanNameRef,
     ));
     delegate!(set_tab_style(style: TabStyle, target: &MsgTarget,));
+    delegate!(set_casemapping(serv_name: &str, casemapping: CaseMapping,));
+    delegate!(set_client_cmds(cmds: Vec<String>,));
+    delegate!(set_client_servers(servers: Vec<String>,));
+    delegate!(show_chan_list(tab: &str, serv_name: &str, filter: ChanListFilter,));
+    delegate!(set_chan_list_entries(
+        tab: &str,
+        serv_name: &str,
+        entries: Vec<ChanListEntry>,
+    ));
 
     pub fn user_tab_exists(&self, serv_name: &str, nick: &str) -> bool {
         match self.inner.upgrade() {
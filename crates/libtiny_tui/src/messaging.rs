@@ -4,9 +4,10 @@ use std::convert::From;
 
 use time::{self, Tm};
 
+use crate::chan_list::ChanList;
 use crate::config::Colors;
 use crate::exit_dialogue::ExitDialogue;
-use crate::input_area::InputArea;
+use crate::input_area::{CompletionCtx, InputArea};
 use crate::key_map::KeyAction;
 use crate::msg_area::line::SegStyle;
 use crate::msg_area::{Layout, MsgArea};
@@ -24,6 +25,9 @@ pub(crate) struct MessagingUI {
     input_field: InputArea,
     exit_dialogue: Option<ExitDialogue>,
 
+    // Channel list, shown instead of the message area when available
+    chan_list: Option<ChanList>,
+
     width: i32,
     height: i32,
 
@@ -99,6 +103,7 @@ impl MessagingUI {
             msg_area: MsgArea::new(width, height - 1, scrollback, msg_layout),
             input_field: InputArea::new(width, get_input_field_max_height(height)),
             exit_dialogue: None,
+            chan_list: None,
             width,
             height,
             show_status: status,
@@ -132,10 +137,85 @@ impl MessagingUI {
                     .draw(tb, colors, pos_x, pos_y, self.height, &mut self.msg_area);
             }
         }
-        self.msg_area.draw(tb, colors, pos_x, pos_y);
+        match &mut self.chan_list {
+            Some(chan_list) => {
+                let height = self.msg_area.get_height();
+                chan_list.draw(tb, colors, pos_x, pos_y, self.width, height);
+            }
+            None => self.msg_area.draw(tb, colors, pos_x, pos_y),
+        }
+    }
+
+    /// Where the word before the cursor is in the input field. `None` when the exit dialogue is
+    /// shown.
+    pub(crate) fn completion_ctx(&mut self) -> Option<CompletionCtx> {
+        if self.exit_dialogue.is_some() {
+            return None;
+        }
+        Some(self.input_field.completion_ctx())
+    }
+
+    /// Complete the word before the cursor using `dict` instead of the nicks in the tab.
+    pub(crate) fn autocomplete(&mut self, dict: &Trie, is_word_char: fn(char) -> bool) {
+        self.input_field.autocomplete_word(dict, is_word_char);
+    }
+
+    pub(crate) fn set_chan_list(&mut self, chan_list: ChanList) {
+        self.chan_list = Some(chan_list);
+    }
+
+    pub(crate) fn chan_list(&self) -> Option<&ChanList> {
+        self.chan_list.as_ref()
+    }
+
+    pub(crate) fn chan_list_mut(&mut self) -> Option<&mut ChanList> {
+        self.chan_list.as_mut()
+    }
+
+    /// Handle keys in channel list mode: arrow and page keys move the selection, tab changes
+    /// sorting, enter joins the selected channel. Inputs other than commands set the filter.
+    fn chan_list_keypressed(&mut self, key_action: KeyAction) -> WidgetRet {
+        let chan_list = self.chan_list.as_mut().unwrap();
+        match key_action {
+            KeyAction::InputPrevEntry | KeyAction::MessagesScrollUp => chan_list.move_selection(-1),
+            KeyAction::InputNextEntry | KeyAction::MessagesScrollDown => {
+                chan_list.move_selection(1)
+            }
+            KeyAction::MessagesPageUp => chan_list.page_up(),
+            KeyAction::MessagesPageDown => chan_list.page_down(),
+            KeyAction::MessagesScrollTop => chan_list.select_first(),
+            KeyAction::MessagesScrollBottom => chan_list.select_last(),
+            KeyAction::InputAutoComplete => chan_list.toggle_sort(),
+            KeyAction::InputSend if self.input_field.current_buffer_len() == 0 => {
+                return match chan_list.selected_chan() {
+                    Some(chan) => WidgetRet::Join {
+                        serv: chan_list.serv().to_owned(),
+                        chan: chan.to_owned(),
+                    },
+                    None => WidgetRet::KeyHandled,
+                };
+            }
+            key_action => {
+                return match self.input_field.keypressed(key_action) {
+                    WidgetRet::Input(input) if input.first() != Some(&'/') => {
+                        let filter: String = input.into_iter().collect();
+                        chan_list.set_filter(&filter);
+                        WidgetRet::KeyHandled
+                    }
+                    ret => ret,
+                };
+            }
+        }
+        WidgetRet::KeyHandled
     }
 
     pub(crate) fn keypressed(&mut self, key_action: KeyAction) -> WidgetRet {
+        if self.chan_list.is_some()
+            && self.exit_dialogue.is_none()
+            && !matches!(key_action, KeyAction::Exit)
+        {
+            return self.chan_list_keypressed(key_action);
+        }
         match key_action {
             KeyAction::Exit => {
                 self.toggle_exit_dialogue();
@@ -201,7 +281,7 @@ impl MessagingUI {
         self.msg_area.resize(width, msg_area_height);
 
         // We don't show the nick in exit dialogue, so it has the full width
-        for exit_dialogue in &mut self.exit_dialogue {
+        if let Some(exit_dialogue) = &mut self.exit_dialogue {
             exit_dialogue.resize(width);
         }
     }
@@ -227,7 +307,7 @@ impl MessagingUI {
     }
 
     fn toggle_exit_dialogue(&mut self) {
-        let exit_dialogue = ::std::mem::replace(&mut self.exit_dialogue, None);
+        let exit_dialogue = self.exit_dialogue.take();
         if exit_dialogue.is_none() {
             // We don't show the nick in exit dialogue, so it has the full width
             self.exit_dialogue = Some(ExitDialogue::new(self.width));
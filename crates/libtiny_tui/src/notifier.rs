@@ -39,18 +39,22 @@ impl Notifier {
         let msg = remove_irc_control_chars(msg);
 
         match *target {
-            MsgTarget::Chan { chan, .. } => {
-                if *self == Notifier::Messages || (*self == Notifier::Mentions && mention) {
-                    notify(&format!("{} in {}", sender, chan.display()), &msg)
-                }
+            MsgTarget::Chan { chan, .. }
+                if *self == Notifier::Messages || (*self == Notifier::Mentions && mention) =>
+            {
+                notify(&format!("{} in {}", sender, chan.display()), &msg)
             }
             MsgTarget::User {
                 nick: ref nick_sender,
                 ..
-            } => {
-                if *self != Notifier::Off {
-                    notify(&format!("{} sent a private message", nick_sender), &msg)
-                }
+            } if *self != Notifier::Off => {
+                notify(&format!("{} sent a private message", nick_sender), &msg)
+            }
+            MsgTarget::DccChat {
+                nick: ref nick_sender,
+                ..
+            } if *self != Notifier::Off => {
+                notify(&format!("{} sent a DCC chat message", nick_sender), &msg)
             }
             _ => {}
         }
@@ -17,6 +17,9 @@ pub(crate) struct Tab {
     /// Alt-character to use to switch to this tab.
     pub(crate) switch: Option<char>,
     pub(crate) notifier: Notifier,
+    /// Whether we're no longer in the channel of the tab (e.g. after a kick). Messages typed in
+    /// the tab are not sent until the channel is joined again.
+    pub(crate) parted: bool,
 }
 
 fn tab_style(style: TabStyle, colors: &Colors) -> Style {
@@ -31,7 +34,7 @@ fn tab_style(style: TabStyle, colors: &Colors) -> Style {
 impl Tab {
     pub(crate) fn visible_name(&self) -> &str {
         match self.alias {
-            Some(ref alias) => &alias,
+            Some(ref alias) => alias,
             None => self.src.visible_name(),
         }
     }
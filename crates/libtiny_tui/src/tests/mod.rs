@@ -1,8 +1,8 @@
 use crate::msg_area::Layout;
-use crate::tui::TUI;
+use crate::tui::{CmdResult, TUIRet, TUI};
 
 use crate::test_utils::expect_screen;
-use libtiny_common::{ChanNameRef, MsgTarget};
+use libtiny_common::{ChanListEntry, ChanListFilter, ChanName, ChanNameRef, MsgSource, MsgTarget};
 use term_input::{Event, Key};
 
 use std::fs::File;
@@ -489,3 +489,157 @@ fn test_resize() {
         tui.draw();
     }
 }
+
+#[test]
+fn test_cmd_completion() {
+    let mut tui = TUI::new_test(20, 3);
+    tui.set_client_cmds(vec!["join".to_owned(), "jump".to_owned()]);
+
+    enter_string(&mut tui, "/rel");
+    tui.handle_input_event(Event::Key(Key::Tab), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|will be listed here.|
+         |/reload             |
+         |mentions            |";
+    expect_screen(screen, &tui.get_front_buffer(), 20, 3, Location::caller());
+
+    // Client commands are completed too. Arguments are not completed as commands.
+    tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None);
+    enter_string(&mut tui, "/jo");
+    tui.handle_input_event(Event::Key(Key::Tab), &mut None);
+    enter_string(&mut tui, " /cl");
+    tui.handle_input_event(Event::Key(Key::Tab), &mut None);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|will be listed here.|
+         |/join /cl           |
+         |mentions            |";
+    expect_screen(screen, &tui.get_front_buffer(), 20, 3, Location::caller());
+}
+
+#[test]
+fn test_cmd_prefix() {
+    let mut tui = TUI::new_test(70, 3);
+    tui.set_client_cmds(vec!["join".to_owned(), "quote".to_owned()]);
+    let src = MsgSource::Serv {
+        serv: "mentions".to_owned(),
+    };
+
+    // Client commands are expanded and passed to the client
+    assert_eq!(
+        tui.try_handle_cmd("jo #tiny", &src),
+        CmdResult::Continue("join #tiny".to_owned())
+    );
+    assert_eq!(
+        tui.try_handle_cmd("foo bar", &src),
+        CmdResult::Continue("foo bar".to_owned())
+    );
+
+    // TUI commands are expanded and handled
+    assert_eq!(tui.try_handle_cmd("qui", &src), CmdResult::Quit);
+
+    // Ambiguous prefixes are not passed to the client
+    assert_eq!(tui.try_handle_cmd("q", &src), CmdResult::Ok);
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|Ambiguous command \"/q\", did you mean one of: /quit, /quote            |
+         |                                                                      |
+         |mentions                                                              |";
+    expect_screen(screen, &tui.get_front_buffer(), 70, 3, Location::caller());
+}
+
+#[test]
+fn test_arg_completion() {
+    /// Enter the string, complete it, and send it
+    fn complete(tui: &mut TUI, s: &str) -> String {
+        enter_string(tui, s);
+        tui.handle_input_event(Event::Key(Key::Tab), &mut None);
+        match tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None) {
+            TUIRet::Input { msg, .. } => msg.into_iter().collect(),
+            other => panic!("Unexpected TUI return: {:?}", other),
+        }
+    }
+
+    let mut tui = TUI::new_test(40, 5);
+    let serv = "irc.server_1.org";
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, ChanNameRef::new("#tiny"));
+    tui.new_server_tab("irc.server_2.org", None);
+    tui.new_chan_tab("irc.server_2.org", ChanNameRef::new("#rust"));
+    tui.set_client_cmds(vec!["join".to_owned(), "connect".to_owned()]);
+    tui.set_client_servers(vec!["irc.server_3.org:6697".to_owned()]);
+    tui.next_tab();
+    tui.next_tab(); // #tiny
+
+    // Channels of the current server after `#`
+    assert_eq!(complete(&mut tui, "hi #t"), "hi #tiny");
+    assert_eq!(complete(&mut tui, "hi #r"), "hi #r");
+
+    // Command arguments, command names can be abbreviated
+    assert_eq!(complete(&mut tui, "/j #ti"), "/j #tiny");
+    assert_eq!(complete(&mut tui, "/switch #r"), "/switch #rust");
+    assert_eq!(
+        complete(&mut tui, "/conn irc"),
+        "/conn irc.server_3.org:6697"
+    );
+    assert_eq!(complete(&mut tui, "/notify of"), "/notify off");
+    assert_eq!(complete(&mut tui, "/join #a,#ti"), "/join #a,#tiny");
+
+    // Only first arguments are completed as channels and servers
+    assert_eq!(complete(&mut tui, "/connect irc irc"), "/connect irc irc");
+
+    // Channels in the channel list of the server
+    tui.show_chan_list("channels", serv, ChanListFilter::default());
+    tui.set_chan_list_entries(
+        "channels",
+        serv,
+        vec![ChanListEntry {
+            chan: ChanName::new("#haskell".to_owned()),
+            users: 10,
+            topic: String::new(),
+        }],
+    );
+    assert_eq!(complete(&mut tui, "/join #h"), "/join #haskell");
+}
+
+#[test]
+fn test_parted_chan_tab() {
+    let mut tui = TUI::new_test(70, 4);
+    let serv = "irc.server_1.org";
+    let chan = ChanNameRef::new("#tiny");
+    tui.new_server_tab(serv, None);
+    tui.new_chan_tab(serv, chan);
+    tui.next_tab();
+    tui.next_tab(); // #tiny
+    tui.set_chan_parted(serv, chan);
+
+    // Messages are not sent, commands are
+    enter_string(&mut tui, "hi");
+    assert!(matches!(
+        tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None),
+        TUIRet::KeyHandled
+    ));
+    tui.draw();
+
+    #[rustfmt::skip]
+    let screen =
+        "|                                                                      |
+         |You're not in this channel, use `/join` to join it again              |
+         |hi                                                                    |
+         |mentions irc.server_1.org #tiny                                       |";
+    expect_screen(screen, &tui.get_front_buffer(), 70, 4, Location::caller());
+
+    // Rejoining clears the parted state
+    tui.new_chan_tab(serv, chan);
+    match tui.handle_input_event(Event::Key(Key::Char('\r')), &mut None) {
+        TUIRet::Input { msg, .. } => assert_eq!(msg.into_iter().collect::<String>(), "hi"),
+        other => panic!("Unexpected TUI return: {:?}", other),
+    }
+}
@@ -9,16 +9,23 @@ use std::path::PathBuf;
 use std::str::{self, SplitWhitespace};
 use time::Tm;
 
+use crate::chan_list::ChanList;
 use crate::config::{parse_config, Colors, Config, Style};
 use crate::editor;
+use crate::input_area::{is_arg_char, CompletionCtx};
 use crate::key_map::{KeyAction, KeyMap};
 use crate::messaging::{MessagingUI, Timestamp};
 use crate::msg_area::Layout;
 use crate::notifier::Notifier;
 use crate::tab::Tab;
+use crate::trie::Trie;
+use crate::utils;
 use crate::widget::WidgetRet;
 
-use libtiny_common::{ChanNameRef, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{
+    ambiguous_cmd_msg, match_cmd, CaseMapping, ChanListEntry, ChanListFilter, ChanNameRef,
+    CmdMatch, CmdUsage, MsgSource, MsgTarget, Nick, TabStyle,
+};
 use term_input::{Event, Key};
 pub use termbox_simple::{CellBuf, Termbox};
 
@@ -26,8 +33,8 @@ pub use termbox_simple::{CellBuf, Termbox};
 pub(crate) enum TUIRet {
     Abort,
     KeyHandled,
-    KeyIgnored(Key),
-    EventIgnored(Event),
+    KeyIgnored,
+    EventIgnored,
 
     /// INVARIANT: The vec will have at least one char.
     // Can't make MsgSource a ref because of this weird error:
@@ -41,21 +48,8 @@ pub(crate) enum TUIRet {
 const LEFT_ARROW: char = '<';
 const RIGHT_ARROW: char = '>';
 
-struct CmdUsage {
-    name: &'static str,
-    description: &'static str,
-    usage: &'static str,
-}
-
-impl CmdUsage {
-    const fn new(name: &'static str, description: &'static str, usage: &'static str) -> CmdUsage {
-        CmdUsage {
-            name,
-            description,
-            usage,
-        }
-    }
-}
+/// Shown when a message is typed in a parted channel tab
+const PARTED_ERR_MSG: &str = "You're not in this channel, use `/join` to join it again";
 
 const QUIT_CMD: CmdUsage = CmdUsage::new("quit", "Quit tiny", "`/quit`");
 const CLEAR_CMD: CmdUsage = CmdUsage::new("clear", "Clears current tab", "`/clear`");
@@ -72,6 +66,19 @@ const TUI_COMMANDS: [CmdUsage; 6] = [
     QUIT_CMD, CLEAR_CMD, IGNORE_CMD, NOTIFY_CMD, SWITCH_CMD, RELOAD_CMD,
 ];
 
+/// Commands handled by the TUI. These commands are not passed to the client.
+pub fn tui_cmds() -> &'static [CmdUsage] {
+    &TUI_COMMANDS
+}
+
+fn tui_cmds_trie() -> Trie {
+    let mut cmds = Trie::new();
+    for cmd in TUI_COMMANDS.iter() {
+        cmds.insert(cmd.name);
+    }
+    cmds
+}
+
 // Public for benchmarks
 pub struct TUI {
     /// Termbox instance
@@ -94,15 +101,29 @@ pub struct TUI {
 
     key_map: KeyMap,
 
+    /// Command names, for completing and expanding command name prefixes. Has TUI commands and
+    /// the commands set with `set_client_cmds`.
+    cmds: Trie,
+
+    /// Servers the client can connect to, for completing `/connect` arguments. Set with
+    /// `set_client_servers`.
+    servers: Trie,
+
+    /// Casemappings of servers, used to find user tabs. Servers not in the map use the default
+    /// casemapping.
+    casemappings: HashMap<String, CaseMapping>,
+
     /// Config file path
     config_path: Option<PathBuf>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
 pub(crate) enum CmdResult {
     /// Command executed successfully
     Ok,
-    /// Pass command through to cmd.rs for further handling
-    Continue,
+    /// Pass command through to cmd.rs for further handling. A unique prefix of a command name is
+    /// expanded to the full name.
+    Continue(String),
     /// Quit command was executed
     Quit,
 }
@@ -142,8 +163,8 @@ impl TUI {
         // This is now done in reload_config() below
         // tb.set_clear_attributes(colors.clear.fg as u8, colors.clear.bg as u8);
 
-        let width = tb.width() as i32;
-        let height = tb.height() as i32;
+        let width = tb.width();
+        let height = tb.height();
 
         let mut tui = TUI {
             tb,
@@ -156,6 +177,9 @@ impl TUI {
             height,
             h_scroll: 0,
             key_map: KeyMap::default(),
+            cmds: tui_cmds_trie(),
+            servers: Trie::new(),
+            casemappings: HashMap::new(),
             config_path,
         };
 
@@ -174,19 +198,19 @@ impl TUI {
     fn ignore(&mut self, src: &MsgSource) {
         match src {
             MsgSource::Serv { serv } => {
-                self.toggle_ignore(&MsgTarget::AllServTabs { serv: &serv });
+                self.toggle_ignore(&MsgTarget::AllServTabs { serv });
             }
             MsgSource::Chan { serv, chan } => {
                 self.toggle_ignore(&MsgTarget::Chan {
-                    serv: &serv,
+                    serv,
                     chan: chan.borrow(),
                 });
             }
             MsgSource::User { serv, nick } => {
-                self.toggle_ignore(&MsgTarget::User {
-                    serv: &serv,
-                    nick: &nick,
-                });
+                self.toggle_ignore(&MsgTarget::User { serv, nick });
+            }
+            MsgSource::DccChat { serv, nick } => {
+                self.toggle_ignore(&MsgTarget::DccChat { serv, nick });
             }
         }
     }
@@ -248,12 +272,23 @@ impl TUI {
                     chan: chan.borrow(),
                 },
                 MsgSource::User { ref serv, ref nick } => MsgTarget::User { serv, nick },
+                MsgSource::DccChat { ref serv, ref nick } => MsgTarget::DccChat { serv, nick },
             };
             self.set_notifier(notifier, &tab_target);
         }
     }
 
     pub(crate) fn try_handle_cmd(&mut self, cmd: &str, src: &MsgSource) -> CmdResult {
+        let cmd = match self.expand_cmd_name(cmd.trim_start()) {
+            Ok(cmd) => cmd,
+            Err(candidates) => {
+                self.add_client_err_msg(
+                    &ambiguous_cmd_msg(cmd, &candidates),
+                    &MsgTarget::CurrentTab,
+                );
+                return CmdResult::Ok;
+            }
+        };
         let mut words = cmd.split_whitespace();
         match words.next() {
             Some("clear") => {
@@ -282,22 +317,130 @@ impl TUI {
                 self.reload_config();
                 CmdResult::Ok
             }
-            Some("help") => {
-                self.add_client_msg("TUI Commands: ", &MsgTarget::CurrentTab);
-                for cmd in TUI_COMMANDS.iter() {
-                    self.add_client_msg(
-                        &format!(
-                            "/{:<10} - {:<25} - Usage: {}",
-                            cmd.name, cmd.description, cmd.usage
-                        ),
-                        &MsgTarget::CurrentTab,
-                    );
+            Some("quit") => CmdResult::Quit,
+            // `/help` is handled by the client, which lists TUI commands too
+            _ => CmdResult::Continue(cmd),
+        }
+    }
+
+    /// Complete the word before the cursor in the current tab, depending on where the word is:
+    /// command names after `/`, channels, servers, and notification modes in the first argument of
+    /// `/join` and `/switch`, `/connect`, and `/notify`, and channels of the current server in
+    /// words starting with `#`. Returns `false` when the word should be completed as a nick by the
+    /// tab.
+    fn autocomplete(&mut self) -> bool {
+        let ctx = match self.tabs[self.active_idx].widget.completion_ctx() {
+            None => return false,
+            Some(ctx) => ctx,
+        };
+        let word = match ctx {
+            CompletionCtx::CmdName => {
+                self.tabs[self.active_idx]
+                    .widget
+                    .autocomplete(&self.cmds, utils::is_nick_char);
+                return true;
+            }
+            CompletionCtx::CmdArg {
+                cmd,
+                arg_idx: 0,
+                word,
+            } => {
+                let dict = match self.expand_cmd_name(&cmd).as_deref() {
+                    Ok("join") => Some(self.serv_chans()),
+                    Ok("switch") => {
+                        let mut chans = Trie::new();
+                        for tab in &self.tabs {
+                            if let MsgSource::Chan { chan, .. } = &tab.src {
+                                chans.insert(chan.display());
+                            }
+                        }
+                        Some(chans)
+                    }
+                    Ok("connect") => {
+                        self.tabs[self.active_idx]
+                            .widget
+                            .autocomplete(&self.servers, is_arg_char);
+                        return true;
+                    }
+                    Ok("notify") => {
+                        let mut modes = Trie::new();
+                        for mode in &["off", "mentions", "messages"] {
+                            modes.insert(mode);
+                        }
+                        Some(modes)
+                    }
+                    _ => None,
+                };
+                if let Some(dict) = dict {
+                    self.tabs[self.active_idx]
+                        .widget
+                        .autocomplete(&dict, is_arg_char);
+                    return true;
+                }
+                word
+            }
+            CompletionCtx::CmdArg { word, .. } | CompletionCtx::Word { word } => word,
+        };
+        if !word.starts_with('#') {
+            return false;
+        }
+        let chans = self.serv_chans();
+        self.tabs[self.active_idx]
+            .widget
+            .autocomplete(&chans, is_arg_char);
+        true
+    }
+
+    /// Channels of the current server: joined channels, and channels in the channel lists of the
+    /// server.
+    fn serv_chans(&self) -> Trie {
+        let current_tab = &self.tabs[self.active_idx];
+        let serv = match current_tab.widget.chan_list() {
+            Some(chan_list) => chan_list.serv(),
+            None => current_tab.src.serv_name(),
+        };
+        let mut chans = Trie::new();
+        for tab in &self.tabs {
+            if let MsgSource::Chan {
+                serv: tab_serv,
+                chan,
+            } = &tab.src
+            {
+                if tab_serv == serv {
+                    chans.insert(chan.display());
+                }
+            }
+            if let Some(chan_list) = tab.widget.chan_list() {
+                if chan_list.serv() == serv {
+                    for chan in chan_list.chans() {
+                        chans.insert(chan.display());
+                    }
                 }
-                // Fall through to print help for cmd.rs commands
-                CmdResult::Continue
             }
-            Some("quit") => CmdResult::Quit,
-            _ => CmdResult::Continue,
+        }
+        chans
+    }
+
+    /// Expand a unique prefix of a command name to the full name. Returns the command as-is when
+    /// the name doesn't match any commands, and names of the matching commands when the prefix is
+    /// ambiguous.
+    fn expand_cmd_name(&self, cmd: &str) -> Result<String, Vec<String>> {
+        let name = match cmd.split_whitespace().next() {
+            None => return Ok(cmd.to_owned()),
+            Some(name) => name,
+        };
+        let cmds: Vec<String> = self
+            .cmds
+            .drop_pfx(&mut name.chars())
+            .into_iter()
+            .map(|suffix| format!("{}{}", name, suffix))
+            .collect();
+        match match_cmd(name, cmds.iter().map(String::as_str)) {
+            CmdMatch::Match(full_name) => Ok(format!("{}{}", full_name, &cmd[name.len()..])),
+            CmdMatch::Ambiguous(candidates) => {
+                Err(candidates.into_iter().map(str::to_owned).collect())
+            }
+            CmdMatch::Unknown => Ok(cmd.to_owned()),
         }
     }
 
@@ -361,7 +504,7 @@ impl TUI {
             let mut n = 0;
             let visible_name = match alias.as_ref() {
                 None => src.visible_name(),
-                Some(ref alias) => alias,
+                Some(alias) => alias,
             };
             for ch in visible_name.chars() {
                 if !ch.is_alphabetic() {
@@ -373,7 +516,7 @@ impl TUI {
                         break;
                     }
                     Some(n_) => {
-                        if ret == None || n > *n_ {
+                        if ret.is_none() || n > *n_ {
                             ret = Some(ch);
                             n = *n_;
                         }
@@ -398,6 +541,7 @@ impl TUI {
                 style: TabStyle::Normal,
                 switch,
                 notifier,
+                parted: false,
             },
         );
     }
@@ -426,8 +570,65 @@ impl TUI {
         }
     }
 
+    /// Show a channel list of server `serv` in server tab `tab`, creating the tab if it doesn't
+    /// exist. The list is empty until `set_chan_list_entries`.
+    pub(crate) fn show_chan_list(&mut self, tab: &str, serv: &str, filter: ChanListFilter) {
+        self.new_server_tab(tab, None);
+        if let Some(tab_idx) = self.find_serv_tab_idx(tab) {
+            self.tabs[tab_idx]
+                .widget
+                .set_chan_list(ChanList::new(serv, filter));
+            self.select_tab(tab_idx);
+        }
+    }
+
+    /// Set channels of the channel list shown in server tab `tab`. Ignored if the tab was closed
+    /// or shows the list of another server.
+    pub(crate) fn set_chan_list_entries(
+        &mut self,
+        tab: &str,
+        serv: &str,
+        entries: Vec<ChanListEntry>,
+    ) {
+        if let Some(tab_idx) = self.find_serv_tab_idx(tab) {
+            if let Some(chan_list) = self.tabs[tab_idx].widget.chan_list_mut() {
+                if chan_list.serv() == serv {
+                    chan_list.set_entries(entries);
+                }
+            }
+        }
+    }
+
+    /// Set names of commands handled by the client (e.g. `join`, and user-defined aliases), to
+    /// complete and expand prefixes of them along with the TUI commands.
+    pub(crate) fn set_client_cmds(&mut self, cmds: Vec<String>) {
+        self.cmds = tui_cmds_trie();
+        for cmd in &cmds {
+            self.cmds.insert(cmd);
+        }
+    }
+
+    /// Set servers the client can connect to, to complete `/connect` arguments.
+    pub(crate) fn set_client_servers(&mut self, servers: Vec<String>) {
+        self.servers = Trie::new();
+        for serv in &servers {
+            self.servers.insert(serv);
+        }
+    }
+
+    /// Set casemapping of a server, used when comparing nicks.
+    pub(crate) fn set_casemapping(&mut self, serv: &str, casemapping: CaseMapping) {
+        self.casemappings.insert(serv.to_owned(), casemapping);
+    }
+
+    fn user_nick(&self, serv: &str, nick: &str) -> Nick {
+        let casemapping = self.casemappings.get(serv).copied().unwrap_or_default();
+        Nick::new(nick.to_owned(), casemapping)
+    }
+
     /// Closes a server tab and all associated channel tabs.
     pub(crate) fn close_server_tab(&mut self, serv: &str) {
+        self.casemappings.remove(serv);
         if let Some(tab_idx) = self.find_serv_tab_idx(serv) {
             self.tabs.retain(|tab: &Tab| tab.src.serv_name() != serv);
             if self.active_idx == tab_idx {
@@ -477,7 +678,11 @@ impl TUI {
                     Some(tab_idx)
                 }
             },
-            Some(_) => None,
+            Some(tab_idx) => {
+                // Rejoining a channel
+                self.tabs[tab_idx].parted = false;
+                None
+            }
         }
     }
 
@@ -531,6 +736,47 @@ impl TUI {
         self.fix_scroll_after_close();
     }
 
+    /// Returns index of the new tab if a new tab is created. DCC chat tabs are shown as `=nick`,
+    /// to distinguish them from privmsg tabs.
+    pub(crate) fn new_dcc_chat_tab(&mut self, serv: &str, nick: &str) -> Option<usize> {
+        match self.find_dcc_chat_tab_idx(serv, nick) {
+            None => match self.find_last_serv_tab_idx(serv) {
+                None => {
+                    self.new_server_tab(serv, None);
+                    self.new_dcc_chat_tab(serv, nick)
+                }
+                Some(tab_idx) => {
+                    self.new_tab(
+                        tab_idx + 1,
+                        MsgSource::DccChat {
+                            serv: serv.to_owned(),
+                            nick: nick.to_owned(),
+                        },
+                        true,
+                        Notifier::Messages,
+                        Some(format!("={}", nick)),
+                    );
+                    if let Some(nick) = self.tabs[tab_idx].widget.get_nick() {
+                        self.tabs[tab_idx + 1].widget.set_nick(nick);
+                    }
+                    self.tabs[tab_idx + 1].widget.join(nick, None);
+                    Some(tab_idx + 1)
+                }
+            },
+            Some(_) => None,
+        }
+    }
+
+    pub(crate) fn close_dcc_chat_tab(&mut self, serv: &str, nick: &str) {
+        if let Some(tab_idx) = self.find_dcc_chat_tab_idx(serv, nick) {
+            self.tabs.remove(tab_idx);
+            if self.active_idx == tab_idx {
+                self.select_tab(if tab_idx == 0 { 0 } else { tab_idx - 1 });
+            }
+        }
+        self.fix_scroll_after_close();
+    }
+
     pub(crate) fn handle_input_event(
         &mut self,
         ev: Event,
@@ -554,7 +800,7 @@ impl TUI {
                 TUIRet::KeyHandled
             }
 
-            ev => TUIRet::EventIgnored(ev),
+            _ => TUIRet::EventIgnored,
         }
     }
 
@@ -578,6 +824,12 @@ impl TUI {
                     for line in &lines {
                         tab.add_input_field_history(line);
                     }
+                    if self.tabs[self.active_idx].parted {
+                        self.tabs[self.active_idx]
+                            .widget
+                            .add_client_err_msg(PARTED_ERR_MSG);
+                        return None;
+                    }
                     Some((lines, self.tabs[self.active_idx].src.clone()))
                 }
             }
@@ -637,31 +889,47 @@ impl TUI {
         key: Key,
         rcv_editor_ret: &mut Option<editor::ResultReceiver>,
     ) -> TUIRet {
-        let key_action = self.key_map.get(&key).or_else(|| match key {
+        let key_action = self.key_map.get(&key).or(match key {
             Key::Char(c) => Some(KeyAction::Input(c)),
             Key::AltChar(c) => Some(KeyAction::TabGoto(c)),
             _ => None,
         });
 
         if let Some(key_action) = key_action {
+            if key_action == KeyAction::InputAutoComplete && self.autocomplete() {
+                return TUIRet::KeyHandled;
+            }
             match self.tabs[self.active_idx].widget.keypressed(key_action) {
                 WidgetRet::KeyHandled => TUIRet::KeyHandled,
-                WidgetRet::KeyIgnored => self.handle_keypress(key, key_action, rcv_editor_ret),
-                WidgetRet::Input(input) => TUIRet::Input {
-                    msg: input,
-                    from: self.tabs[self.active_idx].src.clone(),
+                WidgetRet::KeyIgnored => self.handle_keypress(key_action, rcv_editor_ret),
+                WidgetRet::Input(input) => {
+                    let tab = &mut self.tabs[self.active_idx];
+                    if tab.parted && input.first() != Some(&'/') {
+                        // Put the message back so that it can be sent after rejoining
+                        let input: String = input.into_iter().collect();
+                        tab.widget.set_input_field(&input);
+                        tab.widget.add_client_err_msg(PARTED_ERR_MSG);
+                        return TUIRet::KeyHandled;
+                    }
+                    TUIRet::Input {
+                        msg: input,
+                        from: tab.src.clone(),
+                    }
+                }
+                WidgetRet::Join { serv, chan } => TUIRet::Input {
+                    msg: format!("/join {}", chan.display()).chars().collect(),
+                    from: MsgSource::Serv { serv },
                 },
                 WidgetRet::Remove => unimplemented!(),
                 WidgetRet::Abort => TUIRet::Abort,
             }
         } else {
-            TUIRet::KeyIgnored(key)
+            TUIRet::KeyIgnored
         }
     }
 
     fn handle_keypress(
         &mut self,
-        key: Key,
         key_action: KeyAction,
         rcv_editor_ret: &mut Option<editor::ResultReceiver>,
     ) -> TUIRet {
@@ -687,7 +955,7 @@ impl TUI {
                 TUIRet::KeyHandled
             }
             KeyAction::TabGoto(c) => self.go_to_tab(c),
-            _ => TUIRet::KeyIgnored(key),
+            _ => TUIRet::KeyIgnored,
         }
     }
 
@@ -833,7 +1101,7 @@ impl TUI {
                 width_left -= 2;
             }
             // drop any tabs that overflows from the screen
-            for (tab_idx, tab) in (&self.tabs[i..]).iter().enumerate() {
+            for (tab_idx, tab) in self.tabs[i..].iter().enumerate() {
                 if tab.width() > width_left {
                     break;
                 } else {
@@ -883,7 +1151,7 @@ impl TUI {
         // debug!("left_arr: {}, right_arr: {}", left_arr, right_arr);
 
         // finally draw the tabs
-        for (tab_idx, tab) in (&self.tabs[tab_left..tab_right]).iter().enumerate() {
+        for (tab_idx, tab) in self.tabs[tab_left..tab_right].iter().enumerate() {
             tab.draw(
                 &mut self.tb,
                 &self.colors,
@@ -891,7 +1159,7 @@ impl TUI {
                 self.height - 1,
                 self.active_idx == tab_idx + tab_left,
             );
-            pos_x += tab.width() as i32 + 1; // +1 for margin
+            pos_x += tab.width() + 1; // +1 for margin
         }
 
         if right_arr {
@@ -989,7 +1257,7 @@ impl TUI {
                         break;
                     }
                 }
-                MsgSource::User { ref nick, .. } => {
+                MsgSource::User { ref nick, .. } | MsgSource::DccChat { ref nick, .. } => {
                     if nick.contains(string) {
                         next_idx = tab_idx;
                         break;
@@ -1060,8 +1328,7 @@ impl TUI {
                     insert_idx -= 1;
                 }
                 let to_move: Vec<Tab> = self.tabs.drain(left..right).collect();
-                self.tabs
-                    .splice(insert_idx..insert_idx, to_move.into_iter());
+                self.tabs.splice(insert_idx..insert_idx, to_move);
                 self.select_tab(insert_idx);
             }
         } else if !self.is_server_tab(self.active_idx - 1) {
@@ -1083,8 +1350,7 @@ impl TUI {
                 let right_next = self.server_tab_range(right).1;
                 let insert_idx = right_next - (right - left);
                 let to_move: Vec<Tab> = self.tabs.drain(left..right).collect();
-                self.tabs
-                    .splice(insert_idx..insert_idx, to_move.into_iter());
+                self.tabs.splice(insert_idx..insert_idx, to_move);
                 self.select_tab(insert_idx);
             }
         } else if !self.is_server_tab(self.active_idx + 1) {
@@ -1188,6 +1454,10 @@ impl TUI {
                 }
             }
 
+            MsgTarget::DccChat { serv, nick } => {
+                target_idxs.extend(self.find_dcc_chat_tab_idx(serv, nick));
+            }
+
             MsgTarget::AllServTabs { serv } => {
                 for (tab_idx, tab) in self.tabs.iter().enumerate() {
                     if tab.src.serv_name() == serv {
@@ -1223,6 +1493,8 @@ impl TUI {
 
             MsgTarget::User { serv, nick } => self.new_user_tab(serv, nick),
 
+            MsgTarget::DccChat { serv, nick } => self.new_dcc_chat_tab(serv, nick),
+
             _ => None,
         }
     }
@@ -1314,6 +1586,17 @@ impl TUI {
         });
     }
 
+    /// Mark a channel tab as parted (e.g. after a kick) without closing it. Clears the nick list,
+    /// which is filled again when the channel is rejoined. Messages typed in the tab are not sent
+    /// until then.
+    pub(crate) fn set_chan_parted(&mut self, serv: &str, chan: &ChanNameRef) {
+        let target = MsgTarget::Chan { serv, chan };
+        self.apply_to_target(&target, false, &|tab: &mut Tab, _| {
+            tab.parted = true;
+            tab.widget.clear_nicks();
+        });
+    }
+
     pub(crate) fn add_nick(&mut self, nick: &str, ts: Option<Tm>, target: &MsgTarget) {
         self.apply_to_target(target, false, &|tab: &mut Tab, _| {
             tab.widget.join(nick, ts.map(Timestamp::from));
@@ -1379,14 +1662,7 @@ impl TUI {
 
     // TODO: Maybe remove this and add a `create: bool` field to MsgTarget::User
     pub(crate) fn user_tab_exists(&self, serv_: &str, nick_: &str) -> bool {
-        for tab in &self.tabs {
-            if let MsgSource::User { ref serv, ref nick } = tab.src {
-                if serv_ == serv && nick_ == nick {
-                    return true;
-                }
-            }
-        }
-        false
+        self.find_user_tab_idx(serv_, nick_).is_some()
     }
 
     pub(crate) fn set_notifier(&mut self, notifier: Notifier, target: &MsgTarget) {
@@ -1432,9 +1708,22 @@ impl TUI {
     }
 
     fn find_user_tab_idx(&self, serv_: &str, nick_: &str) -> Option<usize> {
+        let nick_ = self.user_nick(serv_, nick_);
         for (tab_idx, tab) in self.tabs.iter().enumerate() {
             if let MsgSource::User { ref serv, ref nick } = tab.src {
-                if serv_ == serv && nick_ == nick {
+                if serv_ == serv && nick_.matches(nick) {
+                    return Some(tab_idx);
+                }
+            }
+        }
+        None
+    }
+
+    fn find_dcc_chat_tab_idx(&self, serv_: &str, nick_: &str) -> Option<usize> {
+        let nick_ = self.user_nick(serv_, nick_);
+        for (tab_idx, tab) in self.tabs.iter().enumerate() {
+            if let MsgSource::DccChat { ref serv, ref nick } = tab.src {
+                if serv_ == serv && nick_.matches(nick) {
                     return Some(tab_idx);
                 }
             }
@@ -1455,7 +1744,7 @@ impl TUI {
     fn is_server_tab(&self, idx: usize) -> bool {
         match self.tabs[idx].src {
             MsgSource::Serv { .. } => true,
-            MsgSource::Chan { .. } | MsgSource::User { .. } => false,
+            MsgSource::Chan { .. } | MsgSource::User { .. } | MsgSource::DccChat { .. } => false,
         }
     }
 
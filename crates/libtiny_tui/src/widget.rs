@@ -1,3 +1,5 @@
+use libtiny_common::ChanName;
+
 pub(crate) enum WidgetRet {
     /// Key is handled by the widget.
     KeyHandled,
@@ -8,6 +10,9 @@ pub(crate) enum WidgetRet {
     /// An input is submitted.
     Input(Vec<char>),
 
+    /// A channel is selected in a channel list to join.
+    Join { serv: String, chan: ChanName },
+
     /// Remove the widget. E.g. close the tab, hide the dialogue etc.
     Remove,
 
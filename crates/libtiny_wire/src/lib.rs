@@ -5,6 +5,7 @@
 //! This library is for implementing clients rather than servers or services, and does not support
 //! the IRC message format in full generality.
 
+use std::collections::HashMap;
 use std::str;
 
 use libtiny_common::{ChanName, ChanNameRef};
@@ -45,8 +46,35 @@ where
     format!("JOIN {}\r\n", chans.join(","))
 }
 
-pub fn part(chan: &ChanNameRef) -> String {
-    format!("PART {}\r\n", chan.display())
+/// Join channels with keys. Channels with keys are listed first, as keys are matched to channels
+/// by position.
+pub fn join_with_keys<'a, I>(chans: I) -> String
+where
+    I: Iterator<Item = (&'a ChanNameRef, Option<&'a str>)> + 'a,
+{
+    let (mut with_key, without_key): (Vec<_>, Vec<_>) = chans.partition(|(_, key)| key.is_some());
+    let keys = with_key
+        .iter()
+        .filter_map(|(_, key)| *key)
+        .collect::<Vec<_>>();
+    with_key.extend(without_key);
+    let chans = with_key
+        .iter()
+        .map(|(chan, _)| chan.display())
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        format!("JOIN {}\r\n", chans.join(","))
+    } else {
+        format!("JOIN {} {}\r\n", chans.join(","), keys.join(","))
+    }
+}
+
+pub fn part<'a, I>(chans: I) -> String
+where
+    I: Iterator<Item = &'a ChanNameRef> + 'a,
+{
+    let chans = chans.map(|c| c.display()).collect::<Vec<_>>();
+    format!("PART {}\r\n", chans.join(","))
 }
 
 pub fn privmsg(msgtarget: &str, msg: &str) -> String {
@@ -62,6 +90,23 @@ pub fn action(msgtarget: &str, msg: &str) -> String {
     format!("PRIVMSG {} :\x01ACTION {}\x01\r\n", msgtarget, msg)
 }
 
+/// A CTCP request, sent as a PRIVMSG.
+pub fn ctcp(msgtarget: &str, ctcp: &CTCP, args: Option<&str>) -> String {
+    format!("PRIVMSG {} :{}\r\n", msgtarget, ctcp_body(ctcp, args))
+}
+
+/// A CTCP reply, sent as a NOTICE.
+pub fn ctcp_reply(msgtarget: &str, ctcp: &CTCP, args: Option<&str>) -> String {
+    format!("NOTICE {} :{}\r\n", msgtarget, ctcp_body(ctcp, args))
+}
+
+fn ctcp_body(ctcp: &CTCP, args: Option<&str>) -> String {
+    match args {
+        None => format!("\x01{}\x01", ctcp.as_str()),
+        Some(args) => format!("\x01{} {}\x01", ctcp.as_str(), args),
+    }
+}
+
 pub fn away(msg: Option<&str>) -> String {
     match msg {
         None => "AWAY\r\n".to_string(),
@@ -69,8 +114,76 @@ pub fn away(msg: Option<&str>) -> String {
     }
 }
 
+/// A MODE message. Without `modes` this queries the current modes of the target.
+pub fn mode(target: &str, modes: &[&str]) -> String {
+    if modes.is_empty() {
+        format!("MODE {}\r\n", target)
+    } else {
+        format!("MODE {} {}\r\n", target, modes.join(" "))
+    }
+}
+
+/// MODE messages that set (or unset, when `set` is `false`) `mode` with each of `params`, e.g.
+/// `+o` for a list of nicks. Each message has at most `max_modes` changes; `None` means no limit.
+pub fn mode_batches(
+    target: &str,
+    set: bool,
+    mode: char,
+    params: &[&str],
+    max_modes: Option<usize>,
+) -> Vec<String> {
+    let batch_size = max_modes.unwrap_or(params.len()).max(1);
+    params
+        .chunks(batch_size)
+        .map(|params| {
+            let modes: String = std::iter::once(if set { '+' } else { '-' })
+                .chain(std::iter::repeat_n(mode, params.len()))
+                .collect();
+            format!("MODE {} {} {}\r\n", target, modes, params.join(" "))
+        })
+        .collect()
+}
+
+pub fn kick(chan: &ChanNameRef, nick: &str, reason: Option<&str>) -> String {
+    match reason {
+        None => format!("KICK {} {}\r\n", chan.display(), nick),
+        Some(reason) => format!("KICK {} {} :{}\r\n", chan.display(), nick, reason),
+    }
+}
+
+pub fn invite(nick: &str, chan: &ChanNameRef) -> String {
+    format!("INVITE {} {}\r\n", nick, chan.display())
+}
+
+pub fn list() -> String {
+    "LIST\r\n".to_string()
+}
+
+pub fn whois(nick: &str) -> String {
+    format!("WHOIS {}\r\n", nick)
+}
+
+/// A WHOWAS query. `count` is the max. number of entries to return.
+pub fn whowas(nick: &str, count: Option<u32>) -> String {
+    match count {
+        None => format!("WHOWAS {}\r\n", nick),
+        Some(count) => format!("WHOWAS {} {}\r\n", nick, count),
+    }
+}
+
+/// Set the topic of a channel, or query it when `topic` is `None`. An empty topic clears the
+/// topic.
+pub fn topic(chan: &ChanNameRef, topic: Option<&str>) -> String {
+    match topic {
+        None => format!("TOPIC {}\r\n", chan.display()),
+        Some(topic) => format!("TOPIC {} :{}\r\n", chan.display(), topic),
+    }
+}
+
+/// Request the capability list, using version 302 of capability negotiation. See
+/// https://ircv3.net/specs/extensions/capability-negotiation#cap-ls-version
 pub fn cap_ls() -> String {
-    "CAP LS\r\n".to_string()
+    "CAP LS 302\r\n".to_string()
 }
 
 pub fn cap_req(cap_identifiers: &[&str]) -> String {
@@ -85,6 +198,92 @@ pub fn authenticate(msg: &str) -> String {
     format!("AUTHENTICATE {}\r\n", msg)
 }
 
+/// Attach IRCv3 message tags to a message generated by one of the functions in this module. Tags
+/// with empty values are sent without a value (e.g. `label=` is sent as `label`). Values are
+/// escaped as described in https://ircv3.net/specs/extensions/message-tags#escaping-values.
+///
+/// Client-only tags (e.g. `+reply`) need to have the `+` prefix in the key.
+pub fn with_tags(tags: &[(&str, &str)], msg: &str) -> String {
+    if tags.is_empty() {
+        return msg.to_owned();
+    }
+
+    let mut ret = String::with_capacity(msg.len() + 32);
+    ret.push('@');
+    for (tag_idx, (key, value)) in tags.iter().enumerate() {
+        if tag_idx != 0 {
+            ret.push(';');
+        }
+        ret.push_str(key);
+        if !value.is_empty() {
+            ret.push('=');
+            escape_tag_value(value, &mut ret);
+        }
+    }
+    ret.push(' ');
+    ret.push_str(msg);
+    ret
+}
+
+/// A TAGMSG, used to send tags without a message (e.g. typing notifications). Requires the
+/// `message-tags` capability.
+pub fn tagmsg(msgtarget: &str, tags: &[(&str, &str)]) -> String {
+    with_tags(tags, &format!("TAGMSG {}\r\n", msgtarget))
+}
+
+/// A single mode change in a MODE message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeChange {
+    /// `true` for `+`, `false` for `-`.
+    pub set: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+/// Split a mode string into changes, assigning parameters to modes in order. `takes_param` is
+/// called with `set` (`true` for `+`) and the mode character. Changes without a parameter when
+/// one is needed are dropped.
+pub fn split_mode_changes<F>(modes: &str, params: &[String], takes_param: F) -> Vec<ModeChange>
+where
+    F: Fn(bool, char) -> bool,
+{
+    let mut params = params.iter();
+    let mut changes = vec![];
+    let mut set = true;
+    for mode in modes.chars() {
+        match mode {
+            '+' => set = true,
+            '-' => set = false,
+            _ => {
+                let param = if takes_param(set, mode) {
+                    match params.next() {
+                        None => continue,
+                        Some(param) => Some(param.clone()),
+                    }
+                } else {
+                    None
+                };
+                changes.push(ModeChange { set, mode, param });
+            }
+        }
+    }
+    changes
+}
+
+/// Default rules for channel mode parameters, used when the server's modes are not known: `b`,
+/// `e`, `I`, `k`, and the membership modes `q`, `a`, `o`, `h`, `v` always take a parameter, `l`
+/// takes one when set. User modes don't take parameters.
+pub fn default_mode_takes_param(target: &str, set: bool, mode: char) -> bool {
+    if !(target.starts_with('#') || target.starts_with('&')) {
+        return false;
+    }
+    match mode {
+        'b' | 'e' | 'I' | 'k' | 'q' | 'a' | 'o' | 'h' | 'v' => true,
+        'l' => set,
+        _ => false,
+    }
+}
+
 /// Sender of a message ("prefix" in the RFC). Instead of returning a `String` we parse prefix part
 /// of the message according to the RFC because users of this library sometimes need to distinguish
 /// a server from a user. For example, in tiny if a PRIVMSG to us is coming from a server then we
@@ -148,8 +347,8 @@ impl Pfx {
 fn parse_pfx(pfx: &str) -> Pfx {
     match pfx.find(&['!', '@'][..]) {
         Some(idx) => Pfx::User {
-            nick: (&pfx[0..idx]).to_owned(),
-            user: (&pfx[idx + 1..]).to_owned(),
+            nick: pfx[0..idx].to_owned(),
+            user: pfx[idx + 1..].to_owned(),
         },
         None => {
             // Chars that nicks can have but servernames cannot
@@ -186,6 +385,13 @@ pub enum MsgTarget {
 /// An IRC message
 #[derive(Debug, PartialEq, Eq)]
 pub struct Msg {
+    /// IRCv3 message tags (https://ircv3.net/specs/extensions/message-tags). Values are unescaped.
+    /// Tags without a value are mapped to empty strings, as the spec says the two are equivalent.
+    /// Client-only tags keep their `+` prefix in the key.
+    pub tags: HashMap<String, String>,
+    /// Time the message was sent, from the `time` tag (the `server-time` extension). In local
+    /// time zone. `None` when the server did not send a `time` tag or it could not be parsed.
+    pub server_time: Option<time::Tm>,
     /// Sender of a message. According to RFC 2812 it's optional:
     ///
     /// > If the prefix is missing from the message, it is assumed to have originated from the
@@ -199,17 +405,44 @@ pub struct Msg {
 pub enum CTCP {
     Version,
     Action,
+    Ping,
+    Time,
+    ClientInfo,
+    Source,
+    UserInfo,
+    Dcc,
     Other(String),
 }
 
 impl CTCP {
-    fn parse(s: &str) -> CTCP {
+    /// Parse a CTCP type. Types are case sensitive, unknown types are returned as `Other`.
+    pub fn parse(s: &str) -> CTCP {
         match s {
             "VERSION" => CTCP::Version,
             "ACTION" => CTCP::Action,
+            "PING" => CTCP::Ping,
+            "TIME" => CTCP::Time,
+            "CLIENTINFO" => CTCP::ClientInfo,
+            "SOURCE" => CTCP::Source,
+            "USERINFO" => CTCP::UserInfo,
+            "DCC" => CTCP::Dcc,
             _ => CTCP::Other(s.to_owned()),
         }
     }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            CTCP::Version => "VERSION",
+            CTCP::Action => "ACTION",
+            CTCP::Ping => "PING",
+            CTCP::Time => "TIME",
+            CTCP::ClientInfo => "CLIENTINFO",
+            CTCP::Source => "SOURCE",
+            CTCP::UserInfo => "USERINFO",
+            CTCP::Dcc => "DCC",
+            CTCP::Other(s) => s,
+        }
+    }
 }
 
 /// An IRC command or reply
@@ -217,23 +450,23 @@ impl CTCP {
 pub enum Cmd {
     /// A PRIVMSG or NOTICE. Check `is_notice` field.
     PRIVMSG {
-        // TODO: In theory this should be a list of targets, but in practice I've never
-        // encountered that case.
-        target: MsgTarget,
+        /// Targets of the message. Almost always a single target, but the protocol allows
+        /// comma-separated lists (see TARGMAX in `RPL_ISUPPORT`).
+        targets: Vec<MsgTarget>,
         msg: String,
         is_notice: bool,
         ctcp: Option<CTCP>,
     },
 
     JOIN {
-        // TODO: Same as above, this should be a list ...
-        chan: ChanName, // TODO: key field might be useful when joining restricted channels. In
-                        // practice I've never needed it.
+        chans: Vec<ChanName>,
+        /// Channel keys, in the same order as `chans`. Usually empty as servers don't include
+        /// keys when relaying JOINs. Channels without a key come after the ones with a key.
+        keys: Vec<String>,
     },
 
     PART {
-        // TODO: List of channels
-        chan: ChanName,
+        chans: Vec<ChanName>,
         msg: Option<String>,
     },
 
@@ -274,12 +507,61 @@ pub enum Cmd {
         client: String,
         subcommand: String,
         params: Vec<String>,
+        /// Whether more messages will follow this one in a multiline `LS` or `LIST` reply (CAP
+        /// version 302).
+        more: bool,
     },
 
     AUTHENTICATE {
         param: String,
     },
 
+    /// Channel or user mode changes.
+    MODE {
+        target: String,
+        /// Mode string as sent by the server, e.g. `+ov-b`.
+        modes: String,
+        /// Parameters of the modes, e.g. `["nick1", "nick2", "*!*@host"]`.
+        params: Vec<String>,
+        /// `modes` split into changes with their parameters. Split using the default parameter
+        /// rules (see `split_mode_changes`); `libtiny_client` splits them again using the modes
+        /// advertised by the server.
+        changes: Vec<ModeChange>,
+    },
+
+    KICK {
+        chan: ChanName,
+        nick: String,
+        msg: Option<String>,
+    },
+
+    /// We're invited to a channel, or with `invite-notify`, someone else is invited to a channel
+    /// that we're in.
+    INVITE {
+        nick: String,
+        chan: ChanName,
+    },
+
+    /// Account of a user changed (`account-notify`). `None` means the user logged out.
+    ACCOUNT {
+        account: Option<String>,
+    },
+
+    /// Username or hostname of a user changed (`chghost`).
+    CHGHOST {
+        new_user: String,
+        new_host: String,
+    },
+
+    /// Real name of a user changed (`setname`).
+    SETNAME {
+        realname: String,
+    },
+
+    WALLOPS {
+        msg: String,
+    },
+
     /// An IRC message other than the ones listed above.
     Other {
         cmd: String,
@@ -307,12 +589,7 @@ static CRLF: [u8; 2] = [b'\r', b'\n'];
 pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Result<Msg, String>> {
     // Find "\r\n" separator. We can't do this *after* generating the lossy UTF-8, as that may have
     // different size than the original buffer after inserting "REPLACEMENT CHARACTER"s.
-    let crlf_idx = {
-        match buf.windows(2).position(|sub| sub == CRLF) {
-            None => return None,
-            Some(i) => i,
-        }
-    };
+    let crlf_idx = buf.windows(2).position(|sub| sub == CRLF)?;
 
     let msg_owned: String = String::from_utf8_lossy(&buf[0..crlf_idx]).to_string();
     let msg: &str = &msg_owned;
@@ -325,6 +602,20 @@ pub fn parse_irc_msg(buf: &mut Vec<u8>) -> Option<Result<Msg, String>> {
 
 // NB. 'msg' does not contain '\r\n' suffix.
 fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
+    let tags: HashMap<String, String> = {
+        if let Some('@') = msg.chars().next() {
+            let ws_idx = msg.find(' ').ok_or(format!(
+                "Can't find tags terminator (' ') in msg: {:?}",
+                msg
+            ))?;
+            let tags = parse_tags(&msg[1..ws_idx]); // consume '@'
+            msg = msg[ws_idx + 1..].trim_start_matches(' '); // consume ' '
+            tags
+        } else {
+            HashMap::new()
+        }
+    };
+
     let pfx: Option<Pfx> = {
         if let Some(':') = msg.chars().next() {
             // parse prefix
@@ -357,13 +648,18 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
     let cmd = match msg_ty {
         MsgType::Cmd("PRIVMSG") | MsgType::Cmd("NOTICE") if params.len() == 2 => {
             let is_notice = matches!(msg_ty, MsgType::Cmd("NOTICE"));
-            let target = params[0];
+            let targets = params[0]
+                .split(',')
+                .filter(|target| !target.is_empty())
+                .map(|target| {
+                    if target.starts_with('#') {
+                        MsgTarget::Chan(ChanName::new(target.to_owned()))
+                    } else {
+                        MsgTarget::User(target.to_owned())
+                    }
+                })
+                .collect();
             let mut msg = params[1];
-            let target = if target.starts_with('#') {
-                MsgTarget::Chan(ChanName::new(target.to_owned()))
-            } else {
-                MsgTarget::User(target.to_owned())
-            };
 
             let mut ctcp: Option<CTCP> = None;
             if !msg.is_empty() && msg.as_bytes()[0] == 0x01 {
@@ -389,16 +685,23 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
             }
 
             Cmd::PRIVMSG {
-                target,
+                targets,
                 msg: msg.to_owned(),
                 is_notice,
                 ctcp,
             }
         }
-        MsgType::Cmd("JOIN") if params.len() == 1 => {
-            let chan = params[0];
+        // With extended-join JOINs have two more parameters: account name and real name. With two
+        // parameters the second one is the list of channel keys.
+        MsgType::Cmd("JOIN") if (1..=3).contains(&params.len()) => {
+            let keys = if params.len() == 2 {
+                split_list(params[1])
+            } else {
+                vec![]
+            };
             Cmd::JOIN {
-                chan: ChanName::new(chan.to_owned()),
+                chans: split_chans(params[0]),
+                keys,
             }
         }
         MsgType::Cmd("PART") if params.len() == 1 || params.len() == 2 => {
@@ -408,7 +711,7 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
                 None
             };
             Cmd::PART {
-                chan: ChanName::new(params[0].to_owned()),
+                chans: split_chans(params[0]),
                 msg: mb_msg,
             }
         }
@@ -443,11 +746,59 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
         MsgType::Cmd("CAP") if params.len() == 3 => Cmd::CAP {
             client: params[0].to_owned(),
             subcommand: params[1].to_owned(),
-            params: params[2].split(' ').map(|s| s.to_owned()).collect(),
+            params: parse_cap_list(params[2]),
+            more: false,
+        },
+        // Multiline LS and LIST replies: `CAP <client> LS * :<caps>`
+        MsgType::Cmd("CAP") if params.len() == 4 && params[2] == "*" => Cmd::CAP {
+            client: params[0].to_owned(),
+            subcommand: params[1].to_owned(),
+            params: parse_cap_list(params[3]),
+            more: true,
         },
         MsgType::Cmd("AUTHENTICATE") if params.len() == 1 => Cmd::AUTHENTICATE {
             param: params[0].to_owned(),
         },
+        MsgType::Cmd("MODE") if params.len() >= 2 => {
+            let target = params[0];
+            let modes = params[1];
+            let params: Vec<String> = params[2..].iter().map(|s| (*s).to_owned()).collect();
+            let changes = split_mode_changes(modes, &params, |set, mode| {
+                default_mode_takes_param(target, set, mode)
+            });
+            Cmd::MODE {
+                target: target.to_owned(),
+                modes: modes.to_owned(),
+                params,
+                changes,
+            }
+        }
+        MsgType::Cmd("KICK") if params.len() == 2 || params.len() == 3 => Cmd::KICK {
+            chan: ChanName::new(params[0].to_owned()),
+            nick: params[1].to_owned(),
+            msg: params.get(2).map(|s| (*s).to_owned()),
+        },
+        MsgType::Cmd("INVITE") if params.len() == 2 => Cmd::INVITE {
+            nick: params[0].to_owned(),
+            chan: ChanName::new(params[1].to_owned()),
+        },
+        MsgType::Cmd("ACCOUNT") if params.len() == 1 => Cmd::ACCOUNT {
+            account: if params[0] == "*" {
+                None
+            } else {
+                Some(params[0].to_owned())
+            },
+        },
+        MsgType::Cmd("CHGHOST") if params.len() == 2 => Cmd::CHGHOST {
+            new_user: params[0].to_owned(),
+            new_host: params[1].to_owned(),
+        },
+        MsgType::Cmd("SETNAME") if params.len() == 1 => Cmd::SETNAME {
+            realname: params[0].to_owned(),
+        },
+        MsgType::Cmd("WALLOPS") if params.len() == 1 => Cmd::WALLOPS {
+            msg: params[0].to_owned(),
+        },
         MsgType::Num(n) => Cmd::Reply {
             num: n,
             params: params.into_iter().map(|s| s.to_owned()).collect(),
@@ -458,7 +809,111 @@ fn parse_one_message(mut msg: &str) -> Result<Msg, String> {
         },
     };
 
-    Ok(Msg { pfx, cmd })
+    let server_time = tags.get("time").and_then(|time| parse_server_time(time));
+
+    Ok(Msg {
+        tags,
+        server_time,
+        pfx,
+        cmd,
+    })
+}
+
+// https://ircv3.net/specs/extensions/server-time
+//
+// Format is `YYYY-MM-DDThh:mm:ss.sssZ`, always in UTC. Milliseconds are ignored.
+fn parse_server_time(time: &str) -> Option<time::Tm> {
+    if !time.ends_with('Z') {
+        return None;
+    }
+    time::strptime(time, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .map(|tm| tm.to_local())
+}
+
+// https://ircv3.net/specs/extensions/message-tags#format
+//
+//     <tags>          ::= <tag> [';' <tag>]*
+//     <tag>           ::= <key> ['=' <escaped_value>]
+//     <key>           ::= [ <client_prefix> ] [ <vendor> '/' ] <key_name>
+//
+// When a key is repeated the last value is used.
+fn parse_tags(tags: &str) -> HashMap<String, String> {
+    let mut ret = HashMap::new();
+    for tag in tags.split(';') {
+        if tag.is_empty() {
+            continue;
+        }
+        match tag.find('=') {
+            None => {
+                ret.insert(tag.to_owned(), String::new());
+            }
+            Some(eq_idx) => {
+                let key = &tag[..eq_idx];
+                if key.is_empty() {
+                    continue;
+                }
+                ret.insert(key.to_owned(), unescape_tag_value(&tag[eq_idx + 1..]));
+            }
+        }
+    }
+    ret
+}
+
+// https://ircv3.net/specs/extensions/message-tags#escaping-values
+fn unescape_tag_value(value: &str) -> String {
+    let mut ret = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => ret.push(';'),
+            Some('s') => ret.push(' '),
+            Some('\\') => ret.push('\\'),
+            Some('r') => ret.push('\r'),
+            Some('n') => ret.push('\n'),
+            // Invalid escapes drop the backslash
+            Some(c) => ret.push(c),
+            // Trailing backslash is dropped
+            None => {}
+        }
+    }
+    ret
+}
+
+fn escape_tag_value(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+}
+
+fn parse_cap_list(caps: &str) -> Vec<String> {
+    caps.split(' ')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+/// Split a comma-separated list, e.g. list of channels in JOIN or PART.
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect()
+}
+
+fn split_chans(chans: &str) -> Vec<ChanName> {
+    split_list(chans).into_iter().map(ChanName::new).collect()
 }
 
 fn parse_params(chrs: &str) -> Vec<&str> {
@@ -516,15 +971,16 @@ fn parse_params(chrs: &str) -> Vec<&str> {
 ///
 /// Channel Membership Prefixes: http://modern.ircdocs.horse/#channel-membership-prefixes
 ///
-/// Returns the nick without prefix.
+/// Returns the nick without prefix. With `multi-prefix` capability a nick can have more than one
+/// prefix, all of them are dropped.
+///
+/// This only knows the common prefixes `~&@%+`. It's a fallback for when the server's prefixes
+/// are not known, `libtiny_client::ServerFeatures::drop_nick_prefix` uses the prefixes advertised
+/// in `PREFIX`.
 pub fn drop_nick_prefix(nick: &str) -> &str {
     static PREFIXES: [char; 5] = ['~', '&', '@', '%', '+'];
 
-    if PREFIXES.contains(&nick.chars().next().unwrap()) {
-        &nick[1..]
-    } else {
-        nick
-    }
+    nick.trim_start_matches(&PREFIXES[..])
 }
 
 #[cfg(test)]
@@ -570,12 +1026,14 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "nick".to_owned(),
                     user: "~nick@unaffiliated/nick".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("tiny".to_owned()),
+                    targets: vec![MsgTarget::User("tiny".to_owned())],
                     msg: "a b c".to_owned(),
                     is_notice: false,
                     ctcp: None,
@@ -596,9 +1054,11 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::Server("barjavel.freenode.net".to_owned())),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("*".to_owned()),
+                    targets: vec![MsgTarget::User("*".to_owned())],
                     msg: "*** Looking up your hostname...".to_owned(),
                     is_notice: true,
                     ctcp: None,
@@ -654,18 +1114,110 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@123.123.123.123".to_owned(),
                 }),
                 cmd: Cmd::PART {
-                    chan: ChanName::new("#haskell".to_owned()),
+                    chans: vec![ChanName::new("#haskell".to_owned())],
                     msg: None,
                 },
             }
         );
     }
 
+    #[test]
+    fn test_mode_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":op!~op@host MODE #tiny +ov-b+l nick1 nick2 *!*@spam 10\r\n"
+        )
+        .unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        let change = |set, mode, param: Option<&str>| ModeChange {
+            set,
+            mode,
+            param: param.map(str::to_owned),
+        };
+        match msg.cmd {
+            Cmd::MODE {
+                target,
+                modes,
+                params,
+                changes,
+            } => {
+                assert_eq!(target, "#tiny");
+                assert_eq!(modes, "+ov-b+l");
+                assert_eq!(params.len(), 4);
+                assert_eq!(
+                    changes,
+                    vec![
+                        change(true, 'o', Some("nick1")),
+                        change(true, 'v', Some("nick2")),
+                        change(false, 'b', Some("*!*@spam")),
+                        change(true, 'l', Some("10")),
+                    ]
+                );
+            }
+            other => panic!("Unexpected cmd: {:?}", other),
+        }
+
+        // User modes
+        write!(&mut buf, ":tiny MODE tiny :+iw\r\n").unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        match msg.cmd {
+            Cmd::MODE { changes, .. } => {
+                assert_eq!(
+                    changes,
+                    vec![change(true, 'i', None), change(true, 'w', None)]
+                );
+            }
+            other => panic!("Unexpected cmd: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_kick_invite_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":op!~op@host KICK #tiny tiny :bye\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::KICK {
+                chan: ChanName::new("#tiny".to_owned()),
+                nick: "tiny".to_owned(),
+                msg: Some("bye".to_owned()),
+            }
+        );
+
+        write!(&mut buf, ":op!~op@host INVITE tiny :#tiny\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::INVITE {
+                nick: "tiny".to_owned(),
+                chan: ChanName::new("#tiny".to_owned()),
+            }
+        );
+
+        write!(&mut buf, ":nick!~u@h ACCOUNT *\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::ACCOUNT { account: None }
+        );
+
+        write!(&mut buf, ":nick!~u@h CHGHOST newuser new.host\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::CHGHOST {
+                new_user: "newuser".to_owned(),
+                new_host: "new.host".to_owned(),
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
     #[test]
     fn test_join_parsing() {
         let mut buf = vec![];
@@ -673,12 +1225,15 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "tiny".to_owned(),
                     user: "~tiny@192.168.0.1".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
-                    chan: ChanName::new("#haskell".to_owned()),
+                    chans: vec![ChanName::new("#haskell".to_owned())],
+                    keys: vec![],
                 },
             }
         );
@@ -697,12 +1252,14 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "dan".to_owned(),
                     user: "u@localhost".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::Chan(ChanName::new("#ircv3".to_owned())),
+                    targets: vec![MsgTarget::Chan(ChanName::new("#ircv3".to_owned()))],
                     msg: "writes some specs!".to_owned(),
                     is_notice: false,
                     ctcp: Some(CTCP::Action),
@@ -728,7 +1285,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "msg contents".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Action),
@@ -741,7 +1298,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Action),
@@ -756,7 +1313,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "’’’’’’’".to_owned(),
                 is_notice: false,
                 ctcp: None,
@@ -772,7 +1329,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Version),
@@ -784,7 +1341,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Version),
@@ -799,7 +1356,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "blah ".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Other("blah".to_owned())),
@@ -811,7 +1368,7 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
             Cmd::PRIVMSG {
-                target: MsgTarget::User("target".to_owned()),
+                targets: vec![MsgTarget::User("target".to_owned())],
                 msg: "blah ".to_owned(),
                 is_notice: false,
                 ctcp: Some(CTCP::Other("blah".to_owned())),
@@ -819,6 +1376,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn ctcp_ping_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":a!b@c NOTICE target :\x01PING 1234\x01\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![MsgTarget::User("target".to_owned())],
+                msg: "1234".to_owned(),
+                is_notice: true,
+                ctcp: Some(CTCP::Ping),
+            }
+        );
+    }
+
+    #[test]
+    fn ctcp_generation() {
+        assert_eq!(
+            ctcp("nick", &CTCP::Version, None),
+            "PRIVMSG nick :\x01VERSION\x01\r\n"
+        );
+        assert_eq!(
+            ctcp_reply("nick", &CTCP::Ping, Some("1234")),
+            "NOTICE nick :\x01PING 1234\x01\r\n"
+        );
+        assert_eq!(
+            ctcp("nick", &CTCP::Other("FOO".to_owned()), Some("a b")),
+            "PRIVMSG nick :\x01FOO a b\x01\r\n"
+        );
+    }
+
     #[test]
     fn test_error_parsing() {
         let mut buf = vec![];
@@ -830,6 +1418,8 @@ mod tests {
         assert_eq!(
             parse_irc_msg(&mut buf).unwrap().unwrap(),
             Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: None,
                 cmd: Cmd::ERROR {
                     msg: "Closing Link: 212.252.143.51 (Excess Flood)".to_owned(),
@@ -838,6 +1428,243 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tags_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@aaa=bbb;ccc;example.com/ddd=eee;+fff=a\\sb\\:c\\\\ :nick!ident@host.com PRIVMSG me :Hello\r\n"
+        )
+        .unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        let mut tags = HashMap::new();
+        tags.insert("aaa".to_owned(), "bbb".to_owned());
+        tags.insert("ccc".to_owned(), "".to_owned());
+        tags.insert("example.com/ddd".to_owned(), "eee".to_owned());
+        tags.insert("+fff".to_owned(), "a b;c\\".to_owned());
+        assert_eq!(
+            msg,
+            Msg {
+                tags,
+                server_time: None,
+                pfx: Some(Pfx::User {
+                    nick: "nick".to_owned(),
+                    user: "ident@host.com".to_owned(),
+                }),
+                cmd: Cmd::PRIVMSG {
+                    targets: vec![MsgTarget::User("me".to_owned())],
+                    msg: "Hello".to_owned(),
+                    is_notice: false,
+                    ctcp: None,
+                },
+            }
+        );
+        assert_eq!(buf.len(), 0);
+
+        // Tags without a prefix
+        let mut buf = vec![];
+        write!(&mut buf, "@time=2021-01-01T00:00:00.000Z PING :x\r\n").unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.pfx, None);
+        assert_eq!(
+            msg.tags.get("time").map(String::as_str),
+            Some("2021-01-01T00:00:00.000Z")
+        );
+        assert_eq!(
+            msg.cmd,
+            Cmd::PING {
+                server: "x".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn test_server_time_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            "@time=2011-10-19T16:40:51.620Z :a!b@c PRIVMSG #chan :Hello\r\n"
+        )
+        .unwrap();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        let ts = msg.server_time.unwrap();
+        assert_eq!(ts.to_timespec(), time::Timespec::new(1319042451, 0));
+
+        // Invalid timestamps are ignored
+        let mut buf = vec![];
+        write!(&mut buf, "@time=yesterday :a!b@c PRIVMSG #chan :Hello\r\n").unwrap();
+        assert_eq!(parse_irc_msg(&mut buf).unwrap().unwrap().server_time, None);
+    }
+
+    #[test]
+    fn test_tag_value_escaping() {
+        assert_eq!(unescape_tag_value("a\\sb"), "a b");
+        assert_eq!(unescape_tag_value("\\:\\s\\\\\\r\\n"), "; \\\r\n");
+        // Invalid escapes drop the backslash, trailing backslash is dropped
+        assert_eq!(unescape_tag_value("\\b"), "b");
+        assert_eq!(unescape_tag_value("ab\\"), "ab");
+
+        for value in &["", "a b", "a;b", "\\", "\r\n", "x\\sy"] {
+            let mut escaped = String::new();
+            escape_tag_value(value, &mut escaped);
+            assert!(!escaped.contains(' ') && !escaped.contains(';'));
+            assert_eq!(&unescape_tag_value(&escaped), value);
+        }
+    }
+
+    #[test]
+    fn test_tags_generation() {
+        assert_eq!(
+            with_tags(&[], &privmsg("nick", "hi")),
+            "PRIVMSG nick :hi\r\n"
+        );
+        assert_eq!(
+            with_tags(&[("+reply", "abc"), ("label", "")], &privmsg("#chan", "hi")),
+            "@+reply=abc;label PRIVMSG #chan :hi\r\n"
+        );
+        assert_eq!(
+            tagmsg("#chan", &[("+typing", "active")]),
+            "@+typing=active TAGMSG #chan\r\n"
+        );
+        assert_eq!(
+            with_tags(&[("+x", "a b;c")], "PING x\r\n"),
+            "@+x=a\\sb\\:c PING x\r\n"
+        );
+
+        let mut buf = with_tags(&[("+x", "a b;c"), ("y", "")], &privmsg("t", "m")).into_bytes();
+        let msg = parse_irc_msg(&mut buf).unwrap().unwrap();
+        assert_eq!(msg.tags.get("+x").map(String::as_str), Some("a b;c"));
+        assert_eq!(msg.tags.get("y").map(String::as_str), Some(""));
+    }
+
+    #[test]
+    fn test_cap_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":irc.example.com CAP * LS * :multi-prefix extended-join sasl=PLAIN,EXTERNAL\r\n"
+        )
+        .unwrap();
+        write!(&mut buf, ":irc.example.com CAP * LS :server-time \r\n").unwrap();
+        write!(&mut buf, ":irc.example.com CAP tiny ACK :server-time\r\n").unwrap();
+
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec![
+                    "multi-prefix".to_owned(),
+                    "extended-join".to_owned(),
+                    "sasl=PLAIN,EXTERNAL".to_owned()
+                ],
+                more: true,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::CAP {
+                client: "*".to_owned(),
+                subcommand: "LS".to_owned(),
+                params: vec!["server-time".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::CAP {
+                client: "tiny".to_owned(),
+                subcommand: "ACK".to_owned(),
+                params: vec!["server-time".to_owned()],
+                more: false,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_extended_join_parsing() {
+        let mut buf = vec![];
+        write!(
+            &mut buf,
+            ":tiny!~tiny@192.168.0.1 JOIN #haskell tiny_account :Real Name\r\n"
+        )
+        .unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec![ChanName::new("#haskell".to_owned())],
+                keys: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_multi_target_parsing() {
+        let mut buf = vec![];
+        write!(&mut buf, ":a!b@c JOIN #a,#b key1\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::JOIN {
+                chans: vec![
+                    ChanName::new("#a".to_owned()),
+                    ChanName::new("#b".to_owned())
+                ],
+                keys: vec!["key1".to_owned()],
+            }
+        );
+
+        write!(&mut buf, ":a!b@c PART #a,#b :bye\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PART {
+                chans: vec![
+                    ChanName::new("#a".to_owned()),
+                    ChanName::new("#b".to_owned())
+                ],
+                msg: Some("bye".to_owned()),
+            }
+        );
+
+        write!(&mut buf, ":a!b@c PRIVMSG #a,nick :hi\r\n").unwrap();
+        assert_eq!(
+            parse_irc_msg(&mut buf).unwrap().unwrap().cmd,
+            Cmd::PRIVMSG {
+                targets: vec![
+                    MsgTarget::Chan(ChanName::new("#a".to_owned())),
+                    MsgTarget::User("nick".to_owned())
+                ],
+                msg: "hi".to_owned(),
+                is_notice: false,
+                ctcp: None,
+            }
+        );
+        assert_eq!(buf.len(), 0);
+    }
+
+    #[test]
+    fn test_join_part_generation() {
+        let a = ChanNameRef::new("#a");
+        let b = ChanNameRef::new("#b");
+        let c = ChanNameRef::new("#c");
+        assert_eq!(
+            join_with_keys(vec![(a, None), (b, Some("key_b")), (c, Some("key_c"))].into_iter()),
+            "JOIN #b,#c,#a key_b,key_c\r\n"
+        );
+        assert_eq!(
+            join_with_keys(vec![(a, None), (b, None)].into_iter()),
+            "JOIN #a,#b\r\n"
+        );
+        assert_eq!(part(vec![a, b].into_iter()), "PART #a,#b\r\n");
+    }
+
+    #[test]
+    fn test_drop_nick_prefix() {
+        assert_eq!(drop_nick_prefix("nick"), "nick");
+        assert_eq!(drop_nick_prefix("@nick"), "nick");
+        assert_eq!(drop_nick_prefix("@+nick"), "nick");
+    }
+
     #[test]
     fn test_parse_pfx() {
         use Pfx::*;
@@ -870,4 +1697,16 @@ mod tests {
             }
         );
     }
+    #[test]
+    fn test_mode_batches() {
+        assert_eq!(
+            mode_batches("#chan", true, 'o', &["a", "b", "c", "d"], Some(3)),
+            vec!["MODE #chan +ooo a b c\r\n", "MODE #chan +o d\r\n"]
+        );
+        assert_eq!(
+            mode_batches("#chan", false, 'b', &["*!*@h1", "*!*@h2"], None),
+            vec!["MODE #chan -bb *!*@h1 *!*@h2\r\n"]
+        );
+        assert!(mode_batches("#chan", true, 'v', &[], Some(3)).is_empty());
+    }
 }
@@ -140,11 +140,7 @@ byte_seq_parser! {
 // static XTERM_FOCUS_LOST: [u8; 3] = [27, 91, 79];
 
 pub fn is_valid_key(key: Key) -> bool {
-    parse_key_bytes_is_valid_key(key)
-        | match key {
-            Key::AltChar(_) | Key::Char(_) => true,
-            _ => false,
-        }
+    parse_key_bytes_is_valid_key(key) | matches!(key, Key::AltChar(_) | Key::Char(_))
 }
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -164,11 +160,8 @@ pub struct Input {
 impl Drop for Input {
     fn drop(&mut self) {
         if let Some(old_flags) = self.old_stdin_flags.take() {
-            match fcntl(libc::STDIN_FILENO, FcntlArg::F_SETFL(old_flags)) {
-                Err(err) => {
-                    error!("Unable to restore stdin flags: {:?}", err);
-                }
-                Ok(_) => {}
+            if let Err(err) = fcntl(libc::STDIN_FILENO, FcntlArg::F_SETFL(old_flags)) {
+                error!("Unable to restore stdin flags: {:?}", err);
             }
         }
     }
@@ -230,7 +223,7 @@ impl Stream for Input {
     type Item = std::io::Result<Event>;
 
     fn poll_next(mut self: Pin<&mut Input>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let self_: &mut Input = &mut *self;
+        let self_: &mut Input = &mut self;
 
         'main: loop {
             // Try to parse any bytes in the input buffer from the last poll
@@ -416,12 +409,19 @@ pub fn read_stdin(buf: &mut Vec<u8>) -> Result<(), nix::Error> {
     loop {
         let old_len = buf.len();
         buf.reserve(100);
-        unsafe {
-            buf.set_len(old_len + 100);
-        }
-
-        match nix::unistd::read(libc::STDIN_FILENO, &mut buf[old_len..]) {
+        let spare = &mut buf.spare_capacity_mut()[..100];
+        let ret = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                spare.as_mut_ptr() as *mut libc::c_void,
+                spare.len(),
+            )
+        };
+
+        match nix::errno::Errno::result(ret) {
             Ok(n_read) => {
+                // `read` initialized the first `n_read` bytes of the spare capacity
+                let n_read = n_read as usize;
                 unsafe { buf.set_len(old_len + n_read) };
                 if n_read == 0 {
                     // We're in non-canonical mode, or stdin is closed. We can't distinguish the
@@ -429,17 +429,14 @@ pub fn read_stdin(buf: &mut Vec<u8>) -> Result<(), nix::Error> {
                     return Ok(());
                 }
             }
-            Err(err) => {
-                unsafe { buf.set_len(old_len) };
-                match err {
-                    nix::Error::Sys(nix::errno::EWOULDBLOCK) => {
-                        return Ok(());
-                    }
-                    _ => {
-                        return Err(err);
-                    }
+            Err(err) => match err {
+                nix::Error::Sys(nix::errno::EWOULDBLOCK) => {
+                    return Ok(());
                 }
-            }
+                _ => {
+                    return Err(err);
+                }
+            },
         }
     }
 }
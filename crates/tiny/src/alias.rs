@@ -0,0 +1,203 @@
+//! Expansion of user-defined command aliases. See `expand` for the template syntax.
+
+use crate::utils;
+
+/// Values of the variables in an alias template.
+pub(crate) struct AliasEnv<'a> {
+    /// Arguments the alias is called with
+    pub(crate) args: &'a str,
+    /// Our nick in the current server. `None` when the current tab doesn't have a server.
+    pub(crate) nick: Option<&'a str>,
+    pub(crate) serv: &'a str,
+    /// Current channel. `None` when the current tab is not a channel tab.
+    pub(crate) chan: Option<&'a str>,
+}
+
+enum Var<'a> {
+    /// `$N`: Nth argument, starting from 1
+    Arg(usize),
+    /// `$N-`: Arguments starting from the Nth argument
+    ArgsFrom(usize),
+    /// `$nick`, `$chan`, `$serv`
+    Named(&'a str),
+}
+
+/// Expand an alias template to a list of commands and messages.
+///
+/// Parts of the template separated by `;` are expanded separately. In each part `$1`, `$2`, ...
+/// are replaced with the arguments, `$2-` is replaced with the arguments starting from the
+/// second (possibly empty), `$nick`, `$chan`, and `$serv` are replaced with our nick, the current
+/// channel, and the current server, and `$$` is replaced with `$`.
+///
+/// Returns an error when an argument or variable is not available, e.g. `$2` when the alias is
+/// called with one argument, or `$chan` in a server tab.
+pub(crate) fn expand(template: &str, env: &AliasEnv) -> Result<Vec<String>, String> {
+    parts(template)
+        .map(|part| {
+            substitute(part, |var| match var {
+                Var::Arg(n) => env
+                    .args
+                    .split_whitespace()
+                    .nth(n - 1)
+                    .ok_or_else(|| format!("Argument {} is missing", n)),
+                Var::ArgsFrom(n) => Ok(utils::split_whitespace_indices(env.args)
+                    .nth(n - 1)
+                    .map(|idx| env.args[idx..].trim_end())
+                    .unwrap_or("")),
+                Var::Named("nick") => env
+                    .nick
+                    .ok_or_else(|| "$nick can only be used in server tabs".to_owned()),
+                Var::Named("chan") => env
+                    .chan
+                    .ok_or_else(|| "$chan can only be used in channel tabs".to_owned()),
+                Var::Named("serv") => Ok(env.serv),
+                Var::Named(name) => Err(format!("Unknown variable: ${}", name)),
+            })
+        })
+        .collect()
+}
+
+/// Check an alias template for syntax errors and unknown variables.
+pub(crate) fn check(template: &str) -> Result<(), String> {
+    for part in parts(template) {
+        substitute(part, |var| match var {
+            Var::Arg(_) | Var::ArgsFrom(_) | Var::Named("nick" | "chan" | "serv") => Ok(""),
+            Var::Named(name) => Err(format!("Unknown variable: ${}", name)),
+        })?;
+    }
+    Ok(())
+}
+
+fn parts(template: &str) -> impl Iterator<Item = &str> {
+    template
+        .split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+}
+
+fn substitute<'a, 'b, F>(part: &'a str, mut lookup: F) -> Result<String, String>
+where
+    F: FnMut(Var<'a>) -> Result<&'b str, String>,
+{
+    let mut ret = String::with_capacity(part.len());
+    let mut rest = part;
+    while let Some(dollar_idx) = rest.find('$') {
+        ret.push_str(&rest[..dollar_idx]);
+        rest = &rest[dollar_idx + 1..];
+
+        if let Some(rest_) = rest.strip_prefix('$') {
+            ret.push('$');
+            rest = rest_;
+            continue;
+        }
+
+        let digits_len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits_len != 0 {
+            let n: usize = rest[..digits_len]
+                .parse()
+                .map_err(|_| format!("Invalid argument number: ${}", &rest[..digits_len]))?;
+            if n == 0 {
+                return Err("Arguments start from $1".to_owned());
+            }
+            rest = &rest[digits_len..];
+            let var = match rest.strip_prefix('-') {
+                Some(rest_) => {
+                    rest = rest_;
+                    Var::ArgsFrom(n)
+                }
+                None => Var::Arg(n),
+            };
+            ret.push_str(lookup(var)?);
+            continue;
+        }
+
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        if name_len != 0 {
+            ret.push_str(lookup(Var::Named(&rest[..name_len]))?);
+            rest = &rest[name_len..];
+            continue;
+        }
+
+        // Not a variable
+        ret.push('$');
+    }
+    ret.push_str(rest);
+    Ok(ret.trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(args: &'static str) -> AliasEnv<'static> {
+        AliasEnv {
+            args,
+            nick: Some("me"),
+            serv: "irc.server",
+            chan: Some("#tiny"),
+        }
+    }
+
+    #[test]
+    fn expand_args() {
+        assert_eq!(
+            expand("/msg $1 $2-", &env("nick hi  there ")),
+            Ok(vec!["/msg nick hi  there".to_owned()])
+        );
+        assert_eq!(
+            expand("/msg $1 $2-", &env("nick")),
+            Ok(vec!["/msg nick".to_owned()])
+        );
+        assert_eq!(
+            expand("/msg $1 $2", &env("nick")),
+            Err("Argument 2 is missing".to_owned())
+        );
+        assert_eq!(
+            expand("/join #$1; /topic $2-", &env("tiny new topic")),
+            Ok(vec![
+                "/join #tiny".to_owned(),
+                "/topic new topic".to_owned()
+            ])
+        );
+        assert_eq!(expand("$1$1", &env("a")), Ok(vec!["aa".to_owned()]));
+    }
+
+    #[test]
+    fn expand_vars() {
+        assert_eq!(
+            expand("/msg NickServ info $nick; $serv $chan", &env("")),
+            Ok(vec![
+                "/msg NickServ info me".to_owned(),
+                "irc.server #tiny".to_owned()
+            ])
+        );
+        assert_eq!(
+            expand("costs $$5 or $ 5", &env("")),
+            Ok(vec!["costs $5 or $ 5".to_owned()])
+        );
+
+        let serv_env = AliasEnv {
+            chan: None,
+            ..env("")
+        };
+        assert_eq!(
+            expand("/mode $chan +m", &serv_env),
+            Err("$chan can only be used in channel tabs".to_owned())
+        );
+        assert_eq!(
+            expand("$foo", &env("")),
+            Err("Unknown variable: $foo".to_owned())
+        );
+    }
+
+    #[test]
+    fn check_templates() {
+        assert_eq!(check("/msg $1 $2-; /join $chan; $nick $serv $$"), Ok(()));
+        assert_eq!(check("/msg $0"), Err("Arguments start from $1".to_owned()));
+        assert_eq!(check("/msg $foo"), Err("Unknown variable: $foo".to_owned()));
+    }
+}
@@ -1,16 +1,16 @@
 use crate::config;
-use crate::ui::UI;
+use crate::ui::{CHAN_LIST_TAB, TRANSFERS_TAB, UI};
 use crate::utils;
-use libtiny_client::{Client, ServerInfo};
-use libtiny_common::{ChanName, ChanNameRef, MsgSource, MsgTarget};
+use libtiny_client::{wire, Client, ServerInfo};
+use libtiny_common::{match_cmd, ChanListFilter, ChanName, CmdMatch, MsgSource, MsgTarget, Nick};
 
 use std::borrow::Borrow;
 use std::path::Path;
 
 pub(crate) struct CmdArgs<'a> {
     pub args: &'a str,
-    pub config_path: &'a Path,
     pub defaults: &'a config::Defaults,
+    pub aliases: &'a config::Aliases,
     pub ui: &'a UI,
     pub clients: &'a mut Vec<Client>,
     pub src: MsgSource,
@@ -38,13 +38,14 @@ pub(crate) enum ParseCmdResult<'a> {
         rest: &'a str,
     },
 
-    // Command name is ambiguous, here are possible values
-    // Ambiguous(Vec<&'static str>),
+    /// Command name is a prefix of multiple commands, here are their names
+    Ambiguous(Vec<&'static str>),
+
     /// Unknown command
     Unknown,
 }
 
-pub(crate) fn parse_cmd(cmd: &str) -> ParseCmdResult {
+pub(crate) fn parse_cmd(cmd: &str) -> ParseCmdResult<'_> {
     match cmd.split_whitespace().next() {
         None => ParseCmdResult::Unknown,
         Some(cmd_name) => {
@@ -56,29 +57,28 @@ pub(crate) fn parse_cmd(cmd: &str) -> ParseCmdResult {
                     Some(rest_idx) => &cmd[rest_idx..],
                 }
             };
-            // let mut possibilities: Vec<&'static Cmd> = vec![];
-            for cmd in &CMDS {
-                if cmd_name == cmd.name {
-                    // exact match, return
-                    return ParseCmdResult::Ok { cmd, rest };
-                }
+            match match_cmd(cmd_name, builtin_names()) {
+                CmdMatch::Match(name) => ParseCmdResult::Ok {
+                    cmd: find_builtin(name).unwrap(),
+                    rest,
+                },
+                CmdMatch::Ambiguous(names) => ParseCmdResult::Ambiguous(names),
+                CmdMatch::Unknown => ParseCmdResult::Unknown,
             }
-            ParseCmdResult::Unknown
-            // match possibilities.len() {
-            //     0 =>
-            //         ParseCmdResult::Unknown,
-            //     1 =>
-            //         ParseCmdResult::Ok {
-            //             cmd: possibilities[0],
-            //             rest,
-            //         },
-            //     _ =>
-            //         ParseCmdResult::Ambiguous(possibilities.into_iter().map(|cmd| cmd.name).collect()),
-            // }
         }
     }
 }
 
+/// Find a built-in command by name.
+pub(crate) fn find_builtin(name: &str) -> Option<&'static Cmd> {
+    CMDS.iter().copied().find(|cmd| cmd.name == name)
+}
+
+/// Names of the built-in commands.
+pub(crate) fn builtin_names() -> impl Iterator<Item = &'static str> {
+    CMDS.iter().map(|cmd| cmd.name)
+}
+
 fn find_client_idx(clients: &[Client], serv_name: &str) -> Option<usize> {
     for (client_idx, client) in clients.iter().enumerate() {
         if client.get_serv_name() == serv_name {
@@ -88,24 +88,70 @@ fn find_client_idx(clients: &[Client], serv_name: &str) -> Option<usize> {
     None
 }
 
-fn find_client<'a>(clients: &'a mut Vec<Client>, serv_name: &str) -> Option<&'a mut Client> {
+fn find_client<'a>(clients: &'a mut [Client], serv_name: &str) -> Option<&'a mut Client> {
     match find_client_idx(clients, serv_name) {
         None => None,
         Some(idx) => Some(&mut clients[idx]),
     }
 }
 
+/// Find the client and channel of a command that is only supported in channel tabs. Shows an
+/// error in the current tab when the command is run in another tab.
+fn find_chan_client<'a>(
+    ui: &UI,
+    clients: &'a mut [Client],
+    src: &MsgSource,
+    cmd: &Cmd,
+) -> Option<(&'a mut Client, ChanName)> {
+    match src {
+        MsgSource::Chan { serv, chan } => match find_client(clients, serv) {
+            Some(client) => Some((client, chan.clone())),
+            None => {
+                ui.add_client_err_msg(
+                    &format!("Can't run /{}: Not connected to server {}", cmd.name, serv),
+                    &MsgTarget::CurrentTab,
+                );
+                None
+            }
+        },
+        _ => {
+            ui.add_client_err_msg(
+                &format!("/{} only supported in chan tabs", cmd.name),
+                &MsgTarget::CurrentTab,
+            );
+            None
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
-static CMDS: [&Cmd; 9] = [
+static CMDS: [&Cmd; 26] = [
     &AWAY_CMD,
+    &BAN_CMD,
     &CLOSE_CMD,
     &CONNECT_CMD,
+    &CTCP_CMD,
+    &DCC_CMD,
+    &DEOP_CMD,
+    &DEVOICE_CMD,
+    &INVITE_CMD,
     &JOIN_CMD,
+    &KICK_CMD,
+    &LIST_CMD,
     &ME_CMD,
+    &MODE_CMD,
     &MSG_CMD,
     &NAMES_CMD,
     &NICK_CMD,
+    &OP_CMD,
+    &QUOTE_CMD,
+    &RAW_CMD,
+    &TOPIC_CMD,
+    &UNBAN_CMD,
+    &VOICE_CMD,
+    &WHOIS_CMD,
+    &WHOWAS_CMD,
     &HELP_CMD,
 ];
 
@@ -131,6 +177,81 @@ fn away(args: CmdArgs) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static BAN_CMD: Cmd = Cmd {
+    name: "ban",
+    cmd_fn: ban,
+    description: "Bans users from the channel",
+    usage: "`/ban <nick or mask> ...`",
+};
+
+fn ban(args: CmdArgs) {
+    ban_(args, &BAN_CMD, true)
+}
+
+static UNBAN_CMD: Cmd = Cmd {
+    name: "unban",
+    cmd_fn: unban,
+    description: "Removes bans from the channel",
+    usage: "`/unban <nick or mask> ...`",
+};
+
+fn unban(args: CmdArgs) {
+    ban_(args, &UNBAN_CMD, false)
+}
+
+fn ban_(args: CmdArgs, cmd: &Cmd, set: bool) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.is_empty() {
+        return ui.add_client_err_msg(&format!("Usage: {}", cmd.usage), &MsgTarget::CurrentTab);
+    }
+    if let Some((client, chan)) = find_chan_client(ui, clients, &src, cmd) {
+        let masks: Vec<String> = words
+            .iter()
+            .map(
+                |word| match ban_mask(word, client.get_user_host(word).as_deref()) {
+                    Some(mask) => mask,
+                    None => {
+                        let mask = format!("{}!*@*", word);
+                        ui.add_client_msg(
+                            &format!(
+                                "Host of {} is not known, using {}. Use `/{} *!*@<host>` to \
+                                 match the host.",
+                                word, mask, cmd.name
+                            ),
+                            &MsgTarget::CurrentTab,
+                        );
+                        mask
+                    }
+                },
+            )
+            .collect();
+        let masks: Vec<&str> = masks.iter().map(String::as_str).collect();
+        client.chan_mode_changes(&chan, set, 'b', &masks);
+    }
+}
+
+/// Make a ban mask for an argument of `/ban` or `/unban`. Arguments with `!` or `@` are masks and
+/// used as-is. For nicks the mask matches the user's host (`user_host`). Returns `None` when the
+/// host of the nick is not known.
+fn ban_mask(arg: &str, user_host: Option<&str>) -> Option<String> {
+    if arg.contains(['!', '@']) {
+        return Some(arg.to_owned());
+    }
+    match user_host.and_then(|user_host| user_host.split_once('@')) {
+        Some((_, host)) if !host.is_empty() => Some(format!("*!*@{}", host)),
+        _ => None,
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static CLOSE_CMD: Cmd = Cmd {
     name: "close",
     cmd_fn: close,
@@ -146,18 +267,27 @@ fn close(args: CmdArgs) {
         MsgSource::Serv { ref serv } if serv == "mentions" => {
             // ignore
         }
+        MsgSource::Serv { ref serv } if serv == TRANSFERS_TAB || serv == CHAN_LIST_TAB => {
+            ui.close_server_tab(serv);
+        }
         MsgSource::Serv { serv } => {
             ui.close_server_tab(&serv);
-            let client_idx = find_client_idx(&clients, &serv).unwrap();
+            let client_idx = find_client_idx(clients, &serv).unwrap();
             // TODO: this probably won't close the connection?
             let mut client = clients.remove(client_idx);
             client.quit(None);
         }
         MsgSource::Chan { serv, chan } => {
             ui.close_chan_tab(&serv, chan.borrow());
-            let client_idx = find_client_idx(&clients, &serv).unwrap();
+            let client_idx = find_client_idx(clients, &serv).unwrap();
             clients[client_idx].part(&chan);
         }
+        MsgSource::DccChat { serv, nick } => {
+            ui.close_dcc_chat_tab(&serv, &nick);
+            if let Some(client) = find_client(clients, &serv) {
+                client.dcc_chat_close(&nick);
+            }
+        }
         MsgSource::User { serv, nick } => {
             ui.close_user_tab(&serv, &nick);
         }
@@ -195,7 +325,7 @@ fn connect(args: CmdArgs) {
     }
 }
 
-fn reconnect(ui: &UI, clients: &mut Vec<Client>, src: MsgSource) {
+fn reconnect(ui: &UI, clients: &mut [Client], src: MsgSource) {
     if let Some(client) = find_client(clients, src.serv_name()) {
         ui.add_client_msg(
             "Reconnecting...",
@@ -256,16 +386,24 @@ fn connect_(
         addr: serv_name.to_owned(),
         port: serv_port,
         tls: defaults.tls,
+        proxy: None,
+        tls_client_cert: None,
         realname: defaults.realname.clone(),
         pass: pass.map(str::to_owned),
         nicks: defaults.nicks.clone(),
         auto_join: defaults
             .join
             .iter()
-            .map(|c| ChanNameRef::new(c).to_owned())
+            .map(config::JoinChan::to_auto_join)
             .collect(),
         nickserv_ident: None,
         sasl_auth: None,
+        reconnect: libtiny_client::ReconnectPolicy::default(),
+        flood_control: libtiny_client::FloodControl::default(),
+        rejoin_on_kick: None,
+        ctcp_replies: config::Ctcp::default().to_client_ctcp_replies(),
+        dcc: config::Dcc::default().to_client_dcc_config(),
+        caps: config::default_caps(),
     });
 
     // Spawn UI task
@@ -278,11 +416,238 @@ fn connect_(
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static CTCP_CMD: Cmd = Cmd {
+    name: "ctcp",
+    cmd_fn: ctcp,
+    description: "Sends a CTCP request",
+    usage: "`/ctcp <nick> <type> [<args>]`, e.g. `/ctcp <nick> version` or `/ctcp <nick> ping`",
+};
+
+fn ctcp(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let fail = || {
+        ui.add_client_err_msg(
+            &format!("Usage: {}", CTCP_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    };
+
+    let (target, rest) = match args.trim().split_once(char::is_whitespace) {
+        None => return fail(),
+        Some((target, rest)) => (target, rest.trim_start()),
+    };
+    let (ty, ctcp_args) = match rest.split_once(char::is_whitespace) {
+        None => (rest, None),
+        Some((ty, ctcp_args)) => (ty, Some(ctcp_args.trim()).filter(|args| !args.is_empty())),
+    };
+    if ty.is_empty() {
+        return fail();
+    }
+    let ctcp = wire::CTCP::parse(&ty.to_uppercase());
+
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            client.ctcp(target, &ctcp, ctcp_args);
+            ui.add_client_msg(
+                &format!("Sent CTCP {} request to {}", ctcp.as_str(), target),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't send CTCP request: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static DCC_CMD: Cmd = Cmd {
+    name: "dcc",
+    cmd_fn: dcc,
+    description: "Sends files, starts chats, and accepts, rejects, or cancels DCC transfers and chats",
+    usage: "`/dcc send <nick> <file>`, `/dcc chat <nick>`, `/dcc accept <id>`, `/dcc reject <id>`, or `/dcc cancel <id>`",
+};
+
+fn dcc(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let fail = || {
+        ui.add_client_err_msg(&format!("Usage: {}", DCC_CMD.usage), &MsgTarget::CurrentTab);
+    };
+
+    let (subcmd, rest) = match args.trim().split_once(char::is_whitespace) {
+        None => return fail(),
+        Some((subcmd, rest)) => (subcmd, rest.trim()),
+    };
+
+    if subcmd == "send" {
+        let (nick, path) = match rest.split_once(char::is_whitespace) {
+            None => return fail(),
+            Some((nick, path)) => (nick, path.trim()),
+        };
+        let path = match path.strip_prefix("~/").zip(dirs::home_dir()) {
+            Some((path, home)) => home.join(path),
+            None => Path::new(path).to_owned(),
+        };
+        return match find_client(clients, src.serv_name()) {
+            Some(client) => match client.dcc_send(nick, &path) {
+                Ok(id) => ui.add_client_msg(
+                    &format!(
+                        "[{}] Offered {} to {}, waiting for the transfer to start",
+                        id,
+                        path.display(),
+                        nick
+                    ),
+                    &MsgTarget::CurrentTab,
+                ),
+                Err(err) => ui.add_client_err_msg(
+                    &format!("Can't send file: {}", err),
+                    &MsgTarget::CurrentTab,
+                ),
+            },
+            None => ui.add_client_err_msg(
+                &format!(
+                    "Can't send file: Not connected to server {}",
+                    src.serv_name()
+                ),
+                &MsgTarget::CurrentTab,
+            ),
+        };
+    }
+
+    if subcmd == "chat" {
+        if rest.is_empty() || rest.contains(char::is_whitespace) {
+            return fail();
+        }
+        let serv = src.serv_name();
+        return match find_client(clients, serv) {
+            Some(client) => match client.dcc_chat(rest) {
+                Ok(_) => {
+                    ui.new_dcc_chat_tab(serv, rest);
+                    ui.add_client_msg(
+                        &format!("Offered DCC chat to {}, waiting for the connection", rest),
+                        &MsgTarget::DccChat { serv, nick: rest },
+                    );
+                }
+                Err(err) => ui.add_client_err_msg(
+                    &format!("Can't start DCC chat: {}", err),
+                    &MsgTarget::CurrentTab,
+                ),
+            },
+            None => ui.add_client_err_msg(
+                &format!("Can't start DCC chat: Not connected to server {}", serv),
+                &MsgTarget::CurrentTab,
+            ),
+        };
+    }
+
+    let id = match rest.parse::<usize>() {
+        Ok(id) => id,
+        Err(_) => return fail(),
+    };
+    // Transfer ids are unique across servers, so transfers can be managed from any tab
+    let client = match clients
+        .iter_mut()
+        .find(|client| client.has_dcc_transfer(id))
+    {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't find DCC transfer {}", id),
+                &MsgTarget::CurrentTab,
+            )
+        }
+    };
+    match subcmd {
+        "accept" => {
+            if let Err(err) = client.dcc_accept(id) {
+                ui.add_client_err_msg(
+                    &format!("Can't accept DCC offer: {}", err),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        }
+        "reject" | "cancel" => {
+            client.dcc_cancel(id);
+            ui.add_client_msg(
+                &format!(
+                    "[{}] DCC {}",
+                    id,
+                    if subcmd == "reject" {
+                        "offer rejected"
+                    } else {
+                        "transfer cancelled"
+                    }
+                ),
+                &MsgTarget::CurrentTab,
+            );
+        }
+        _ => fail(),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static INVITE_CMD: Cmd = Cmd {
+    name: "invite",
+    cmd_fn: invite,
+    description: "Invites a user to a channel",
+    usage: "`/invite <nick> [<channel>]` (channel defaults to current channel)",
+};
+
+fn invite(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (nick, chan) = match (words.as_slice(), &src) {
+        ([nick, chan], _) => (*nick, ChanName::new((*chan).to_owned())),
+        ([nick], MsgSource::Chan { chan, .. }) => (*nick, chan.clone()),
+        _ => {
+            return ui.add_client_err_msg(
+                &format!("Usage: {}", INVITE_CMD.usage),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    match find_client(clients, src.serv_name()) {
+        Some(client) => client.invite(nick, &chan),
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't send INVITE: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static JOIN_CMD: Cmd = Cmd {
     name: "join",
     cmd_fn: join,
     description: "Joins a channel",
-    usage: "`/join <chan1>,<chan2>,...` or `/join` in a channel tab to rejoin",
+    usage: "`/join <chan1>,<chan2>,... [<key1>,<key2>,...]` or `/join` in a channel tab to rejoin",
 };
 
 fn join(args: CmdArgs) {
@@ -303,10 +668,26 @@ fn join(args: CmdArgs) {
         }
     }
 
-    let words = args
-        .split_whitespace()
-        .map(|c| ChanName::new(c.to_owned()))
-        .collect::<Vec<_>>();
+    let client = match find_client(clients, src.serv_name()) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!("Can't join: Not connected to server {}", src.serv_name()),
+                &MsgTarget::CurrentTab,
+            )
+        }
+    };
+
+    let mut words = args.split_whitespace().collect::<Vec<_>>();
+
+    // The last argument is a list of keys if it's not a channel
+    let keys: Vec<&str> = match words.last() {
+        Some(word) if words.len() > 1 && !client.is_chan_name(word) => word.split(',').collect(),
+        _ => vec![],
+    };
+    if !keys.is_empty() {
+        words.pop();
+    }
 
     let chans: Vec<ChanName> = if words.is_empty() {
         match ui.current_tab() {
@@ -323,12 +704,87 @@ fn join(args: CmdArgs) {
         }
     } else {
         words
+            .iter()
+            .flat_map(|word| word.split(','))
+            .filter(|chan| !chan.is_empty())
+            .map(|chan| ChanName::new(chan.to_owned()))
+            .collect()
     };
 
-    match find_client(clients, src.serv_name()) {
-        Some(client) => client.join(chans.iter().map(ChanName::as_ref)),
+    client.join(chans.iter().enumerate().map(|(i, chan)| {
+        let key = keys.get(i).copied().filter(|key| !key.is_empty());
+        (chan.as_ref(), key)
+    }));
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static KICK_CMD: Cmd = Cmd {
+    name: "kick",
+    cmd_fn: kick,
+    description: "Kicks a user from the channel",
+    usage: "`/kick <nick> [<reason>]`",
+};
+
+fn kick(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let args = args.trim();
+    if args.is_empty() {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", KICK_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    let (nick, reason) = match args.split_once(char::is_whitespace) {
+        None => (args, None),
+        Some((nick, reason)) => (nick, Some(reason.trim())),
+    };
+    if let Some((client, chan)) = find_chan_client(ui, clients, &src, &KICK_CMD) {
+        client.kick(&chan, nick, reason);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static LIST_CMD: Cmd = Cmd {
+    name: "list",
+    cmd_fn: list,
+    description: "Shows channels of the server",
+    usage: "`/list [<pattern>] [><users>]`",
+};
+
+fn list(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let filter = match ChanListFilter::parse(args) {
+        Ok(filter) => filter,
+        Err(err) => {
+            return ui.add_client_err_msg(
+                &format!("{}. Usage: {}", err, LIST_CMD.usage),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    let serv = src.serv_name();
+    match find_client(clients, serv) {
+        Some(client) => {
+            ui.new_server_tab(CHAN_LIST_TAB, None);
+            ui.show_chan_list(CHAN_LIST_TAB, serv, filter);
+            client.list();
+        }
         None => ui.add_client_err_msg(
-            &format!("Can't join: Not connected to server {}", src.serv_name()),
+            &format!("Can't send LIST: Not connected to server {}", serv),
             &MsgTarget::CurrentTab,
         ),
     }
@@ -359,6 +815,60 @@ fn me(args: CmdArgs) {
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
 
+static MODE_CMD: Cmd = Cmd {
+    name: "mode",
+    cmd_fn: mode,
+    description: "Sets or shows channel or user modes",
+    usage:
+        "`/mode [<channel or your nick>] [<modes> [<params>]]` (target defaults to current channel)",
+};
+
+fn mode(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let client = match find_client(clients, src.serv_name()) {
+        Some(client) => client,
+        None => {
+            return ui.add_client_err_msg(
+                &format!(
+                    "Can't send MODE: Not connected to server {}",
+                    src.serv_name()
+                ),
+                &MsgTarget::CurrentTab,
+            );
+        }
+    };
+    let mut words: Vec<&str> = args.split_whitespace().collect();
+    let target = match words.first() {
+        Some(word)
+            if client.is_chan_name(word)
+                || client
+                    .server_features()
+                    .casemapping
+                    .eq_ignore_case(word, &client.get_nick()) =>
+        {
+            words.remove(0).to_owned()
+        }
+        _ => match &src {
+            MsgSource::Chan { chan, .. } => chan.display().to_owned(),
+            _ => {
+                return ui.add_client_err_msg(
+                    &format!("Usage: {}", MODE_CMD.usage),
+                    &MsgTarget::CurrentTab,
+                );
+            }
+        },
+    };
+    client.mode(&target, &words);
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static MSG_CMD: Cmd = Cmd {
     name: "msg",
     cmd_fn: msg,
@@ -366,26 +876,23 @@ static MSG_CMD: Cmd = Cmd {
     usage: "`/msg <nick> <message>`",
 };
 
-fn split_msg_args(args: &str) -> Option<(&str, &str)> {
-    let mut char_indices = args.char_indices();
-
-    // We could check for validity of the nick according to RFC 2812 but we do the simple thing for
-    // now and and only check the first character, to avoid confusing the UI by returning a
-    // `MsgSource::User` with a channel name as `nick`.
-    match char_indices.next() {
-        None => {
-            return None;
-        }
-        Some((_, c)) => {
-            if !utils::is_nick_first_char(c) {
+/// `is_chan_name` is used to check the target, as the server's channel prefixes (CHANTYPES) are
+/// not known here.
+fn split_msg_args<F>(args: &str, is_chan_name: F) -> Option<(&str, &str)>
+where
+    F: Fn(&str) -> bool,
+{
+    for (i, c) in args.char_indices() {
+        if c.is_whitespace() {
+            let (target, msg) = (&args[0..i], &args[i + 1..]);
+            // We could check for validity of the nick according to RFC 2812 but we do the simple
+            // thing for now and only check that it's not a channel name, to avoid confusing the
+            // UI by returning a `MsgSource::User` with a channel name as `nick`.
+            // (https://github.com/osa1/tiny/issues/324)
+            if target.is_empty() || is_chan_name(target) {
                 return None;
             }
-        }
-    }
-
-    for (i, c) in char_indices {
-        if c.is_whitespace() {
-            return Some((&args[0..i], &args[i + 1..]));
+            return Some((target, msg));
         }
     }
 
@@ -404,7 +911,12 @@ fn msg(args: CmdArgs) {
         ui.add_client_err_msg(&format!("Usage: {}", MSG_CMD.usage), &MsgTarget::CurrentTab);
     };
 
-    let (target, msg) = match split_msg_args(args) {
+    let is_chan_name = |name: &str| match find_client_idx(clients, src.serv_name()) {
+        None => name.starts_with('#'),
+        Some(client_idx) => clients[client_idx].is_chan_name(name),
+    };
+
+    let (target, msg) = match split_msg_args(args, is_chan_name) {
         None => return fail(),
         Some((target, msg)) => {
             if msg.is_empty() {
@@ -511,6 +1023,249 @@ fn nick(args: CmdArgs) {
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static OP_CMD: Cmd = Cmd {
+    name: "op",
+    cmd_fn: op,
+    description: "Gives channel operator status to users",
+    usage: "`/op <nick> ...`",
+};
+
+fn op(args: CmdArgs) {
+    member_mode(args, &OP_CMD, true, 'o')
+}
+
+static DEOP_CMD: Cmd = Cmd {
+    name: "deop",
+    cmd_fn: deop,
+    description: "Takes channel operator status from users",
+    usage: "`/deop <nick> ...`",
+};
+
+fn deop(args: CmdArgs) {
+    member_mode(args, &DEOP_CMD, false, 'o')
+}
+
+static VOICE_CMD: Cmd = Cmd {
+    name: "voice",
+    cmd_fn: voice,
+    description: "Gives voice to users",
+    usage: "`/voice <nick> ...`",
+};
+
+fn voice(args: CmdArgs) {
+    member_mode(args, &VOICE_CMD, true, 'v')
+}
+
+static DEVOICE_CMD: Cmd = Cmd {
+    name: "devoice",
+    cmd_fn: devoice,
+    description: "Takes voice from users",
+    usage: "`/devoice <nick> ...`",
+};
+
+fn devoice(args: CmdArgs) {
+    member_mode(args, &DEVOICE_CMD, false, 'v')
+}
+
+/// Set or unset a membership mode (e.g. `o` for channel operators) of users in the channel.
+fn member_mode(args: CmdArgs, cmd: &Cmd, set: bool, mode: char) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let nicks: Vec<&str> = args.split_whitespace().collect();
+    if nicks.is_empty() {
+        return ui.add_client_err_msg(&format!("Usage: {}", cmd.usage), &MsgTarget::CurrentTab);
+    }
+    if let Some((client, chan)) = find_chan_client(ui, clients, &src, cmd) {
+        client.chan_mode_changes(&chan, set, mode, &nicks);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static RAW_CMD: Cmd = Cmd {
+    name: "raw",
+    cmd_fn: raw,
+    description: "Sends a raw IRC message to the server",
+    usage: "`/raw <message>`",
+};
+
+static QUOTE_CMD: Cmd = Cmd {
+    name: "quote",
+    cmd_fn: raw,
+    description: "Same as /raw",
+    usage: "`/quote <message>`",
+};
+
+/// Max. length of an IRC message, without the trailing "\r\n".
+const MAX_RAW_MSG_LEN: usize = 510;
+
+fn check_raw_msg(msg: &str) -> Result<(), String> {
+    if msg.is_empty() {
+        Err("Message is empty".to_owned())
+    } else if msg.contains(['\r', '\n', '\0']) {
+        Err("Message can't contain line breaks or NUL characters".to_owned())
+    } else if msg.len() > MAX_RAW_MSG_LEN {
+        Err(format!(
+            "Message is too long ({} bytes, max. {} bytes)",
+            msg.len(),
+            MAX_RAW_MSG_LEN
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn raw(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let msg = args.trim();
+    if msg.is_empty() {
+        return ui.add_client_err_msg(&format!("Usage: {}", RAW_CMD.usage), &MsgTarget::CurrentTab);
+    }
+    if let Err(err) = check_raw_msg(msg) {
+        return ui.add_client_err_msg(
+            &format!("Can't send message: {}", err),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    let serv = src.serv_name();
+    match find_client(clients, serv) {
+        Some(client) => {
+            client.raw_msg(msg);
+            ui.add_client_msg(&format!("> {}", msg), &MsgTarget::Server { serv });
+        }
+        None => ui.add_client_err_msg(
+            &format!("Can't send message: Not connected to server {}", serv),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static TOPIC_CMD: Cmd = Cmd {
+    name: "topic",
+    cmd_fn: topic,
+    description: "Sets or shows the channel topic",
+    usage: "`/topic [<topic>]`",
+};
+
+fn topic(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let topic = args.trim();
+    if let Some((client, chan)) = find_chan_client(ui, clients, &src, &TOPIC_CMD) {
+        client.topic(&chan, if topic.is_empty() { None } else { Some(topic) });
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static WHOIS_CMD: Cmd = Cmd {
+    name: "whois",
+    cmd_fn: whois,
+    description: "Shows information about a user",
+    usage: "`/whois <nick>`",
+};
+
+fn whois(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let words: Vec<&str> = args.split_whitespace().collect();
+    if words.len() != 1 {
+        return ui.add_client_err_msg(
+            &format!("Usage: {}", WHOIS_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    }
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            let nick = Nick::new(words[0].to_owned(), client.server_features().casemapping);
+            ui.set_whois_src(src.serv_name(), nick, src.clone());
+            client.whois(words[0]);
+        }
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't send WHOIS: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+static WHOWAS_CMD: Cmd = Cmd {
+    name: "whowas",
+    cmd_fn: whowas,
+    description: "Shows information about a user that is no longer online",
+    usage: "`/whowas <nick> [<count>]`",
+};
+
+fn whowas(args: CmdArgs) {
+    let CmdArgs {
+        args,
+        ui,
+        clients,
+        src,
+        ..
+    } = args;
+    let fail = || {
+        ui.add_client_err_msg(
+            &format!("Usage: {}", WHOWAS_CMD.usage),
+            &MsgTarget::CurrentTab,
+        );
+    };
+    let words: Vec<&str> = args.split_whitespace().collect();
+    let (nick, count) = match words.as_slice() {
+        [nick] => (*nick, None),
+        [nick, count] => match count.parse::<u32>() {
+            Ok(count) => (*nick, Some(count)),
+            Err(_) => return fail(),
+        },
+        _ => return fail(),
+    };
+    match find_client(clients, src.serv_name()) {
+        Some(client) => {
+            let nick_ = Nick::new(nick.to_owned(), client.server_features().casemapping);
+            ui.set_whois_src(src.serv_name(), nick_, src.clone());
+            client.whowas(nick, count);
+        }
+        None => ui.add_client_err_msg(
+            &format!(
+                "Can't send WHOWAS: Not connected to server {}",
+                src.serv_name()
+            ),
+            &MsgTarget::CurrentTab,
+        ),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
 static HELP_CMD: Cmd = Cmd {
     name: "help",
     cmd_fn: help,
@@ -519,16 +1274,29 @@ static HELP_CMD: Cmd = Cmd {
 };
 
 fn help(args: CmdArgs) {
-    let CmdArgs { ui, .. } = args;
-    ui.add_client_msg("Client Commands:", &MsgTarget::CurrentTab);
-    for cmd in CMDS.iter() {
+    let CmdArgs { ui, aliases, .. } = args;
+    let show_cmd = |name: &str, description: &str, usage: &str| {
         ui.add_client_msg(
-            &format!(
-                "/{:<10} - {:<25} - Usage: {}",
-                cmd.name, cmd.description, cmd.usage
-            ),
+            &format!("/{:<10} - {:<25} - Usage: {}", name, description, usage),
             &MsgTarget::CurrentTab,
         )
+    };
+    ui.add_client_msg("TUI Commands:", &MsgTarget::CurrentTab);
+    for cmd in libtiny_tui::tui_cmds() {
+        show_cmd(cmd.name, cmd.description, cmd.usage);
+    }
+    ui.add_client_msg("Client Commands:", &MsgTarget::CurrentTab);
+    for cmd in CMDS.iter() {
+        show_cmd(cmd.name, cmd.description, cmd.usage);
+    }
+    if !aliases.is_empty() {
+        ui.add_client_msg("Aliases:", &MsgTarget::CurrentTab);
+        for (name, alias) in aliases {
+            ui.add_client_msg(
+                &format!("/{:<10} - {}", name, alias.cmd()),
+                &MsgTarget::CurrentTab,
+            )
+        }
     }
 }
 
@@ -557,13 +1325,72 @@ fn test_parse_cmd() {
             panic!("Can't parse cmd");
         }
     }
+
+    // Unique prefix
+    match parse_cmd("j #foo") {
+        ParseCmdResult::Ok { cmd, rest } => {
+            assert_eq!(cmd.name, "join");
+            assert_eq!(rest, "#foo");
+        }
+        _ => {
+            panic!("Can't parse cmd");
+        }
+    }
+
+    match parse_cmd("n") {
+        ParseCmdResult::Ambiguous(names) => assert_eq!(names, vec!["names", "nick"]),
+        _ => panic!("Prefix should be ambiguous"),
+    }
+
+    match parse_cmd("who nick") {
+        ParseCmdResult::Ambiguous(names) => assert_eq!(names, vec!["whois", "whowas"]),
+        _ => panic!("Prefix should be ambiguous"),
+    }
+
+    assert!(matches!(parse_cmd("foo"), ParseCmdResult::Unknown));
 }
 
 #[test]
 fn test_msg_args() {
-    assert_eq!(split_msg_args("foo,bar"), None);
-    assert_eq!(split_msg_args("foo bar"), Some(("foo", "bar")));
-    assert_eq!(split_msg_args("foo, bar"), Some(("foo,", "bar"))); // nick not valid according to RFC but whatever
-    assert_eq!(split_msg_args("foo ,bar"), Some(("foo", ",bar")));
-    assert_eq!(split_msg_args("#blah blah"), None);
+    let is_chan_name = |name: &str| name.starts_with('#') || name.starts_with('&');
+    assert_eq!(split_msg_args("foo,bar", is_chan_name), None);
+    assert_eq!(
+        split_msg_args("foo bar", is_chan_name),
+        Some(("foo", "bar"))
+    );
+    assert_eq!(
+        split_msg_args("foo, bar", is_chan_name),
+        Some(("foo,", "bar"))
+    ); // nick not valid according to RFC but whatever
+    assert_eq!(
+        split_msg_args("foo ,bar", is_chan_name),
+        Some(("foo", ",bar"))
+    );
+    assert_eq!(split_msg_args("#blah blah", is_chan_name), None);
+    assert_eq!(split_msg_args("&blah blah", is_chan_name), None);
+}
+
+#[test]
+fn test_check_raw_msg() {
+    assert!(check_raw_msg("OPER name password").is_ok());
+    assert!(check_raw_msg("").is_err());
+    assert!(check_raw_msg("PRIVMSG #chan :hi\r\nQUIT").is_err());
+    assert!(check_raw_msg("PRIVMSG #chan :hi\nQUIT").is_err());
+    assert!(check_raw_msg(&"a".repeat(MAX_RAW_MSG_LEN)).is_ok());
+    assert!(check_raw_msg(&"a".repeat(MAX_RAW_MSG_LEN + 1)).is_err());
+}
+
+#[test]
+fn test_ban_mask() {
+    assert_eq!(
+        ban_mask("nick", Some("~user@host.example")),
+        Some("*!*@host.example".to_owned())
+    );
+    assert_eq!(ban_mask("nick", None), None);
+    assert_eq!(ban_mask("nick", Some("user@")), None);
+    assert_eq!(
+        ban_mask("*!*@1.2.3.4", Some("user@host")),
+        Some("*!*@1.2.3.4".to_owned())
+    );
+    assert_eq!(ban_mask("nick!*@*", None), Some("nick!*@*".to_owned()));
 }
@@ -1,13 +1,223 @@
+use crate::alias;
+use crate::cmd;
+use libtiny_common::{ChanName, ChanNameRef};
 use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct SASLAuth {
-    pub(crate) username: String,
-    pub(crate) password: String,
+    #[serde(default)]
+    pub(crate) mechanism: SASLMechanism,
+
+    /// Only used with `password`
+    pub(crate) username: Option<String>,
+
+    /// Only used with `password`
+    pub(crate) password: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SASLMechanism {
+    /// Authenticate with `username` and `password`, using the strongest mechanism supported by
+    /// the server: SCRAM-SHA-256, SCRAM-SHA-1, or PLAIN
+    #[default]
+    Password,
+    /// Authenticate with the TLS client certificate (`tls_cert`)
+    External,
+}
+
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct Proxy {
+    #[serde(rename = "type")]
+    pub(crate) kind: ProxyKind,
+    pub(crate) addr: String,
+    pub(crate) port: u16,
+    pub(crate) username: Option<String>,
+    pub(crate) password: Option<String>,
+    /// SOCKS5 only: resolve server address at the proxy. Defaults to `true`.
+    #[serde(default = "default_remote_dns")]
+    pub(crate) remote_dns: bool,
+}
+
+#[derive(Clone, Copy, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+fn default_remote_dns() -> bool {
+    true
+}
+
+/// Reconnect policy. See `libtiny_client::ReconnectPolicy`. Durations are in seconds.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct Reconnect {
+    pub(crate) initial_delay: u64,
+    pub(crate) max_delay: u64,
+    pub(crate) jitter: f64,
+    pub(crate) max_attempts: Option<u32>,
+    pub(crate) ping_interval: u64,
+    pub(crate) pong_timeout: u64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        let policy = libtiny_client::ReconnectPolicy::default();
+        Reconnect {
+            initial_delay: policy.initial_delay.as_secs(),
+            max_delay: policy.max_delay.as_secs(),
+            jitter: policy.jitter,
+            max_attempts: policy.max_attempts,
+            ping_interval: policy.ping_interval.as_secs(),
+            pong_timeout: policy.pong_timeout.as_secs(),
+        }
+    }
+}
+
+impl Reconnect {
+    pub(crate) fn to_policy(&self) -> libtiny_client::ReconnectPolicy {
+        libtiny_client::ReconnectPolicy {
+            initial_delay: Duration::from_secs(self.initial_delay),
+            max_delay: Duration::from_secs(self.max_delay),
+            jitter: self.jitter,
+            max_attempts: self.max_attempts,
+            ping_interval: Duration::from_secs(self.ping_interval),
+            pong_timeout: Duration::from_secs(self.pong_timeout),
+        }
+    }
+}
+
+/// Outgoing message rate limit. See `libtiny_client::FloodControl`.
+#[derive(Clone, Deserialize, Debug, PartialEq)]
+#[serde(default)]
+pub(crate) struct FloodControl {
+    /// Max. number of messages to send at once
+    pub(crate) burst: u32,
+    /// Messages per second after a burst. 0 disables flood control.
+    pub(crate) rate: f64,
+}
+
+impl Default for FloodControl {
+    fn default() -> Self {
+        let flood_control = libtiny_client::FloodControl::default();
+        FloodControl {
+            burst: flood_control.burst,
+            rate: 1.0 / flood_control.interval.as_secs_f64(),
+        }
+    }
+}
+
+impl FloodControl {
+    pub(crate) fn to_client_flood_control(&self) -> libtiny_client::FloodControl {
+        libtiny_client::FloodControl {
+            burst: self.burst,
+            interval: if self.rate == 0.0 {
+                Duration::from_secs(0)
+            } else {
+                Duration::from_secs_f64(1.0 / self.rate)
+            },
+        }
+    }
+}
+
+/// Replies to CTCP requests. See `libtiny_client::CtcpReplies`. `version`, `source`, and
+/// `userinfo` replies are disabled with `null`.
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct Ctcp {
+    pub(crate) version: Option<String>,
+    pub(crate) source: Option<String>,
+    pub(crate) userinfo: Option<String>,
+    pub(crate) ping: bool,
+    pub(crate) time: bool,
+    pub(crate) clientinfo: bool,
+}
+
+impl Default for Ctcp {
+    fn default() -> Self {
+        let replies = libtiny_client::CtcpReplies::default();
+        Ctcp {
+            version: Some(format!("tiny {}", env!("CARGO_PKG_VERSION"))),
+            source: replies.source,
+            userinfo: replies.userinfo,
+            ping: replies.ping,
+            time: replies.time,
+            clientinfo: replies.clientinfo,
+        }
+    }
+}
+
+impl Ctcp {
+    pub(crate) fn to_client_ctcp_replies(&self) -> libtiny_client::CtcpReplies {
+        libtiny_client::CtcpReplies {
+            version: self.version.clone(),
+            source: self.source.clone(),
+            userinfo: self.userinfo.clone(),
+            ping: self.ping,
+            time: self.time,
+            clientinfo: self.clientinfo,
+        }
+    }
+}
+
+/// DCC file transfer settings. See `libtiny_client::DccConfig`.
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub(crate) struct Dcc {
+    /// Accept DCC offers and allow sending files
+    pub(crate) enabled: bool,
+    /// Where to save received files. Defaults to the user's download directory.
+    pub(crate) download_dir: Option<PathBuf>,
+    /// Max. size of received files, in bytes
+    pub(crate) max_size: Option<u64>,
+    /// IP address to send in offers, when the address of the server connection is not reachable
+    pub(crate) ip: Option<IpAddr>,
+    /// Ports to listen on, e.g. `[5000, 5010]`
+    pub(crate) ports: Option<(u16, u16)>,
+    /// Send passive offers
+    pub(crate) passive: bool,
+}
+
+impl Default for Dcc {
+    fn default() -> Self {
+        Dcc {
+            enabled: true,
+            download_dir: None,
+            max_size: None,
+            ip: None,
+            ports: None,
+            passive: false,
+        }
+    }
+}
+
+impl Dcc {
+    /// Returns `None` when DCC is disabled.
+    pub(crate) fn to_client_dcc_config(&self) -> Option<libtiny_client::DccConfig> {
+        if !self.enabled {
+            return None;
+        }
+        let download_dir = match self.download_dir {
+            Some(ref dir) => dir.clone(),
+            None => dirs::download_dir().or_else(dirs::home_dir)?,
+        };
+        Some(libtiny_client::DccConfig {
+            download_dir,
+            max_size: self.max_size,
+            ip: self.ip,
+            ports: self.ports,
+            passive: self.passive,
+        })
+    }
 }
 
 #[derive(Clone, Deserialize)]
@@ -26,6 +236,18 @@ pub(crate) struct Server {
     #[serde(default)]
     pub(crate) tls: bool,
 
+    /// Proxy to connect through (optional)
+    #[serde(default)]
+    pub(crate) proxy: Option<Proxy>,
+
+    /// TLS client certificate file (PEM), for SASL EXTERNAL authentication (CertFP)
+    #[serde(default)]
+    pub(crate) tls_cert: Option<PathBuf>,
+
+    /// Private key file (PEM) of `tls_cert`. Can be omitted when the key is in `tls_cert`.
+    #[serde(default)]
+    pub(crate) tls_key: Option<PathBuf>,
+
     /// Server password (optional)
     #[serde(default)]
     pub(crate) pass: Option<String>,
@@ -41,7 +263,7 @@ pub(crate) struct Server {
 
     /// Channels to automatically join.
     #[serde(default)]
-    pub(crate) join: Vec<String>,
+    pub(crate) join: Vec<JoinChan>,
 
     /// NickServ identification password. Used on connecting to the server and nick change.
     pub(crate) nickserv_ident: Option<String>,
@@ -49,6 +271,30 @@ pub(crate) struct Server {
     /// Authenication method
     #[serde(rename = "sasl")]
     pub(crate) sasl_auth: Option<SASLAuth>,
+
+    /// IRCv3 capabilities to request. Defaults to `libtiny_client::DEFAULT_CAPS`.
+    #[serde(default = "default_caps")]
+    pub(crate) caps: Vec<String>,
+
+    /// Reconnect policy
+    #[serde(default)]
+    pub(crate) reconnect: Reconnect,
+
+    /// Outgoing message rate limit
+    #[serde(default)]
+    pub(crate) flood_control: FloodControl,
+
+    /// Rejoin channels after being kicked, after this many seconds (optional)
+    #[serde(default)]
+    pub(crate) rejoin_on_kick: Option<u64>,
+
+    /// Replies to CTCP requests
+    #[serde(default)]
+    pub(crate) ctcp: Ctcp,
+
+    /// DCC file transfers
+    #[serde(default)]
+    pub(crate) dcc: Dcc,
 }
 
 /// Similar to `Server`, but used when connecting via the `/connect` command.
@@ -59,7 +305,7 @@ pub(crate) struct Defaults {
     #[serde(deserialize_with = "deser_trimmed_str")]
     pub(crate) realname: String,
     #[serde(default)]
-    pub(crate) join: Vec<String>,
+    pub(crate) join: Vec<JoinChan>,
     #[serde(default)]
     pub(crate) tls: bool,
 }
@@ -69,6 +315,81 @@ pub(crate) struct Config {
     pub(crate) servers: Vec<Server>,
     pub(crate) defaults: Defaults,
     pub(crate) log_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) aliases: Aliases,
+}
+
+/// User-defined command aliases, by name.
+pub(crate) type Aliases = BTreeMap<String, Alias>;
+
+/// A command alias. Either just the command template (`"/join $1"`), or a template and options
+/// (`{ cmd: "/join $1", override_builtin: true }`). See `alias::expand` for the template syntax.
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum Alias {
+    Cmd(String),
+    WithOptions {
+        cmd: String,
+        /// Allow the alias to replace a built-in command with the same name
+        #[serde(default)]
+        override_builtin: bool,
+    },
+}
+
+impl Alias {
+    pub(crate) fn cmd(&self) -> &str {
+        match self {
+            Alias::Cmd(cmd) | Alias::WithOptions { cmd, .. } => cmd.trim(),
+        }
+    }
+
+    pub(crate) fn override_builtin(&self) -> bool {
+        match self {
+            Alias::Cmd(_) => false,
+            Alias::WithOptions {
+                override_builtin, ..
+            } => *override_builtin,
+        }
+    }
+}
+
+/// A channel to join. Either just the channel name (`"#tiny"`), or a name and key
+/// (`{ name: "#tiny", key: "hunter2" }`).
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub(crate) enum JoinChan {
+    Name(String),
+    WithKey { name: String, key: Option<String> },
+}
+
+impl JoinChan {
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            JoinChan::Name(name) | JoinChan::WithKey { name, .. } => name.trim(),
+        }
+    }
+
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            JoinChan::Name(_) => None,
+            JoinChan::WithKey { key, .. } => key.as_deref(),
+        }
+    }
+
+    /// Channel name and key, for `libtiny_client::ServerInfo::auto_join`.
+    pub(crate) fn to_auto_join(&self) -> (ChanName, Option<String>) {
+        (
+            ChanNameRef::new(self.name()).to_owned(),
+            self.key().map(str::to_owned),
+        )
+    }
+}
+
+pub(crate) fn default_caps() -> Vec<String> {
+    libtiny_client::DEFAULT_CAPS
+        .iter()
+        .map(|cap| (*cap).to_owned())
+        .collect()
 }
 
 fn deser_trimmed_str<'de, D>(d: D) -> Result<String, D::Error>
@@ -87,6 +408,48 @@ where
     Ok(strs.into_iter().map(|s| s.trim().to_owned()).collect())
 }
 
+impl Proxy {
+    pub(crate) fn to_client_proxy(&self) -> libtiny_client::Proxy {
+        libtiny_client::Proxy {
+            kind: match self.kind {
+                ProxyKind::Socks5 => libtiny_client::ProxyKind::Socks5 {
+                    remote_dns: self.remote_dns,
+                },
+                ProxyKind::Http => libtiny_client::ProxyKind::Http,
+            },
+            addr: self.addr.clone(),
+            port: self.port,
+            auth: match (&self.username, &self.password) {
+                (Some(username), Some(password)) => Some(libtiny_client::ProxyAuth {
+                    username: username.clone(),
+                    password: password.clone(),
+                }),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Server {
+    /// Read the TLS client certificate and private key files. Returns an error description on
+    /// failure.
+    pub(crate) fn read_tls_client_cert(
+        &self,
+    ) -> Result<Option<libtiny_client::TlsClientCert>, String> {
+        let cert_path = match self.tls_cert {
+            None => return Ok(None),
+            Some(ref cert_path) => cert_path,
+        };
+        let key_path = self.tls_key.as_ref().unwrap_or(cert_path);
+        let read =
+            |path: &Path| fs::read(path).map_err(|err| format!("Can't read {:?}: {}", path, err));
+        Ok(Some(libtiny_client::TlsClientCert {
+            cert_pem: read(cert_path)?,
+            key_pem: read(key_path)?,
+        }))
+    }
+}
+
 impl Config {
     /// Returns error descriptions
     pub(crate) fn validate(&self) -> Vec<String> {
@@ -133,6 +496,114 @@ impl Config {
                     server.addr
                 ));
             }
+
+            if (server.tls_cert.is_some() || server.tls_key.is_some()) && !server.tls {
+                errors.push(format!(
+                    "'tls_cert' and 'tls_key' need 'tls: true', please update '{}'",
+                    server.addr
+                ));
+            }
+
+            if let Some(ref proxy) = server.proxy {
+                if proxy.username.is_some() != proxy.password.is_some() {
+                    errors.push(format!(
+                        "Proxy needs both 'username' and 'password' or neither, please update 'proxy' field of '{}'",
+                        server.addr
+                    ));
+                }
+            }
+
+            let reconnect = &server.reconnect;
+            if reconnect.initial_delay == 0
+                || reconnect.max_delay < reconnect.initial_delay
+                || !(0.0..=1.0).contains(&reconnect.jitter)
+                || reconnect.ping_interval == 0
+                || reconnect.pong_timeout == 0
+            {
+                errors.push(format!(
+                    "Invalid 'reconnect' settings for '{}': delays and timeouts need to be positive, 'max_delay' can't be smaller than 'initial_delay', 'jitter' needs to be between 0 and 1",
+                    server.addr
+                ));
+            }
+
+            if server.flood_control.burst == 0
+                || !(server.flood_control.rate >= 0.0 && server.flood_control.rate.is_finite())
+            {
+                errors.push(format!(
+                    "Invalid 'flood_control' settings for '{}': 'burst' needs to be at least 1, 'rate' can't be negative",
+                    server.addr
+                ));
+            }
+
+            if let Some((first, last)) = server.dcc.ports {
+                if first == 0 || last < first {
+                    errors.push(format!(
+                        "Invalid 'dcc' settings for '{}': 'ports' needs to be a range of non-zero ports, e.g. [5000, 5010]",
+                        server.addr
+                    ));
+                }
+            }
+
+            if server.tls_key.is_some() && server.tls_cert.is_none() {
+                errors.push(format!(
+                    "'tls_key' is set but 'tls_cert' is not, please update '{}'",
+                    server.addr
+                ));
+            }
+
+            if let Some(ref sasl_auth) = server.sasl_auth {
+                match sasl_auth.mechanism {
+                    SASLMechanism::Password => {
+                        if sasl_auth.username.is_none() || sasl_auth.password.is_none() {
+                            errors.push(format!(
+                                "SASL authentication needs 'username' and 'password', please update 'sasl' field of '{}'",
+                                server.addr
+                            ));
+                        }
+                    }
+                    SASLMechanism::External => {
+                        if server.tls_cert.is_none() {
+                            errors.push(format!(
+                                "SASL EXTERNAL needs a TLS client certificate, please add 'tls_cert' field to '{}'",
+                                server.addr
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (name, alias) in &self.aliases {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+            {
+                errors.push(format!(
+                    "Invalid alias name '{}', alias names can only have letters, digits, '-', and '_'",
+                    name
+                ));
+            } else if libtiny_tui::tui_cmds()
+                .iter()
+                .any(|tui_cmd| tui_cmd.name == name)
+            {
+                errors.push(format!(
+                    "Alias '{}' can't override the TUI command /{}, please rename the alias",
+                    name, name
+                ));
+            } else if cmd::find_builtin(name).is_some() && !alias.override_builtin() {
+                errors.push(format!(
+                    "Alias '{}' overrides the built-in command /{}, please rename the alias or \
+                     add 'override_builtin: true' to it",
+                    name, name
+                ));
+            }
+
+            if alias.cmd().is_empty() {
+                errors.push(format!("Command of alias '{}' is empty", name));
+            } else if let Err(err) = alias::check(alias.cmd()) {
+                errors.push(format!("Command of alias '{}' is invalid: {}", name, err));
+            }
         }
 
         errors
@@ -213,7 +684,6 @@ fn get_default_config_yaml() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_yaml;
 
     #[test]
     fn parse_default_config() {
@@ -223,12 +693,32 @@ mod tests {
                 panic!();
             }
             Ok(Config { servers, .. }) => {
-                assert_eq!(servers[0].join, vec!["#tiny".to_owned()]);
-                assert_eq!(servers[0].tls, true);
+                assert_eq!(servers[0].join, vec![JoinChan::Name("#tiny".to_owned())]);
+                assert!(servers[0].tls);
+                assert_eq!(servers[0].caps, default_caps());
+                assert_eq!(servers[0].reconnect, Reconnect::default());
+                assert_eq!(servers[0].ctcp, Ctcp::default());
+                assert_eq!(
+                    servers[0].flood_control.to_client_flood_control().interval,
+                    Duration::from_secs(2)
+                );
             }
         }
     }
 
+    #[test]
+    fn parse_join_chans() {
+        let chans: Vec<JoinChan> =
+            serde_yaml::from_str("- '#a'\n- name: '#b'\n  key: hunter2\n- name: '#c'\n").unwrap();
+        assert_eq!(
+            chans
+                .iter()
+                .map(|c| (c.name(), c.key()))
+                .collect::<Vec<_>>(),
+            vec![("#a", None), ("#b", Some("hunter2")), ("#c", None)]
+        );
+    }
+
     #[test]
     fn validation() {
         // We trim the string fields when deserializing, so `validate` doesn't consider non-empty
@@ -239,12 +729,35 @@ mod tests {
                 alias: None,
                 port: 123,
                 tls: false,
+                proxy: Some(Proxy {
+                    kind: ProxyKind::Socks5,
+                    addr: "localhost".to_owned(),
+                    port: 9050,
+                    username: Some("user".to_owned()),
+                    password: None,
+                    remote_dns: true,
+                }),
+                tls_cert: None,
+                tls_key: Some("key.pem".into()),
                 pass: None,
                 realname: "".to_owned(),
                 nicks: vec!["".to_owned()],
                 join: vec![],
                 nickserv_ident: None,
-                sasl_auth: None,
+                sasl_auth: Some(SASLAuth {
+                    mechanism: SASLMechanism::External,
+                    username: None,
+                    password: None,
+                }),
+                caps: vec![],
+                reconnect: Reconnect {
+                    jitter: 2.0,
+                    ..Reconnect::default()
+                },
+                flood_control: FloodControl::default(),
+                rejoin_on_kick: None,
+                ctcp: Ctcp::default(),
+                dcc: Dcc::default(),
             }],
             defaults: Defaults {
                 nicks: vec!["".to_owned()],
@@ -253,10 +766,27 @@ mod tests {
                 tls: false,
             },
             log_dir: None,
+            aliases: vec![
+                ("bad name", Alias::Cmd("/join $1".to_owned())),
+                ("clear", Alias::Cmd("/close".to_owned())),
+                ("join", Alias::Cmd("/join #$1".to_owned())),
+                (
+                    "msg",
+                    Alias::WithOptions {
+                        cmd: "/msg $1 $2-".to_owned(),
+                        override_builtin: true,
+                    },
+                ),
+                ("empty", Alias::Cmd(" ".to_owned())),
+                ("vars", Alias::Cmd("/msg $nick $foo".to_owned())),
+            ]
+            .into_iter()
+            .map(|(name, alias)| (name.to_owned(), alias))
+            .collect(),
         };
 
         let errors = config.validate();
-        assert_eq!(errors.len(), 4);
+        assert_eq!(errors.len(), 14);
 
         assert_eq!(
             &errors[0],
@@ -271,5 +801,72 @@ mod tests {
             &errors[3],
             "'realname' can't be empty, please update 'realname' field of 'my_server'"
         );
+        assert_eq!(
+            &errors[4],
+            "'tls_cert' and 'tls_key' need 'tls: true', please update 'my_server'"
+        );
+        assert_eq!(
+            &errors[5],
+            "Proxy needs both 'username' and 'password' or neither, please update 'proxy' field of 'my_server'"
+        );
+        assert!(errors[6].starts_with("Invalid 'reconnect' settings for 'my_server'"));
+        assert_eq!(
+            &errors[7],
+            "'tls_key' is set but 'tls_cert' is not, please update 'my_server'"
+        );
+        assert_eq!(
+            &errors[8],
+            "SASL EXTERNAL needs a TLS client certificate, please add 'tls_cert' field to 'my_server'"
+        );
+        // Aliases are sorted by name
+        assert!(errors[9].starts_with("Invalid alias name 'bad name'"));
+        assert_eq!(
+            &errors[10],
+            "Alias 'clear' can't override the TUI command /clear, please rename the alias"
+        );
+        assert_eq!(&errors[11], "Command of alias 'empty' is empty");
+        assert!(errors[12].starts_with("Alias 'join' overrides the built-in command /join"));
+        assert_eq!(
+            &errors[13],
+            "Command of alias 'vars' is invalid: Unknown variable: $foo"
+        );
+    }
+
+    #[test]
+    fn parse_aliases() {
+        let aliases: Aliases = serde_yaml::from_str(
+            "j: /join $1\nmsg:\n  cmd: /msg $1 $2-\n  override_builtin: true\n",
+        )
+        .unwrap();
+        assert_eq!(aliases["j"].cmd(), "/join $1");
+        assert!(!aliases["j"].override_builtin());
+        assert_eq!(aliases["msg"].cmd(), "/msg $1 $2-");
+        assert!(aliases["msg"].override_builtin());
+    }
+
+    #[test]
+    fn parse_proxy() {
+        let proxy: Proxy =
+            serde_yaml::from_str("type: socks5\naddr: localhost\nport: 9050").unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Socks5);
+        assert!(proxy.remote_dns);
+        assert!(proxy.to_client_proxy().auth.is_none());
+    }
+
+    #[test]
+    fn parse_sasl_auth() {
+        let plain: SASLAuth = serde_yaml::from_str("username: u\npassword: p").unwrap();
+        assert_eq!(
+            plain,
+            SASLAuth {
+                mechanism: SASLMechanism::Password,
+                username: Some("u".to_owned()),
+                password: Some("p".to_owned()),
+            }
+        );
+
+        let external: SASLAuth = serde_yaml::from_str("mechanism: external").unwrap();
+        assert_eq!(external.mechanism, SASLMechanism::External);
+        assert_eq!(external.username, None);
     }
 }
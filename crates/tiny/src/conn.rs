@@ -3,8 +3,8 @@
 
 //! IRC event handling
 
-use crate::ui::UI;
-use libtiny_common::{ChanNameRef, MsgTarget, TabStyle};
+use crate::ui::{CHAN_LIST_TAB, TRANSFERS_TAB, UI};
+use libtiny_common::{CaseMapping, ChanNameRef, MsgTarget, Nick, TabStyle};
 use libtiny_wire as wire;
 
 use tokio::sync::mpsc;
@@ -17,6 +17,10 @@ pub(crate) trait Client {
     fn get_nick(&self) -> String;
 
     fn is_nick_accepted(&self) -> bool;
+
+    fn casemapping(&self) -> CaseMapping;
+
+    fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str;
 }
 
 impl Client for libtiny_client::Client {
@@ -31,6 +35,14 @@ impl Client for libtiny_client::Client {
     fn is_nick_accepted(&self) -> bool {
         self.is_nick_accepted()
     }
+
+    fn casemapping(&self) -> CaseMapping {
+        self.server_features().casemapping
+    }
+
+    fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        self.drop_nick_prefix(nick)
+    }
 }
 
 pub(crate) async fn task(
@@ -75,15 +87,36 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
         Disconnected => {
             let serv = client.get_serv_name();
             ui.add_err_msg(
-                &format!(
-                    "Disconnected. Will try to reconnect in {} seconds.",
-                    libtiny_client::RECONNECT_SECS
-                ),
+                "Disconnected.",
                 time::now(),
                 &MsgTarget::AllServTabs { serv },
             );
             ui.clear_nicks(serv);
         }
+        ReconnectScheduled { attempt, delay } => {
+            ui.add_client_msg(
+                &format!(
+                    "Will try to reconnect in {} seconds (attempt {}).",
+                    delay.as_secs(),
+                    attempt
+                ),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        ReconnectGaveUp { attempts } => {
+            ui.add_err_msg(
+                &format!(
+                    "Giving up reconnecting after {} attempts. Use `/connect` to try again.",
+                    attempts
+                ),
+                time::now(),
+                &MsgTarget::AllServTabs {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
         IoErr(err) => {
             ui.add_err_msg(
                 &format!("Connection error: {}", err),
@@ -123,9 +156,42 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
         NickChange { new_nick } => {
             ui.set_nick(client.get_serv_name(), &new_nick);
         }
+        SendQueue { len } => {
+            let msg = if len == 0 {
+                "All messages waiting for flood control are sent.".to_owned()
+            } else {
+                format!(
+                    "{} message{} waiting to be sent because of flood control.",
+                    len,
+                    if len == 1 { " is" } else { "s are" }
+                )
+            };
+            ui.add_client_msg(
+                &msg,
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
         Msg(msg) => {
             handle_irc_msg(ui, client, msg);
         }
+        Whois(info) => {
+            // Shown in the tab the query was sent from. Queries not sent with `/whois` or
+            // `/whowas` (e.g. with `/raw`) are shown in the current tab.
+            let serv = client.get_serv_name();
+            let src = ui.whois_src(serv, Nick::new(info.nick.clone(), client.casemapping()));
+            let target = match &src {
+                Some(src) => src.to_target(),
+                None => MsgTarget::CurrentTab,
+            };
+            for line in whois_lines(&info) {
+                ui.add_client_msg(&line, &target);
+            }
+        }
+        ChanList(entries) => {
+            ui.set_chan_list_entries(CHAN_LIST_TAB, client.get_serv_name(), entries);
+        }
         WireError(err) => {
             ui.add_err_msg(
                 &format!("Wire protocol error: {}", err),
@@ -135,6 +201,19 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 },
             );
         }
+        Kicked { chan, by, reason } => {
+            let target = MsgTarget::Chan {
+                serv: client.get_serv_name(),
+                chan: &chan,
+            };
+            let msg = match reason {
+                None => format!("You were kicked by {}", by),
+                Some(reason) => format!("You were kicked by {} ({})", by, reason),
+            };
+            ui.add_err_msg(&msg, time::now(), &target);
+            ui.set_chan_parted(client.get_serv_name(), &chan);
+            ui.set_tab_style(TabStyle::Highlight, &target);
+        }
         ChannelJoinError { chan, msg } => ui.add_client_msg(
             &msg,
             &MsgTarget::Chan {
@@ -142,19 +221,279 @@ fn handle_conn_ev(ui: &UI, client: &dyn Client, ev: libtiny_client::Event) {
                 chan: &chan,
             },
         ),
+        DccOffer(transfer) => {
+            let target = transfers_tab(ui);
+            ui.add_client_msg(
+                &format!(
+                    "{} offers file {}{}. Accept with `/dcc accept {}`, reject with `/dcc reject {}`",
+                    transfer.nick,
+                    transfer.filename,
+                    transfer
+                        .size
+                        .map(|size| format!(" ({})", format_size(size)))
+                        .unwrap_or_default(),
+                    transfer.id,
+                    transfer.id
+                ),
+                &target,
+            );
+            ui.set_tab_style(TabStyle::Highlight, &target);
+        }
+        DccStarted(transfer) => {
+            let msg = if transfer.incoming {
+                format!(
+                    "[{}] Receiving {} from {}",
+                    transfer.id, transfer.filename, transfer.nick
+                )
+            } else {
+                format!(
+                    "[{}] Sending {} to {}",
+                    transfer.id, transfer.filename, transfer.nick
+                )
+            };
+            ui.add_client_msg(&msg, &transfers_tab(ui));
+        }
+        DccProgress {
+            transfer,
+            transferred,
+        } => {
+            let progress = match transfer.size {
+                Some(size) if size > 0 => format!(
+                    "{} of {} ({}%)",
+                    format_size(transferred),
+                    format_size(size),
+                    transferred * 100 / size
+                ),
+                _ => format_size(transferred),
+            };
+            ui.add_client_msg(
+                &format!("[{}] {}: {}", transfer.id, transfer.filename, progress),
+                &transfers_tab(ui),
+            );
+        }
+        DccDone { transfer, path } => {
+            let target = transfers_tab(ui);
+            let msg = if transfer.incoming {
+                format!(
+                    "[{}] Received {} from {}, saved to {}",
+                    transfer.id,
+                    transfer.filename,
+                    transfer.nick,
+                    path.display()
+                )
+            } else {
+                format!(
+                    "[{}] Sent {} to {}",
+                    transfer.id, transfer.filename, transfer.nick
+                )
+            };
+            ui.add_client_msg(&msg, &target);
+            ui.set_tab_style(TabStyle::NewMsg, &target);
+        }
+        DccFailed { transfer, msg } => {
+            let target = transfers_tab(ui);
+            ui.add_err_msg(
+                &format!(
+                    "[{}] Transfer of {} {} {} failed: {}",
+                    transfer.id,
+                    transfer.filename,
+                    if transfer.incoming { "from" } else { "to" },
+                    transfer.nick,
+                    msg
+                ),
+                time::now(),
+                &target,
+            );
+            ui.set_tab_style(TabStyle::Highlight, &target);
+        }
+        DccChatOffer(chat) => {
+            let serv = client.get_serv_name();
+            let target = MsgTarget::DccChat {
+                serv,
+                nick: &chat.nick,
+            };
+            ui.new_dcc_chat_tab(serv, &chat.nick);
+            ui.add_client_msg(
+                &format!(
+                    "{} offers a DCC chat. Accept with `/dcc accept {}`, reject with `/dcc reject {}`",
+                    chat.nick, chat.id, chat.id
+                ),
+                &target,
+            );
+            ui.set_tab_style(TabStyle::Highlight, &target);
+        }
+        DccChatStarted(chat) => {
+            ui.add_client_msg(
+                &format!("DCC chat with {} connected", chat.nick),
+                &MsgTarget::DccChat {
+                    serv: client.get_serv_name(),
+                    nick: &chat.nick,
+                },
+            );
+        }
+        DccChatMsg {
+            chat,
+            msg,
+            is_action,
+        } => {
+            let target = MsgTarget::DccChat {
+                serv: client.get_serv_name(),
+                nick: &chat.nick,
+            };
+            ui.add_privmsg(&chat.nick, &msg, time::now(), &target, false, is_action);
+            ui.set_tab_style(TabStyle::Highlight, &target);
+        }
+        DccChatClosed { chat, msg } => {
+            let target = MsgTarget::DccChat {
+                serv: client.get_serv_name(),
+                nick: &chat.nick,
+            };
+            match msg {
+                None => ui.add_client_msg(&format!("{} closed the DCC chat", chat.nick), &target),
+                Some(msg) => ui.add_err_msg(
+                    &format!("DCC chat with {} failed: {}", chat.nick, msg),
+                    time::now(),
+                    &target,
+                ),
+            }
+        }
+        SASLNotSupported => {
+            ui.add_err_msg(
+                "Server does not support SASL authentication",
+                time::now(),
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+        SASLFailed { reason: _, msg } => {
+            ui.add_err_msg(
+                &format!("SASL authentication failed: {}", msg),
+                time::now(),
+                &MsgTarget::Server {
+                    serv: client.get_serv_name(),
+                },
+            );
+        }
+    }
+}
+
+/// Create the DCC transfers tab if it doesn't exist. Returns the tab.
+fn transfers_tab(ui: &UI) -> MsgTarget<'static> {
+    ui.new_server_tab(TRANSFERS_TAB, None);
+    MsgTarget::Server {
+        serv: TRANSFERS_TAB,
     }
 }
 
+/// Lines to show for a WHOIS or WHOWAS reply.
+fn whois_lines(info: &libtiny_client::WhoisInfo) -> Vec<String> {
+    if let Some(ref error) = info.error {
+        return vec![format!("{}: {}", info.nick, error)];
+    }
+
+    let mut header = info.nick.clone();
+    if info.whowas {
+        header.push_str(" was");
+    }
+    if let (Some(user), Some(host)) = (&info.user, &info.host) {
+        header.push_str(&format!(" ({}@{})", user, host));
+    }
+    if let Some(ref realname) = info.realname {
+        header.push_str(&format!(": {}", realname));
+    }
+
+    let mut lines = vec![header];
+    if let Some(ref server) = info.server {
+        match info.server_info {
+            None => lines.push(format!("  server: {}", server)),
+            Some(ref server_info) => lines.push(format!("  server: {} ({})", server, server_info)),
+        }
+    }
+    if let Some(ref account) = info.account {
+        lines.push(format!("  account: {}", account));
+    }
+    if !info.channels.is_empty() {
+        lines.push(format!("  channels: {}", info.channels.join(" ")));
+    }
+    if let Some(ref away) = info.away {
+        lines.push(format!("  away: {}", away));
+    }
+    if let Some(ref actual_host) = info.actual_host {
+        lines.push(format!("  actual host: {}", actual_host));
+    }
+    if let Some(idle) = info.idle {
+        let mut line = format!("  idle: {}", format_duration(idle.as_secs()));
+        if let Some(signon) = info.signon {
+            let signon = time::at(time::Timespec::new(signon as i64, 0));
+            line.push_str(&format!(
+                ", signed on: {}",
+                time::strftime("%Y-%m-%d %H:%M:%S", &signon).unwrap()
+            ));
+        }
+        lines.push(line);
+    }
+    if let Some(ref operator) = info.operator {
+        lines.push(format!("  {}", operator));
+    }
+    if info.secure {
+        lines.push("  using a secure connection".to_owned());
+    }
+    for other in &info.other {
+        lines.push(format!("  {}", other));
+    }
+    lines
+}
+
+/// Format a duration in seconds for humans, e.g. `1h 2m 3s`.
+fn format_duration(secs: u64) -> String {
+    let units = [(86400, "d"), (3600, "h"), (60, "m")];
+    let mut parts = vec![];
+    let mut secs = secs;
+    for (unit_secs, unit) in units {
+        if secs >= unit_secs {
+            parts.push(format!("{}{}", secs / unit_secs, unit));
+            secs %= unit_secs;
+        }
+    }
+    if secs > 0 || parts.is_empty() {
+        parts.push(format!("{}s", secs));
+    }
+    parts.join(" ")
+}
+
+/// Format a file size for humans, e.g. `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
     use wire::Cmd::*;
     use wire::Pfx::*;
 
-    let wire::Msg { pfx, cmd } = msg;
-    let ts = time::now();
+    let wire::Msg {
+        pfx,
+        cmd,
+        server_time,
+        ..
+    } = msg;
+    // Use the time sent by the server when available (`server-time` extension) so that messages
+    // replayed by bouncers or delayed by lag are shown and logged with the right time.
+    let ts = server_time.unwrap_or_else(time::now);
     let serv = client.get_serv_name();
     match cmd {
         PRIVMSG {
-            target,
+            targets,
             msg,
             is_notice,
             ctcp,
@@ -173,130 +512,118 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 User { ref nick, .. } | Ambiguous(ref nick) => nick,
             };
 
-            if ctcp == Some(wire::CTCP::Version) {
-                let msg_target = if ui.user_tab_exists(serv, sender) {
-                    MsgTarget::User { serv, nick: sender }
-                } else {
-                    MsgTarget::Server { serv }
-                };
-                ui.add_client_msg(
-                    &format!("Received version request from {}", sender),
-                    &msg_target,
-                );
-                return;
+            // CTCP requests are answered by the client. Requests and replies are shown in the
+            // sender's tab if we have one, otherwise in the server tab.
+            match ctcp {
+                Some(wire::CTCP::Action) | None => {}
+                // DCC messages are handled by the client, see `DccOffer` and other DCC events
+                Some(wire::CTCP::Dcc) => return,
+                Some(ref ctcp) => {
+                    let msg_target = if ui.user_tab_exists(serv, sender) {
+                        MsgTarget::User { serv, nick: sender }
+                    } else {
+                        MsgTarget::Server { serv }
+                    };
+                    let ui_msg = if !is_notice {
+                        format!("Received CTCP {} request from {}", ctcp.as_str(), sender)
+                    } else if *ctcp == wire::CTCP::Ping {
+                        match libtiny_client::ctcp_ping_rtt(&msg) {
+                            Some(rtt) => {
+                                format!("CTCP PING reply from {}: {} ms", sender, rtt.as_millis())
+                            }
+                            None => format!("CTCP PING reply from {}: {}", sender, msg),
+                        }
+                    } else {
+                        format!("CTCP {} reply from {}: {}", ctcp.as_str(), sender, msg)
+                    };
+                    ui.add_client_msg(&ui_msg, &msg_target);
+                    if is_notice {
+                        ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                    }
+                    return;
+                }
             }
 
             let is_action = ctcp == Some(wire::CTCP::Action);
 
-            match target {
-                wire::MsgTarget::Chan(chan) => {
-                    let ui_msg_target = MsgTarget::Chan { serv, chan: &chan };
-                    // highlight the message if it mentions us
-                    if msg.find(&client.get_nick()).is_some() {
-                        ui.add_privmsg(sender, &msg, ts, &ui_msg_target, true, is_action);
-                        ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
-                        let mentions_target = MsgTarget::Server { serv: "mentions" };
-                        ui.add_msg(
-                            &format!("{} in {}:{}: {}", sender, serv, chan.display(), msg),
-                            ts,
-                            &mentions_target,
-                        );
-                        ui.set_tab_style(TabStyle::Highlight, &mentions_target);
-                    } else {
-                        ui.add_privmsg(sender, &msg, ts, &ui_msg_target, false, is_action);
-                        ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
+            for target in targets {
+                match target {
+                    wire::MsgTarget::Chan(chan) => {
+                        let ui_msg_target = MsgTarget::Chan { serv, chan: &chan };
+                        // highlight the message if it mentions us. Our own messages can be sent back
+                        // to us by the server (`echo-message`), those are not highlighted.
+                        let nick = client.get_nick();
+                        if !is_our_nick(client, sender) && msg.contains(&nick) {
+                            ui.add_privmsg(sender, &msg, ts, &ui_msg_target, true, is_action);
+                            ui.set_tab_style(TabStyle::Highlight, &ui_msg_target);
+                            let mentions_target = MsgTarget::Server { serv: "mentions" };
+                            ui.add_msg(
+                                &format!("{} in {}:{}: {}", sender, serv, chan.display(), msg),
+                                ts,
+                                &mentions_target,
+                            );
+                            ui.set_tab_style(TabStyle::Highlight, &mentions_target);
+                        } else {
+                            ui.add_privmsg(sender, &msg, ts, &ui_msg_target, false, is_action);
+                            ui.set_tab_style(TabStyle::NewMsg, &ui_msg_target);
+                        }
                     }
-                }
-                wire::MsgTarget::User(target) => {
-                    // If the sender is a server we show the message in the server tab. Otherwise
-                    // we show it in a private tab.
-                    //
-                    // Some bouncers send PRIVMSGs from users with ambiguous prefix without a
-                    // `user@host` part so we treat ambiguity as nick. See #247.
-                    match pfx {
-                        Server(_) => {
-                            let msg_target = MsgTarget::Server { serv };
-                            ui.add_privmsg(serv, &msg, ts, &msg_target, false, is_action);
-                            if target == client.get_nick() {
-                                ui.set_tab_style(TabStyle::Highlight, &msg_target);
-                            } else {
-                                ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                    wire::MsgTarget::User(target) => {
+                        // If the sender is a server we show the message in the server tab. Otherwise
+                        // we show it in a private tab.
+                        //
+                        // Some bouncers send PRIVMSGs from users with ambiguous prefix without a
+                        // `user@host` part so we treat ambiguity as nick. See #247.
+                        match pfx {
+                            Server(_) => {
+                                let msg_target = MsgTarget::Server { serv };
+                                ui.add_privmsg(serv, &msg, ts, &msg_target, false, is_action);
+                                if is_our_nick(client, &target) {
+                                    ui.set_tab_style(TabStyle::Highlight, &msg_target);
+                                } else {
+                                    ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                                }
                             }
-                        }
-                        User { ref nick, .. } | Ambiguous(ref nick) => {
-                            if target == client.get_nick() {
-                                // Message is sent to us. Show NOTICE messages in server tabs if we
-                                // don't have a tab for the sender already (see #21).
-                                let msg_target = if is_notice && !ui.user_tab_exists(serv, nick) {
-                                    MsgTarget::Server { serv }
+                            User { ref nick, .. } | Ambiguous(ref nick) => {
+                                if is_our_nick(client, &target) {
+                                    // Message is sent to us. Show NOTICE messages in server tabs if we
+                                    // don't have a tab for the sender already (see #21).
+                                    let msg_target = if is_notice && !ui.user_tab_exists(serv, nick)
+                                    {
+                                        MsgTarget::Server { serv }
+                                    } else {
+                                        MsgTarget::User { serv, nick }
+                                    };
+                                    ui.add_privmsg(nick, &msg, ts, &msg_target, false, is_action);
+                                    ui.set_tab_style(TabStyle::Highlight, &msg_target);
                                 } else {
-                                    MsgTarget::User { serv, nick }
-                                };
-                                ui.add_privmsg(nick, &msg, ts, &msg_target, false, is_action);
-                                ui.set_tab_style(TabStyle::Highlight, &msg_target);
-                            } else {
-                                // PRIVMSG not sent to us. This case can happen in a few cases:
-                                //
-                                // - When using a bouncer, see #271. When multiple clients connect
-                                //   to the same bouncer and one of them sends a PRIVMSG, the
-                                //   message is relayed to the other clients. Example:
-                                //
-                                //       <our_nick> PRIVMSG <target> :...
-                                //
-                                //   In this case (when the sender is us) we show the message in
-                                //   the target's tab and our nick as the sender.
-                                //
-                                // - When the message target is a "host mask" (e.g. message was
-                                //   sent to all users matching a mask), see #278. Example:
-                                //
-                                //       <some prefix> PRIVMSG $$* :...
-                                //
-                                //    In this case (when the sender is not us) we show the message
-                                //    in the target's tab as the prefix as the sender.
-
-                                match pfx {
-                                    Server(ref sender) => {
-                                        // A message sent by a server to someone other than us?
-                                        // The target is probably a host mask. Show the message in
-                                        // the server tab.
-                                        let msg_target = MsgTarget::Server { serv };
-                                        ui.add_privmsg(
-                                            sender,
-                                            &msg,
-                                            ts,
-                                            &msg_target,
-                                            false,
-                                            is_action,
-                                        );
-                                        ui.set_tab_style(TabStyle::Highlight, &msg_target);
-                                    }
-                                    User { ref nick, .. } | Ambiguous(ref nick) => {
-                                        if nick == &client.get_nick() {
-                                            // Case (1). Don't highlight the tab as `Highlight`:
-                                            // the message was sent by us so the tab probably
-                                            // doesn't need that much attention. Highlight as
-                                            // `NewMsg` instead.
-                                            let msg_target = MsgTarget::User {
-                                                serv,
-                                                nick: &target,
-                                            };
-                                            ui.add_privmsg(
-                                                &client.get_nick(),
-                                                &msg,
-                                                ts,
-                                                &msg_target,
-                                                false,
-                                                is_action,
-                                            );
-                                            // Don't highlight the tab as `Highlight`: the message was sent by us so
-                                            // the tab probably doesn't need that much attention. Highlight as `NewMsg`
-                                            // instead.
-                                            ui.set_tab_style(TabStyle::NewMsg, &msg_target);
-                                        } else {
-                                            // Case (2)
-                                            let msg_target = MsgTarget::User { serv, nick };
+                                    // PRIVMSG not sent to us. This case can happen in a few cases:
+                                    //
+                                    // - When using a bouncer, see #271. When multiple clients connect
+                                    //   to the same bouncer and one of them sends a PRIVMSG, the
+                                    //   message is relayed to the other clients. Example:
+                                    //
+                                    //       <our_nick> PRIVMSG <target> :...
+                                    //
+                                    //   In this case (when the sender is us) we show the message in
+                                    //   the target's tab and our nick as the sender.
+                                    //
+                                    // - When the message target is a "host mask" (e.g. message was
+                                    //   sent to all users matching a mask), see #278. Example:
+                                    //
+                                    //       <some prefix> PRIVMSG $$* :...
+                                    //
+                                    //    In this case (when the sender is not us) we show the message
+                                    //    in the target's tab as the prefix as the sender.
+
+                                    match pfx {
+                                        Server(ref sender) => {
+                                            // A message sent by a server to someone other than us?
+                                            // The target is probably a host mask. Show the message in
+                                            // the server tab.
+                                            let msg_target = MsgTarget::Server { serv };
                                             ui.add_privmsg(
-                                                nick,
+                                                sender,
                                                 &msg,
                                                 ts,
                                                 &msg_target,
@@ -305,8 +632,44 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                                             );
                                             ui.set_tab_style(TabStyle::Highlight, &msg_target);
                                         }
-                                    }
-                                };
+                                        User { ref nick, .. } | Ambiguous(ref nick) => {
+                                            if is_our_nick(client, nick) {
+                                                // Case (1). Don't highlight the tab as `Highlight`:
+                                                // the message was sent by us so the tab probably
+                                                // doesn't need that much attention. Highlight as
+                                                // `NewMsg` instead.
+                                                let msg_target = MsgTarget::User {
+                                                    serv,
+                                                    nick: &target,
+                                                };
+                                                ui.add_privmsg(
+                                                    &client.get_nick(),
+                                                    &msg,
+                                                    ts,
+                                                    &msg_target,
+                                                    false,
+                                                    is_action,
+                                                );
+                                                // Don't highlight the tab as `Highlight`: the message was sent by us so
+                                                // the tab probably doesn't need that much attention. Highlight as `NewMsg`
+                                                // instead.
+                                                ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+                                            } else {
+                                                // Case (2)
+                                                let msg_target = MsgTarget::User { serv, nick };
+                                                ui.add_privmsg(
+                                                    nick,
+                                                    &msg,
+                                                    ts,
+                                                    &msg_target,
+                                                    false,
+                                                    is_action,
+                                                );
+                                                ui.set_tab_style(TabStyle::Highlight, &msg_target);
+                                            }
+                                        }
+                                    };
+                                }
                             }
                         }
                     }
@@ -314,53 +677,53 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             }
         }
 
-        JOIN { chan } => {
+        JOIN { chans, keys } => {
             let nick = match pfx {
                 Some(User { nick, .. }) | Some(Ambiguous(nick)) => nick,
                 Some(Server(_)) | None => {
                     debug!(
                         "JOIN with weird prefix: pfx={:?}, cmd={:?}",
                         pfx,
-                        JOIN { chan }
+                        JOIN { chans, keys }
                     );
                     return;
                 }
             };
 
-            if nick == client.get_nick() {
-                ui.new_chan_tab(serv, &chan);
-            } else {
-                let nick = wire::drop_nick_prefix(&nick);
-                let ts = Some(time::now());
-                ui.add_nick(nick, ts, &MsgTarget::Chan { serv, chan: &chan });
-                // Also update the private message tab if it exists
-                // Nothing will be shown if the user already known to be online by the tab
-                if ui.user_tab_exists(serv, nick) {
-                    ui.add_nick(nick, ts, &MsgTarget::User { serv, nick });
+            for chan in &chans {
+                if is_our_nick(client, &nick) {
+                    ui.new_chan_tab(serv, chan);
+                } else {
+                    let nick = client.drop_nick_prefix(&nick);
+                    let ts = Some(ts);
+                    ui.add_nick(nick, ts, &MsgTarget::Chan { serv, chan });
+                    // Also update the private message tab if it exists
+                    // Nothing will be shown if the user already known to be online by the tab
+                    if ui.user_tab_exists(serv, nick) {
+                        ui.add_nick(nick, ts, &MsgTarget::User { serv, nick });
+                    }
+                    ui.set_tab_style(TabStyle::JoinOrPart, &MsgTarget::Chan { serv, chan })
                 }
-                ui.set_tab_style(TabStyle::JoinOrPart, &MsgTarget::Chan { serv, chan: &chan })
             }
         }
 
-        PART { chan, msg } => {
+        PART { chans, msg } => {
             let nick = match pfx {
                 Some(User { nick, .. }) | Some(Ambiguous(nick)) => nick,
                 Some(Server(_)) | None => {
                     debug!(
                         "PART with weird prefix: pfx={:?}, cmd={:?}",
                         pfx,
-                        PART { chan, msg }
+                        PART { chans, msg }
                     );
                     return;
                 }
             };
-            if nick != client.get_nick() {
-                ui.remove_nick(
-                    &nick,
-                    Some(time::now()),
-                    &MsgTarget::Chan { serv, chan: &chan },
-                );
-                ui.set_tab_style(TabStyle::JoinOrPart, &MsgTarget::Chan { serv, chan: &chan })
+            if !is_our_nick(client, &nick) {
+                for chan in &chans {
+                    ui.remove_nick(&nick, Some(ts), &MsgTarget::Chan { serv, chan });
+                    ui.set_tab_style(TabStyle::JoinOrPart, &MsgTarget::Chan { serv, chan })
+                }
             }
         }
 
@@ -378,10 +741,10 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             };
 
             for chan in &chans {
-                ui.remove_nick(nick, Some(time::now()), &MsgTarget::Chan { serv, chan });
+                ui.remove_nick(nick, Some(ts), &MsgTarget::Chan { serv, chan });
             }
             if ui.user_tab_exists(serv, nick) {
-                ui.remove_nick(nick, Some(time::now()), &MsgTarget::User { serv, nick });
+                ui.remove_nick(nick, Some(ts), &MsgTarget::User { serv, nick });
             }
         }
 
@@ -399,18 +762,13 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             };
 
             for chan in &chans {
-                ui.rename_nick(
-                    &old_nick,
-                    &nick,
-                    time::now(),
-                    &MsgTarget::Chan { serv, chan },
-                );
+                ui.rename_nick(&old_nick, &nick, ts, &MsgTarget::Chan { serv, chan });
             }
             if ui.user_tab_exists(serv, &old_nick) {
                 ui.rename_nick(
                     &old_nick,
                     &nick,
-                    time::now(),
+                    ts,
                     &MsgTarget::User {
                         serv,
                         nick: &old_nick,
@@ -425,7 +783,7 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 // Nick change request from user failed. Just show an error message.
                 ui.add_err_msg(
                     "Nickname is already in use",
-                    time::now(),
+                    ts,
                     &MsgTarget::AllServTabs { serv },
                 );
             }
@@ -436,39 +794,87 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
         }
 
         ERROR { msg } => {
-            ui.add_err_msg(&msg, time::now(), &MsgTarget::AllServTabs { serv });
+            ui.add_err_msg(&msg, ts, &MsgTarget::AllServTabs { serv });
         }
 
         TOPIC { chan, topic } => {
-            ui.set_topic(&topic, time::now(), serv, &chan);
+            ui.set_topic(&topic, ts, serv, &chan);
+        }
+
+        MODE {
+            target,
+            modes,
+            params,
+            ..
+        } => {
+            let mut mode_str = modes;
+            for param in &params {
+                mode_str.push(' ');
+                mode_str.push_str(param);
+            }
+            if is_our_nick(client, &target) {
+                // User mode
+                let msg = format!("Your user mode is now {}", mode_str);
+                ui.add_msg(&msg, ts, &MsgTarget::Server { serv });
+            } else {
+                let msg = format!("{} sets mode {}", pfx_name(&pfx), mode_str);
+                let chan = ChanNameRef::new(&target);
+                ui.add_msg(&msg, ts, &MsgTarget::Chan { serv, chan });
+            }
+        }
+
+        KICK { chan, nick, msg } => {
+            let reason = msg.map(|msg| format!(" ({})", msg)).unwrap_or_default();
+            let chan_target = MsgTarget::Chan { serv, chan: &chan };
+            // Kicks of us are reported with `Event::Kicked`
+            if !is_our_nick(client, &nick) {
+                let msg = format!("{} was kicked by {}{}", nick, pfx_name(&pfx), reason);
+                ui.remove_nick(&nick, Some(ts), &chan_target);
+                ui.add_msg(&msg, ts, &chan_target);
+                ui.set_tab_style(TabStyle::JoinOrPart, &chan_target);
+            }
+        }
+
+        INVITE { nick, chan } => {
+            if is_our_nick(client, &nick) {
+                let msg = format!("{} invited you to {}", pfx_name(&pfx), chan.display());
+                let msg_target = MsgTarget::Server { serv };
+                ui.add_msg(&msg, ts, &msg_target);
+                ui.set_tab_style(TabStyle::Highlight, &msg_target);
+            } else {
+                // invite-notify
+                let msg = format!("{} invited {}", pfx_name(&pfx), nick);
+                ui.add_msg(&msg, ts, &MsgTarget::Chan { serv, chan: &chan });
+            }
+        }
+
+        WALLOPS { msg } => {
+            let msg_target = MsgTarget::Server { serv };
+            ui.add_privmsg(pfx_name(&pfx), &msg, ts, &msg_target, false, false);
+            ui.set_tab_style(TabStyle::NewMsg, &msg_target);
+        }
+
+        ACCOUNT { .. } | CHGHOST { .. } | SETNAME { .. } => {
+            // Not shown
         }
 
         CAP {
             client: _,
             subcommand,
             params,
+            ..
         } => match subcommand.as_ref() {
             "NAK" => {
                 if params.iter().any(|cap| cap.as_str() == "sasl") {
                     let msg_target = MsgTarget::Server { serv };
                     ui.add_err_msg(
-                        "Server rejected using SASL authenication capability",
-                        time::now(),
+                        "Server rejected using SASL authentication capability",
+                        ts,
                         &msg_target,
                     );
                 }
             }
-            "LS" => {
-                if !params.iter().any(|cap| cap.as_str() == "sasl") {
-                    let msg_target = MsgTarget::Server { serv };
-                    ui.add_err_msg(
-                        "Server does not support SASL authenication",
-                        time::now(),
-                        &msg_target,
-                    );
-                }
-            }
-            "ACK" => {}
+            "LS" | "ACK" => {}
             cmd => {
                 debug!("Ignoring CAP subcommand {}: params={:?}", cmd, params);
             }
@@ -480,6 +886,10 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
 
         Reply { num: n, params } => {
             let n_params = params.len();
+            if n == 5 {
+                // RPL_ISUPPORT: Nicks in the UI are compared using the server's casemapping
+                ui.set_casemapping(serv, client.casemapping());
+            }
             if (
                 n <= 003 // RPL_WELCOME, RPL_YOURHOST, RPL_CREATED
                     || n == 251 // RPL_LUSERCLIENT
@@ -491,17 +901,17 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
             ) && n_params == 2
             {
                 let msg = &params[1];
-                ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
+                ui.add_msg(msg, ts, &MsgTarget::Server { serv });
             } else if n == 4 // RPL_MYINFO
                     || n == 5 // RPL_BOUNCE
-                    || (n >= 252 && n <= 254)
+                    || (252..=254).contains(&n)
             // RPL_LUSEROP, RPL_LUSERUNKNOWN, RPL_LUSERCHANNELS
             {
                 let msg = params.into_iter().collect::<Vec<String>>().join(" ");
-                ui.add_msg(&msg, time::now(), &MsgTarget::Server { serv });
+                ui.add_msg(&msg, ts, &MsgTarget::Server { serv });
             } else if (n == 265 || n == 266 || n == 250) && n_params > 0 {
                 let msg = &params[n_params - 1];
-                ui.add_msg(msg, time::now(), &MsgTarget::Server { serv });
+                ui.add_msg(msg, ts, &MsgTarget::Server { serv });
             }
             // RPL_TOPIC
             else if n == 332 && (n_params == 3 || n_params == 2) {
@@ -509,7 +919,7 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 // one being our nick).
                 let chan = &params[n_params - 2];
                 let topic = &params[n_params - 1];
-                ui.set_topic(topic, time::now(), serv, ChanNameRef::new(chan));
+                ui.set_topic(topic, ts, serv, ChanNameRef::new(chan));
             }
             // RPL_NAMREPLY: List of users in a channel
             else if n == 353 && n_params > 3 {
@@ -520,12 +930,43 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                 };
 
                 for nick in params[3].split_whitespace() {
-                    ui.add_nick(wire::drop_nick_prefix(nick), None, &chan_target);
+                    ui.add_nick(client.drop_nick_prefix(nick), None, &chan_target);
                 }
             }
             // RPL_ENDOFNAMES: End of NAMES list
             else if n == 366 {
             }
+            // RPL_CHANNELMODEIS: Reply to `/mode <chan>`
+            else if n == 324 && n_params > 2 {
+                let chan = ChanNameRef::new(&params[1]);
+                let modes = params[2..].join(" ");
+                ui.add_client_msg(
+                    &format!("Channel modes: {}", modes),
+                    &MsgTarget::Chan { serv, chan },
+                );
+            }
+            // RPL_CREATIONTIME: Reply to `/mode <chan>`, available in client state
+            else if n == 329 {
+            }
+            // RPL_INVITELIST, RPL_EXCEPTLIST, RPL_BANLIST: Replies to `/mode <chan> I`, `e`, `b`
+            else if (n == 346 || n == 348 || n == 367) && n_params > 2 {
+                let chan = ChanNameRef::new(&params[1]);
+                let list = match n {
+                    346 => "Invite list",
+                    348 => "Exception list",
+                    _ => "Ban list",
+                };
+                let msg = match params.get(3) {
+                    Some(set_by) => format!("{}: {} (set by {})", list, params[2], set_by),
+                    None => format!("{}: {}", list, params[2]),
+                };
+                ui.add_client_msg(&msg, &MsgTarget::Chan { serv, chan });
+            }
+            // RPL_ENDOFINVITELIST, RPL_ENDOFEXCEPTLIST, RPL_ENDOFBANLIST
+            else if (n == 347 || n == 349 || n == 368) && n_params > 2 {
+                let chan = ChanNameRef::new(&params[1]);
+                ui.add_client_msg(&params[n_params - 1], &MsgTarget::Chan { serv, chan });
+            }
             // RPL_UNAWAY or RPL_NOWAWAY
             else if (n == 305 || n == 306) && n_params > 1 {
                 let msg = &params[1];
@@ -544,18 +985,27 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
                     &format!("{} is away: {}", nick, msg),
                     &MsgTarget::User { serv, nick },
                 );
+            }
+            // ERR_CHANOPRIVSNEEDED: Reply to operator commands (/kick, /op etc.). Shown in the
+            // current tab as the command may be for a channel we don't have a tab for.
+            else if n == 482 && n_params > 1 {
+                ui.add_err_msg(
+                    &format!(
+                        "You need to be a channel operator in {} to do that",
+                        params[1]
+                    ),
+                    ts,
+                    &MsgTarget::CurrentTab,
+                );
+            }
+            // ERR_NICKLOCKED, ERR_SASLFAIL, ERR_SASLTOOLONG, ERR_SASLABORTED, ERR_SASLALREADY:
+            // reported with `Event::SASLFailed`
+            else if n == 902 || (904..=907).contains(&n) {
             } else {
                 match pfx {
                     Some(Server(msg_serv)) | Some(Ambiguous(msg_serv)) => {
                         let msg_target = MsgTarget::Server { serv };
-                        ui.add_privmsg(
-                            &msg_serv,
-                            &params.join(" "),
-                            time::now(),
-                            &msg_target,
-                            false,
-                            false,
-                        );
+                        ui.add_privmsg(&msg_serv, &params.join(" "), ts, &msg_target, false, false);
                         ui.set_tab_style(TabStyle::NewMsg, &msg_target);
                     }
                     Some(User { .. }) | None => {
@@ -571,14 +1021,7 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
         Other { cmd, params } => match pfx {
             Some(Server(msg_serv)) => {
                 let msg_target = MsgTarget::Server { serv };
-                ui.add_privmsg(
-                    &msg_serv,
-                    &params.join(" "),
-                    time::now(),
-                    &msg_target,
-                    false,
-                    false,
-                );
+                ui.add_privmsg(&msg_serv, &params.join(" "), ts, &msg_target, false, false);
                 ui.set_tab_style(TabStyle::NewMsg, &msg_target);
             }
             Some(User { .. }) | Some(Ambiguous(_)) | None => {
@@ -590,3 +1033,19 @@ fn handle_irc_msg(ui: &UI, client: &dyn Client, msg: wire::Msg) {
         },
     }
 }
+
+/// Is the nick our current nick, according to the server's casemapping?
+fn is_our_nick(client: &dyn Client, nick: &str) -> bool {
+    client
+        .casemapping()
+        .eq_ignore_case(nick, &client.get_nick())
+}
+
+/// Nick or server name in a prefix.
+fn pfx_name(pfx: &Option<wire::Pfx>) -> &str {
+    match pfx {
+        Some(wire::Pfx::User { nick, .. }) | Some(wire::Pfx::Ambiguous(nick)) => nick,
+        Some(wire::Pfx::Server(server)) => server,
+        None => "",
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(clippy::zero_prefixed_literal)]
 
+mod alias;
 mod cli;
 mod cmd;
 mod config;
@@ -12,7 +13,7 @@ mod utils;
 mod tests;
 
 use libtiny_client::{Client, ServerInfo};
-use libtiny_common::{ChanNameRef, MsgTarget};
+use libtiny_common::MsgTarget;
 use libtiny_logger::{Logger, LoggerInitError};
 use libtiny_tui::TUI;
 use ui::UI;
@@ -58,8 +59,15 @@ fn main() {
                     servers,
                     defaults,
                     log_dir,
+                    aliases,
                 } = config;
 
+                // All servers in the config, for completing `/connect` arguments
+                let serv_addrs: Vec<String> = servers
+                    .iter()
+                    .map(|s| format!("{}:{}", s.addr, s.port))
+                    .collect();
+
                 let servers = if !server_args.is_empty() {
                     // Connect only to servers that match at least one of the given patterns
                     servers
@@ -69,7 +77,7 @@ fn main() {
                 } else {
                     servers
                 };
-                run(servers, defaults, config_path, log_dir)
+                run(servers, serv_addrs, defaults, aliases, config_path, log_dir)
             }
         }
     }
@@ -79,7 +87,9 @@ const DEBUG_LOG_FILE: &str = "tiny_debug_logs.txt";
 
 fn run(
     servers: Vec<config::Server>,
+    serv_addrs: Vec<String>,
     defaults: config::Defaults,
+    aliases: config::Aliases,
     config_path: PathBuf,
     log_dir: Option<PathBuf>,
 ) {
@@ -104,6 +114,13 @@ fn run(
     local.block_on(&runtime, async move {
         // Create TUI task
         let (tui, rcv_tui_ev) = TUI::run(config_path.clone());
+        tui.set_client_cmds(
+            cmd::builtin_names()
+                .map(str::to_owned)
+                .chain(aliases.keys().cloned())
+                .collect(),
+        );
+        tui.set_client_servers(serv_addrs);
         tui.draw();
 
         // Create logger
@@ -140,25 +157,50 @@ fn run(
         let mut clients: Vec<Client> = Vec::with_capacity(servers.len());
 
         for server in servers.iter().cloned() {
+            let tls_client_cert = server.read_tls_client_cert();
+
             tui.new_server_tab(&server.addr, server.alias);
 
+            let tls_client_cert = match tls_client_cert {
+                Ok(tls_client_cert) => tls_client_cert,
+                Err(err) => {
+                    tui.add_client_err_msg(
+                        &format!("Can't load TLS client certificate: {}", err),
+                        &MsgTarget::Server { serv: &server.addr },
+                    );
+                    None
+                }
+            };
+
             let server_info = ServerInfo {
                 addr: server.addr,
                 port: server.port,
                 tls: server.tls,
+                proxy: server.proxy.as_ref().map(config::Proxy::to_client_proxy),
+                tls_client_cert,
                 pass: server.pass,
                 realname: server.realname,
                 nicks: server.nicks,
                 auto_join: server
                     .join
                     .iter()
-                    .map(|c| ChanNameRef::new(c).to_owned())
+                    .map(config::JoinChan::to_auto_join)
                     .collect(),
                 nickserv_ident: server.nickserv_ident,
-                sasl_auth: server.sasl_auth.map(|auth| libtiny_client::SASLAuth {
-                    username: auth.username,
-                    password: auth.password,
+                sasl_auth: server.sasl_auth.map(|auth| match auth.mechanism {
+                    // `validate` checks that username and password are available
+                    config::SASLMechanism::Password => libtiny_client::SASLAuth::Password {
+                        username: auth.username.unwrap_or_default(),
+                        password: auth.password.unwrap_or_default(),
+                    },
+                    config::SASLMechanism::External => libtiny_client::SASLAuth::External,
                 }),
+                reconnect: server.reconnect.to_policy(),
+                flood_control: server.flood_control.to_client_flood_control(),
+                rejoin_on_kick: server.rejoin_on_kick.map(std::time::Duration::from_secs),
+                ctcp_replies: server.ctcp.to_client_ctcp_replies(),
+                dcc: server.dcc.to_client_dcc_config(),
+                caps: server.caps,
             };
 
             let (client, rcv_conn_ev) = Client::new(server_info);
@@ -173,7 +215,7 @@ fn run(
         }
 
         // Block on TUI task
-        ui::task(config_path, defaults, tui, clients, rcv_tui_ev).await;
+        ui::task(defaults, aliases, tui, clients, rcv_tui_ev).await;
     });
 
     runtime.block_on(local);
@@ -1,6 +1,6 @@
 use crate::conn;
 use crate::ui::UI;
-use libtiny_common::ChanName;
+use libtiny_common::{CaseMapping, ChanName};
 use libtiny_tui::test_utils::expect_screen;
 use libtiny_tui::TUI;
 use libtiny_wire::{Cmd, Msg, MsgTarget, Pfx};
@@ -10,6 +10,7 @@ use termbox_simple::CellBuf;
 use libtiny_client as client;
 use term_input as input;
 
+use std::collections::HashMap;
 use std::future::Future;
 use std::panic::Location;
 
@@ -33,6 +34,14 @@ impl conn::Client for TestClient {
     fn is_nick_accepted(&self) -> bool {
         true
     }
+
+    fn casemapping(&self) -> CaseMapping {
+        CaseMapping::default()
+    }
+
+    fn drop_nick_prefix<'a>(&self, nick: &'a str) -> &'a str {
+        libtiny_wire::drop_nick_prefix(nick)
+    }
 }
 
 static SERV_NAME: &str = "x.y.z";
@@ -113,12 +122,15 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Join a channel to test msg sent to channel
             let join = Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "osa1".to_owned(),
                     user: "a@b".to_owned(),
                 }),
                 cmd: Cmd::JOIN {
-                    chan: ChanName::new("#chan".to_owned()),
+                    chans: vec![ChanName::new("#chan".to_owned())],
+                    keys: vec![],
                 },
             };
             snd_conn_ev.send(client::Event::Msg(join)).await.unwrap();
@@ -126,9 +138,11 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Send a PRIVMSG to the channel
             let chan_msg = Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::Ambiguous("blah".to_owned())),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::Chan(ChanName::new("#chan".to_owned())),
+                    targets: vec![MsgTarget::Chan(ChanName::new("#chan".to_owned()))],
                     msg: "msg to chan".to_owned(),
                     is_notice: false,
                     ctcp: None,
@@ -142,9 +156,11 @@ fn test_privmsg_from_user_without_user_or_host_part_issue_247() {
 
             // Send a PRIVMSG to current nick
             let msg = Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::Ambiguous("blah".to_owned())),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("osa1".to_owned()),
+                    targets: vec![MsgTarget::User("osa1".to_owned())],
                     msg: "msg to user".to_owned(),
                     is_notice: false,
                     ctcp: None,
@@ -221,12 +237,14 @@ fn test_bouncer_relay_issue_271() {
                 .unwrap();
 
             let msg = Msg {
+                tags: HashMap::new(),
+                server_time: None,
                 pfx: Some(Pfx::User {
                     nick: "osa1-soju".to_owned(),
                     user: "osa1-soju@127.0.0.1".to_owned(),
                 }),
                 cmd: Cmd::PRIVMSG {
-                    target: MsgTarget::User("osa1/oftc".to_owned()),
+                    targets: vec![MsgTarget::User("osa1/oftc".to_owned())],
                     msg: "blah blah".to_owned(),
                     is_notice: false,
                     ctcp: None,
@@ -285,12 +303,14 @@ fn test_privmsg_targetmask_issue_278() {
 
             snd_conn_ev
                 .send(client::Event::Msg(Msg {
+                    tags: HashMap::new(),
+                    server_time: None,
                     pfx: Some(Pfx::User {
                         nick: "e".to_owned(),
                         user: "e@a/b/c.d".to_owned(),
                     }),
                     cmd: Cmd::PRIVMSG {
-                        target: MsgTarget::User("$$*".to_owned()),
+                        targets: vec![MsgTarget::User("$$*".to_owned())],
                         msg: "blah blah blah".to_owned(),
                         is_notice: true,
                         ctcp: None,
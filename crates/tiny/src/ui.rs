@@ -1,19 +1,33 @@
 //! UI event handling
 
+use crate::alias::{self, AliasEnv};
 use crate::cmd::{parse_cmd, CmdArgs, ParseCmdResult};
 use crate::config;
+use crate::utils;
 use libtiny_client::Client;
-use libtiny_common::{ChanNameRef, MsgSource, MsgTarget, TabStyle};
+use libtiny_common::{
+    ambiguous_cmd_msg, CaseMapping, ChanListEntry, ChanListFilter, ChanNameRef, MsgSource,
+    MsgTarget, Nick, TabStyle,
+};
 use libtiny_logger::Logger;
 use libtiny_tui::TUI;
 
-use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
 
 use time::Tm;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 
+/// Name of the tab that shows DCC file transfers. This is a server tab without a client, similar to
+/// the "mentions" tab.
+pub(crate) const TRANSFERS_TAB: &str = "transfers";
+
+/// Name of the tab that shows channel lists requested with `/list`.
+pub(crate) const CHAN_LIST_TAB: &str = "channels";
+
 macro_rules! delegate {
     ( $name:ident ( $( $x:ident: $t:ty, )* )) => {
         pub(crate) fn $name(&self, $($x: $t,)*) {
@@ -37,11 +51,33 @@ macro_rules! delegate_ui {
 pub(crate) struct UI {
     ui: TUI,
     logger: Option<Logger>,
+    /// Tabs WHOIS and WHOWAS queries were sent from, by server name and nick. Replies are shown
+    /// in these tabs.
+    whois_srcs: Rc<RefCell<HashMap<(String, Nick), MsgSource>>>,
 }
 
 impl UI {
     pub(crate) fn new(ui: TUI, logger: Option<Logger>) -> UI {
-        UI { ui, logger }
+        UI {
+            ui,
+            logger,
+            whois_srcs: Default::default(),
+        }
+    }
+
+    /// Record the tab a WHOIS or WHOWAS query for `nick` is sent from.
+    pub(crate) fn set_whois_src(&self, serv: &str, nick: Nick, src: MsgSource) {
+        self.whois_srcs
+            .borrow_mut()
+            .insert((serv.to_owned(), nick), src);
+    }
+
+    /// Tab the last WHOIS or WHOWAS query for `nick` was sent from.
+    pub(crate) fn whois_src(&self, serv: &str, nick: Nick) -> Option<MsgSource> {
+        self.whois_srcs
+            .borrow()
+            .get(&(serv.to_owned(), nick))
+            .cloned()
     }
 
     pub(crate) fn new_server_tab(&self, serv_name: &str, alias: Option<String>) {
@@ -55,6 +91,8 @@ impl UI {
     delegate!(new_chan_tab(serv: &str, chan: &ChanNameRef,));
     delegate!(close_chan_tab(serv: &str, chan: &ChanNameRef,));
     delegate!(close_user_tab(serv: &str, nick: &str,));
+    delegate!(close_dcc_chat_tab(serv: &str, nick: &str,));
+    delegate!(set_casemapping(serv: &str, casemapping: CaseMapping,));
     delegate!(add_client_msg(msg: &str, target: &MsgTarget,));
     delegate!(add_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate!(add_privmsg(
@@ -84,9 +122,17 @@ impl UI {
     delegate_ui!(add_err_msg(msg: &str, ts: Tm, target: &MsgTarget,));
     delegate_ui!(add_client_err_msg(msg: &str, target: &MsgTarget,));
     delegate_ui!(clear_nicks(serv: &str,));
+    delegate_ui!(set_chan_parted(serv: &str, chan: &ChanNameRef,));
     delegate_ui!(set_nick(serv: &str, nick: &str,));
     delegate_ui!(set_tab_style(style: TabStyle, target: &MsgTarget,));
     delegate_ui!(user_tab_exists(serv_name: &str, nick: &str,) -> bool);
+    delegate_ui!(new_dcc_chat_tab(serv: &str, nick: &str,));
+    delegate_ui!(show_chan_list(tab: &str, serv: &str, filter: ChanListFilter,));
+    delegate_ui!(set_chan_list_entries(
+        tab: &str,
+        serv: &str,
+        entries: Vec<ChanListEntry>,
+    ));
 
     pub(crate) fn current_tab(&self) -> Option<MsgSource> {
         self.ui.current_tab()
@@ -94,22 +140,22 @@ impl UI {
 }
 
 pub(crate) async fn task(
-    config_path: PathBuf,
     defaults: config::Defaults,
+    aliases: config::Aliases,
     ui: UI,
     mut clients: Vec<Client>,
     rcv_ev: mpsc::Receiver<libtiny_common::Event>,
 ) {
     let mut rcv_ev = ReceiverStream::new(rcv_ev);
     while let Some(ev) = rcv_ev.next().await {
-        handle_input_ev(&config_path, &defaults, &ui, &mut clients, ev);
+        handle_input_ev(&defaults, &aliases, &ui, &mut clients, ev);
         ui.draw();
     }
 }
 
 fn handle_input_ev(
-    config_path: &Path,
     defaults: &config::Defaults,
+    aliases: &config::Aliases,
     ui: &UI,
     clients: &mut Vec<Client>,
     ev: libtiny_common::Event,
@@ -129,13 +175,76 @@ fn handle_input_ev(
                 send_msg(ui, clients, &source, line, false)
             }
         }
-        Cmd { cmd, source } => handle_cmd(config_path, defaults, ui, clients, source, &cmd),
+        Cmd { cmd, source } => handle_cmd(defaults, aliases, ui, clients, source, &cmd),
     }
 }
 
 fn handle_cmd(
-    config_path: &Path,
     defaults: &config::Defaults,
+    aliases: &config::Aliases,
+    ui: &UI,
+    clients: &mut Vec<Client>,
+    src: MsgSource,
+    cmd: &str,
+) {
+    let mut ws_idxs = utils::split_whitespace_indices(cmd);
+    let alias = cmd
+        .split_whitespace()
+        .next()
+        .and_then(|name| aliases.get(name));
+    match alias {
+        Some(alias) => {
+            ws_idxs.next(); // alias name
+            let args = ws_idxs.next().map(|idx| &cmd[idx..]).unwrap_or("");
+            run_alias(defaults, aliases, ui, clients, src, alias, args)
+        }
+        None => handle_builtin_cmd(defaults, aliases, ui, clients, src, cmd),
+    }
+}
+
+/// Run commands and send messages in an alias. Commands in an alias always run the built-in
+/// commands, so an alias can't call itself or other aliases.
+fn run_alias(
+    defaults: &config::Defaults,
+    aliases: &config::Aliases,
+    ui: &UI,
+    clients: &mut Vec<Client>,
+    src: MsgSource,
+    alias: &config::Alias,
+    args: &str,
+) {
+    let nick = clients
+        .iter()
+        .find(|client| client.get_serv_name() == src.serv_name())
+        .map(Client::get_nick);
+    let chan = match &src {
+        MsgSource::Chan { chan, .. } => Some(chan.display()),
+        _ => None,
+    };
+    let env = AliasEnv {
+        args,
+        nick: nick.as_deref(),
+        serv: src.serv_name(),
+        chan,
+    };
+    match alias::expand(alias.cmd(), &env) {
+        Err(err) => ui.add_client_err_msg(&err, &MsgTarget::CurrentTab),
+        Ok(parts) => {
+            for part in parts {
+                match part.strip_prefix('/') {
+                    Some(cmd) => {
+                        handle_builtin_cmd(defaults, aliases, ui, clients, src.clone(), cmd)
+                    }
+                    None => send_msg(ui, clients, &src, part, false),
+                }
+            }
+        }
+    }
+}
+
+fn handle_builtin_cmd(
+    defaults: &config::Defaults,
+    aliases: &config::Aliases,
     ui: &UI,
     clients: &mut Vec<Client>,
     src: MsgSource,
@@ -145,24 +254,17 @@ fn handle_cmd(
         ParseCmdResult::Ok { cmd, rest } => {
             let cmd_args = CmdArgs {
                 args: rest,
-                config_path,
                 defaults,
+                aliases,
                 ui,
                 clients,
                 src,
             };
             (cmd.cmd_fn)(cmd_args);
         }
-        // ParseCmdResult::Ambiguous(vec) => {
-        //     self.ui.add_client_err_msg(
-        //         &format!("Unsupported command: \"/{}\"", msg),
-        //         &MsgTarget::CurrentTab,
-        //     );
-        //     self.ui.add_client_err_msg(
-        //         &format!("Did you mean one of {:?} ?", vec),
-        //         &MsgTarget::CurrentTab,
-        //     );
-        // },
+        ParseCmdResult::Ambiguous(names) => {
+            ui.add_client_err_msg(&ambiguous_cmd_msg(cmd, &names), &MsgTarget::CurrentTab)
+        }
         ParseCmdResult::Unknown => ui.add_client_err_msg(
             &format!("Unsupported command: \"/{}\"", cmd),
             &MsgTarget::CurrentTab,
@@ -173,13 +275,29 @@ fn handle_cmd(
 // TODO: move this somewhere else
 pub(crate) fn send_msg(
     ui: &UI,
-    clients: &mut Vec<Client>,
+    clients: &mut [Client],
     src: &MsgSource,
     msg: String,
     is_action: bool,
 ) {
+    if src.serv_name() == TRANSFERS_TAB {
+        ui.add_client_err_msg(
+            "You are on the transfers tab, please use `/switch <tab name>` to switch to a tab",
+            &MsgTarget::CurrentTab,
+        );
+        return;
+    }
+
+    if src.serv_name() == CHAN_LIST_TAB {
+        ui.add_client_err_msg(
+            "You are on the channel list tab, please use `/switch <tab name>` to switch to a tab",
+            &MsgTarget::CurrentTab,
+        );
+        return;
+    }
+
     if src.serv_name() == "mentions" {
-        if clients.len() == 0 {
+        if clients.is_empty() {
             ui.add_client_err_msg(
                 "No connected server found, please use `/connect <server>` to connect to a server",
                 &MsgTarget::CurrentTab,
@@ -229,6 +347,23 @@ pub(crate) fn send_msg(
                 };
                 (msg_target, nick)
             }
+
+            MsgSource::DccChat { ref serv, ref nick } => {
+                // DCC chat messages are sent directly to the user, not to the server
+                let ui_target = MsgTarget::DccChat { serv, nick };
+                match client.dcc_chat_send(nick, &msg, is_action) {
+                    Ok(()) => ui.add_privmsg(
+                        &client.get_nick(),
+                        &msg,
+                        time::now(),
+                        &ui_target,
+                        false,
+                        is_action,
+                    ),
+                    Err(err) => ui.add_client_err_msg(&err, &ui_target),
+                }
+                return;
+            }
         }
     };
 
@@ -239,8 +374,12 @@ pub(crate) fn send_msg(
         } else {
             0
         };
+    // With `echo-message` the server sends our messages back to us, they're shown when received
+    let echo = client.enabled_caps().contains("echo-message");
     for msg in client.split_privmsg(extra_len, &msg) {
         client.privmsg(msg_target, msg, is_action);
-        ui.add_privmsg(&client.get_nick(), msg, ts, &ui_target, false, is_action);
+        if !echo {
+            ui.add_privmsg(&client.get_nick(), msg, ts, &ui_target, false, is_action);
+        }
     }
 }
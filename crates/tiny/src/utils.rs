@@ -16,7 +16,7 @@ impl<'a> Iterator for SplitWhitespaceIndices<'a> {
     }
 }
 
-pub(crate) fn split_whitespace_indices(str: &str) -> SplitWhitespaceIndices {
+pub(crate) fn split_whitespace_indices(str: &str) -> SplitWhitespaceIndices<'_> {
     SplitWhitespaceIndices {
         inner: str.split_whitespace(),
         str,
@@ -30,12 +30,6 @@ pub(crate) fn split_whitespace_indices(str: &str) -> SplitWhitespaceIndices {
 // nickname   =  ( letter / special ) *8( letter / digit / special / "-" )
 // letter     =  %x41-5A / %x61-7A ; A-Z / a-z
 // special    =  %x5B-60 / %x7B-7D ; "[", "]", "\", "`", "_", "^", "{", "|", "}"
-//
-// We use a simpler check here (allows strictly more nicks)
-// Only check to see if the nick does not begin with '#', https://github.com/osa1/tiny/issues/324
-pub(crate) fn is_nick_first_char(c: char) -> bool {
-    c != '#'
-}
 
 /*
 pub(crate) fn is_nick_char(c: char) -> bool {